@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // use a vendored protoc, so that building doesn't require one to be installed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/payments.proto")?;
+    tonic_build::compile_protos("proto/domain.proto")?;
+    Ok(())
+}
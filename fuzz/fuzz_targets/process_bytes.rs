@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use payments::accounts::MemoryRepo as AccountsMemoryRepo;
+use payments::events::MemoryRepo as EventsMemoryRepo;
+use payments::fuzz::process_bytes;
+use payments::payments::PaymentsEngine;
+use payments::transactions::MemoryRepo as TransactionsMemoryRepo;
+
+fuzz_target!(|data: &[u8]| {
+    let transactions = TransactionsMemoryRepo::new();
+    let accounts = AccountsMemoryRepo::new();
+    let events = EventsMemoryRepo::new();
+    let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+    process_bytes(data, &engine);
+});
@@ -0,0 +1,110 @@
+/* Generated with cbindgen from src/ffi.rs: `cbindgen --config cbindgen.toml --output include/payments.h` */
+
+#ifndef PAYMENTS_H
+#define PAYMENTS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * payments_status is the outcome of a call
+ */
+typedef enum payments_status {
+  PAYMENTS_OK = 0,
+  /**
+   * The transaction was rejected by the engine, e.g. for insufficient funds
+   */
+  PAYMENTS_REJECTED = 1,
+  /**
+   * A pointer was null, or the type or amount couldn't be parsed
+   */
+  PAYMENTS_INVALID_ARGUMENT = 2,
+  /**
+   * The engine panicked, which is a bug. The engine should be freed.
+   */
+  PAYMENTS_PANIC = 3,
+} payments_status;
+
+/**
+ * payments_engine is an engine along with the in-memory repositories holding its state. It's
+ * opaque to C.
+ */
+typedef struct payments_engine payments_engine;
+
+/**
+ * payments_buffer is a byte buffer owned by the library, which must be released with
+ * `payments_buffer_free`
+ */
+typedef struct payments_buffer {
+  uint8_t *data;
+  uintptr_t len;
+} payments_buffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * payments_engine_new creates an engine with empty in-memory state, which must be released with
+ * `payments_engine_free`
+ */
+struct payments_engine *payments_engine_new(void);
+
+/**
+ * payments_engine_free releases an engine and all of its state
+ *
+ * # Safety
+ * `engine` must be null or have been returned by `payments_engine_new`, and not already freed.
+ */
+void payments_engine_free(struct payments_engine *engine);
+
+/**
+ * payments_submit applies a transaction of the given type (e.g. "deposit", "dispute") to the
+ * engine. The amount is a decimal string, and may be null for types which don't take one. On
+ * failure the reason is available from `payments_last_error`.
+ *
+ * # Safety
+ * `engine` must be a live engine, `kind` a valid C string and `amount` null or a valid C string.
+ */
+enum payments_status payments_submit(struct payments_engine *engine,
+                                     const char *kind,
+                                     uint64_t client,
+                                     uint64_t tx,
+                                     const char *amount);
+
+/**
+ * payments_last_error is the reason the last call on the engine failed, prefixed by its error
+ * code (e.g. "insufficient_funds: insufficient funds"), or null if it succeeded. The string is
+ * owned by the engine, and valid until the next call on it.
+ *
+ * # Safety
+ * `engine` must be null or a live engine.
+ */
+const char *payments_last_error(const struct payments_engine *engine);
+
+/**
+ * payments_statements writes every account's statement as CSV (with client, available, held,
+ * total & locked columns) into a buffer, which is empty if the engine is null or they couldn't
+ * be written
+ *
+ * # Safety
+ * `engine` must be null or a live engine.
+ */
+struct payments_buffer payments_statements(struct payments_engine *engine);
+
+/**
+ * payments_buffer_free releases a buffer returned by the library
+ *
+ * # Safety
+ * `buffer` must have been returned by the library, and not already freed.
+ */
+void payments_buffer_free(struct payments_buffer buffer);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* PAYMENTS_H */
@@ -1,10 +1,16 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::io;
 
 use anyhow::Result;
 use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::encryption::{self, Cipher};
+use crate::tenant::{self, TenantId};
 use crate::transactions::{Transaction, TransactionKind};
 
 #[derive(Error, Debug, PartialEq)]
@@ -15,24 +21,62 @@ pub enum AccountError {
     InvalidClient,
     #[error("account must be opened with a deposit transaction")]
     InvalidInitialTransaction,
+    #[error("account is locked")]
+    Locked,
+    #[error("balance would be too large to represent")]
+    Overflow,
+}
+
+impl AccountError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            AccountError::InsufficientFunds => "insufficient_funds",
+            AccountError::InvalidClient => "invalid_client",
+            AccountError::InvalidInitialTransaction => "invalid_initial_transaction",
+            AccountError::Locked => "account_locked",
+            AccountError::Overflow => "balance_overflow",
+        }
+    }
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 enum LockedStatus {
     Locked,
     Unlocked,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// AccountKind is the type of an account. Deposits into escrow accounts are held until they're
+/// released or refunded, rather than being available straight away.
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountKind {
+    #[default]
+    Standard,
+    Escrow,
+}
+
+impl AccountKind {
+    fn is_standard(&self) -> bool {
+        *self == AccountKind::Standard
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Account {
-    client: u16,
+    client: u64,
     available: Decimal,
     held: Decimal,
     locked: LockedStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<TenantId>,
+    #[serde(default, skip_serializing_if = "AccountKind::is_standard")]
+    kind: AccountKind,
 }
 
 impl Account {
-    /// new creates an account from a deposit transaction
+    /// new creates an account from a deposit transaction, or an escrow account from an escrowed
+    /// deposit
     pub fn new(transaction: Transaction) -> Result<Account, AccountError> {
         match transaction.kind {
             TransactionKind::Deposit { amount } => Ok(Account {
@@ -40,13 +84,58 @@ impl Account {
                 available: amount,
                 held: Decimal::from(0),
                 locked: LockedStatus::Unlocked,
+                tenant: transaction.tenant,
+                kind: AccountKind::Standard,
+            }),
+            TransactionKind::PendingDeposit { amount } => Ok(Account {
+                client: transaction.client,
+                available: Decimal::from(0),
+                held: amount,
+                locked: LockedStatus::Unlocked,
+                tenant: transaction.tenant,
+                kind: AccountKind::Standard,
+            }),
+            TransactionKind::Escrow { amount } => Ok(Account {
+                client: transaction.client,
+                available: Decimal::from(0),
+                held: amount,
+                locked: LockedStatus::Unlocked,
+                tenant: transaction.tenant,
+                kind: AccountKind::Escrow,
             }),
             _ => Err(AccountError::InvalidInitialTransaction),
         }
     }
-    pub fn client(&self) -> u16 {
+    /// from_balances creates an account with the given state, e.g. one decoded from another
+    /// service's message
+    pub fn from_balances(
+        client: u64,
+        available: Decimal,
+        held: Decimal,
+        locked: bool,
+        tenant: Option<TenantId>,
+        kind: AccountKind,
+    ) -> Account {
+        Account {
+            client,
+            available,
+            held,
+            locked: if locked {
+                LockedStatus::Locked
+            } else {
+                LockedStatus::Unlocked
+            },
+            tenant,
+            kind,
+        }
+    }
+    pub fn client(&self) -> u64 {
         self.client
     }
+    /// tenant is the tenant the account belongs to, if it was opened for one
+    pub fn tenant(&self) -> Option<TenantId> {
+        self.tenant
+    }
     pub fn available(&self) -> Decimal {
         self.available
     }
@@ -59,6 +148,25 @@ impl Account {
     pub fn is_locked(&self) -> bool {
         self.locked == LockedStatus::Locked
     }
+    pub fn kind(&self) -> AccountKind {
+        self.kind
+    }
+    /// merge adds another account's balances to this one's. The merged account is locked if
+    /// either of them was.
+    pub fn merge(&self, other: &Account) -> Result<Account, AccountError> {
+        let account = Account {
+            available: add(self.available, other.available)?,
+            held: add(self.held, other.held)?,
+            locked: if other.is_locked() {
+                LockedStatus::Locked
+            } else {
+                self.locked
+            },
+            ..*self
+        };
+        add(account.available, account.held)?;
+        Ok(account)
+    }
     pub fn apply(
         &self,
         Transaction {
@@ -71,18 +179,20 @@ impl Account {
         if self.client != client {
             return Err(AccountError::InvalidClient);
         }
-        if self.is_locked() {
-            return Err(AccountError::InsufficientFunds);
+        if self.is_locked() && !kind.is_admin() {
+            return Err(AccountError::Locked);
         }
-        match kind {
+        let account = match kind {
             TransactionKind::Deposit { .. } => Ok(Account {
                 client,
-                available: self.available + amount,
+                available: add(self.available, amount)?,
                 held: self.held,
                 locked: self.locked,
+                tenant: self.tenant,
+                kind: self.kind,
             }),
-            TransactionKind::Withdrawal { .. } => {
-                let available = self.available - amount;
+            TransactionKind::Withdrawal { .. } | TransactionKind::Payout { .. } => {
+                let available = sub(self.available, amount)?;
                 if available < Decimal::from(0) {
                     return Err(AccountError::InsufficientFunds);
                 }
@@ -91,6 +201,8 @@ impl Account {
                     available,
                     held: self.held,
                     locked: self.locked,
+                    tenant: self.tenant,
+                    kind: self.kind,
                 })
             }
             // @TODO: should dispute, resolve & chargeback transactions error when:
@@ -98,34 +210,168 @@ impl Account {
             //      b) the resulting held balance would be negative ?
             TransactionKind::Dispute => Ok(Account {
                 client,
-                available: self.available - amount,
-                held: self.held + amount,
+                available: sub(self.available, amount)?,
+                held: add(self.held, amount)?,
                 locked: self.locked,
+                tenant: self.tenant,
+                kind: self.kind,
             }),
-            TransactionKind::Resolve => Ok(Account {
+            TransactionKind::Resolve | TransactionKind::ForceResolve => Ok(Account {
                 client,
-                available: self.available + amount,
-                held: self.held - amount,
+                available: add(self.available, amount)?,
+                held: sub(self.held, amount)?,
                 locked: self.locked,
+                tenant: self.tenant,
+                kind: self.kind,
             }),
             TransactionKind::ChargeBack => Ok(Account {
                 client,
                 available: self.available,
-                held: self.held - amount,
+                held: sub(self.held, amount)?,
                 locked: LockedStatus::Locked,
+                tenant: self.tenant,
+                kind: self.kind,
             }),
-        }
+            TransactionKind::Adjustment { .. } => {
+                let available = add(self.available, amount)?;
+                if available < Decimal::from(0) {
+                    return Err(AccountError::InsufficientFunds);
+                }
+                Ok(Account {
+                    client,
+                    available,
+                    held: self.held,
+                    locked: self.locked,
+                    tenant: self.tenant,
+                    kind: self.kind,
+                })
+            }
+            TransactionKind::Escrow { .. } => Ok(Account {
+                held: add(self.held, amount)?,
+                ..*self
+            }),
+            TransactionKind::Release => Ok(Account {
+                available: add(self.available, amount)?,
+                held: sub(self.held, amount)?,
+                ..*self
+            }),
+            TransactionKind::Refund => Ok(Account {
+                held: sub(self.held, amount)?,
+                ..*self
+            }),
+            TransactionKind::PendingDeposit { .. } => Ok(Account {
+                held: add(self.held, amount)?,
+                ..*self
+            }),
+            TransactionKind::PendingWithdrawal { .. } => {
+                let available = sub(self.available, amount)?;
+                if available < Decimal::from(0) {
+                    return Err(AccountError::InsufficientFunds);
+                }
+                Ok(Account {
+                    available,
+                    held: add(self.held, amount)?,
+                    ..*self
+                })
+            }
+            // a settled deposit becomes available, and a failed withdrawal is returned
+            TransactionKind::Settle { withdrawal: false }
+            | TransactionKind::Fail { withdrawal: true } => Ok(Account {
+                available: add(self.available, amount)?,
+                held: sub(self.held, amount)?,
+                ..*self
+            }),
+            // a settled withdrawal is paid out, and a failed deposit never arrives
+            TransactionKind::Settle { withdrawal: true }
+            | TransactionKind::Fail { withdrawal: false } => Ok(Account {
+                held: sub(self.held, amount)?,
+                ..*self
+            }),
+            TransactionKind::Lock => Ok(Account {
+                locked: LockedStatus::Locked,
+                tenant: self.tenant,
+                ..*self
+            }),
+            TransactionKind::Unlock => Ok(Account {
+                locked: LockedStatus::Unlocked,
+                ..*self
+            }),
+        }?;
+        // the total is output alongside the balances, so it must be representable too
+        add(account.available, account.held)?;
+        Ok(account)
     }
 }
 
+fn add(a: Decimal, b: Decimal) -> Result<Decimal, AccountError> {
+    a.checked_add(b).ok_or(AccountError::Overflow)
+}
+
+fn sub(a: Decimal, b: Decimal) -> Result<Decimal, AccountError> {
+    a.checked_sub(b).ok_or(AccountError::Overflow)
+}
+
 pub trait AccountsRepo {
-    fn get(&self, id: u16) -> Result<Option<Account>>;
-    fn save(&self, account: Account) -> Result<u16>;
+    fn get(&self, id: u64) -> Result<Option<Account>>;
+    fn save(&self, account: Account) -> Result<u64>;
     fn get_all(&self) -> Result<Vec<Account>>;
+    fn remove(&self, id: u64) -> Result<Option<Account>>;
+}
+
+impl<T: AccountsRepo + ?Sized> AccountsRepo for Box<T> {
+    fn get(&self, id: u64) -> Result<Option<Account>> {
+        (**self).get(id)
+    }
+    fn save(&self, account: Account) -> Result<u64> {
+        (**self).save(account)
+    }
+    fn get_all(&self) -> Result<Vec<Account>> {
+        (**self).get_all()
+    }
+    fn remove(&self, id: u64) -> Result<Option<Account>> {
+        (**self).remove(id)
+    }
+}
+
+/// AccountStatement is an account's balances as output at the end of a run, i.e. a row of a
+/// statements file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AccountStatement {
+    /// Only output when processing for a tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    pub client: u64,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
 }
 
-pub struct MemoryRepo {
-    data: RefCell<HashMap<u16, Account>>,
+impl From<Account> for AccountStatement {
+    fn from(acc: Account) -> AccountStatement {
+        AccountStatement {
+            tenant: acc.tenant(),
+            client: acc.client(),
+            available: acc.available(),
+            held: acc.held(),
+            total: acc.total(),
+            locked: acc.is_locked(),
+        }
+    }
+}
+
+/// read_statements reads a statements file, as written at the end of a run, in the order of its
+/// rows. Columns other than those of [AccountStatement] are ignored.
+pub fn read_statements<R: io::Read>(reader: R) -> Result<Vec<AccountStatement>> {
+    let mut statements = Vec::new();
+    for result in csv::Reader::from_reader(reader).deserialize() {
+        statements.push(result?);
+    }
+    Ok(statements)
+}
+
+pub struct MemoryRepo<S = RandomState> {
+    data: RefCell<HashMap<u64, Account, S>>,
 }
 
 impl MemoryRepo {
@@ -136,12 +382,28 @@ impl MemoryRepo {
     }
 }
 
-impl AccountsRepo for MemoryRepo {
-    fn get(&self, id: u16) -> Result<Option<Account>> {
+impl Default for MemoryRepo {
+    fn default() -> MemoryRepo {
+        MemoryRepo::new()
+    }
+}
+
+impl<S: BuildHasher> MemoryRepo<S> {
+    /// with_hasher creates a repo which hashes IDs with the given hasher, e.g. a faster one than
+    /// the default DoS-resistant SipHash when the input is trusted
+    pub fn with_hasher(hasher: S) -> MemoryRepo<S> {
+        MemoryRepo {
+            data: RefCell::new(HashMap::with_hasher(hasher)),
+        }
+    }
+}
+
+impl<S: BuildHasher> AccountsRepo for MemoryRepo<S> {
+    fn get(&self, id: u64) -> Result<Option<Account>> {
         Ok(self.data.borrow().get(&id).cloned())
     }
 
-    fn save(&self, account: Account) -> Result<u16> {
+    fn save(&self, account: Account) -> Result<u64> {
         self.data.borrow_mut().insert(account.client, account);
         Ok(account.client)
     }
@@ -149,6 +411,122 @@ impl AccountsRepo for MemoryRepo {
     fn get_all(&self) -> Result<Vec<Account>> {
         Ok(self.data.borrow().values().cloned().collect())
     }
+
+    fn remove(&self, id: u64) -> Result<Option<Account>> {
+        Ok(self.data.borrow_mut().remove(&id))
+    }
+}
+
+pub struct SledRepo {
+    tree: sled::Tree,
+    cipher: Option<Cipher>,
+}
+
+impl SledRepo {
+    pub fn new(db: &sled::Db) -> Result<SledRepo> {
+        SledRepo::for_tenant(db, None)
+    }
+
+    /// for_tenant stores the tenant's accounts in their own tree, isolated from every other tenant's
+    pub fn for_tenant(db: &sled::Db, tenant: Option<TenantId>) -> Result<SledRepo> {
+        Ok(SledRepo {
+            tree: db.open_tree(tenant::tree_name(tenant, "accounts"))?,
+            cipher: None,
+        })
+    }
+
+    /// encrypted makes the repo encrypt everything it stores with `cipher`
+    pub fn encrypted(self, cipher: Cipher) -> SledRepo {
+        SledRepo {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+}
+
+impl AccountsRepo for SledRepo {
+    fn get(&self, id: u64) -> Result<Option<Account>> {
+        match self.tree.get(id.to_be_bytes())? {
+            Some(bytes) => Ok(Some(encryption::decode(self.cipher.as_ref(), &bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, account: Account) -> Result<u64> {
+        self.tree.insert(
+            account.client.to_be_bytes(),
+            encryption::encode(self.cipher.as_ref(), &account)?,
+        )?;
+        Ok(account.client)
+    }
+
+    fn get_all(&self) -> Result<Vec<Account>> {
+        self.tree
+            .iter()
+            .values()
+            .map(|bytes| encryption::decode(self.cipher.as_ref(), &bytes?))
+            .collect()
+    }
+
+    fn remove(&self, id: u64) -> Result<Option<Account>> {
+        match self.tree.remove(id.to_be_bytes())? {
+            Some(bytes) => Ok(Some(encryption::decode(self.cipher.as_ref(), &bytes)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// OverlayRepo reads through to another repo but keeps every write in memory, leaving the
+/// underlying repo untouched
+pub struct OverlayRepo {
+    inner: Box<dyn AccountsRepo>,
+    overlay: MemoryRepo,
+    /// The IDs of accounts removed from the underlying repo, which are hidden instead
+    removed: RefCell<HashSet<u64>>,
+}
+
+impl OverlayRepo {
+    pub fn new(inner: Box<dyn AccountsRepo>) -> OverlayRepo {
+        OverlayRepo {
+            inner,
+            overlay: MemoryRepo::new(),
+            removed: RefCell::new(HashSet::new()),
+        }
+    }
+}
+
+impl AccountsRepo for OverlayRepo {
+    fn get(&self, id: u64) -> Result<Option<Account>> {
+        match self.overlay.get(id)? {
+            Some(account) => Ok(Some(account)),
+            None if self.removed.borrow().contains(&id) => Ok(None),
+            None => self.inner.get(id),
+        }
+    }
+
+    fn save(&self, account: Account) -> Result<u64> {
+        self.overlay.save(account)
+    }
+
+    fn get_all(&self) -> Result<Vec<Account>> {
+        let overlay = self.overlay.data.borrow();
+        let removed = self.removed.borrow();
+        let mut accounts: Vec<Account> = self
+            .inner
+            .get_all()?
+            .into_iter()
+            .filter(|acc| !overlay.contains_key(&acc.client) && !removed.contains(&acc.client))
+            .collect();
+        accounts.extend(overlay.values().cloned());
+        Ok(accounts)
+    }
+
+    fn remove(&self, id: u64) -> Result<Option<Account>> {
+        let account = self.get(id)?;
+        self.overlay.remove(id)?;
+        self.removed.borrow_mut().insert(id);
+        Ok(account)
+    }
 }
 
 #[cfg(test)]
@@ -165,6 +543,9 @@ mod tests {
             },
             client: 1,
             amount: Decimal::from(8),
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         };
 
         let acc = Account::new(transaction);
@@ -181,6 +562,9 @@ mod tests {
                 amount: Decimal::from(8),
             },
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         let acc = Account::new(transaction)?;
         let amount = Decimal::from(7);
@@ -189,6 +573,9 @@ mod tests {
             tx: 1,
             kind: TransactionKind::Deposit { amount },
             amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         assert_eq!(acc.available(), Decimal::from(15));
         Ok(())
@@ -202,6 +589,9 @@ mod tests {
                 amount: Decimal::from(0),
             },
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         let mut acc = Account::new(transaction)?;
         acc.available = Decimal::from(8);
@@ -211,6 +601,9 @@ mod tests {
             client: acc.client,
             kind: TransactionKind::Withdrawal { amount },
             amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         assert_eq!(acc.available(), Decimal::from(1));
         Ok(())
@@ -224,6 +617,9 @@ mod tests {
                 amount: Decimal::from(0),
             },
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         let mut acc = Account::new(transaction)?;
         acc.available = Decimal::from(8);
@@ -233,6 +629,9 @@ mod tests {
             client: acc.client,
             kind: TransactionKind::Withdrawal { amount },
             amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         });
         assert!(res.is_err());
         assert_eq!(res.unwrap_err(), AccountError::InsufficientFunds);
@@ -248,6 +647,9 @@ mod tests {
                 amount: Decimal::from(0),
             },
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         let mut acc = Account::new(transaction)?;
         acc.available = Decimal::from(8);
@@ -257,9 +659,12 @@ mod tests {
             client: acc.client,
             kind: TransactionKind::Dispute,
             amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         assert_eq!(acc.available(), Decimal::from(1));
-        assert_eq!(acc.held(), Decimal::from(amount));
+        assert_eq!(acc.held(), amount);
         Ok(())
     }
 
@@ -271,6 +676,9 @@ mod tests {
                 amount: Decimal::from(0),
             },
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         let mut acc = Account::new(transaction)?;
         acc.held = Decimal::from(7);
@@ -281,6 +689,9 @@ mod tests {
             client: acc.client,
             kind: TransactionKind::Resolve,
             amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         assert_eq!(acc.available(), Decimal::from(8));
         assert_eq!(acc.held(), Decimal::from(0));
@@ -295,6 +706,9 @@ mod tests {
                 amount: Decimal::from(0),
             },
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         let mut acc = Account::new(transaction)?;
         acc.held = Decimal::from(7);
@@ -305,6 +719,9 @@ mod tests {
             client: acc.client,
             kind: TransactionKind::ChargeBack,
             amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         assert_eq!(acc.available(), Decimal::from(1));
         assert_eq!(acc.held(), Decimal::from(5));
@@ -320,6 +737,9 @@ mod tests {
                 amount: Decimal::from(100),
             },
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         let mut acc = Account::new(transaction)?;
         acc.locked = LockedStatus::Locked;
@@ -329,9 +749,52 @@ mod tests {
             client: acc.client,
             kind: TransactionKind::Withdrawal { amount },
             amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         });
         assert!(res.is_err());
+        assert_eq!(res.unwrap_err(), AccountError::Locked);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_admin() -> Result<()> {
+        let transaction = Transaction::try_from(TransactionCommand {
+            tx: 1,
+            kind: TransactionKind::Deposit {
+                amount: Decimal::from(100),
+            },
+            client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        let admin = |tx, kind| Transaction {
+            tx,
+            client: 1,
+            kind,
+            amount: match kind {
+                TransactionKind::Adjustment { amount } => amount,
+                _ => Decimal::from(0),
+            },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+        let acc = Account::new(transaction)?.apply(admin(2, TransactionKind::Lock))?;
+        assert!(acc.is_locked());
+
+        // admin actions still apply to locked accounts
+        let amount = Decimal::from(-30);
+        let acc = acc.apply(admin(3, TransactionKind::Adjustment { amount }))?;
+        assert_eq!(acc.available(), Decimal::from(70));
+        let amount = Decimal::from(-71);
+        let res = acc.apply(admin(4, TransactionKind::Adjustment { amount }));
         assert_eq!(res.unwrap_err(), AccountError::InsufficientFunds);
+
+        let acc = acc.apply(admin(5, TransactionKind::Unlock))?;
+        assert!(!acc.is_locked());
         Ok(())
     }
 
@@ -343,6 +806,9 @@ mod tests {
                 amount: Decimal::from(100),
             },
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         })?;
         let acc = Account::new(transaction)?;
         let amount = Decimal::from(10);
@@ -351,9 +817,109 @@ mod tests {
             client: acc.client + 1,
             kind: TransactionKind::Withdrawal { amount },
             amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         });
         assert!(res.is_err());
         assert_eq!(res.unwrap_err(), AccountError::InvalidClient);
         Ok(())
     }
+
+    #[test]
+    fn test_sled_repo() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let repo = SledRepo::new(&db)?;
+        let transaction = Transaction::try_from(TransactionCommand {
+            tx: 1,
+            kind: TransactionKind::Deposit {
+                amount: Decimal::new(15, 1),
+            },
+            client: 3,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        repo.save(Account::new(transaction)?)?;
+
+        let acc = repo.get(3)?.unwrap();
+        assert_eq!(acc.available(), Decimal::new(15, 1));
+        assert!(repo.get(4)?.is_none());
+        assert_eq!(repo.get_all()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_repo() -> Result<()> {
+        let inner = MemoryRepo::new();
+        for client in [1, 2] {
+            inner.save(Account::new(Transaction::try_from(TransactionCommand {
+                tx: client,
+                kind: TransactionKind::Deposit {
+                    amount: Decimal::from(1),
+                },
+                client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?)?)?;
+        }
+        let db = sled::Config::new().temporary(true).open()?;
+        let sled_repo = SledRepo::new(&db)?;
+        for acc in inner.get_all()? {
+            sled_repo.save(acc)?;
+        }
+
+        let repo = OverlayRepo::new(Box::new(sled_repo));
+        let mut acc = repo.get(2)?.unwrap();
+        acc.available = Decimal::from(5);
+        repo.save(acc)?;
+
+        assert_eq!(repo.get(2)?.unwrap().available(), Decimal::from(5));
+        assert_eq!(repo.get_all()?.len(), 2);
+        assert_eq!(
+            SledRepo::new(&db)?.get(2)?.unwrap().available(),
+            Decimal::from(1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_statements() -> Result<()> {
+        let tenant = TenantId::from_str("acme")?;
+        let statements = vec![
+            AccountStatement {
+                tenant: Some(tenant),
+                client: 2,
+                available: Decimal::new(15, 1),
+                held: Decimal::from(3),
+                total: Decimal::new(45, 1),
+                locked: true,
+            },
+            AccountStatement {
+                tenant: Some(tenant),
+                client: 1,
+                available: Decimal::from(0),
+                held: Decimal::from(0),
+                total: Decimal::from(0),
+                locked: false,
+            },
+        ];
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for statement in &statements {
+            writer.serialize(statement)?;
+        }
+        assert_eq!(
+            read_statements(writer.into_inner()?.as_slice())?,
+            statements
+        );
+
+        // without a tenant column, and with columns the statement doesn't have
+        let read = read_statements(
+            "client,available,held,total,locked,note\n1,2,0,2,false,x\n".as_bytes(),
+        )?;
+        assert_eq!(read[0].tenant, None);
+        assert_eq!(read[0].total, Decimal::from(2));
+        Ok(())
+    }
 }
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::events::EventsRepo;
+use crate::transactions::TransactionKind;
+
+/// ClientActivity is a summary of a client's activity: the count & volume of their transactions
+/// by kind, the fees they were charged, and what became of their disputes
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ClientActivity {
+    pub client: u64,
+    pub deposits: u64,
+    pub deposited: Decimal,
+    pub withdrawals: u64,
+    pub withdrawn: Decimal,
+    pub payouts: u64,
+    pub paid_out: Decimal,
+    /// Fees charged by adjustments, net of any refunded
+    pub fees: Decimal,
+    pub disputes: u64,
+    pub resolved: u64,
+    pub charged_back: u64,
+}
+
+/// client_activity summarizes every client's activity in the event log, ordered by client.
+/// Pending & escrow deposits count as deposits, and pending withdrawals as withdrawals, when
+/// they're made.
+pub fn client_activity(events: &dyn EventsRepo) -> Result<Vec<ClientActivity>> {
+    let mut clients: BTreeMap<u64, ClientActivity> = BTreeMap::new();
+    for event in events.get_all()? {
+        let activity = clients
+            .entry(event.client)
+            .or_insert_with(|| ClientActivity {
+                client: event.client,
+                ..ClientActivity::default()
+            });
+        match event.kind {
+            TransactionKind::Deposit { amount }
+            | TransactionKind::PendingDeposit { amount }
+            | TransactionKind::Escrow { amount } => {
+                activity.deposits += 1;
+                activity.deposited += amount;
+            }
+            TransactionKind::Withdrawal { amount }
+            | TransactionKind::PendingWithdrawal { amount } => {
+                activity.withdrawals += 1;
+                activity.withdrawn += amount;
+            }
+            TransactionKind::Payout { amount, .. } => {
+                activity.payouts += 1;
+                activity.paid_out += amount;
+            }
+            TransactionKind::Adjustment { amount } => activity.fees -= amount,
+            TransactionKind::Dispute => activity.disputes += 1,
+            TransactionKind::Resolve | TransactionKind::ForceResolve => activity.resolved += 1,
+            TransactionKind::ChargeBack => activity.charged_back += 1,
+            _ => (),
+        }
+    }
+    Ok(clients.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::MemoryRepo as AccountsMemoryRepo;
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::payments::{AdminAction, PaymentsEngine};
+    use crate::transactions::{MemoryRepo as TransactionsMemoryRepo, TransactionCommand};
+
+    #[test]
+    fn test_client_activity() -> Result<()> {
+        let transactions = TransactionsMemoryRepo::new();
+        let accounts = AccountsMemoryRepo::new();
+        let events = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+        let process = |kind, tx, client| {
+            engine.process_transaction(TransactionCommand {
+                kind,
+                tx,
+                client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })
+        };
+        let d = Decimal::from;
+        process(TransactionKind::Deposit { amount: d(10) }, 1, 1)?;
+        process(TransactionKind::Deposit { amount: d(5) }, 2, 1)?;
+        process(TransactionKind::PendingDeposit { amount: d(3) }, 3, 1)?;
+        process(TransactionKind::Withdrawal { amount: d(4) }, 4, 1)?;
+        process(TransactionKind::Dispute, 1, 1)?;
+        process(TransactionKind::Resolve, 1, 1)?;
+        process(TransactionKind::Dispute, 2, 1)?;
+        process(TransactionKind::ChargeBack, 2, 1)?;
+        engine.process_admin(AdminAction::Adjust {
+            client: 1,
+            tx: 5,
+            amount: d(-1),
+        })?;
+        process(TransactionKind::Deposit { amount: d(7) }, 6, 2)?;
+
+        assert_eq!(
+            client_activity(&events)?,
+            vec![
+                ClientActivity {
+                    client: 1,
+                    deposits: 3,
+                    deposited: d(18),
+                    withdrawals: 1,
+                    withdrawn: d(4),
+                    payouts: 0,
+                    paid_out: d(0),
+                    fees: d(1),
+                    disputes: 2,
+                    resolved: 1,
+                    charged_back: 1,
+                },
+                ClientActivity {
+                    client: 2,
+                    deposits: 1,
+                    deposited: d(7),
+                    ..ClientActivity::default()
+                },
+            ]
+        );
+        Ok(())
+    }
+}
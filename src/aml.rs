@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::events::EventsRepo;
+use crate::transactions::TransactionKind;
+
+/// AmlConfig is what counts as suspicious activity for a client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmlConfig {
+    /// The reporting threshold which structured deposits are kept just under
+    pub threshold: Decimal,
+    /// How far under the threshold a deposit can be while counting as structured, as a
+    /// proportion of the threshold, e.g. 0.1 for deposits of at least 90% of it
+    pub margin: Decimal,
+    /// The number of structured deposits which makes a client a candidate
+    pub min_structured: u64,
+    /// The total deposited plus withdrawn which makes a client a candidate
+    pub large_total: Decimal,
+}
+
+impl Default for AmlConfig {
+    fn default() -> AmlConfig {
+        AmlConfig {
+            threshold: Decimal::from(10_000),
+            margin: Decimal::new(1, 1),
+            min_structured: 3,
+            large_total: Decimal::from(50_000),
+        }
+    }
+}
+
+/// SarCandidate is a client whose activity should be reviewed for a suspicious activity report,
+/// along with the aggregates which made them a candidate
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct SarCandidate {
+    pub client: u64,
+    /// Why the client is a candidate: structuring, large_movements or both (separated by `;`)
+    pub reasons: String,
+    /// The number of deposits just under the threshold
+    pub structured_deposits: u64,
+    pub structured_total: Decimal,
+    pub deposits: u64,
+    pub deposited: Decimal,
+    pub withdrawals: u64,
+    pub withdrawn: Decimal,
+}
+
+/// sar_candidates aggregates every client's deposits & withdrawals in the event log, returning
+/// the clients who look to be structuring deposits to avoid the threshold or who have moved large
+/// amounts in total, ordered by client
+pub fn sar_candidates(events: &dyn EventsRepo, config: &AmlConfig) -> Result<Vec<SarCandidate>> {
+    let floor = config.threshold * (Decimal::from(1) - config.margin);
+    let mut clients: BTreeMap<u64, SarCandidate> = BTreeMap::new();
+    // each transaction's first event is the deposit or withdrawal which created it, and later
+    // events (disputes etc.) have a different kind, so every transaction is counted once
+    for event in events.get_all()? {
+        let candidate = || SarCandidate {
+            client: event.client,
+            ..SarCandidate::default()
+        };
+        match event.kind {
+            TransactionKind::Deposit { amount }
+            | TransactionKind::PendingDeposit { amount }
+            | TransactionKind::Escrow { amount } => {
+                let c = clients.entry(event.client).or_insert_with(candidate);
+                c.deposits += 1;
+                c.deposited += amount;
+                if amount >= floor && amount < config.threshold {
+                    c.structured_deposits += 1;
+                    c.structured_total += amount;
+                }
+            }
+            TransactionKind::Withdrawal { amount }
+            | TransactionKind::PendingWithdrawal { amount }
+            | TransactionKind::Payout { amount, .. } => {
+                let c = clients.entry(event.client).or_insert_with(candidate);
+                c.withdrawals += 1;
+                c.withdrawn += amount;
+            }
+            _ => (),
+        }
+    }
+
+    Ok(clients
+        .into_values()
+        .filter_map(|mut c| {
+            let mut reasons = Vec::new();
+            if c.structured_deposits >= config.min_structured {
+                reasons.push("structuring");
+            }
+            if c.deposited + c.withdrawn >= config.large_total {
+                reasons.push("large_movements");
+            }
+            if reasons.is_empty() {
+                return None;
+            }
+            c.reasons = reasons.join(";");
+            Some(c)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::MemoryRepo;
+    use crate::transactions::Transaction;
+
+    fn event(tx: u64, client: u64, kind: TransactionKind) -> Transaction {
+        let amount = match kind {
+            TransactionKind::Deposit { amount } | TransactionKind::Withdrawal { amount } => amount,
+            _ => Decimal::from(0),
+        };
+        Transaction {
+            tx,
+            client,
+            amount,
+            kind,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_sar_candidates() -> Result<()> {
+        let events = MemoryRepo::new();
+        let deposit = |amount: i64| TransactionKind::Deposit {
+            amount: Decimal::from(amount),
+        };
+        // client 1 structures deposits just under the threshold
+        for tx in 1..=3 {
+            events.append(event(tx, 1, deposit(9_500)))?;
+        }
+        events.append(event(1, 1, TransactionKind::Dispute))?;
+        // client 2 makes a deposit over the threshold, then moves it on
+        events.append(event(4, 2, deposit(30_000)))?;
+        events.append(event(
+            5,
+            2,
+            TransactionKind::Withdrawal {
+                amount: Decimal::from(30_000),
+            },
+        ))?;
+        // client 3 has two near-threshold deposits and a small amount in total
+        events.append(event(6, 3, deposit(9_000)))?;
+        events.append(event(7, 3, deposit(9_999)))?;
+        events.append(event(8, 3, deposit(8_999)))?;
+
+        let candidates = sar_candidates(&events, &AmlConfig::default())?;
+        let summary: Vec<(u64, &str, u64, u64)> = candidates
+            .iter()
+            .map(|c| {
+                (
+                    c.client,
+                    c.reasons.as_str(),
+                    c.structured_deposits,
+                    c.deposits,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(1, "structuring", 3, 3), (2, "large_movements", 0, 1)]
+        );
+        assert_eq!(candidates[0].structured_total, Decimal::from(28_500));
+        assert_eq!(candidates[1].withdrawn, Decimal::from(30_000));
+
+        let strict = AmlConfig {
+            min_structured: 2,
+            large_total: Decimal::from(28_000),
+            ..AmlConfig::default()
+        };
+        let candidates = sar_candidates(&events, &strict)?;
+        assert_eq!(candidates.len(), 3);
+        assert_eq!(candidates[0].reasons, "structuring;large_movements");
+        Ok(())
+    }
+}
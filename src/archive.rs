@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+
+use crate::accounts::{Account, AccountsRepo};
+use crate::encryption::{self, Cipher};
+use crate::tenant::{self, TenantId};
+
+/// Archive is the store for dormant accounts, which are kept out of the active accounts (and so
+/// out of statements) until they see activity again. It tracks which accounts were saved during
+/// the run, i.e. which of them had any activity.
+pub struct Archive {
+    accounts: sled::Tree,
+    archived: sled::Tree,
+    cipher: Option<Cipher>,
+    active: Mutex<HashSet<u64>>,
+    /// Archived accounts which have been read, and so might be resurrected by being saved
+    restored: Mutex<HashSet<u64>>,
+}
+
+impl Archive {
+    /// open opens the archive alongside a tenant's accounts, encrypting the accounts it stores
+    /// with `cipher` if given
+    pub fn open(
+        db: &sled::Db,
+        tenant: Option<TenantId>,
+        cipher: Option<Cipher>,
+    ) -> Result<Archive> {
+        Ok(Archive {
+            accounts: db.open_tree(tenant::tree_name(tenant, "accounts"))?,
+            archived: db.open_tree(tenant::tree_name(tenant, "archived_accounts"))?,
+            cipher,
+            active: Mutex::default(),
+            restored: Mutex::default(),
+        })
+    }
+
+    /// get_all returns every archived account
+    pub fn get_all(&self) -> Result<Vec<Account>> {
+        self.archived
+            .iter()
+            .values()
+            .map(|bytes| encryption::decode(self.cipher.as_ref(), &bytes?))
+            .collect()
+    }
+
+    /// including_archived adds every archived account to the active accounts, e.g. for the
+    /// opening balances of a run which may resurrect some of them
+    pub fn including_archived(&self, mut accounts: Vec<Account>) -> Result<Vec<Account>> {
+        accounts.extend(self.get_all()?);
+        Ok(accounts)
+    }
+
+    /// archive_dormant moves every account which wasn't saved during the run and has no held
+    /// funds into the archive. Returns the number of accounts archived.
+    pub fn archive_dormant(&self) -> Result<usize> {
+        let active = lock(&self.active)?;
+        let mut dormant = Vec::new();
+        for entry in self.accounts.iter() {
+            let (key, bytes) = entry?;
+            let account: Account = encryption::decode(self.cipher.as_ref(), &bytes)?;
+            if !active.contains(&account.client()) && account.held() == Decimal::from(0) {
+                dormant.push((key, bytes));
+            }
+        }
+        self.move_all(&dormant, &self.accounts, &self.archived)?;
+        Ok(dormant.len())
+    }
+
+    /// restore_all moves every archived account back to the active accounts. Returns the number
+    /// of accounts restored.
+    pub fn restore_all(&self) -> Result<usize> {
+        let archived = self
+            .archived
+            .iter()
+            .collect::<Result<Vec<(sled::IVec, sled::IVec)>, _>>()?;
+        self.move_all(&archived, &self.archived, &self.accounts)?;
+        Ok(archived.len())
+    }
+
+    /// move_all moves entries from one tree to the other, in a single database transaction
+    fn move_all(
+        &self,
+        entries: &[(sled::IVec, sled::IVec)],
+        from: &sled::Tree,
+        to: &sled::Tree,
+    ) -> Result<()> {
+        (from, to)
+            .transaction(|(from, to)| {
+                for (key, value) in entries {
+                    from.remove(key)?;
+                    to.insert(key, value)?;
+                }
+                Ok::<_, ConflictableTransactionError<()>>(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => anyhow::Error::from(e),
+                TransactionError::Abort(()) => anyhow!("archival aborted"),
+            })
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<std::sync::MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| anyhow!("archive lock poisoned"))
+}
+
+/// ArchiveRepo wraps the active accounts, falling back to the archive for accounts which aren't
+/// active. An archived account is resurrected (moved out of the archive) when it's next saved,
+/// i.e. when its client has activity again.
+pub struct ArchiveRepo<R> {
+    inner: R,
+    archive: Arc<Archive>,
+}
+
+impl<R: AccountsRepo> ArchiveRepo<R> {
+    pub fn new(inner: R, archive: Arc<Archive>) -> ArchiveRepo<R> {
+        ArchiveRepo { inner, archive }
+    }
+}
+
+impl<R: AccountsRepo> AccountsRepo for ArchiveRepo<R> {
+    fn get(&self, id: u64) -> Result<Option<Account>> {
+        if let Some(account) = self.inner.get(id)? {
+            return Ok(Some(account));
+        }
+        match self.archive.archived.get(id.to_be_bytes())? {
+            Some(bytes) => {
+                lock(&self.archive.restored)?.insert(id);
+                Ok(Some(encryption::decode(
+                    self.archive.cipher.as_ref(),
+                    &bytes,
+                )?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, account: Account) -> Result<u64> {
+        let client = account.client();
+        lock(&self.archive.active)?.insert(client);
+        let id = self.inner.save(account)?;
+        if lock(&self.archive.restored)?.remove(&client) {
+            self.archive.archived.remove(client.to_be_bytes())?;
+        }
+        Ok(id)
+    }
+
+    fn get_all(&self) -> Result<Vec<Account>> {
+        self.inner.get_all()
+    }
+
+    /// Removes an active account. Archived accounts are left in the archive.
+    fn remove(&self, id: u64) -> Result<Option<Account>> {
+        lock(&self.archive.active)?.remove(&id);
+        self.inner.remove(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::SledRepo as AccountsSledRepo;
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::payments::PaymentsEngine;
+    use crate::settlement;
+    use crate::summary::Summary;
+    use crate::testkit::command;
+    use crate::transactions::{
+        MemoryRepo as TransactionsMemoryRepo, TransactionCommand, TransactionKind,
+    };
+
+    #[test]
+    fn test_archive() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let transactions = TransactionsMemoryRepo::new();
+        let events = EventsMemoryRepo::new();
+        let amount = Decimal::from(5);
+        let run = |commands: Vec<TransactionCommand>| -> Result<(Arc<Archive>, Vec<u64>)> {
+            let archive = Arc::new(Archive::open(&db, None, None)?);
+            let accounts = ArchiveRepo::new(AccountsSledRepo::new(&db)?, archive.clone());
+            let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+            for command in commands {
+                engine.process_transaction(command)?;
+            }
+            archive.archive_dormant()?;
+            let clients = accounts.get_all()?.iter().map(Account::client).collect();
+            Ok((archive, clients))
+        };
+
+        let (_, clients) = run(vec![
+            command(TransactionKind::Deposit { amount }, 1, 1),
+            command(TransactionKind::Deposit { amount }, 2, 2),
+            command(TransactionKind::Deposit { amount }, 3, 3),
+            command(TransactionKind::Dispute, 3, 3),
+        ])?;
+        assert_eq!(clients, vec![1, 2, 3]);
+
+        // client 3 is dormant but has held funds, so stays active
+        let (archive, clients) = run(vec![command(TransactionKind::Deposit { amount }, 1, 4)])?;
+        assert_eq!(clients, vec![1, 3]);
+        assert_eq!(archive.get_all()?.len(), 1);
+
+        // an archived client's activity resurrects their account, with its balance intact
+        let opening = Archive::open(&db, None, None)?
+            .including_archived(AccountsSledRepo::new(&db)?.get_all()?)?;
+        let (archive, clients) = run(vec![command(TransactionKind::Deposit { amount }, 2, 5)])?;
+        assert_eq!(clients, vec![2, 3]);
+        // so only the deposit is settled, and the account isn't counted as a new one
+        let closing = AccountsSledRepo::new(&db)?.get_all()?;
+        assert_eq!(
+            settlement::settlement(&opening, &closing),
+            vec![(2, -amount)]
+        );
+        let mut summary = Summary::default();
+        summary.count_accounts(&opening, &closing);
+        assert_eq!(summary.accounts_created, 0);
+        assert_eq!(
+            AccountsSledRepo::new(&db)?.get(2)?.unwrap().total(),
+            Decimal::from(10)
+        );
+        assert_eq!(archive.get_all()?[0].client(), 1);
+
+        assert_eq!(archive.restore_all()?, 1);
+        assert!(archive.get_all()?.is_empty());
+        assert_eq!(AccountsSledRepo::new(&db)?.get_all()?.len(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// AuditLog is an append-only record of operations on stored state which must be accounted for
+/// later, written as one JSON object per line (NDJSON). Unlike application logs, it keeps full
+/// detail, so it's expected to be kept somewhere secured.
+pub struct AuditLog<W: io::Write> {
+    writer: W,
+}
+
+impl AuditLog<File> {
+    /// open opens an audit log for appending, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AuditLog<File>> {
+        Ok(AuditLog::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))
+    }
+}
+
+impl<W: io::Write> AuditLog<W> {
+    pub fn new(writer: W) -> AuditLog<W> {
+        AuditLog { writer }
+    }
+
+    /// record appends an entry, flushing it immediately so that it isn't lost if the process
+    /// exits before the log is dropped
+    pub fn record<T: Serialize>(&mut self, entry: &T) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use thiserror::Error;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum AuthError {
+    #[error("missing API key")]
+    Missing,
+    #[error("invalid API key")]
+    Invalid,
+    #[error("API key {name:?} does not have the {required} scope")]
+    Forbidden { name: String, required: Scope },
+}
+
+impl From<AuthError> for Status {
+    fn from(error: AuthError) -> Status {
+        match error {
+            AuthError::Missing | AuthError::Invalid => Status::unauthenticated(error.to_string()),
+            AuthError::Forbidden { .. } => Status::permission_denied(error.to_string()),
+        }
+    }
+}
+
+/// Scope is what an API key is allowed to do. Each scope includes the ones before it, so a key
+/// which can submit transactions can also read accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Query accounts & transactions
+    Read,
+    /// Submit transactions
+    Submit,
+    /// Administrative operations
+    Admin,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Submit => write!(f, "submit"),
+            Scope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Caller identifies who made a request, attached to the request once its API key is verified
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    pub name: Arc<str>,
+    pub scope: Scope,
+}
+
+impl Caller {
+    /// anonymous is the caller for every request when authentication is disabled
+    pub fn anonymous() -> Caller {
+        Caller {
+            name: "anonymous".into(),
+            scope: Scope::Admin,
+        }
+    }
+}
+
+/// ApiKeyRecord is a single row of an API keys file
+#[derive(Debug, Deserialize)]
+struct ApiKeyRecord {
+    name: String,
+    key: String,
+    scope: Scope,
+}
+
+/// ApiKeys maps each configured API key to the caller it identifies
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    keys: HashMap<String, Caller>,
+}
+
+/// read_keys reads API keys from a CSV file with `name`, `key` and `scope` columns
+pub fn read_keys<R: io::Read>(reader: R) -> Result<ApiKeys> {
+    let mut keys = HashMap::new();
+    for result in csv::Reader::from_reader(reader).deserialize() {
+        let ApiKeyRecord { name, key, scope } = result?;
+        if key.is_empty() {
+            bail!("API key {:?} is empty", name);
+        }
+        let caller = Caller {
+            name: name.into(),
+            scope,
+        };
+        if let Some(other) = keys.insert(key, caller) {
+            bail!("API key {:?} is a duplicate", other.name);
+        }
+    }
+    Ok(ApiKeys { keys })
+}
+
+impl ApiKeys {
+    /// authenticate finds the caller for the bearer token in the request's `authorization` header
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Result<Caller, AuthError> {
+        let header = metadata
+            .get("authorization")
+            .ok_or(AuthError::Missing)?
+            .to_str()
+            .map_err(|_| AuthError::Invalid)?;
+        let key = header
+            .strip_prefix("Bearer ")
+            .ok_or(AuthError::Invalid)?
+            .trim();
+        self.keys.get(key).cloned().ok_or(AuthError::Invalid)
+    }
+}
+
+/// Authenticator is an interceptor which rejects requests without a valid API key, or allows
+/// every request as an anonymous admin if no keys are configured
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    keys: Option<Arc<ApiKeys>>,
+}
+
+impl Authenticator {
+    pub fn new(keys: Option<ApiKeys>) -> Authenticator {
+        Authenticator {
+            keys: keys.map(Arc::new),
+        }
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let caller = match &self.keys {
+            Some(keys) => keys.authenticate(request.metadata())?,
+            None => Caller::anonymous(),
+        };
+        request.extensions_mut().insert(caller);
+        Ok(request)
+    }
+}
+
+/// authorize checks that the request was made by a caller with at least the `required` scope
+pub fn authorize<T>(request: &Request<T>, required: Scope) -> Result<Caller, AuthError> {
+    let caller = request
+        .extensions()
+        .get::<Caller>()
+        .ok_or(AuthError::Missing)?;
+    if caller.scope < required {
+        return Err(AuthError::Forbidden {
+            name: caller.name.to_string(),
+            required,
+        });
+    }
+    Ok(caller.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authenticate() -> Result<()> {
+        let input = "name,key,scope\nledger,k1,submit\nreporting,k2,read\n";
+        let mut auth = Authenticator::new(Some(read_keys(input.as_bytes())?));
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer k1".parse()?);
+        let request = auth.call(request)?;
+        let caller = authorize(&request, Scope::Read)?;
+        assert_eq!(&*caller.name, "ledger");
+        assert_eq!(authorize(&request, Scope::Submit)?.scope, Scope::Submit);
+        assert_eq!(
+            authorize(&request, Scope::Admin),
+            Err(AuthError::Forbidden {
+                name: "ledger".to_string(),
+                required: Scope::Admin
+            })
+        );
+
+        let err = auth.call(Request::new(())).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer nope".parse()?);
+        assert_eq!(
+            auth.call(request).unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+
+        let request = Authenticator::new(None).call(Request::new(()))?;
+        assert_eq!(authorize(&request, Scope::Admin)?, Caller::anonymous());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_keys_duplicate() {
+        let input = "name,key,scope\na,k1,read\nb,k1,admin\n";
+        assert!(read_keys(input.as_bytes()).is_err());
+        let input = "name,key,scope\na,k1,owner\n";
+        assert!(read_keys(input.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::accounts::{AccountsRepo, SledRepo as AccountsSledRepo};
+use crate::cache::CachedRepo;
+use crate::chaos::{Faults, FaultyRepo};
+use crate::events::{EventsRepo, SledRepo as EventsSledRepo};
+use crate::generate::{CommandRecord, Generator, GeneratorConfig};
+use crate::input::{CommandReader, InputConfig};
+use crate::memory::MemoryConfig;
+use crate::parallel;
+use crate::payments::{EngineOptions, PaymentsEngine};
+use crate::rejections::Rejections;
+use crate::spill::MemoryLimit;
+use crate::threshold::ErrorThreshold;
+use crate::transactions::{SledRepo as TransactionsSledRepo, TransactionsRepo};
+
+/// RowCount is a number of rows, optionally with a K or M suffix (powers of 1000)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowCount(pub u64);
+
+impl FromStr for RowCount {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<RowCount> {
+        let s = s.trim();
+        let (digits, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+            Some('K') => (&s[..s.len() - 1], 1_000),
+            Some('M') => (&s[..s.len() - 1], 1_000_000),
+            _ => (s, 1),
+        };
+        let value: f64 = digits
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("invalid row count {:?}", s))?;
+        Ok(RowCount((value * multiplier as f64) as u64))
+    }
+}
+
+/// Backend is where state is stored during a benchmark
+#[derive(Debug, Clone, Copy)]
+pub enum Backend {
+    Memory(MemoryConfig),
+    /// A temporary sled database, optionally with an LRU cache of this many records
+    Sled(Option<usize>),
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Memory(config) => {
+                write!(f, "memory")?;
+                if config.fast_hash {
+                    write!(f, " fxhash")?;
+                }
+                if config.dense_transactions {
+                    write!(f, " dense")?;
+                }
+                if let Some(MemoryLimit(bytes)) = config.limit {
+                    write!(f, " spill@{}M", bytes >> 20)?;
+                }
+                Ok(())
+            }
+            Backend::Sled(None) => write!(f, "sled"),
+            Backend::Sled(Some(capacity)) => write!(f, "sled cached@{}", capacity),
+        }
+    }
+}
+
+/// Case is a single pipeline configuration to measure
+#[derive(Debug, Clone, Copy)]
+pub struct Case {
+    pub fast_parse: bool,
+    pub workers: usize,
+    pub backend: Backend,
+}
+
+/// Measurement is the result of running a single case
+#[derive(Debug)]
+pub struct Measurement {
+    pub case: Case,
+    pub rows: u64,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    pub fn rows_per_sec(&self) -> f64 {
+        self.rows as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// cases returns every pipeline configuration worth comparing: each parser, each storage backend
+/// and multi-threaded processing with `workers` threads
+pub fn cases(workers: usize) -> Vec<Case> {
+    let memory = |fast_hash, dense_transactions, limit| {
+        Backend::Memory(MemoryConfig {
+            fast_hash,
+            dense_transactions,
+            limit,
+        })
+    };
+    let case = |fast_parse, workers, backend| Case {
+        fast_parse,
+        workers,
+        backend,
+    };
+    let mut cases = vec![
+        case(false, 1, memory(false, false, None)),
+        case(true, 1, memory(false, false, None)),
+        case(true, 1, memory(true, false, None)),
+        case(true, 1, memory(true, true, None)),
+        case(true, 1, memory(false, false, Some(MemoryLimit(64 << 20)))),
+        case(true, 1, Backend::Sled(None)),
+        case(true, 1, Backend::Sled(Some(100_000))),
+    ];
+    if workers > 1 {
+        cases.push(case(true, workers, memory(false, false, None)));
+        cases.push(case(true, workers, memory(true, true, None)));
+    }
+    cases
+}
+
+/// generate writes a transactions file with the given number of rows into memory
+pub fn generate(rows: u64, clients: u64, seed: u64) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let generator = Generator::new(GeneratorConfig {
+        clients,
+        rows,
+        dispute_rate: 0.01,
+        invalid_rate: 0.001,
+        seed,
+    });
+    for command in generator {
+        writer.serialize(CommandRecord::from(command))?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// run processes the input with the given configuration, returning how long it took. With faults
+/// (chaos mode), the single-threaded cases' repositories fail or delay a fraction of their gets &
+/// saves, and the transactions hit by a fault are rejected like any other.
+pub fn run(input: &[u8], rows: u64, case: Case, faults: Option<Faults>) -> Result<Measurement> {
+    let config = InputConfig {
+        fast: case.fast_parse,
+        ..InputConfig::default()
+    };
+    let rejections = || Rejections::new(None, None, ErrorThreshold::default(), false);
+    let start = Instant::now();
+    let reader = CommandReader::from_reader(input, config)?;
+
+    match case.backend {
+        Backend::Memory(memory) if case.workers > 1 => {
+            let rejections = Mutex::new(rejections());
+            parallel::process_partitioned(
+                reader,
+                case.workers,
+                memory,
+                &rejections,
+                EngineOptions::default(),
+            )?;
+        }
+        backend => {
+            let (transactions, accounts, events) = repos(backend)?;
+            process(reader, transactions, accounts, events, faults)?;
+        }
+    }
+
+    Ok(Measurement {
+        case,
+        rows,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// EngineRepos is a set of repositories for a single engine
+pub type EngineRepos = (
+    Box<dyn TransactionsRepo>,
+    Box<dyn AccountsRepo>,
+    Box<dyn EventsRepo>,
+);
+
+/// repos creates empty repositories for a single engine with the given backend, a sled backend
+/// being a temporary database
+pub fn repos(backend: Backend) -> Result<EngineRepos> {
+    match backend {
+        Backend::Memory(memory) => Ok((
+            memory.transactions_repo()?,
+            memory.accounts_repo(),
+            memory.events_repo()?,
+        )),
+        Backend::Sled(cache) => {
+            let db = sled::Config::new().temporary(true).open()?;
+            let mut transactions: Box<dyn TransactionsRepo> =
+                Box::new(TransactionsSledRepo::new(&db)?);
+            let mut accounts: Box<dyn AccountsRepo> = Box::new(AccountsSledRepo::new(&db)?);
+            if let Some(capacity) = cache {
+                transactions = Box::new(CachedRepo::new(transactions, capacity));
+                accounts = Box::new(CachedRepo::new(accounts, capacity));
+            }
+            Ok((transactions, accounts, Box::new(EventsSledRepo::new(&db)?)))
+        }
+    }
+}
+
+/// write_table writes the measurements as an aligned table, fastest first
+pub fn write_table<W: Write>(mut writer: W, measurements: &mut [Measurement]) -> io::Result<()> {
+    measurements.sort_by(|a, b| b.rows_per_sec().total_cmp(&a.rows_per_sec()));
+    writeln!(
+        writer,
+        "{:<6} {:>7}  {:<24} {:>10} {:>9} {:>12}",
+        "parser", "workers", "backend", "rows", "seconds", "rows/sec"
+    )?;
+    for m in measurements.iter() {
+        writeln!(
+            writer,
+            "{:<6} {:>7}  {:<24} {:>10} {:>9.3} {:>12.0}",
+            if m.case.fast_parse { "fast" } else { "serde" },
+            m.case.workers,
+            m.case.backend.to_string(),
+            m.rows,
+            m.elapsed.as_secs_f64(),
+            m.rows_per_sec()
+        )?;
+    }
+    Ok(())
+}
+
+fn process(
+    reader: CommandReader<&[u8]>,
+    transactions: Box<dyn TransactionsRepo>,
+    accounts: Box<dyn AccountsRepo>,
+    events: Box<dyn EventsRepo>,
+    faults: Option<Faults>,
+) -> Result<()> {
+    let (transactions, accounts, events): EngineRepos = match faults {
+        Some(faults) => (
+            Box::new(FaultyRepo::new(transactions, faults)),
+            Box::new(FaultyRepo::new(accounts, faults.offset(1))),
+            Box::new(FaultyRepo::new(events, faults.offset(2))),
+        ),
+        None => (transactions, accounts, events),
+    };
+    let engine = PaymentsEngine::new(transactions.as_ref(), accounts.as_ref(), events.as_ref());
+    for row in reader.flatten() {
+        // rejected transactions are part of the workload, not a failure of the benchmark
+        let _ = engine.process_transaction(row.command);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_row_count() -> Result<()> {
+        assert_eq!("10M".parse::<RowCount>()?, RowCount(10_000_000));
+        assert_eq!("2.5k".parse::<RowCount>()?, RowCount(2_500));
+        assert_eq!("100".parse::<RowCount>()?, RowCount(100));
+        assert!("lots".parse::<RowCount>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run() -> Result<()> {
+        let input = generate(500, 10, 0)?;
+        let mut measurements = vec![];
+        for case in cases(2) {
+            let measurement = run(&input, 500, case, None)?;
+            assert_eq!(measurement.rows, 500);
+            measurements.push(measurement);
+        }
+        let mut table = vec![];
+        write_table(&mut table, &mut measurements)?;
+        let table = String::from_utf8(table)?;
+        assert_eq!(table.lines().count(), measurements.len() + 1);
+        assert!(table.contains("sled cached@100000"));
+        Ok(())
+    }
+}
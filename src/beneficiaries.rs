@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::io;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PayoutError {
+    #[error("beneficiary {beneficiary} isn't registered to account {client}")]
+    UnregisteredBeneficiary { client: u64, beneficiary: u64 },
+}
+
+impl PayoutError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            PayoutError::UnregisteredBeneficiary { .. } => "unregistered_beneficiary",
+        }
+    }
+}
+
+/// Beneficiaries is the registry of destinations (e.g. bank accounts) which payouts can be made
+/// to, each registered to a single account
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Beneficiaries {
+    registered: HashMap<u64, u64>,
+}
+
+impl Beneficiaries {
+    /// check fails unless the beneficiary is registered to the account
+    pub fn check(&self, client: u64, beneficiary: u64) -> Result<(), PayoutError> {
+        match self.registered.get(&beneficiary) {
+            Some(owner) if *owner == client => Ok(()),
+            _ => Err(PayoutError::UnregisteredBeneficiary {
+                client,
+                beneficiary,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BeneficiaryRecord {
+    id: u64,
+    client: u64,
+}
+
+/// read_beneficiaries reads a CSV of registered beneficiaries, with `id` & `client` columns. Any
+/// other columns (e.g. the name & bank details) are ignored.
+pub fn read_beneficiaries<R: io::Read>(reader: R) -> Result<Beneficiaries> {
+    let mut registered = HashMap::new();
+    for result in csv::Reader::from_reader(reader).deserialize() {
+        let BeneficiaryRecord { id, client } = result?;
+        if registered.insert(id, client).is_some() {
+            bail!("beneficiary {} is registered more than once", id);
+        }
+    }
+    Ok(Beneficiaries { registered })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beneficiaries() -> Result<()> {
+        let beneficiaries = read_beneficiaries(
+            "id,client,name,iban\n10,1,Ada,GB33BUKB20201555555555\n11,2,,\n".as_bytes(),
+        )?;
+        assert_eq!(beneficiaries.check(1, 10), Ok(()));
+        assert_eq!(beneficiaries.check(2, 11), Ok(()));
+        assert_eq!(
+            beneficiaries.check(2, 10),
+            Err(PayoutError::UnregisteredBeneficiary {
+                client: 2,
+                beneficiary: 10
+            })
+        );
+        assert!(beneficiaries.check(1, 12).is_err());
+        assert!(Beneficiaries::default().check(1, 10).is_err());
+
+        assert!(read_beneficiaries("id,client\n10,1\n10,2\n".as_bytes()).is_err());
+        Ok(())
+    }
+}
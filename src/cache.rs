@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+
+use anyhow::Result;
+use lru::LruCache;
+
+use crate::accounts::{Account, AccountsRepo};
+use crate::transactions::{Transaction, TransactionsRepo};
+
+/// CachedRepo wraps a repository with an LRU cache of recently read or written records. Writes go
+/// straight through to the wrapped repository, so it's always up to date. Lookups of missing
+/// records are cached too, since every new transaction checks for an existing one with its ID,
+/// which means every write must go through the cache.
+pub struct CachedRepo<R, K: Hash + Eq, V> {
+    inner: R,
+    cache: RefCell<LruCache<K, Option<V>>>,
+}
+
+impl<R, K: Hash + Eq, V: Copy> CachedRepo<R, K, V> {
+    pub fn new(inner: R, capacity: usize) -> CachedRepo<R, K, V> {
+        CachedRepo {
+            inner,
+            cache: RefCell::new(LruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+        }
+    }
+
+    fn get_or_load(&self, id: K, load: impl FnOnce() -> Result<Option<V>>) -> Result<Option<V>> {
+        if let Some(cached) = self.cache.borrow_mut().get(&id) {
+            return Ok(*cached);
+        }
+        let value = load()?;
+        self.cache.borrow_mut().put(id, value);
+        Ok(value)
+    }
+}
+
+impl<R: TransactionsRepo> TransactionsRepo for CachedRepo<R, u64, Transaction> {
+    /// Gets a single transaction by ID, from the cache if possible
+    fn get(&self, id: u64) -> Result<Option<Transaction>> {
+        self.get_or_load(id, || self.inner.get(id))
+    }
+    /// Upserts a transaction in both the wrapped repository & the cache
+    fn save(&self, transaction: Transaction) -> Result<u64> {
+        let id = self.inner.save(transaction)?;
+        self.cache.borrow_mut().put(id, Some(transaction));
+        Ok(id)
+    }
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        self.inner.get_by_client(client)
+    }
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        self.inner.get_all()
+    }
+    /// Removes a transaction from the wrapped repository, caching that it's missing
+    fn remove(&self, id: u64) -> Result<Option<Transaction>> {
+        let transaction = self.inner.remove(id)?;
+        self.cache.borrow_mut().put(id, None);
+        Ok(transaction)
+    }
+    fn erased(&self, id: u64) -> Result<bool> {
+        self.inner.erased(id)
+    }
+}
+
+impl<R: AccountsRepo> AccountsRepo for CachedRepo<R, u64, Account> {
+    /// Gets a single account by client ID, from the cache if possible
+    fn get(&self, id: u64) -> Result<Option<Account>> {
+        self.get_or_load(id, || self.inner.get(id))
+    }
+    /// Upserts an account in both the wrapped repository & the cache
+    fn save(&self, account: Account) -> Result<u64> {
+        let id = self.inner.save(account)?;
+        self.cache.borrow_mut().put(id, Some(account));
+        Ok(id)
+    }
+    fn get_all(&self) -> Result<Vec<Account>> {
+        self.inner.get_all()
+    }
+    /// Removes an account from the wrapped repository, caching that it's missing
+    fn remove(&self, id: u64) -> Result<Option<Account>> {
+        let account = self.inner.remove(id)?;
+        self.cache.borrow_mut().put(id, None);
+        Ok(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::SledRepo as AccountsSledRepo;
+    use crate::transactions::{SledRepo as TransactionsSledRepo, TransactionKind};
+    use rust_decimal::prelude::*;
+
+    #[test]
+    fn test_cached_transactions_repo() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let repo = CachedRepo::new(TransactionsSledRepo::new(&db)?, 2);
+        let amount = Decimal::from(1);
+        assert!(repo.get(1)?.is_none());
+        for tx in 1..=3 {
+            repo.save(Transaction {
+                tx,
+                client: 1,
+                amount,
+                kind: TransactionKind::Deposit { amount },
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+        // written through, and the cached miss for tx 1 replaced
+        assert_eq!(TransactionsSledRepo::new(&db)?.get_all()?.len(), 3);
+        assert_eq!(repo.cache.borrow().len(), 2);
+        assert_eq!(repo.get(1)?.unwrap().tx, 1);
+        assert_eq!(repo.get_by_client(1)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cached_accounts_repo() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let repo = CachedRepo::new(AccountsSledRepo::new(&db)?, 10);
+        let amount = Decimal::from(5);
+        let acc = Account::new(Transaction {
+            tx: 1,
+            client: 3,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        repo.save(acc)?;
+        assert!(repo.cache.borrow().contains(&3));
+        assert_eq!(repo.get(3)?.unwrap().available(), amount);
+        assert_eq!(AccountsSledRepo::new(&db)?.get(3)?.unwrap().total(), amount);
+        Ok(())
+    }
+}
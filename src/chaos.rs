@@ -0,0 +1,236 @@
+use std::cell::{Cell, RefCell};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountsRepo};
+use crate::events::EventsRepo;
+use crate::transactions::{Transaction, TransactionsRepo};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ChaosError {
+    #[error("injected fault in {0}")]
+    Injected(&'static str),
+}
+
+impl ChaosError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            ChaosError::Injected(_) => "injected_fault",
+        }
+    }
+}
+
+/// Faults configures the faults a FaultyRepo injects
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    /// Probability of any given get or save failing
+    pub rate: f64,
+    /// Delay added to every get & save, whether or not it fails
+    pub latency: Option<Duration>,
+    pub seed: u64,
+}
+
+impl Faults {
+    /// offset is the same faults with a different seed, so that repositories configured alike
+    /// don't fail in lockstep
+    pub fn offset(self, offset: u64) -> Faults {
+        Faults {
+            seed: self.seed.wrapping_add(offset),
+            ..self
+        }
+    }
+}
+
+/// FaultyRepo wraps a repository, failing a fraction of its gets & saves (appends, for the event
+/// log) and optionally delaying them, to check how the engine copes with misbehaving storage.
+/// Failing calls fail before reaching the wrapped repository, so they have no effect. Faults are
+/// reproducible for a seed, given the same sequence of calls.
+pub struct FaultyRepo<R> {
+    inner: R,
+    faults: Faults,
+    rng: RefCell<ChaCha8Rng>,
+    injected: Cell<u64>,
+}
+
+impl<R> FaultyRepo<R> {
+    pub fn new(inner: R, faults: Faults) -> FaultyRepo<R> {
+        FaultyRepo {
+            inner,
+            faults,
+            rng: RefCell::new(ChaCha8Rng::seed_from_u64(faults.seed)),
+            injected: Cell::new(0),
+        }
+    }
+
+    /// inner is the wrapped repository, for reading it without faults
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// injected is the number of faults injected so far
+    pub fn injected(&self) -> u64 {
+        self.injected.get()
+    }
+
+    fn inject(&self, operation: &'static str) -> Result<()> {
+        if let Some(latency) = self.faults.latency {
+            thread::sleep(latency);
+        }
+        if self.faults.rate > 0.0 && self.rng.borrow_mut().gen_bool(self.faults.rate.min(1.0)) {
+            self.injected.set(self.injected.get() + 1);
+            return Err(ChaosError::Injected(operation).into());
+        }
+        Ok(())
+    }
+}
+
+impl<R: TransactionsRepo> TransactionsRepo for FaultyRepo<R> {
+    fn get(&self, id: u64) -> Result<Option<Transaction>> {
+        self.inject("transactions get")?;
+        self.inner.get(id)
+    }
+    fn save(&self, transaction: Transaction) -> Result<u64> {
+        self.inject("transactions save")?;
+        self.inner.save(transaction)
+    }
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        self.inner.get_by_client(client)
+    }
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        self.inner.get_all()
+    }
+    fn remove(&self, id: u64) -> Result<Option<Transaction>> {
+        self.inner.remove(id)
+    }
+    fn erased(&self, id: u64) -> Result<bool> {
+        self.inner.erased(id)
+    }
+}
+
+impl<R: AccountsRepo> AccountsRepo for FaultyRepo<R> {
+    fn get(&self, id: u64) -> Result<Option<Account>> {
+        self.inject("accounts get")?;
+        self.inner.get(id)
+    }
+    fn save(&self, account: Account) -> Result<u64> {
+        self.inject("accounts save")?;
+        self.inner.save(account)
+    }
+    fn get_all(&self) -> Result<Vec<Account>> {
+        self.inner.get_all()
+    }
+    fn remove(&self, id: u64) -> Result<Option<Account>> {
+        self.inner.remove(id)
+    }
+}
+
+impl<R: EventsRepo> EventsRepo for FaultyRepo<R> {
+    fn append(&self, transaction: Transaction) -> Result<u64> {
+        self.inject("events append")?;
+        self.inner.append(transaction)
+    }
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        self.inner.get_all()
+    }
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        self.inner.get_by_client(client)
+    }
+    fn truncate(&self, len: u64) -> Result<()> {
+        self.inner.truncate(len)
+    }
+}
+
+/// is_injected is whether an error was caused by an injected fault
+pub fn is_injected(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<ChaosError>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::MemoryRepo as AccountsMemoryRepo;
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::payments::{PaymentsEngine, SaveError};
+    use crate::transactions::{
+        MemoryRepo as TransactionsMemoryRepo, TransactionCommand, TransactionKind,
+    };
+    use rust_decimal::Decimal;
+
+    fn deposit(tx: u64) -> TransactionCommand {
+        TransactionCommand {
+            kind: TransactionKind::Deposit {
+                amount: Decimal::from(1),
+            },
+            tx,
+            client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_faulty_repo() -> Result<()> {
+        let faults = Faults {
+            rate: 0.5,
+            latency: None,
+            seed: 1,
+        };
+        let repo = FaultyRepo::new(TransactionsMemoryRepo::new(), faults);
+        let failed = (0..100).filter(|&id| repo.get(id).is_err()).count();
+        assert_eq!(failed as u64, repo.injected());
+        assert!(failed > 20 && failed < 80, "{}", failed);
+
+        let err = (0..100).find_map(|id| repo.get(id).err()).unwrap();
+        assert!(is_injected(&err));
+        assert_eq!(
+            err.downcast_ref::<ChaosError>().map(ChaosError::code),
+            Some("injected_fault")
+        );
+
+        // the same seed injects the same faults
+        let other = FaultyRepo::new(TransactionsMemoryRepo::new(), faults);
+        let repo = FaultyRepo::new(TransactionsMemoryRepo::new(), faults);
+        let a: Vec<bool> = (0..20).map(|id| repo.get(id).is_ok()).collect();
+        let b: Vec<bool> = (0..20).map(|id| other.get(id).is_ok()).collect();
+        assert_eq!(a, b);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_save_is_undone() -> Result<()> {
+        let always = Faults {
+            rate: 1.0,
+            latency: None,
+            seed: 0,
+        };
+        let transactions = TransactionsMemoryRepo::new();
+        let accounts = AccountsMemoryRepo::new();
+        let events = FaultyRepo::new(EventsMemoryRepo::new(), always);
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+
+        // the transaction & account were saved before the event log failed, and are undone
+        let err = engine.process_transaction(deposit(1)).unwrap_err();
+        assert!(is_injected(&err));
+        assert!(err.downcast_ref::<SaveError>().is_none());
+        assert!(transactions.get(1)?.is_none());
+        assert!(accounts.get(1)?.is_none());
+        assert_eq!(events.injected(), 1);
+
+        // so retrying it once storage recovers applies it exactly once
+        let events = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+        engine.process_transaction(deposit(1))?;
+        assert_eq!(
+            accounts.get(1)?.map(|a| a.available()),
+            Some(Decimal::from(1))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::events::EventsRepo;
+use crate::transactions::TransactionKind;
+
+/// Ratio is a proportion, written either as a percentage (`0.9%`) or a ratio (`0.009`)
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Ratio(pub Decimal);
+
+impl FromStr for Ratio {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Ratio> {
+        let s = s.trim();
+        let ratio = match s.strip_suffix('%') {
+            Some(percent) => Decimal::from_str(percent.trim())? / Decimal::from(100),
+            None => Decimal::from_str(s)?,
+        };
+        if ratio < Decimal::from(0) || ratio > Decimal::from(1) {
+            return Err(anyhow!("ratio must be between 0% and 100%, got {}", s));
+        }
+        Ok(Ratio(ratio))
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", (self.0 * Decimal::from(100)).normalize())
+    }
+}
+
+/// ChargebackRatio is a client's chargebacks relative to their deposits, by count & by value
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ChargebackRatio {
+    pub client: u64,
+    pub deposits: u64,
+    pub deposited: Decimal,
+    pub chargebacks: u64,
+    pub charged_back: Decimal,
+    pub count_ratio: Decimal,
+    pub value_ratio: Decimal,
+    /// Whether either ratio exceeds the maximum
+    pub flagged: bool,
+}
+
+/// chargeback_ratios aggregates every client's deposits & chargebacks in the event log, flagging
+/// the clients where either ratio exceeds `max`, ordered by client. Clients without any deposits
+/// are omitted, since their ratios are undefined.
+pub fn chargeback_ratios(events: &dyn EventsRepo, max: Ratio) -> Result<Vec<ChargebackRatio>> {
+    let mut clients: BTreeMap<u64, ChargebackRatio> = BTreeMap::new();
+    for event in events.get_all()? {
+        let ratio = clients
+            .entry(event.client)
+            .or_insert_with(|| ChargebackRatio {
+                client: event.client,
+                ..ChargebackRatio::default()
+            });
+        match event.kind {
+            TransactionKind::Deposit { amount } | TransactionKind::PendingDeposit { amount } => {
+                ratio.deposits += 1;
+                ratio.deposited += amount;
+            }
+            TransactionKind::ChargeBack => {
+                ratio.chargebacks += 1;
+                ratio.charged_back += event.amount;
+            }
+            _ => (),
+        }
+    }
+
+    Ok(clients
+        .into_values()
+        .filter(|r| r.deposits > 0)
+        .map(|mut r| {
+            r.count_ratio = (Decimal::from(r.chargebacks) / Decimal::from(r.deposits))
+                .round_dp(4)
+                .normalize();
+            if !r.deposited.is_zero() {
+                r.value_ratio = (r.charged_back / r.deposited).round_dp(4).normalize();
+            }
+            r.flagged = r.count_ratio > max.0 || r.value_ratio > max.0;
+            r
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::MemoryRepo;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_ratio() -> Result<()> {
+        assert_eq!("0.9%".parse::<Ratio>()?, Ratio(Decimal::new(9, 3)));
+        assert_eq!("0.009".parse::<Ratio>()?, Ratio(Decimal::new(9, 3)));
+        assert_eq!(Ratio(Decimal::new(9, 3)).to_string(), "0.9%");
+        assert!("150%".parse::<Ratio>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_chargeback_ratios() -> Result<()> {
+        let events = MemoryRepo::new();
+        let event = |tx, client, kind, amount: i64| Transaction {
+            tx,
+            client,
+            amount: Decimal::from(amount),
+            kind,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+        for tx in 1..=4 {
+            let amount = Decimal::from(25);
+            events.append(event(tx, 1, TransactionKind::Deposit { amount }, 25))?;
+        }
+        events.append(event(1, 1, TransactionKind::Dispute, 25))?;
+        events.append(event(1, 1, TransactionKind::ChargeBack, 25))?;
+        let amount = Decimal::from(1000);
+        events.append(event(5, 2, TransactionKind::Deposit { amount }, 1000))?;
+        events.append(event(6, 3, TransactionKind::Dispute, 0))?;
+
+        let ratios = chargeback_ratios(&events, "10%".parse()?)?;
+        assert_eq!(ratios.len(), 2);
+        assert_eq!(ratios[0].chargebacks, 1);
+        assert_eq!(ratios[0].count_ratio, Decimal::new(25, 2));
+        assert_eq!(ratios[0].value_ratio, Decimal::new(25, 2));
+        assert!(ratios[0].flagged);
+        assert_eq!(ratios[1].client, 2);
+        assert!(!ratios[1].flagged);
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use csv::StringRecord;
+use memmap2::Mmap;
+
+use crate::input::{CommandReader, InputConfig, Position, Row, RowError};
+
+/// CHUNK_SIZE is the approximate number of bytes parsed by each thread at a time
+const CHUNK_SIZE: usize = 4 << 20;
+
+/// Chunk is a range of the input which starts & ends on record boundaries
+#[derive(Debug, Clone, PartialEq)]
+struct Chunk {
+    range: Range<usize>,
+    /// The number of lines preceding the chunk
+    lines: u64,
+}
+
+/// ChunkedReader reads transaction commands from a memory-mapped file, parsing chunks of it in
+/// parallel. Rows are yielded in the same order as they appear in the file, so processing is still
+/// ordered per client.
+///
+/// The file must not be modified while it's being read.
+pub struct ChunkedReader {
+    mmap: Arc<Mmap>,
+    headers: StringRecord,
+    chunks: Vec<Chunk>,
+    config: InputConfig,
+    threads: usize,
+}
+
+impl ChunkedReader {
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        config: InputConfig,
+        threads: usize,
+    ) -> Result<ChunkedReader> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read only, and the file is documented as not being modified
+        // while it's read
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let reader = CommandReader::from_reader(&mmap[..], config)?;
+        let headers = reader.headers().clone();
+        let start = if config.has_headers {
+            reader.position().byte as usize
+        } else {
+            0
+        };
+        let chunks = split(&mmap, start, CHUNK_SIZE, config);
+        Ok(ChunkedReader {
+            mmap: Arc::new(mmap),
+            headers,
+            chunks,
+            config,
+            threads: threads.max(1),
+        })
+    }
+
+    /// headers returns the column names which rows are deserialized with
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    /// rows parses up to `threads` chunks at a time, yielding their rows in order
+    pub fn rows(self) -> impl Iterator<Item = Result<Row, RowError>> + Send + 'static {
+        let ChunkedReader {
+            mmap,
+            headers,
+            chunks,
+            config,
+            threads,
+        } = self;
+        let windows: Vec<Vec<Chunk>> = chunks.chunks(threads).map(|w| w.to_vec()).collect();
+        windows.into_iter().flat_map(move |window| {
+            let parsed = thread::scope(|scope| {
+                let handles: Vec<_> = window
+                    .iter()
+                    .map(|chunk| scope.spawn(|| parse(&mmap, chunk, config, &headers)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap_or_else(|_| vec![Err(panicked())]))
+                    .collect::<Vec<_>>()
+            });
+            parsed.into_iter().flatten()
+        })
+    }
+}
+
+fn panicked() -> RowError {
+    RowError {
+        position: Position::default(),
+        record: None,
+        error: anyhow!("parsing thread panicked"),
+    }
+}
+
+/// parse reads every row within a chunk
+fn parse(
+    data: &[u8],
+    chunk: &Chunk,
+    config: InputConfig,
+    headers: &StringRecord,
+) -> Vec<Result<Row, RowError>> {
+    let offset = Position {
+        line: chunk.lines,
+        byte: chunk.range.start as u64,
+    };
+    match CommandReader::from_chunk(&data[chunk.range.clone()], config, headers.clone(), offset) {
+        Ok(reader) => reader.collect(),
+        Err(error) => vec![Err(RowError {
+            position: offset,
+            record: None,
+            error,
+        })],
+    }
+}
+
+/// split divides `data` from `start` onwards into chunks of roughly `size` bytes, ending each on
+/// a line break which isn't within a quoted field. Quotes are expected to enclose whole fields.
+fn split(data: &[u8], start: usize, size: usize, config: InputConfig) -> Vec<Chunk> {
+    let mut lines = data[..start].iter().filter(|b| **b == b'\n').count() as u64;
+    let mut chunks = vec![];
+    let mut chunk = Chunk {
+        range: start..start,
+        lines,
+    };
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, b) in data.iter().enumerate().skip(start) {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match *b {
+            b if quoted && Some(b) == config.escape => escaped = true,
+            b if b == config.quote => quoted = !quoted,
+            b'\n' => {
+                lines += 1;
+                if !quoted && i + 1 - chunk.range.start >= size {
+                    chunk.range.end = i + 1;
+                    chunks.push(chunk);
+                    chunk = Chunk {
+                        range: i + 1..i + 1,
+                        lines,
+                    };
+                }
+            }
+            _ => (),
+        }
+    }
+    if chunk.range.start < data.len() {
+        chunk.range.end = data.len();
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_split() {
+        let data = b"type,client,tx,amount\ndeposit,1,1,1\n\"dep\nosit\",1,2,1\ndeposit,1,3,1";
+        let chunks = split(data, 22, 10, InputConfig::default());
+        assert_eq!(
+            chunks,
+            vec![
+                Chunk {
+                    range: 22..36,
+                    lines: 1
+                },
+                Chunk {
+                    range: 36..53,
+                    lines: 2
+                },
+                Chunk {
+                    range: 53..66,
+                    lines: 4
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rows() -> Result<()> {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=5000 {
+            input.push_str(&format!("deposit,{},{},1.0\n", tx % 7, tx));
+        }
+        input.push_str("deposit,1,x,1.0\n");
+        let path = std::env::temp_dir().join("payments_test_chunked_rows.csv");
+        File::create(&path)?.write_all(input.as_bytes())?;
+
+        let mut reader = ChunkedReader::from_path(&path, InputConfig::default(), 3)?;
+        // split into smaller chunks than usual, so that there are multiple windows of chunks
+        reader.chunks = split(&reader.mmap, 22, 1000, InputConfig::default());
+        assert!(reader.chunks.len() > 3);
+
+        let rows: Vec<Result<Row, RowError>> = reader.rows().collect();
+        assert_eq!(rows.len(), 5001);
+        for (i, row) in rows[..5000].iter().enumerate() {
+            let row = row.as_ref().map_err(|e| anyhow!("{}", e))?;
+            assert_eq!(row.command.tx, i as u64 + 1);
+            assert_eq!(row.position.line, i as u64 + 2);
+        }
+        let err = rows[5000].as_ref().unwrap_err();
+        assert_eq!(err.position.line, 5002);
+        assert_eq!(err.position.byte as usize, input.len() - 16);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::io;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+/// ClientInfo is the metadata held about a client outside of the ledger, which makes output
+/// meaningful to the people reading it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub email: String,
+    pub segment: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientRecord {
+    #[serde(alias = "client")]
+    id: u64,
+    name: String,
+    email: String,
+    segment: String,
+}
+
+/// read_clients reads a CSV of client metadata, with `id`, `name`, `email` & `segment` columns
+pub fn read_clients<R: io::Read>(reader: R) -> Result<HashMap<u64, ClientInfo>> {
+    let mut clients = HashMap::new();
+    for result in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+    {
+        let ClientRecord {
+            id,
+            name,
+            email,
+            segment,
+        } = result?;
+        let info = ClientInfo {
+            name,
+            email,
+            segment,
+        };
+        if clients.insert(id, info).is_some() {
+            bail!("client {} is listed more than once", id);
+        }
+    }
+    Ok(clients)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_clients() -> Result<()> {
+        let clients = read_clients(
+            "id,name,email,segment\n1, Ada Lovelace ,ada@example.com,retail\n2,\"Babbage, Charles\",,business\n"
+                .as_bytes(),
+        )?;
+        assert_eq!(clients.len(), 2);
+        assert_eq!(
+            clients[&1],
+            ClientInfo {
+                name: "Ada Lovelace".to_string(),
+                email: "ada@example.com".to_string(),
+                segment: "retail".to_string(),
+            }
+        );
+        assert_eq!(clients[&2].name, "Babbage, Charles");
+        assert_eq!(clients[&2].email, "");
+
+        assert!(read_clients("id,name,email,segment\n1,a,,\n1,b,,\n".as_bytes()).is_err());
+        assert!(read_clients("id,name,email,segment\nx,a,,\n".as_bytes()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::num::NonZeroU8;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// MAX_LEN is the maximum length of a correlation ID in bytes, long enough for a UUID
+pub const MAX_LEN: usize = 36;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CorrelationError {
+    #[error("correlation id cannot be empty")]
+    Empty,
+    #[error("correlation id is longer than {MAX_LEN} bytes")]
+    TooLong,
+}
+
+/// CorrelationId identifies a payment across systems, so that it can be traced end-to-end. It's
+/// stored inline rather than on the heap, so that transactions remain `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CorrelationId {
+    len: NonZeroU8,
+    bytes: [u8; MAX_LEN],
+}
+
+impl CorrelationId {
+    pub fn as_str(&self) -> &str {
+        // only ever constructed from a valid str
+        std::str::from_utf8(&self.bytes[..self.len.get() as usize]).unwrap_or_default()
+    }
+}
+
+impl FromStr for CorrelationId {
+    type Err = CorrelationError;
+
+    fn from_str(s: &str) -> Result<CorrelationId, CorrelationError> {
+        if s.len() > MAX_LEN {
+            return Err(CorrelationError::TooLong);
+        }
+        let len = NonZeroU8::new(s.len() as u8).ok_or(CorrelationError::Empty)?;
+        let mut bytes = [0; MAX_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(CorrelationId { len, bytes })
+    }
+}
+
+impl TryFrom<String> for CorrelationId {
+    type Error = CorrelationError;
+
+    fn try_from(s: String) -> Result<CorrelationId, CorrelationError> {
+        s.parse()
+    }
+}
+
+impl From<CorrelationId> for String {
+    fn from(id: CorrelationId) -> String {
+        id.as_str().to_string()
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_correlation_id() -> Result<()> {
+        let id: CorrelationId = "9b2f0c1e-5d4a-4c4b-8f3e-2a7d6c5b4a39".parse()?;
+        assert_eq!(id.as_str(), "9b2f0c1e-5d4a-4c4b-8f3e-2a7d6c5b4a39");
+        assert_eq!("".parse::<CorrelationId>(), Err(CorrelationError::Empty));
+        assert_eq!(
+            "x".repeat(MAX_LEN + 1).parse::<CorrelationId>(),
+            Err(CorrelationError::TooLong)
+        );
+
+        let json = serde_json::to_string(&id)?;
+        assert_eq!(json, "\"9b2f0c1e-5d4a-4c4b-8f3e-2a7d6c5b4a39\"");
+        assert_eq!(serde_json::from_str::<CorrelationId>(&json)?, id);
+        Ok(())
+    }
+}
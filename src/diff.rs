@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use anyhow::Result;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::accounts::{self, AccountStatement};
+
+/// read_statements reads a statements CSV into a map keyed by client, so that two files can be
+/// compared regardless of the order of their rows
+pub fn read_statements<R: io::Read>(reader: R) -> Result<BTreeMap<u64, AccountStatement>> {
+    Ok(accounts::read_statements(reader)?
+        .into_iter()
+        .map(|statement| (statement.client, statement))
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LockChange {
+    Locked,
+    Unlocked,
+}
+
+/// ClientDiff describes how a single client's statement differs between two statements files.
+/// Balances are the deltas from the old to the new statement.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ClientDiff {
+    pub client: u64,
+    pub change: Change,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: Option<LockChange>,
+}
+
+/// diff compares two sets of statements, returning a diff for every client which differs,
+/// ordered by client
+pub fn diff(
+    old: &BTreeMap<u64, AccountStatement>,
+    new: &BTreeMap<u64, AccountStatement>,
+) -> Vec<ClientDiff> {
+    let zero = AccountStatement {
+        tenant: None,
+        client: 0,
+        available: Decimal::from(0),
+        held: Decimal::from(0),
+        total: Decimal::from(0),
+        locked: false,
+    };
+    let mut clients: Vec<u64> = old.keys().chain(new.keys()).cloned().collect();
+    clients.sort_unstable();
+    clients.dedup();
+
+    clients
+        .into_iter()
+        .filter_map(|client| {
+            let (change, before, after) = match (old.get(&client), new.get(&client)) {
+                (Some(before), Some(after)) if before == after => return None,
+                (Some(before), Some(after)) => (Change::Changed, before, after),
+                (Some(before), None) => (Change::Removed, before, &zero),
+                (None, Some(after)) => (Change::Added, &zero, after),
+                (None, None) => return None,
+            };
+            let locked = match (before.locked, after.locked) {
+                (false, true) => Some(LockChange::Locked),
+                (true, false) => Some(LockChange::Unlocked),
+                _ => None,
+            };
+            Some(ClientDiff {
+                client,
+                change,
+                available: after.available - before.available,
+                held: after.held - before.held,
+                total: after.total - before.total,
+                locked,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() -> Result<()> {
+        let old = read_statements(
+            "client,available,held,total,locked
+2,0,2,2,false
+1,9.5,0,9.5,false
+3,1,0,1,false
+"
+            .as_bytes(),
+        )?;
+        let new = read_statements(
+            "client,available,held,total,locked
+1,9.5,0,9.5,true
+4,5,0,5,false
+2,0,2,2,false
+"
+            .as_bytes(),
+        )?;
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                ClientDiff {
+                    client: 1,
+                    change: Change::Changed,
+                    available: Decimal::from(0),
+                    held: Decimal::from(0),
+                    total: Decimal::from(0),
+                    locked: Some(LockChange::Locked),
+                },
+                ClientDiff {
+                    client: 3,
+                    change: Change::Removed,
+                    available: Decimal::from(-1),
+                    held: Decimal::from(0),
+                    total: Decimal::from(-1),
+                    locked: None,
+                },
+                ClientDiff {
+                    client: 4,
+                    change: Change::Added,
+                    available: Decimal::from(5),
+                    held: Decimal::from(0),
+                    total: Decimal::from(5),
+                    locked: None,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_equal_with_different_precision() -> Result<()> {
+        let old =
+            read_statements("client,available,held,total,locked\n1,9.5,0,9.5,false\n".as_bytes())?;
+        let new = read_statements(
+            "client,available,held,total,locked\n1,9.5000,0.0000,9.5000,false\n".as_bytes(),
+        )?;
+        assert!(diff(&old, &new).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, TimeZone, Utc};
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountKind};
+use crate::correlation::{CorrelationError, CorrelationId};
+use crate::tenant::{TenantId, TenantIdError};
+use crate::transactions::{Transaction, TransactionCommand, TransactionKind};
+
+/// proto holds the domain types defined in `proto/domain.proto`, for services which exchange them
+/// over gRPC or Kafka. Encode & decode them with [prost::Message].
+pub mod proto {
+    tonic::include_proto!("payments.domain.v1");
+}
+
+use proto::transaction_kind::Kind;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum DomainError {
+    #[error("transaction kind is required")]
+    MissingKind,
+    #[error("invalid amount {0:?}")]
+    InvalidAmount(String),
+    #[error("invalid timestamp")]
+    InvalidTimestamp,
+    #[error("invalid account kind {0}")]
+    InvalidAccountKind(i32),
+    #[error(transparent)]
+    InvalidCorrelation(#[from] CorrelationError),
+    #[error(transparent)]
+    InvalidTenant(#[from] TenantIdError),
+}
+
+impl DomainError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            DomainError::MissingKind => "missing_kind",
+            DomainError::InvalidAmount(_) => "invalid_amount",
+            DomainError::InvalidTimestamp => "invalid_timestamp",
+            DomainError::InvalidAccountKind(_) => "invalid_account_kind",
+            DomainError::InvalidCorrelation(_) => "invalid_correlation_id",
+            DomainError::InvalidTenant(_) => "invalid_tenant",
+        }
+    }
+}
+
+fn amount(amount: &str) -> Result<Decimal, DomainError> {
+    Decimal::from_str(amount.trim()).map_err(|_| DomainError::InvalidAmount(amount.to_string()))
+}
+
+fn correlation(id: &str) -> Result<Option<CorrelationId>, DomainError> {
+    match id {
+        "" => Ok(None),
+        id => Ok(Some(CorrelationId::from_str(id)?)),
+    }
+}
+
+fn tenant(id: &str) -> Result<Option<TenantId>, DomainError> {
+    match id {
+        "" => Ok(None),
+        id => Ok(Some(TenantId::from_str(id)?)),
+    }
+}
+
+fn timestamp(
+    timestamp: Option<prost_types::Timestamp>,
+) -> Result<Option<DateTime<Utc>>, DomainError> {
+    let timestamp = match timestamp {
+        Some(timestamp) => timestamp,
+        None => return Ok(None),
+    };
+    let nanos = u32::try_from(timestamp.nanos).map_err(|_| DomainError::InvalidTimestamp)?;
+    Utc.timestamp_opt(timestamp.seconds, nanos)
+        .single()
+        .map(Some)
+        .ok_or(DomainError::InvalidTimestamp)
+}
+
+fn to_timestamp(timestamp: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: timestamp.timestamp(),
+        nanos: timestamp.timestamp_subsec_nanos() as i32,
+    }
+}
+
+impl From<TransactionKind> for proto::TransactionKind {
+    fn from(kind: TransactionKind) -> proto::TransactionKind {
+        let amount = |amount: Decimal| amount.to_string();
+        let kind = match kind {
+            TransactionKind::Deposit { amount: a } => {
+                Kind::Deposit(proto::Deposit { amount: amount(a) })
+            }
+            TransactionKind::Withdrawal { amount: a } => {
+                Kind::Withdrawal(proto::Withdrawal { amount: amount(a) })
+            }
+            TransactionKind::Payout {
+                amount: a,
+                beneficiary,
+            } => Kind::Payout(proto::Payout {
+                amount: amount(a),
+                beneficiary,
+            }),
+            TransactionKind::Dispute => Kind::Dispute(proto::Dispute {}),
+            TransactionKind::Resolve => Kind::Resolve(proto::Resolve {}),
+            TransactionKind::ChargeBack => Kind::Chargeback(proto::ChargeBack {}),
+            TransactionKind::Adjustment { amount: a } => {
+                Kind::Adjustment(proto::Adjustment { amount: amount(a) })
+            }
+            TransactionKind::Lock => Kind::Lock(proto::Lock {}),
+            TransactionKind::Unlock => Kind::Unlock(proto::Unlock {}),
+            TransactionKind::ForceResolve => Kind::ForceResolve(proto::ForceResolve {}),
+            TransactionKind::Escrow { amount: a } => {
+                Kind::Escrow(proto::Escrow { amount: amount(a) })
+            }
+            TransactionKind::Release => Kind::Release(proto::Release {}),
+            TransactionKind::Refund => Kind::Refund(proto::Refund {}),
+            TransactionKind::PendingDeposit { amount: a } => {
+                Kind::PendingDeposit(proto::PendingDeposit { amount: amount(a) })
+            }
+            TransactionKind::PendingWithdrawal { amount: a } => {
+                Kind::PendingWithdrawal(proto::PendingWithdrawal { amount: amount(a) })
+            }
+            TransactionKind::Settle { withdrawal } => Kind::Settle(proto::Settle { withdrawal }),
+            TransactionKind::Fail { withdrawal } => Kind::Fail(proto::Fail { withdrawal }),
+        };
+        proto::TransactionKind { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<proto::TransactionKind> for TransactionKind {
+    type Error = DomainError;
+    fn try_from(kind: proto::TransactionKind) -> Result<TransactionKind, DomainError> {
+        Ok(match kind.kind.ok_or(DomainError::MissingKind)? {
+            Kind::Deposit(k) => TransactionKind::Deposit {
+                amount: amount(&k.amount)?,
+            },
+            Kind::Withdrawal(k) => TransactionKind::Withdrawal {
+                amount: amount(&k.amount)?,
+            },
+            Kind::Payout(k) => TransactionKind::Payout {
+                amount: amount(&k.amount)?,
+                beneficiary: k.beneficiary,
+            },
+            Kind::Dispute(_) => TransactionKind::Dispute,
+            Kind::Resolve(_) => TransactionKind::Resolve,
+            Kind::Chargeback(_) => TransactionKind::ChargeBack,
+            Kind::Adjustment(k) => TransactionKind::Adjustment {
+                amount: amount(&k.amount)?,
+            },
+            Kind::Lock(_) => TransactionKind::Lock,
+            Kind::Unlock(_) => TransactionKind::Unlock,
+            Kind::ForceResolve(_) => TransactionKind::ForceResolve,
+            Kind::Escrow(k) => TransactionKind::Escrow {
+                amount: amount(&k.amount)?,
+            },
+            Kind::Release(_) => TransactionKind::Release,
+            Kind::Refund(_) => TransactionKind::Refund,
+            Kind::PendingDeposit(k) => TransactionKind::PendingDeposit {
+                amount: amount(&k.amount)?,
+            },
+            Kind::PendingWithdrawal(k) => TransactionKind::PendingWithdrawal {
+                amount: amount(&k.amount)?,
+            },
+            Kind::Settle(k) => TransactionKind::Settle {
+                withdrawal: k.withdrawal,
+            },
+            Kind::Fail(k) => TransactionKind::Fail {
+                withdrawal: k.withdrawal,
+            },
+        })
+    }
+}
+
+impl From<TransactionCommand> for proto::TransactionCommand {
+    fn from(command: TransactionCommand) -> proto::TransactionCommand {
+        proto::TransactionCommand {
+            kind: Some(command.kind.into()),
+            tx: command.tx,
+            client: command.client,
+            correlation_id: command
+                .correlation
+                .map(|c| c.to_string())
+                .unwrap_or_default(),
+            tenant: command.tenant.map(String::from).unwrap_or_default(),
+            timestamp: command.timestamp.map(to_timestamp),
+        }
+    }
+}
+
+impl TryFrom<proto::TransactionCommand> for TransactionCommand {
+    type Error = DomainError;
+    fn try_from(command: proto::TransactionCommand) -> Result<TransactionCommand, DomainError> {
+        Ok(TransactionCommand {
+            kind: command.kind.ok_or(DomainError::MissingKind)?.try_into()?,
+            tx: command.tx,
+            client: command.client,
+            correlation: correlation(&command.correlation_id)?,
+            tenant: tenant(&command.tenant)?,
+            timestamp: timestamp(command.timestamp)?,
+        })
+    }
+}
+
+impl From<Transaction> for proto::Transaction {
+    fn from(transaction: Transaction) -> proto::Transaction {
+        proto::Transaction {
+            tx: transaction.tx,
+            amount: transaction.amount.to_string(),
+            kind: Some(transaction.kind.into()),
+            client: transaction.client,
+            correlation_id: transaction
+                .correlation
+                .map(|c| c.to_string())
+                .unwrap_or_default(),
+            tenant: transaction.tenant.map(String::from).unwrap_or_default(),
+            timestamp: transaction.timestamp.map(to_timestamp),
+        }
+    }
+}
+
+impl TryFrom<proto::Transaction> for Transaction {
+    type Error = DomainError;
+    fn try_from(transaction: proto::Transaction) -> Result<Transaction, DomainError> {
+        Ok(Transaction {
+            tx: transaction.tx,
+            amount: amount(&transaction.amount)?,
+            kind: transaction
+                .kind
+                .ok_or(DomainError::MissingKind)?
+                .try_into()?,
+            client: transaction.client,
+            correlation: correlation(&transaction.correlation_id)?,
+            tenant: tenant(&transaction.tenant)?,
+            timestamp: timestamp(transaction.timestamp)?,
+        })
+    }
+}
+
+impl From<Account> for proto::Account {
+    fn from(account: Account) -> proto::Account {
+        let kind = match account.kind() {
+            AccountKind::Standard => proto::AccountKind::Standard,
+            AccountKind::Escrow => proto::AccountKind::Escrow,
+        };
+        proto::Account {
+            client: account.client(),
+            available: account.available().to_string(),
+            held: account.held().to_string(),
+            total: account.total().to_string(),
+            locked: account.is_locked(),
+            tenant: account.tenant().map(String::from).unwrap_or_default(),
+            kind: kind.into(),
+        }
+    }
+}
+
+/// The total is ignored when decoding an account, since it's implied by the available & held
+/// balances
+impl TryFrom<proto::Account> for Account {
+    type Error = DomainError;
+    fn try_from(account: proto::Account) -> Result<Account, DomainError> {
+        let kind = match proto::AccountKind::try_from(account.kind) {
+            Ok(proto::AccountKind::Standard) => AccountKind::Standard,
+            Ok(proto::AccountKind::Escrow) => AccountKind::Escrow,
+            Err(_) => return Err(DomainError::InvalidAccountKind(account.kind)),
+        };
+        Ok(Account::from_balances(
+            account.client,
+            amount(&account.available)?,
+            amount(&account.held)?,
+            account.locked,
+            tenant(&account.tenant)?,
+            kind,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        let amount = Decimal::new(15, 1);
+        let kinds = [
+            TransactionKind::Deposit { amount },
+            TransactionKind::Payout {
+                amount,
+                beneficiary: 7,
+            },
+            TransactionKind::ChargeBack,
+            TransactionKind::Adjustment { amount: -amount },
+            TransactionKind::ForceResolve,
+            TransactionKind::Settle { withdrawal: true },
+        ];
+        for kind in kinds {
+            let command = TransactionCommand {
+                kind,
+                tx: 2,
+                client: 1,
+                correlation: Some(CorrelationId::from_str("abc-123")?),
+                tenant: Some(TenantId::from_str("acme")?),
+                timestamp: Some(Utc.ymd(2024, 1, 31).and_hms_milli(9, 30, 0, 250)),
+            };
+            // through the wire format, as another service would receive it
+            let bytes = proto::TransactionCommand::from(command).encode_to_vec();
+            let decoded = proto::TransactionCommand::decode(bytes.as_slice())?;
+            assert_eq!(TransactionCommand::try_from(decoded)?, command);
+        }
+
+        let transaction = Transaction::try_from(TransactionCommand {
+            kind: TransactionKind::Deposit { amount },
+            tx: 1,
+            client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        let decoded = Transaction::try_from(proto::Transaction::from(transaction))?;
+        assert_eq!(
+            (decoded.tx, decoded.client, decoded.amount, decoded.kind),
+            (1, 1, amount, transaction.kind)
+        );
+        assert_eq!(decoded.timestamp, None);
+
+        let account = Account::new(transaction)?;
+        let encoded = proto::Account::from(account);
+        assert_eq!(encoded.total, "1.5");
+        let decoded = Account::try_from(encoded)?;
+        assert_eq!(
+            (decoded.client(), decoded.available(), decoded.held()),
+            (1, amount, Decimal::from(0))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        let command = proto::TransactionCommand {
+            kind: None,
+            tx: 1,
+            client: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            TransactionCommand::try_from(command.clone()),
+            Err(DomainError::MissingKind)
+        );
+        let deposit = proto::Deposit {
+            amount: "ten".to_string(),
+        };
+        let command = proto::TransactionCommand {
+            kind: Some(proto::TransactionKind {
+                kind: Some(Kind::Deposit(deposit)),
+            }),
+            ..command
+        };
+        assert_eq!(
+            TransactionCommand::try_from(command),
+            Err(DomainError::InvalidAmount("ten".to_string()))
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::env;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+/// KEY_VAR is the environment variable the encryption key is read from, as 64 hex characters
+pub const KEY_VAR: &str = "PAYMENTS_ENCRYPTION_KEY";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum EncryptionError {
+    #[error("unable to decrypt, either the key is wrong or the data isn't encrypted")]
+    Decrypt,
+}
+
+/// Cipher encrypts data at rest with AES-256-GCM. Every value is sealed with a random nonce,
+/// which is stored in front of the ciphertext.
+#[derive(Clone)]
+pub struct Cipher {
+    key: Arc<LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl Cipher {
+    /// new creates a cipher from a 32 byte key
+    pub fn new(key: &[u8]) -> Result<Cipher> {
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| anyhow!("encryption key must be {} bytes", AES_256_GCM.key_len()))?;
+        Ok(Cipher {
+            key: Arc::new(LessSafeKey::new(key)),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// from_env creates a cipher from the hex encoded key in PAYMENTS_ENCRYPTION_KEY, or returns
+    /// `None` if it isn't set, in which case data is stored unencrypted
+    pub fn from_env() -> Result<Option<Cipher>> {
+        match env::var(KEY_VAR) {
+            Ok(key) if !key.is_empty() => {
+                let key = hex(key.trim()).with_context(|| format!("invalid {}", KEY_VAR))?;
+                Ok(Some(Cipher::new(&key)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// seal encrypts & authenticates `plaintext`, returning the nonce followed by the ciphertext
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("unable to generate a nonce"))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + plaintext.len() + AES_256_GCM.tag_len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(plaintext);
+        let tag = self
+            .key
+            .seal_in_place_separate_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed[NONCE_LEN..],
+            )
+            .map_err(|_| anyhow!("unable to encrypt"))?;
+        sealed.extend_from_slice(tag.as_ref());
+        Ok(sealed)
+    }
+
+    /// open decrypts data sealed by [Cipher::seal], failing if it was sealed with a different key
+    /// or has been tampered with
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        if sealed.len() < NONCE_LEN + AES_256_GCM.tag_len() {
+            return Err(EncryptionError::Decrypt);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce =
+            Nonce::try_assume_unique_for_key(nonce).map_err(|_| EncryptionError::Decrypt)?;
+        let mut plaintext = ciphertext.to_vec();
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut plaintext)
+            .map_err(|_| EncryptionError::Decrypt)?
+            .len();
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+}
+
+/// encode serializes a value to be stored as JSON, encrypted if there's a cipher
+pub fn encode<T: Serialize>(cipher: Option<&Cipher>, value: &T) -> Result<Vec<u8>> {
+    let json = serde_json::to_vec(value)?;
+    match cipher {
+        Some(cipher) => cipher.seal(&json),
+        None => Ok(json),
+    }
+}
+
+/// decode deserializes a value stored by [encode]
+pub fn decode<T: DeserializeOwned>(cipher: Option<&Cipher>, bytes: &[u8]) -> Result<T> {
+    match cipher {
+        Some(cipher) => Ok(serde_json::from_slice(&cipher.open(bytes)?)?),
+        None => serde_json::from_slice(bytes).with_context(|| {
+            format!(
+                "unable to decode stored value, set {} if it's encrypted",
+                KEY_VAR
+            )
+        }),
+    }
+}
+
+/// hex decodes a hex string, e.g. a key read from the environment
+pub fn hex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        bail!("must be hex encoded");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| anyhow!("must be hex encoded")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cipher() -> Result<()> {
+        let cipher = Cipher::new(&[7; 32])?;
+        let sealed = cipher.seal(b"client,available\n1,1.5\n")?;
+        assert!(!sealed.windows(3).any(|w| w == b"1.5"));
+        assert_eq!(cipher.open(&sealed)?, b"client,available\n1,1.5\n");
+        // nonces are random, so the same plaintext is sealed differently every time
+        assert_ne!(cipher.seal(b"same")?, cipher.seal(b"same")?);
+
+        let other = Cipher::new(&[8; 32])?;
+        assert_eq!(other.open(&sealed), Err(EncryptionError::Decrypt));
+        assert_eq!(cipher.open(b"{}"), Err(EncryptionError::Decrypt));
+        assert!(Cipher::new(&[7; 16]).is_err());
+
+        assert_eq!(hex("00ff10")?, vec![0, 255, 16]);
+        assert!(hex("0g").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode() -> Result<()> {
+        let cipher = Cipher::new(&[7; 32])?;
+        let encoded = encode(Some(&cipher), &vec![1, 2, 3])?;
+        assert_eq!(decode::<Vec<u8>>(Some(&cipher), &encoded)?, vec![1, 2, 3]);
+        assert!(decode::<Vec<u8>>(None, &encoded).is_err());
+        assert_eq!(encode(None, &vec![1])?, b"[1]");
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use thiserror::Error;
+
+use crate::accounts::Account;
+use crate::encryption::{self, Cipher};
+use crate::events;
+use crate::tenant::{self, TenantId};
+use crate::transactions::{Transaction, TransactionKind, ERASED_IDS_TREE};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ErasureError {
+    #[error("client {0} has no stored account or transactions")]
+    UnknownClient(u64),
+}
+
+/// Tombstone is an erased transaction or event, which keeps its ID, amount & type so that totals
+/// across the ledger are unchanged, but no longer records the client it belonged to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub tx: u64,
+    pub amount: Decimal,
+    pub kind: TransactionKind,
+}
+
+impl From<Transaction> for Tombstone {
+    fn from(transaction: Transaction) -> Tombstone {
+        Tombstone {
+            tx: transaction.tx,
+            amount: transaction.amount,
+            kind: transaction.kind,
+        }
+    }
+}
+
+/// ErasureReceipt records that a client was forgotten, along with what was erased, so that the
+/// erasure can be accounted for later. It's the only remaining link between the client and their
+/// tombstones.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErasureReceipt {
+    pub event: &'static str,
+    /// Identifies the erasure, which the client's tombstones are keyed by
+    pub erasure: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    pub client: u64,
+    /// Seconds since the Unix epoch
+    pub erased_at: u64,
+    pub transactions: u64,
+    pub events: u64,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+/// forget erases a client from a database: their account (whether active or archived) is
+/// removed, and their stored transactions & events are moved to the `erased_transactions` &
+/// `erased_events` trees as tombstones, keyed by the erasure followed by the transaction ID or
+/// event sequence number. Erased transactions' IDs are kept in the `erased_transaction_ids`
+/// tree, so that they can't be reused. All of it happens in a single database transaction, so the
+/// client is either forgotten entirely or not at all. Tombstones are encrypted with `cipher`,
+/// which the database must have been encrypted with too, if given. A tenant's client is erased
+/// from, and tombstoned in, the tenant's own trees.
+pub fn forget(
+    db: &sled::Db,
+    client: u64,
+    tenant: Option<TenantId>,
+    cipher: Option<&Cipher>,
+) -> Result<ErasureReceipt> {
+    let tree = |name| db.open_tree(tenant::tree_name(tenant, name));
+    let accounts = tree("accounts")?;
+    let archived_accounts = tree("archived_accounts")?;
+    let transactions = tree("transactions")?;
+    let events = tree("events")?;
+    let events_by_client = tree("events_by_client")?;
+    let erased_transactions = tree("erased_transactions")?;
+    let erased_events = tree("erased_events")?;
+    let erased_ids = tree(ERASED_IDS_TREE)?;
+
+    // a dormant client's account may have been archived
+    let account: Option<Account> = match accounts
+        .get(client.to_be_bytes())?
+        .or(archived_accounts.get(client.to_be_bytes())?)
+    {
+        Some(bytes) => Some(encryption::decode(cipher, &bytes)?),
+        None => None,
+    };
+    let client_transactions = owned_by(&transactions, client, cipher)?;
+    let client_events = owned_by(&events, client, cipher)?;
+    if account.is_none() && client_transactions.is_empty() && client_events.is_empty() {
+        return Err(ErasureError::UnknownClient(client).into());
+    }
+
+    let erasure = db.generate_id()?;
+    let tombstones = |erased: &[(sled::IVec, Transaction)]| -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        erased
+            .iter()
+            .map(|(key, transaction)| {
+                let mut tombstone_key = erasure.to_be_bytes().to_vec();
+                tombstone_key.extend_from_slice(key);
+                Ok((
+                    tombstone_key,
+                    encryption::encode(cipher, &Tombstone::from(*transaction))?,
+                ))
+            })
+            .collect()
+    };
+    let transaction_tombstones = tombstones(&client_transactions)?;
+    let event_tombstones = tombstones(&client_events)?;
+
+    (
+        &accounts,
+        &archived_accounts,
+        &transactions,
+        &events,
+        &events_by_client,
+        &erased_transactions,
+        &erased_events,
+        &erased_ids,
+    )
+        .transaction(
+            |(
+                accounts,
+                archived_accounts,
+                transactions,
+                events,
+                events_by_client,
+                erased_transactions,
+                erased_events,
+                erased_ids,
+            )| {
+                accounts.remove(&client.to_be_bytes())?;
+                archived_accounts.remove(&client.to_be_bytes())?;
+                for ((key, _), (tombstone_key, tombstone)) in
+                    client_transactions.iter().zip(&transaction_tombstones)
+                {
+                    transactions.remove(key)?;
+                    erased_ids.insert(key, &[])?;
+                    erased_transactions.insert(tombstone_key.as_slice(), tombstone.as_slice())?;
+                }
+                for ((key, _), (tombstone_key, tombstone)) in
+                    client_events.iter().zip(&event_tombstones)
+                {
+                    events.remove(key)?;
+                    events_by_client.remove(events::index_key(client, key))?;
+                    erased_events.insert(tombstone_key.as_slice(), tombstone.as_slice())?;
+                }
+                Ok::<_, ConflictableTransactionError<()>>(())
+            },
+        )
+        .map_err(|e| match e {
+            TransactionError::Storage(e) => anyhow::Error::from(e),
+            TransactionError::Abort(()) => anyhow::anyhow!("erasure aborted"),
+        })?;
+    db.flush()?;
+
+    Ok(ErasureReceipt {
+        event: "erasure",
+        erasure,
+        tenant,
+        client,
+        erased_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        transactions: client_transactions.len() as u64,
+        events: client_events.len() as u64,
+        available: account.map(|a| a.available()).unwrap_or_default(),
+        held: account.map(|a| a.held()).unwrap_or_default(),
+        total: account.map(|a| a.total()).unwrap_or_default(),
+        locked: account.is_some_and(|a| a.is_locked()),
+    })
+}
+
+/// erased_events reads the tombstones of every erased event, along with the erasure each belongs
+/// to, ordered by erasure & then by the order they were logged in
+pub fn erased_events(
+    db: &sled::Db,
+    tenant: Option<TenantId>,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<(u64, Tombstone)>> {
+    let tree = db.open_tree(tenant::tree_name(tenant, "erased_events"))?;
+    let mut erased = Vec::new();
+    for entry in tree.iter() {
+        let (key, bytes) = entry?;
+        let erasure = u64::from_be_bytes(key[..8].try_into()?);
+        erased.push((erasure, encryption::decode(cipher, &bytes)?));
+    }
+    Ok(erased)
+}
+
+/// owned_by finds every transaction stored in a tree which belongs to the client, along with its
+/// key
+pub fn owned_by(
+    tree: &sled::Tree,
+    client: u64,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<(sled::IVec, Transaction)>> {
+    let mut owned = Vec::new();
+    for entry in tree.iter() {
+        let (key, bytes) = entry?;
+        let transaction: Transaction = encryption::decode(cipher, &bytes)?;
+        if transaction.client == client {
+            owned.push((key, transaction));
+        }
+    }
+    Ok(owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::{AccountsRepo, SledRepo as AccountsSledRepo};
+    use crate::events::{EventsRepo, SledRepo as EventsSledRepo};
+    use crate::ledger::{self, LedgerAccount, Posting};
+    use crate::payments::PaymentsEngine;
+    use crate::transactions::{
+        SledRepo as TransactionsSledRepo, TransactionCommand, TransactionError, TransactionsRepo,
+    };
+
+    /// balances are the ledger's balance of each internal account
+    fn balances(postings: &[Posting]) -> Vec<Decimal> {
+        LedgerAccount::ALL
+            .iter()
+            .map(|&account| {
+                postings
+                    .iter()
+                    .filter(|p| p.account == account)
+                    .map(|p| p.debit - p.credit)
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_forget() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let transactions = TransactionsSledRepo::new(&db)?;
+        let accounts = AccountsSledRepo::new(&db)?;
+        let events = EventsSledRepo::new(&db)?;
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+        let deposit = |amount: i64| TransactionKind::Deposit {
+            amount: Decimal::from(amount),
+        };
+        let command = |tx, client, kind| TransactionCommand {
+            kind,
+            tx,
+            client,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+        for (tx, client, kind) in [
+            (1, 1, deposit(10)),
+            (2, 2, deposit(5)),
+            (1, 1, TransactionKind::Dispute),
+        ] {
+            engine.process_transaction(command(tx, client, kind))?;
+        }
+        let before = balances(ledger::ledger(&events, &[])?.postings());
+
+        let receipt = forget(&db, 1, None, None)?;
+        assert_eq!(receipt.client, 1);
+        assert_eq!(receipt.transactions, 1);
+        assert_eq!(receipt.events, 2);
+        assert_eq!(receipt.held, Decimal::from(10));
+        assert_eq!(receipt.total, Decimal::from(10));
+
+        assert!(accounts.get(1)?.is_none());
+        assert!(transactions.get(1)?.is_none());
+        assert!(events.get_all()?.iter().all(|e| e.client == 2));
+        assert!(events.get_by_client(1)?.is_empty());
+        assert!(db
+            .open_tree("events_by_client")?
+            .scan_prefix(1u64.to_be_bytes())
+            .next()
+            .is_none());
+        assert!(accounts.get(2)?.is_some());
+
+        let tombstones: Vec<Tombstone> = db
+            .open_tree("erased_events")?
+            .scan_prefix(receipt.erasure.to_be_bytes())
+            .values()
+            .map(|bytes| Ok(serde_json::from_slice(&bytes?)?))
+            .collect::<Result<_>>()?;
+        assert_eq!(tombstones.len(), 2);
+        assert_eq!(tombstones[0].amount, Decimal::from(10));
+        assert_eq!(tombstones[1].kind, TransactionKind::Dispute);
+
+        // the erased amounts stay in the ledger, under the erasure rather than the client
+        let erased = erased_events(&db, None, None)?;
+        assert_eq!(erased.len(), 2);
+        let ledger = ledger::ledger(&events, &erased)?;
+        assert_eq!(balances(ledger.postings()), before);
+        let last = ledger.postings().last().unwrap();
+        assert_eq!((last.client, last.erasure), (None, Some(receipt.erasure)));
+
+        // and the erased transaction's ID can't be reused
+        assert!(transactions.erased(1)?);
+        let err = engine
+            .process_transaction(command(1, 3, deposit(10)))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::Duplicate { tx: 1 })
+        );
+
+        assert_eq!(
+            forget(&db, 1, None, None)
+                .unwrap_err()
+                .downcast::<ErasureError>()?,
+            ErasureError::UnknownClient(1)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::accounts::AccountError;
+use crate::beneficiaries::PayoutError;
+use crate::chaos::ChaosError;
+use crate::correlation::CorrelationId;
+use crate::domain::DomainError;
+use crate::input::InputError;
+use crate::invariants::InvariantError;
+use crate::iso8583::Iso8583Error;
+use crate::kyc::KycError;
+use crate::payments::SaveError;
+use crate::rules::RuleError;
+use crate::signature::SignatureError;
+use crate::timestamps::TimestampError;
+use crate::transactions::TransactionError;
+
+/// ErrorEntry is a single rejected row in the error log. The tx and client are omitted when the
+/// row couldn't be parsed far enough to know them, as is the correlation ID when there isn't one.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ErrorEntry {
+    pub line: u64,
+    pub tx: Option<u64>,
+    pub client: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<CorrelationId>,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// code classifies an error which caused a row to be rejected, so that specific failure classes
+/// can be alerted on without matching against messages
+pub fn code(error: &anyhow::Error) -> &'static str {
+    if let Some(e) = error.downcast_ref::<AccountError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<TransactionError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<InputError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<RuleError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<KycError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<SignatureError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<PayoutError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<TimestampError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<InvariantError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<DomainError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<Iso8583Error>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<SaveError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<ChaosError>() {
+        return e.code();
+    }
+    if error.is::<csv::Utf8Error>() {
+        return "invalid_utf8";
+    }
+    if let Some(e) = error.downcast_ref::<csv::Error>() {
+        if let csv::ErrorKind::Deserialize { .. } = e.kind() {
+            return "invalid_field";
+        }
+    }
+    "unknown"
+}
+
+/// ErrorLog writes one JSON object per rejected row (NDJSON)
+pub struct ErrorLog<W: io::Write> {
+    writer: io::BufWriter<W>,
+}
+
+impl ErrorLog<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<ErrorLog<File>> {
+        Ok(ErrorLog::new(File::create(path)?))
+    }
+}
+
+impl<W: io::Write> ErrorLog<W> {
+    pub fn new(writer: W) -> ErrorLog<W> {
+        ErrorLog {
+            writer: io::BufWriter::new(writer),
+        }
+    }
+
+    pub fn log(&mut self, entry: &ErrorEntry) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, entry)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// RejectionRecord is a rejected row in the rejection report, flattened into CSV columns
+#[derive(Debug, Serialize, PartialEq)]
+struct RejectionRecord<'a> {
+    line: u64,
+    tx: Option<u64>,
+    client: Option<u64>,
+    correlation_id: Option<&'a str>,
+    code: &'static str,
+    message: &'a str,
+}
+
+/// RejectionReport writes every rejected row as CSV, for tooling which aggregates rejections by
+/// their code
+pub struct RejectionReport<W: io::Write> {
+    writer: csv::Writer<W>,
+}
+
+impl RejectionReport<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<RejectionReport<File>> {
+        Ok(RejectionReport::new(File::create(path)?))
+    }
+}
+
+impl<W: io::Write> RejectionReport<W> {
+    pub fn new(writer: W) -> RejectionReport<W> {
+        RejectionReport {
+            writer: csv::Writer::from_writer(writer),
+        }
+    }
+
+    pub fn log(&mut self, entry: &ErrorEntry) -> Result<()> {
+        self.writer.serialize(RejectionRecord {
+            line: entry.line,
+            tx: entry.tx,
+            client: entry.client,
+            correlation_id: entry.correlation_id.as_ref().map(CorrelationId::as_str),
+            code: entry.code,
+            message: &entry.message,
+        })?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{CommandReader, InputConfig};
+
+    #[test]
+    fn test_code() {
+        assert_eq!(
+            code(&AccountError::InsufficientFunds.into()),
+            "insufficient_funds"
+        );
+        assert_eq!(
+            code(&TransactionError::InvalidInitialState.into()),
+            "invalid_initial_state"
+        );
+        assert_eq!(code(&anyhow::anyhow!("sled is on fire")), "unknown");
+
+        let input = "type,client,tx,amount\ndeposit,1,x,1.0\n";
+        let mut reader = CommandReader::from_reader(input.as_bytes(), InputConfig::default())
+            .expect("headers are valid");
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(code(&err.error), "invalid_field");
+    }
+
+    #[test]
+    fn test_log() -> Result<()> {
+        let mut log = ErrorLog::new(vec![]);
+        log.log(&ErrorEntry {
+            line: 3,
+            tx: Some(5),
+            client: Some(2),
+            code: "insufficient_funds",
+            message: "insufficient funds".to_string(),
+            correlation_id: None,
+        })?;
+        log.log(&ErrorEntry {
+            line: 4,
+            tx: None,
+            client: None,
+            code: "invalid_field",
+            message: "invalid digit".to_string(),
+            correlation_id: None,
+        })?;
+
+        let output = String::from_utf8(log.writer.into_inner()?)?;
+        assert_eq!(
+            output,
+            r#"{"line":3,"tx":5,"client":2,"code":"insufficient_funds","message":"insufficient funds"}
+{"line":4,"tx":null,"client":null,"code":"invalid_field","message":"invalid digit"}
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejection_report() -> Result<()> {
+        let mut report = RejectionReport::new(vec![]);
+        report.log(&ErrorEntry {
+            line: 3,
+            tx: Some(5),
+            client: Some(2),
+            code: "duplicate_tx",
+            message: "transaction 5 already exists".to_string(),
+            correlation_id: Some("abc".parse()?),
+        })?;
+        report.log(&ErrorEntry {
+            line: 4,
+            tx: None,
+            client: None,
+            code: "invalid_field",
+            message: "invalid digit".to_string(),
+            correlation_id: None,
+        })?;
+
+        let output = String::from_utf8(report.writer.into_inner()?)?;
+        assert_eq!(
+            output,
+            "line,tx,client,correlation_id,code,message
+3,5,2,abc,duplicate_tx,transaction 5 already exists
+4,,,,invalid_field,invalid digit
+"
+        );
+        Ok(())
+    }
+}
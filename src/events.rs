@@ -0,0 +1,488 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+
+use crate::accounts::Account;
+use crate::encryption::{self, Cipher};
+use crate::tenant::{self, TenantId};
+use crate::transactions::Transaction;
+use crate::verify::{self, Until};
+
+/// EventsRepo is an append-only log of every transaction which was successfully applied to an
+/// account, in the order it was applied. Unlike the transactions repo, which only holds the latest
+/// state of each transaction, the log can be replayed to rebuild account state at any point.
+pub trait EventsRepo {
+    fn append(&self, transaction: Transaction) -> Result<u64>;
+    fn get_all(&self) -> Result<Vec<Transaction>>;
+    /// get_by_client gets every event for a client's transactions, in the order they were
+    /// appended, without reading the rest of the log
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>>;
+    /// truncate drops every event after the first `len`, rolling the log back to an earlier point
+    fn truncate(&self, len: u64) -> Result<()>;
+}
+
+impl<T: EventsRepo + ?Sized> EventsRepo for Box<T> {
+    fn append(&self, transaction: Transaction) -> Result<u64> {
+        (**self).append(transaction)
+    }
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        (**self).get_all()
+    }
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        (**self).get_by_client(client)
+    }
+    fn truncate(&self, len: u64) -> Result<()> {
+        (**self).truncate(len)
+    }
+}
+
+pub struct MemoryRepo {
+    data: RefCell<Vec<Transaction>>,
+    /// The positions in the log of each client's events
+    by_client: RefCell<HashMap<u64, Vec<usize>>>,
+}
+
+impl MemoryRepo {
+    pub fn new() -> MemoryRepo {
+        MemoryRepo {
+            data: RefCell::new(Vec::new()),
+            by_client: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryRepo {
+    fn default() -> MemoryRepo {
+        MemoryRepo::new()
+    }
+}
+
+impl EventsRepo for MemoryRepo {
+    /// Appends a transaction to the end of the log, returning its sequence number
+    fn append(&self, transaction: Transaction) -> Result<u64> {
+        let mut data = self.data.borrow_mut();
+        self.by_client
+            .borrow_mut()
+            .entry(transaction.client)
+            .or_default()
+            .push(data.len());
+        data.push(transaction);
+        Ok(data.len() as u64)
+    }
+    /// Gets every event in the order it was appended
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        Ok(self.data.borrow().clone())
+    }
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        let data = self.data.borrow();
+        Ok(self
+            .by_client
+            .borrow()
+            .get(&client)
+            .map(|positions| positions.iter().map(|&i| data[i]).collect())
+            .unwrap_or_default())
+    }
+    fn truncate(&self, len: u64) -> Result<()> {
+        let len = len as usize;
+        self.data.borrow_mut().truncate(len);
+        self.by_client.borrow_mut().retain(|_, positions| {
+            positions.retain(|&i| i < len);
+            !positions.is_empty()
+        });
+        Ok(())
+    }
+}
+
+/// INDEXED_KEY marks a client index as complete, i.e. including every event appended before the
+/// index existed. It's shorter than any index key, so never mistaken for one.
+const INDEXED_KEY: &[u8] = b"indexed";
+
+/// index_key is the key of an event in the index of each client's events: the client followed by
+/// the event's key (its sequence number) in the log
+pub fn index_key(client: u64, seq: &[u8]) -> Vec<u8> {
+    let mut key = client.to_be_bytes().to_vec();
+    key.extend_from_slice(seq);
+    key
+}
+
+pub struct SledRepo {
+    tree: sled::Tree,
+    /// The sequence numbers of each client's events, keyed by `index_key`
+    by_client: sled::Tree,
+    cipher: Option<Cipher>,
+}
+
+impl SledRepo {
+    pub fn new(db: &sled::Db) -> Result<SledRepo> {
+        SledRepo::for_tenant(db, None)
+    }
+
+    /// for_tenant stores the tenant's events in their own tree, isolated from every other tenant's
+    pub fn for_tenant(db: &sled::Db, tenant: Option<TenantId>) -> Result<SledRepo> {
+        Ok(SledRepo {
+            tree: db.open_tree(tenant::tree_name(tenant, "events"))?,
+            by_client: db.open_tree(tenant::tree_name(tenant, "events_by_client"))?,
+            cipher: None,
+        })
+    }
+
+    /// reindex adds every event to the client index, e.g. those appended before it existed
+    fn reindex(&self) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for entry in self.tree.iter() {
+            let (seq, bytes) = entry?;
+            let event: Transaction = encryption::decode(self.cipher.as_ref(), &bytes)?;
+            batch.insert(index_key(event.client, &seq), &[]);
+        }
+        batch.insert(INDEXED_KEY, &[]);
+        self.by_client.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// encrypted makes the repo encrypt everything it stores with `cipher`
+    pub fn encrypted(self, cipher: Cipher) -> SledRepo {
+        SledRepo {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+}
+
+impl EventsRepo for SledRepo {
+    /// Appends a transaction to the end of the log, returning its sequence number
+    fn append(&self, transaction: Transaction) -> Result<u64> {
+        let seq = match self.tree.last()? {
+            Some((key, _)) => u64::from_be_bytes(key.as_ref().try_into()?) + 1,
+            None => 1,
+        };
+        let value = encryption::encode(self.cipher.as_ref(), &transaction)?;
+        let index = index_key(transaction.client, &seq.to_be_bytes());
+        (&self.tree, &self.by_client)
+            .transaction(|(tree, by_client)| {
+                tree.insert(&seq.to_be_bytes(), value.as_slice())?;
+                by_client.insert(index.as_slice(), &[])?;
+                Ok::<_, ConflictableTransactionError<()>>(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => anyhow::Error::from(e),
+                TransactionError::Abort(()) => anyhow::anyhow!("append aborted"),
+            })?;
+        Ok(seq)
+    }
+    /// Gets every event in the order it was appended
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        self.tree
+            .iter()
+            .values()
+            .map(|bytes| encryption::decode(self.cipher.as_ref(), &bytes?))
+            .collect()
+    }
+    /// Index entries for events which have since been erased, or merged into another client, are
+    /// skipped
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        if !self.by_client.contains_key(INDEXED_KEY)? {
+            self.reindex()?;
+        }
+        let mut events = Vec::new();
+        for key in self.by_client.scan_prefix(client.to_be_bytes()).keys() {
+            let key = key?;
+            if let Some(bytes) = self.tree.get(&key[8..])? {
+                let event: Transaction = encryption::decode(self.cipher.as_ref(), &bytes)?;
+                if event.client == client {
+                    events.push(event);
+                }
+            }
+        }
+        Ok(events)
+    }
+    fn truncate(&self, len: u64) -> Result<()> {
+        // sequence numbers start at 1, so the first `len` events are keyed 1..=len
+        for entry in self.tree.range((len + 1).to_be_bytes()..) {
+            let (seq, bytes) = entry?;
+            let event: Transaction = encryption::decode(self.cipher.as_ref(), &bytes)?;
+            self.by_client.remove(index_key(event.client, &seq))?;
+            self.tree.remove(seq)?;
+        }
+        Ok(())
+    }
+}
+
+/// OverlayRepo reads through to another repo but appends every event in memory, leaving the
+/// underlying repo untouched
+pub struct OverlayRepo {
+    inner: Box<dyn EventsRepo>,
+    inner_len: u64,
+    overlay: MemoryRepo,
+}
+
+impl OverlayRepo {
+    pub fn new(inner: Box<dyn EventsRepo>) -> Result<OverlayRepo> {
+        Ok(OverlayRepo {
+            inner_len: inner.get_all()?.len() as u64,
+            inner,
+            overlay: MemoryRepo::new(),
+        })
+    }
+}
+
+impl EventsRepo for OverlayRepo {
+    fn append(&self, transaction: Transaction) -> Result<u64> {
+        Ok(self.inner_len + self.overlay.append(transaction)?)
+    }
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        let mut events = self.inner.get_all()?;
+        events.extend(self.overlay.get_all()?);
+        Ok(events)
+    }
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        let mut events = self.inner.get_by_client(client)?;
+        events.extend(self.overlay.get_by_client(client)?);
+        Ok(events)
+    }
+    /// The underlying repo is left untouched, so only events appended to the overlay can be dropped
+    fn truncate(&self, len: u64) -> Result<()> {
+        if len < self.inner_len {
+            bail!(
+                "unable to truncate the event log to {} events, below the {} it's overlaid on",
+                len,
+                self.inner_len
+            );
+        }
+        self.overlay.truncate(len - self.inner_len)
+    }
+}
+
+/// account_as_of reconstructs a client's account by replaying the event log up to a point: up to
+/// and including the first event for a transaction id, or up to a timestamp (see `events_until`).
+/// Returns `None` when the client had no account at that point, or an error if the transaction
+/// isn't in the log.
+pub fn account_as_of(
+    events: &dyn EventsRepo,
+    client: u64,
+    as_of: Until,
+) -> Result<Option<Account>> {
+    let events = events.get_all()?;
+    let mut account: Option<Account> = None;
+    for &transaction in verify::events_until(&events, as_of)? {
+        if transaction.client == client {
+            account = Some(match account {
+                Some(acc) => acc.apply(transaction)?,
+                None => Account::new(transaction)?,
+            });
+        }
+    }
+    Ok(account)
+}
+
+/// BalanceChange is a single entry in a client's balance timeline, recording the effect of one
+/// transaction on the total balance and the resulting available/held balances.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BalanceChange {
+    pub client: u64,
+    pub tx: u64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub delta: Decimal,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// balance_history replays the event log and returns every client's balance timeline, grouped by
+/// client and in the order the transactions were applied
+pub fn balance_history(events: &dyn EventsRepo) -> Result<Vec<BalanceChange>> {
+    let mut accounts: HashMap<u64, Account> = HashMap::new();
+    let mut history = Vec::new();
+    for transaction in events.get_all()? {
+        let (previous, updated) = match accounts.get(&transaction.client) {
+            Some(acc) => (acc.total(), acc.apply(transaction)?),
+            None => (Decimal::from(0), Account::new(transaction)?),
+        };
+        history.push(BalanceChange {
+            client: transaction.client,
+            tx: transaction.tx,
+            kind: transaction.kind.to_string(),
+            delta: updated.total() - previous,
+            available: updated.available(),
+            held: updated.held(),
+            timestamp: transaction.timestamp,
+        });
+        accounts.insert(transaction.client, updated);
+    }
+    history.sort_by_key(|change| change.client);
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::TransactionKind;
+    use crate::verify::ReplayError;
+
+    fn deposit(tx: u64, client: u64, amount: i64) -> Transaction {
+        let amount = Decimal::from(amount);
+        Transaction {
+            tx,
+            client,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_append() -> Result<()> {
+        let repo = MemoryRepo::new();
+        assert_eq!(repo.append(deposit(1, 1, 10))?, 1);
+        assert_eq!(repo.append(deposit(2, 1, 10))?, 2);
+        let events = repo.get_all()?;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].tx, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let repos: Vec<Box<dyn EventsRepo>> =
+            vec![Box::new(MemoryRepo::new()), Box::new(SledRepo::new(&db)?)];
+        for repo in repos {
+            for tx in 1..=3 {
+                repo.append(deposit(tx, 1, 10))?;
+            }
+            repo.truncate(1)?;
+            assert_eq!(repo.append(deposit(4, 1, 10))?, 2);
+            let txs: Vec<u64> = repo.get_all()?.iter().map(|e| e.tx).collect();
+            assert_eq!(txs, vec![1, 4]);
+        }
+
+        let inner = MemoryRepo::new();
+        inner.append(deposit(1, 1, 10))?;
+        let overlay = OverlayRepo::new(Box::new(inner))?;
+        overlay.append(deposit(2, 1, 10))?;
+        overlay.truncate(1)?;
+        assert_eq!(overlay.get_all()?.len(), 1);
+        assert!(overlay.truncate(0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_by_client() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let repos: Vec<Box<dyn EventsRepo>> = vec![
+            Box::new(MemoryRepo::new()),
+            Box::new(SledRepo::new(&db)?),
+            Box::new(OverlayRepo::new(Box::new(MemoryRepo::new()))?),
+        ];
+        for repo in repos {
+            for (tx, client) in [(1, 1), (2, 2), (3, 1), (4, 1)] {
+                repo.append(deposit(tx, client, 10))?;
+            }
+            let txs = |client| -> Result<Vec<u64>> {
+                Ok(repo.get_by_client(client)?.iter().map(|e| e.tx).collect())
+            };
+            assert_eq!(txs(1)?, vec![1, 3, 4]);
+            assert_eq!(txs(2)?, vec![2]);
+            assert!(txs(3)?.is_empty());
+
+            repo.truncate(3)?;
+            assert_eq!(txs(1)?, vec![1, 3]);
+        }
+
+        // events appended before the index existed are indexed when it's first read
+        let db = sled::Config::new().temporary(true).open()?;
+        db.open_tree("events")?
+            .insert(1u64.to_be_bytes(), serde_json::to_vec(&deposit(1, 5, 10))?)?;
+        let repo = SledRepo::new(&db)?;
+        let events = repo.get_by_client(5)?;
+        assert_eq!((events.len(), events[0].tx), (1, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_as_of() -> Result<()> {
+        let repo = MemoryRepo::new();
+        repo.append(deposit(1, 1, 10))?;
+        repo.append(deposit(2, 2, 5))?;
+        repo.append(deposit(3, 1, 7))?;
+        repo.append(Transaction {
+            kind: TransactionKind::Dispute,
+            ..deposit(1, 1, 10)
+        })?;
+
+        let acc = account_as_of(&repo, 1, Until::Tx(2))?.unwrap();
+        assert_eq!(acc.available(), Decimal::from(10));
+
+        let acc = account_as_of(&repo, 1, Until::Tx(3))?.unwrap();
+        assert_eq!(acc.available(), Decimal::from(17));
+        assert_eq!(acc.held(), Decimal::from(0));
+
+        assert!(account_as_of(&repo, 2, Until::Tx(1))?.is_none());
+
+        let err = account_as_of(&repo, 1, Until::Tx(4)).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ReplayError::UnknownTx(4)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_balance_history() -> Result<()> {
+        let repo = MemoryRepo::new();
+        repo.append(deposit(1, 2, 10))?;
+        repo.append(deposit(2, 1, 5))?;
+        repo.append(Transaction {
+            kind: TransactionKind::Dispute,
+            ..deposit(1, 2, 10)
+        })?;
+        repo.append(Transaction {
+            kind: TransactionKind::ChargeBack,
+            ..deposit(1, 2, 10)
+        })?;
+
+        let history = balance_history(&repo)?;
+        let summary: Vec<(u64, u64, &str, Decimal, Decimal)> = history
+            .iter()
+            .map(|c| (c.client, c.tx, c.kind.as_str(), c.delta, c.held))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, 2, "deposit", Decimal::from(5), Decimal::from(0)),
+                (2, 1, "deposit", Decimal::from(10), Decimal::from(0)),
+                (2, 1, "dispute", Decimal::from(0), Decimal::from(10)),
+                (2, 1, "chargeback", Decimal::from(-10), Decimal::from(0)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sled_repo() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let repo = SledRepo::new(&db)?;
+        assert_eq!(repo.append(deposit(1, 1, 10))?, 1);
+        assert_eq!(repo.append(deposit(2, 1, 10))?, 2);
+        let events = repo.get_all()?;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].tx, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_repo() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        SledRepo::new(&db)?.append(deposit(1, 1, 10))?;
+
+        let repo = OverlayRepo::new(Box::new(SledRepo::new(&db)?))?;
+        assert_eq!(repo.append(deposit(2, 1, 10))?, 2);
+        assert_eq!(repo.get_all()?.len(), 2);
+        assert_eq!(SledRepo::new(&db)?.get_all()?.len(), 1);
+        Ok(())
+    }
+}
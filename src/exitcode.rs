@@ -0,0 +1,105 @@
+use crate::accounts::AccountError;
+use crate::beneficiaries::PayoutError;
+use crate::chaos::ChaosError;
+use crate::input::{InputError, RowError};
+use crate::invariants::InvariantError;
+use crate::kyc::KycError;
+use crate::payments::SaveError;
+use crate::reconcile::ReconcileError;
+use crate::rules::RuleError;
+use crate::scenarios::ScenarioError;
+use crate::simulation::SimulationError;
+use crate::threshold::ThresholdError;
+use crate::timestamps::TimestampError;
+use crate::transactions::TransactionError;
+use crate::verify::{ReplayError, VerifyError};
+
+/// FAILURE is the exit code for any failure which doesn't have a more specific code
+pub const FAILURE: i32 = 1;
+/// INPUT is the exit code when the input can't be read or parsed
+pub const INPUT: i32 = 2;
+/// STORAGE is the exit code when the database can't be read or written, or was left inconsistent
+pub const STORAGE: i32 = 3;
+/// REJECTED is the exit code when the run is aborted due to rejected rows, either because the
+/// error count or rate threshold was exceeded or on the first rejection in strict mode
+pub const REJECTED: i32 = 4;
+/// MISMATCH is the exit code when reconciliation, an audit, a simulation or a scenario finds
+/// discrepancies
+pub const MISMATCH: i32 = 5;
+
+/// exit_code classifies an error into the exit code for its failure class, based on the most
+/// specific error in its chain
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if cause.is::<ThresholdError>() {
+            return REJECTED;
+        }
+        if cause.is::<ReconcileError>()
+            || cause.is::<VerifyError>()
+            || cause.is::<SimulationError>()
+            || matches!(cause.downcast_ref(), Some(ScenarioError::Failed { .. }))
+        {
+            return MISMATCH;
+        }
+        if cause.is::<sled::Error>() || cause.is::<SaveError>() || cause.is::<ChaosError>() {
+            return STORAGE;
+        }
+        if cause.is::<InputError>()
+            || cause.is::<csv::Error>()
+            || cause.is::<RowError>()
+            || cause.is::<ScenarioError>()
+            || cause.is::<ReplayError>()
+        {
+            return INPUT;
+        }
+        // only surfaces as an error in strict mode, where the first rejection aborts the run
+        if cause.is::<AccountError>()
+            || cause.is::<TransactionError>()
+            || cause.is::<RuleError>()
+            || cause.is::<KycError>()
+            || cause.is::<PayoutError>()
+            || cause.is::<TimestampError>()
+            || cause.is::<InvariantError>()
+        {
+            return REJECTED;
+        }
+    }
+    FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_exit_code() {
+        let threshold: anyhow::Error = ThresholdError::TooManyErrors {
+            rejected: 2,
+            max: 1,
+        }
+        .into();
+        assert_eq!(exit_code(&threshold), REJECTED);
+        assert_eq!(
+            exit_code(&anyhow::Error::from(ReconcileError::Mismatch(1))),
+            MISMATCH
+        );
+        let storage = anyhow::Error::from(sled::Error::Unsupported("x".to_string()));
+        assert_eq!(exit_code(&storage.context("opening database")), STORAGE);
+        let strict = anyhow::Error::from(AccountError::InsufficientFunds)
+            .context("unable to process transaction tx=1 client=1 at line 2");
+        assert_eq!(exit_code(&strict), REJECTED);
+        let parse = Err::<(), _>(InputError::UnknownType("refund".to_string()))
+            .context("unable to parse row at line 2")
+            .unwrap_err();
+        assert_eq!(exit_code(&parse), INPUT);
+        let scenarios = ScenarioError::Failed {
+            failed: 1,
+            total: 2,
+        };
+        assert_eq!(exit_code(&scenarios.into()), MISMATCH);
+        let empty = ScenarioError::NoScenarios("corpus".to_string());
+        assert_eq!(exit_code(&empty.into()), INPUT);
+        assert_eq!(exit_code(&anyhow!("something else")), FAILURE);
+    }
+}
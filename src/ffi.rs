@@ -0,0 +1,316 @@
+//! A C API for embedding the engine, declared in `include/payments.h`. An engine keeps its state
+//! in memory, and is driven one transaction at a time:
+//!
+//! ```c
+//! payments_engine *engine = payments_engine_new();
+//! if (payments_submit(engine, "deposit", 1, 1, "10.5") != PAYMENTS_OK) {
+//!     fprintf(stderr, "%s\n", payments_last_error(engine));
+//! }
+//! payments_buffer statements = payments_statements(engine);
+//! fwrite(statements.data, 1, statements.len, stdout);
+//! payments_buffer_free(statements);
+//! payments_engine_free(engine);
+//! ```
+//!
+//! Functions never unwind into the caller: a panic is reported as `PAYMENTS_PANIC`.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+
+use crate::accounts::{AccountStatement, AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+use crate::errorlog;
+use crate::events::MemoryRepo as EventsMemoryRepo;
+use crate::payments::PaymentsEngine;
+use crate::transactions::{
+    MemoryRepo as TransactionsMemoryRepo, TransactionCommand, TransactionKind,
+};
+
+/// payments_status is the outcome of a call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum payments_status {
+    PAYMENTS_OK = 0,
+    /// The transaction was rejected by the engine, e.g. for insufficient funds
+    PAYMENTS_REJECTED = 1,
+    /// A pointer was null, or the type or amount couldn't be parsed
+    PAYMENTS_INVALID_ARGUMENT = 2,
+    /// The engine panicked, which is a bug. The engine should be freed.
+    PAYMENTS_PANIC = 3,
+}
+
+/// payments_buffer is a byte buffer owned by the library, which must be released with
+/// `payments_buffer_free`
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct payments_buffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl payments_buffer {
+    fn empty() -> payments_buffer {
+        payments_buffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+/// payments_engine is an engine along with the in-memory repositories holding its state. It's
+/// opaque to C.
+#[allow(non_camel_case_types)]
+pub struct payments_engine {
+    transactions: TransactionsMemoryRepo,
+    accounts: AccountsMemoryRepo,
+    events: EventsMemoryRepo,
+    /// The reason the last call failed, if it did
+    error: Option<CString>,
+}
+
+impl payments_engine {
+    fn engine(&self) -> PaymentsEngine<'_, '_, '_> {
+        PaymentsEngine::new(&self.transactions, &self.accounts, &self.events)
+    }
+
+    /// fail records the reason for a failed call, returning its status
+    fn fail(&mut self, status: payments_status, error: &anyhow::Error) -> payments_status {
+        // a message with an interior nul is truncated rather than lost
+        let message = format!("{}: {:#}", errorlog::code(error), error);
+        let message = message.split('\0').next().unwrap_or_default();
+        self.error = CString::new(message).ok();
+        status
+    }
+}
+
+/// command builds a transaction command from the C arguments, mirroring the gRPC API. Payouts
+/// aren't supported, since there's no way to register beneficiaries.
+///
+/// # Safety
+/// `kind` must be a valid C string, and `amount` either null or a valid C string.
+unsafe fn command(
+    kind: *const c_char,
+    client: u64,
+    tx: u64,
+    amount: *const c_char,
+) -> Result<TransactionCommand> {
+    let kind = CStr::from_ptr(kind).to_str()?;
+    let amount = || -> Result<Decimal> {
+        if amount.is_null() {
+            return Err(anyhow!("amount is required for a {}", kind));
+        }
+        Ok(Decimal::from_str(CStr::from_ptr(amount).to_str()?.trim())?)
+    };
+    let kind = match TransactionKind::canonical_name(kind) {
+        Some("deposit") => TransactionKind::Deposit { amount: amount()? },
+        Some("withdrawal") => TransactionKind::Withdrawal { amount: amount()? },
+        Some("dispute") => TransactionKind::Dispute,
+        Some("resolve") => TransactionKind::Resolve,
+        Some("chargeback") => TransactionKind::ChargeBack,
+        Some("release") => TransactionKind::Release,
+        Some("refund") => TransactionKind::Refund,
+        Some("settle") => TransactionKind::Settle { withdrawal: false },
+        Some("fail") => TransactionKind::Fail { withdrawal: false },
+        _ => return Err(anyhow!("unsupported transaction type {:?}", kind)),
+    };
+    Ok(TransactionCommand {
+        kind,
+        tx,
+        client,
+        correlation: None,
+        tenant: None,
+        timestamp: None,
+    })
+}
+
+/// statements writes every account's statement as CSV, as the CLI does
+fn statements(accounts: &dyn AccountsRepo) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for account in accounts.get_all()? {
+        writer.serialize(AccountStatement::from(account))?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// payments_engine_new creates an engine with empty in-memory state, which must be released with
+/// `payments_engine_free`
+#[no_mangle]
+pub extern "C" fn payments_engine_new() -> *mut payments_engine {
+    Box::into_raw(Box::new(payments_engine {
+        transactions: TransactionsMemoryRepo::new(),
+        accounts: AccountsMemoryRepo::new(),
+        events: EventsMemoryRepo::new(),
+        error: None,
+    }))
+}
+
+/// payments_engine_free releases an engine and all of its state
+///
+/// # Safety
+/// `engine` must be null or have been returned by `payments_engine_new`, and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn payments_engine_free(engine: *mut payments_engine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// payments_submit applies a transaction of the given type (e.g. "deposit", "dispute") to the
+/// engine. The amount is a decimal string, and may be null for types which don't take one. On
+/// failure the reason is available from `payments_last_error`.
+///
+/// # Safety
+/// `engine` must be a live engine, `kind` a valid C string and `amount` null or a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn payments_submit(
+    engine: *mut payments_engine,
+    kind: *const c_char,
+    client: u64,
+    tx: u64,
+    amount: *const c_char,
+) -> payments_status {
+    let engine = match engine.as_mut() {
+        Some(engine) => engine,
+        None => return payments_status::PAYMENTS_INVALID_ARGUMENT,
+    };
+    engine.error = None;
+    if kind.is_null() {
+        let error = anyhow!("transaction type is required");
+        return engine.fail(payments_status::PAYMENTS_INVALID_ARGUMENT, &error);
+    }
+    let command = match command(kind, client, tx, amount) {
+        Ok(command) => command,
+        Err(e) => return engine.fail(payments_status::PAYMENTS_INVALID_ARGUMENT, &e),
+    };
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        engine.engine().process_transaction(command)
+    }));
+    match res {
+        Ok(Ok(_)) => payments_status::PAYMENTS_OK,
+        Ok(Err(e)) => engine.fail(payments_status::PAYMENTS_REJECTED, &e),
+        Err(_) => engine.fail(payments_status::PAYMENTS_PANIC, &anyhow!("engine panicked")),
+    }
+}
+
+/// payments_last_error is the reason the last call on the engine failed, prefixed by its error
+/// code (e.g. "insufficient_funds: insufficient funds"), or null if it succeeded. The string is
+/// owned by the engine, and valid until the next call on it.
+///
+/// # Safety
+/// `engine` must be null or a live engine.
+#[no_mangle]
+pub unsafe extern "C" fn payments_last_error(engine: *const payments_engine) -> *const c_char {
+    match engine.as_ref().and_then(|e| e.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// payments_statements writes every account's statement as CSV (with client, available, held,
+/// total & locked columns) into a buffer, which is empty if the engine is null or they couldn't
+/// be written
+///
+/// # Safety
+/// `engine` must be null or a live engine.
+#[no_mangle]
+pub unsafe extern "C" fn payments_statements(engine: *mut payments_engine) -> payments_buffer {
+    let engine = match engine.as_mut() {
+        Some(engine) => engine,
+        None => return payments_buffer::empty(),
+    };
+    engine.error = None;
+    let res = panic::catch_unwind(AssertUnwindSafe(|| statements(&engine.accounts)));
+    let bytes = match res {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => {
+            engine.fail(payments_status::PAYMENTS_REJECTED, &e);
+            return payments_buffer::empty();
+        }
+        Err(_) => {
+            engine.fail(payments_status::PAYMENTS_PANIC, &anyhow!("engine panicked"));
+            return payments_buffer::empty();
+        }
+    };
+    let bytes = bytes.into_boxed_slice();
+    let len = bytes.len();
+    payments_buffer {
+        data: Box::into_raw(bytes) as *mut u8,
+        len,
+    }
+}
+
+/// payments_buffer_free releases a buffer returned by the library
+///
+/// # Safety
+/// `buffer` must have been returned by the library, and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn payments_buffer_free(buffer: payments_buffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        let c = |s: &str| CString::new(s).unwrap();
+        unsafe {
+            let engine = payments_engine_new();
+            let submit = |kind: &str, client, tx, amount: Option<&str>| {
+                let amount = amount.map(c);
+                payments_submit(
+                    engine,
+                    c(kind).as_ptr(),
+                    client,
+                    tx,
+                    amount.as_ref().map_or(ptr::null(), |a| a.as_ptr()),
+                )
+            };
+            assert_eq!(
+                submit("deposit", 1, 1, Some("10.5")),
+                payments_status::PAYMENTS_OK
+            );
+            assert!(payments_last_error(engine).is_null());
+            assert_eq!(
+                submit("withdrawal", 1, 2, Some("20")),
+                payments_status::PAYMENTS_REJECTED
+            );
+            assert_eq!(
+                CStr::from_ptr(payments_last_error(engine))
+                    .to_str()
+                    .unwrap(),
+                "insufficient_funds: insufficient funds"
+            );
+            assert_eq!(
+                submit("deposit", 1, 3, None),
+                payments_status::PAYMENTS_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                submit("transfer", 1, 3, Some("1")),
+                payments_status::PAYMENTS_INVALID_ARGUMENT
+            );
+            assert_eq!(submit("dispute", 1, 1, None), payments_status::PAYMENTS_OK);
+
+            let buffer = payments_statements(engine);
+            let statements = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(
+                std::str::from_utf8(statements).unwrap(),
+                "client,available,held,total,locked\n1,0.0,10.5,10.5,false\n"
+            );
+            payments_buffer_free(buffer);
+            payments_engine_free(engine);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Instant;
+
+use crate::errorlog;
+use crate::input::{CommandReader, InputConfig, RowError};
+use crate::payments::PaymentsEngine;
+use crate::summary::Summary;
+
+fn reject(summary: &mut Summary, error: &anyhow::Error) {
+    *summary.rejected.entry(errorlog::code(error)).or_default() += 1;
+}
+
+/// process_bytes processes a transactions file held in memory through the engine, counting every
+/// row it rejects by error code in the summary rather than failing the run. However malformed the
+/// input, it never panics or returns an error, which makes it a fuzz target (see `fuzz/`) for the
+/// whole path from raw bytes to account state.
+///
+/// Input which can't be read as CSV at all (e.g. an unreadable header row) counts as a single
+/// rejection, with no rows read.
+pub fn process_bytes(input: &[u8], engine: &PaymentsEngine) -> Summary {
+    let start = Instant::now();
+    let mut summary = Summary::default();
+    let reader = match CommandReader::from_reader(input, InputConfig::default()) {
+        Ok(reader) => reader,
+        Err(e) => {
+            reject(&mut summary, &e);
+            return summary;
+        }
+    };
+    for row in reader {
+        summary.rows += 1;
+        match row {
+            Ok(row) => match engine.process_transaction(row.command) {
+                Ok(flags) => {
+                    summary.accept(&row.command);
+                    summary.flagged.extend(flags);
+                }
+                Err(e) => reject(&mut summary, &e),
+            },
+            Err(RowError {
+                record: None,
+                error,
+                ..
+            }) => {
+                // the rest of the input can't be read
+                reject(&mut summary, &error);
+                break;
+            }
+            Err(RowError { error, .. }) => reject(&mut summary, &error),
+        }
+    }
+    summary.elapsed = start.elapsed();
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::{AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::transactions::MemoryRepo as TransactionsMemoryRepo;
+    use rand::prelude::*;
+    use rand_chacha::ChaCha8Rng;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_process_bytes() {
+        let transactions = TransactionsMemoryRepo::new();
+        let accounts = AccountsMemoryRepo::new();
+        let events = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+
+        let input = b"type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,20\nrefund,1,3,\nwithdrawal,1,4,2.5\n\xff,1,5,1\n";
+        let summary = process_bytes(input, &engine);
+        assert_eq!((summary.rows, summary.accepted), (5, 2));
+        assert_eq!(summary.rejected.get("insufficient_funds"), Some(&1));
+        assert_eq!(summary.rejected.get("invalid_utf8"), Some(&1));
+        assert_eq!(summary.rejected.values().sum::<u64>(), 3);
+        assert_eq!(summary.deposits, Decimal::from(10));
+        assert_eq!(
+            accounts.get(1).unwrap().map(|a| a.available()),
+            Some(Decimal::new(75, 1))
+        );
+    }
+
+    #[test]
+    fn test_process_garbage() {
+        let transactions = TransactionsMemoryRepo::new();
+        let accounts = AccountsMemoryRepo::new();
+        let events = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+
+        // mutations of a valid file, & random bytes, must be rejected without panicking
+        let valid = b"type,client,tx,amount\ndeposit,1,1,1.5\ndispute,1,1,\nchargeback,1,1,\n";
+        let alphabet = b"type,clientxamoun\n\r\"0123456789.-e \xff\x00";
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..2000 {
+            let mut input = valid.to_vec();
+            for _ in 0..rng.gen_range(1..8) {
+                let i = rng.gen_range(0..input.len());
+                let byte = *alphabet.choose(&mut rng).unwrap();
+                match rng.gen_range(0..3) {
+                    0 => input[i] = byte,
+                    1 => input.insert(i, byte),
+                    _ => {
+                        input.remove(i);
+                    }
+                }
+            }
+            process_bytes(&input, &engine);
+        }
+        // amounts at the limit of what a decimal can hold
+        let huge = "50000000000000000000000000000.5";
+        let input = format!(
+            "type,client,tx,amount\ndeposit,9,100,{huge}\ndeposit,9,101,{huge}\ndeposit,8,102,{huge}\n"
+        );
+        let summary = process_bytes(input.as_bytes(), &engine);
+        assert_eq!(summary.rejected.get("balance_overflow"), Some(&1));
+        assert_eq!(summary.deposits, Decimal::max_value());
+
+        let noise: Vec<u8> = (0..4096).map(|_| rng.gen()).collect();
+        process_bytes(&noise, &engine);
+        process_bytes(b"", &engine);
+    }
+}
@@ -0,0 +1,250 @@
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::transactions::{TransactionCommand, TransactionKind};
+
+/// GeneratorConfig controls the shape of the generated transaction stream
+#[derive(Debug, Clone, Copy)]
+pub struct GeneratorConfig {
+    pub clients: u64,
+    pub rows: u64,
+    /// Probability of any given row disputing a previous deposit
+    pub dispute_rate: f64,
+    /// Probability of any given row being one which the engine should reject
+    pub invalid_rate: f64,
+    pub seed: u64,
+}
+
+/// Generator produces a reproducible stream of transaction commands for load testing & demos.
+///
+/// Deposits and withdrawals make up the bulk of the stream. Disputes reference earlier deposits
+/// and are later resolved or charged back, so complete dispute chains are exercised. A fraction
+/// of rows are deliberately invalid (negative amounts, disputes of unknown transactions and
+/// resolves of undisputed transactions).
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: ChaCha8Rng,
+    generated: u64,
+    next_tx: u64,
+    deposits: Vec<(u64, u64)>,
+    disputes: Vec<(u64, u64)>,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Generator {
+        Generator {
+            config,
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            generated: 0,
+            next_tx: 1,
+            deposits: Vec::new(),
+            disputes: Vec::new(),
+        }
+    }
+
+    fn client(&mut self) -> u64 {
+        self.rng.gen_range(1..=self.config.clients.max(1))
+    }
+
+    fn amount(&mut self) -> Decimal {
+        Decimal::new(self.rng.gen_range(1..10_000_000), 4)
+    }
+
+    fn tx(&mut self) -> u64 {
+        let tx = self.next_tx;
+        self.next_tx += 1;
+        tx
+    }
+
+    fn invalid(&mut self) -> TransactionCommand {
+        match self.rng.gen_range(0..3) {
+            0 => {
+                let amount = -self.amount();
+                TransactionCommand {
+                    kind: TransactionKind::Deposit { amount },
+                    tx: self.tx(),
+                    client: self.client(),
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                }
+            }
+            1 => TransactionCommand {
+                kind: TransactionKind::Dispute,
+                tx: self.next_tx + self.rng.gen_range(1_000_000..2_000_000),
+                client: self.client(),
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            },
+            _ => match self.deposits.choose(&mut self.rng) {
+                Some(&(tx, client)) => TransactionCommand {
+                    kind: TransactionKind::Resolve,
+                    tx,
+                    client,
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                },
+                None => TransactionCommand {
+                    kind: TransactionKind::Resolve,
+                    tx: self.next_tx,
+                    client: self.client(),
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                },
+            },
+        }
+    }
+
+    fn dispute(&mut self) -> Option<TransactionCommand> {
+        if self.deposits.is_empty() {
+            return None;
+        }
+        let i = self.rng.gen_range(0..self.deposits.len());
+        let (tx, client) = self.deposits.swap_remove(i);
+        self.disputes.push((tx, client));
+        Some(TransactionCommand {
+            kind: TransactionKind::Dispute,
+            tx,
+            client,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })
+    }
+
+    fn settle(&mut self) -> Option<TransactionCommand> {
+        if self.disputes.is_empty() {
+            return None;
+        }
+        let i = self.rng.gen_range(0..self.disputes.len());
+        let (tx, client) = self.disputes.swap_remove(i);
+        let kind = if self.rng.gen_bool(0.5) {
+            TransactionKind::Resolve
+        } else {
+            TransactionKind::ChargeBack
+        };
+        Some(TransactionCommand {
+            kind,
+            tx,
+            client,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })
+    }
+
+    fn payment(&mut self) -> TransactionCommand {
+        let tx = self.tx();
+        let client = self.client();
+        let amount = self.amount();
+        if self.rng.gen_bool(0.6) {
+            self.deposits.push((tx, client));
+            TransactionCommand {
+                kind: TransactionKind::Deposit { amount },
+                tx,
+                client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            }
+        } else {
+            TransactionCommand {
+                kind: TransactionKind::Withdrawal { amount },
+                tx,
+                client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            }
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = TransactionCommand;
+
+    fn next(&mut self) -> Option<TransactionCommand> {
+        if self.generated >= self.config.rows {
+            return None;
+        }
+        self.generated += 1;
+
+        let roll: f64 = self.rng.gen();
+        let command = if roll < self.config.invalid_rate {
+            Some(self.invalid())
+        } else if roll < self.config.invalid_rate + self.config.dispute_rate {
+            self.dispute()
+        } else if roll < self.config.invalid_rate + self.config.dispute_rate * 2.0 {
+            self.settle()
+        } else {
+            None
+        };
+        Some(command.unwrap_or_else(|| self.payment()))
+    }
+}
+
+/// CommandRecord is the CSV representation of a transaction command, matching the input format
+#[derive(Debug, Serialize)]
+pub struct CommandRecord {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub client: u64,
+    pub tx: u64,
+    pub amount: Option<Decimal>,
+}
+
+impl From<TransactionCommand> for CommandRecord {
+    fn from(command: TransactionCommand) -> CommandRecord {
+        let amount = match command.kind {
+            TransactionKind::Deposit { amount } | TransactionKind::Withdrawal { amount } => {
+                Some(amount)
+            }
+            _ => None,
+        };
+        CommandRecord {
+            kind: command.kind.to_string(),
+            client: command.client,
+            tx: command.tx,
+            amount,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(seed: u64) -> GeneratorConfig {
+        GeneratorConfig {
+            clients: 10,
+            rows: 1000,
+            dispute_rate: 0.05,
+            invalid_rate: 0.01,
+            seed,
+        }
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let a: Vec<TransactionCommand> = Generator::new(config(42)).collect();
+        let b: Vec<TransactionCommand> = Generator::new(config(42)).collect();
+        let c: Vec<TransactionCommand> = Generator::new(config(43)).collect();
+        assert_eq!(a.len(), 1000);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_dispute_chains() {
+        let commands: Vec<TransactionCommand> = Generator::new(config(1)).collect();
+        let count = |kind: TransactionKind| commands.iter().filter(|c| c.kind == kind).count();
+        assert!(count(TransactionKind::Dispute) > 0);
+        assert!(count(TransactionKind::Resolve) + count(TransactionKind::ChargeBack) > 0);
+        assert!(commands.iter().all(|c| c.client >= 1 && c.client <= 10));
+    }
+}
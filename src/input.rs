@@ -0,0 +1,996 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use std::borrow::Cow;
+use std::mem;
+use std::str::FromStr;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use csv::{ByteRecord, StringRecord};
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::correlation::CorrelationId;
+use crate::tenant::TenantId;
+use crate::transactions::{TransactionCommand, TransactionKind};
+
+/// HEADERS is the column order of a transactions file which has no header row
+pub const HEADERS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// CORRELATION_HEADER is the optional column holding the ID which traces a payment across systems
+pub const CORRELATION_HEADER: &str = "correlation_id";
+
+/// TENANT_HEADER is the optional column holding the tenant a row belongs to
+pub const TENANT_HEADER: &str = "tenant";
+
+/// SIGNATURE_HEADER is the optional column holding the row's HMAC, see [crate::signature]
+pub const SIGNATURE_HEADER: &str = "signature";
+
+/// TIMESTAMP_HEADER is the optional column holding when the row happened, in RFC 3339 format
+/// (e.g. `2024-01-31T09:30:00Z`)
+pub const TIMESTAMP_HEADER: &str = "timestamp";
+
+/// BENEFICIARY_HEADER is the optional column holding the registered beneficiary of a payout
+pub const BENEFICIARY_HEADER: &str = "beneficiary";
+
+/// STATUS_HEADER is the optional column holding whether a deposit or withdrawal is `pending`
+/// until it's settled or failed, or `settled` straight away (the default if empty)
+pub const STATUS_HEADER: &str = "status";
+
+/// OPTIONAL_HEADERS are the columns which may be present besides [HEADERS]
+const OPTIONAL_HEADERS: [&str; 6] = [
+    CORRELATION_HEADER,
+    TENANT_HEADER,
+    SIGNATURE_HEADER,
+    BENEFICIARY_HEADER,
+    TIMESTAMP_HEADER,
+    STATUS_HEADER,
+];
+
+#[derive(Error, Debug, PartialEq)]
+pub enum InputError {
+    #[error("unknown column {0:?}")]
+    UnknownColumn(String),
+    #[error("expected {expected} fields, found {found}")]
+    UnexpectedFieldCount { expected: usize, found: usize },
+    #[error("missing field `{0}`")]
+    MissingField(&'static str),
+    #[error("invalid {field} {value:?}")]
+    InvalidField { field: &'static str, value: String },
+    #[error("unknown transaction type {0:?}")]
+    UnknownType(String),
+}
+
+impl InputError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            InputError::UnknownColumn(_) => "unknown_column",
+            InputError::UnexpectedFieldCount { .. } => "unexpected_field_count",
+            InputError::MissingField(_) => "missing_field",
+            InputError::InvalidField { .. } => "invalid_field",
+            InputError::UnknownType(_) => "unknown_type",
+        }
+    }
+}
+
+/// InputConfig controls how a transactions file is read
+#[derive(Debug, Clone, Copy)]
+pub struct InputConfig {
+    pub has_headers: bool,
+    pub delimiter: u8,
+    pub quote: u8,
+    pub escape: Option<u8>,
+    /// Amounts use a comma as their decimal mark, e.g. `1,5`
+    pub decimal_comma: bool,
+    /// Disables the leniency towards messy input. By default whitespace around fields is
+    /// trimmed, unknown columns are ignored, rows may have differing numbers of fields and
+    /// transaction types are matched regardless of casing or known aliases.
+    pub strict: bool,
+    /// Parses fields directly rather than deserializing each row with serde, which is
+    /// considerably faster on large files
+    pub fast: bool,
+}
+
+impl Default for InputConfig {
+    fn default() -> InputConfig {
+        InputConfig {
+            has_headers: true,
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            decimal_comma: false,
+            strict: false,
+            fast: false,
+        }
+    }
+}
+
+/// Position locates a row within the input
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Position {
+    pub line: u64,
+    pub byte: u64,
+}
+
+/// Row is a transaction command along with the raw record it was parsed from
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub command: TransactionCommand,
+    pub position: Position,
+    pub record: StringRecord,
+}
+
+/// RowError is an error reading or parsing a single row, located within the input
+#[derive(Error, Debug)]
+#[error("line {}: {error}", .position.line)]
+pub struct RowError {
+    pub position: Position,
+    /// The fields of the row, when it could be read at all
+    pub record: Option<StringRecord>,
+    pub error: anyhow::Error,
+}
+
+/// raw formats a record's fields as a single CSV line, as close as possible to how it was read
+pub fn raw(record: &StringRecord) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    if writer.write_record(record).is_err() {
+        return record.iter().collect::<Vec<&str>>().join(",");
+    }
+    let bytes = writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+fn csv_reader<R: io::Read>(reader: R, config: InputConfig) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .has_headers(config.has_headers)
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .escape(config.escape)
+        .double_quote(config.escape.is_none())
+        .flexible(true)
+        .from_reader(reader)
+}
+
+/// CommandReader reads transaction commands from CSV input
+pub struct CommandReader<R> {
+    reader: csv::Reader<R>,
+    headers: StringRecord,
+    kind: Option<usize>,
+    client: Option<usize>,
+    tx: Option<usize>,
+    amount: Option<usize>,
+    beneficiary: Option<usize>,
+    correlation: Option<usize>,
+    tenant: Option<usize>,
+    timestamp: Option<usize>,
+    status: Option<usize>,
+    config: InputConfig,
+    buffer: ByteRecord,
+    scratch: StringRecord,
+    /// Added to the position of every row, when reading from part way through the input
+    offset: Position,
+}
+
+impl CommandReader<File> {
+    pub fn from_path<P: AsRef<Path>>(path: P, config: InputConfig) -> Result<CommandReader<File>> {
+        CommandReader::from_reader(File::open(path)?, config)
+    }
+}
+
+impl<R: io::Read> CommandReader<R> {
+    pub fn from_reader(reader: R, config: InputConfig) -> Result<CommandReader<R>> {
+        let mut reader = csv_reader(reader, config);
+        let headers = if config.has_headers {
+            reader.headers()?.clone()
+        } else {
+            StringRecord::from(HEADERS.to_vec())
+        };
+        CommandReader::new(reader, headers, config)
+    }
+
+    /// from_chunk reads a chunk of the input which starts on a record boundary at `offset`,
+    /// where rows are deserialized with the headers already read from the start of the input
+    pub fn from_chunk(
+        reader: R,
+        config: InputConfig,
+        headers: StringRecord,
+        offset: Position,
+    ) -> Result<CommandReader<R>> {
+        let config = InputConfig {
+            has_headers: false,
+            ..config
+        };
+        let mut reader = CommandReader::new(csv_reader(reader, config), headers, config)?;
+        reader.offset = offset;
+        Ok(reader)
+    }
+
+    fn new(
+        reader: csv::Reader<R>,
+        mut headers: StringRecord,
+        config: InputConfig,
+    ) -> Result<CommandReader<R>> {
+        if !config.strict {
+            headers.trim();
+        }
+        if config.strict {
+            if let Some(unknown) = headers
+                .iter()
+                .find(|h| !HEADERS.contains(h) && !OPTIONAL_HEADERS.contains(h))
+            {
+                return Err(InputError::UnknownColumn(unknown.to_string()).into());
+            }
+        }
+        Ok(CommandReader {
+            kind: headers.iter().position(|h| h == "type"),
+            client: headers.iter().position(|h| h == "client"),
+            tx: headers.iter().position(|h| h == "tx"),
+            amount: headers.iter().position(|h| h == "amount"),
+            beneficiary: headers.iter().position(|h| h == BENEFICIARY_HEADER),
+            correlation: headers.iter().position(|h| h == CORRELATION_HEADER),
+            tenant: headers.iter().position(|h| h == TENANT_HEADER),
+            timestamp: headers.iter().position(|h| h == TIMESTAMP_HEADER),
+            status: headers.iter().position(|h| h == STATUS_HEADER),
+            reader,
+            headers,
+            config,
+            buffer: ByteRecord::new(),
+            scratch: StringRecord::new(),
+            offset: Position::default(),
+        })
+    }
+
+    /// position returns the position of the next row to be read
+    pub fn position(&self) -> Position {
+        self.locate(self.reader.position())
+    }
+
+    fn locate(&self, position: &csv::Position) -> Position {
+        Position {
+            line: self.offset.line + position.line(),
+            byte: self.offset.byte + position.byte(),
+        }
+    }
+
+    /// headers returns the column names which rows are deserialized with
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    fn check_field_count(&self, record: &StringRecord) -> Result<(), InputError> {
+        if self.config.strict && record.len() != self.headers.len() {
+            return Err(InputError::UnexpectedFieldCount {
+                expected: self.headers.len(),
+                found: record.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// parse deserializes a record. Unless the record can be deserialized as is, its fields are
+    /// trimmed & normalized into a scratch record which is reused between rows, rather than
+    /// allocating a new record each time.
+    fn parse(&mut self, record: &StringRecord) -> Result<TransactionCommand> {
+        self.check_field_count(record)?;
+        if self.config.strict
+            && !self.config.decimal_comma
+            && self.correlation.is_none()
+            && self.tenant.is_none()
+        {
+            let command = record.deserialize(Some(&self.headers))?;
+            return Ok(self.check_input_kind(record, command)?);
+        }
+
+        let mut scratch = mem::take(&mut self.scratch);
+        scratch.clear();
+        for (i, field) in record.iter().enumerate() {
+            // the correlation ID & tenant are read separately, and would be mistaken for numbers
+            // by serde
+            if Some(i) == self.correlation || Some(i) == self.tenant {
+                scratch.push_field("");
+                continue;
+            }
+            let field = if self.config.strict {
+                field
+            } else {
+                field.trim()
+            };
+            scratch.push_field(&self.normalize(i, field));
+        }
+        // rows with missing trailing fields are padded, so that they are deserialized as empty
+        while scratch.len() < self.headers.len() {
+            scratch.push_field("");
+        }
+        let command = scratch.deserialize(Some(&self.headers));
+        self.scratch = scratch;
+        Ok(self.check_input_kind(record, command?)?)
+    }
+
+    /// check_input_kind rejects the types which are only ever recorded by the engine (e.g.
+    /// `pending_deposit` or `force_resolve`), which serde would otherwise deserialize as readily
+    /// as the input types, so that they're rejected as by `parse_fast`
+    fn check_input_kind(
+        &self,
+        record: &StringRecord,
+        command: TransactionCommand,
+    ) -> Result<TransactionCommand, InputError> {
+        let name = command.kind.to_string();
+        if TransactionKind::canonical_name(&name) == Some(name.as_str()) {
+            return Ok(command);
+        }
+        let field = self.kind.and_then(|i| record.get(i)).unwrap_or(&name);
+        Err(InputError::UnknownType(field.to_string()))
+    }
+
+    /// parse_fast parses a record by reading its fields directly, accepting the same input as
+    /// `parse` without deserializing or allocating per field
+    fn parse_fast(&self, record: &StringRecord) -> Result<TransactionCommand, InputError> {
+        self.check_field_count(record)?;
+        let field = |index: Option<usize>| -> &str {
+            let field = index.and_then(|i| record.get(i)).unwrap_or("");
+            if self.config.strict {
+                field
+            } else {
+                field.trim()
+            }
+        };
+
+        let kind = field(self.kind);
+        let name = match kind {
+            "deposit" | "withdrawal" | "payout" | "dispute" | "resolve" | "chargeback"
+            | "release" | "refund" | "settle" | "fail" => Some(kind),
+            "" => return Err(InputError::MissingField("type")),
+            _ if self.config.strict => None,
+            _ => TransactionKind::canonical_name(kind),
+        };
+        let client = parse_number(field(self.client), "client")?;
+        let tx = parse_number(field(self.tx), "tx")?;
+        let kind = match name {
+            Some("deposit") => TransactionKind::Deposit {
+                amount: self.parse_amount(field(self.amount))?,
+            },
+            Some("withdrawal") => TransactionKind::Withdrawal {
+                amount: self.parse_amount(field(self.amount))?,
+            },
+            Some("payout") => TransactionKind::Payout {
+                amount: self.parse_amount(field(self.amount))?,
+                beneficiary: parse_number(field(self.beneficiary), BENEFICIARY_HEADER)?,
+            },
+            Some("dispute") => TransactionKind::Dispute,
+            Some("resolve") => TransactionKind::Resolve,
+            Some("chargeback") => TransactionKind::ChargeBack,
+            Some("release") => TransactionKind::Release,
+            Some("refund") => TransactionKind::Refund,
+            Some("settle") => TransactionKind::Settle { withdrawal: false },
+            Some("fail") => TransactionKind::Fail { withdrawal: false },
+            _ => return Err(InputError::UnknownType(kind.to_string())),
+        };
+        Ok(TransactionCommand {
+            kind,
+            client,
+            tx,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })
+    }
+
+    fn parse_amount(&self, field: &str) -> Result<Decimal, InputError> {
+        if field.is_empty() {
+            return Err(InputError::MissingField("amount"));
+        }
+        let invalid = || InputError::InvalidField {
+            field: "amount",
+            value: field.to_string(),
+        };
+        if self.config.decimal_comma && field.contains(',') {
+            let field = field.replace(',', ".");
+            return Decimal::from_str(&field)
+                .or_else(|_| Decimal::from_scientific(&field))
+                .map_err(|_| invalid());
+        }
+        Decimal::from_str(field)
+            .or_else(|_| Decimal::from_scientific(field))
+            .map_err(|_| invalid())
+    }
+
+    /// normalize rewrites a field into the form expected when deserializing, accepting any casing
+    /// or alias of the transaction type (unless strict) and comma decimal marks (if configured)
+    fn normalize<'f>(&self, i: usize, field: &'f str) -> Cow<'f, str> {
+        if Some(i) == self.kind && !self.config.strict {
+            if let Some(name) = TransactionKind::canonical_name(field) {
+                return Cow::Borrowed(name);
+            }
+        }
+        if Some(i) == self.amount && self.config.decimal_comma && field.contains(',') {
+            return Cow::Owned(field.replace(',', "."));
+        }
+        Cow::Borrowed(field)
+    }
+}
+
+impl<R: io::Read> CommandReader<R> {
+    fn parse_record(&mut self, record: &StringRecord) -> Result<TransactionCommand> {
+        let mut command = if self.config.fast {
+            self.parse_fast(record)?
+        } else {
+            self.parse(record)?
+        };
+        command.correlation = self.parse_correlation(record)?;
+        command.tenant = self.parse_tenant(record)?;
+        command.timestamp = self.parse_timestamp(record)?;
+        command.kind = self.parse_status(record, command.kind)?;
+        Ok(command)
+    }
+
+    /// parse_correlation reads the correlation ID column, if there is one. Empty fields are
+    /// treated as having no correlation ID.
+    fn parse_correlation(
+        &self,
+        record: &StringRecord,
+    ) -> Result<Option<CorrelationId>, InputError> {
+        let field = match self.correlation.and_then(|i| record.get(i)) {
+            Some(field) if self.config.strict => field,
+            Some(field) => field.trim(),
+            None => return Ok(None),
+        };
+        if field.is_empty() {
+            return Ok(None);
+        }
+        field
+            .parse()
+            .map(Some)
+            .map_err(|_| InputError::InvalidField {
+                field: CORRELATION_HEADER,
+                value: field.to_string(),
+            })
+    }
+
+    /// parse_tenant reads the tenant column, if there is one. Empty fields are treated as having
+    /// no tenant, i.e. belonging to the tenant being processed.
+    fn parse_tenant(&self, record: &StringRecord) -> Result<Option<TenantId>, InputError> {
+        let field = match self.tenant.and_then(|i| record.get(i)) {
+            Some(field) if self.config.strict => field,
+            Some(field) => field.trim(),
+            None => return Ok(None),
+        };
+        if field.is_empty() {
+            return Ok(None);
+        }
+        field
+            .parse()
+            .map(Some)
+            .map_err(|_| InputError::InvalidField {
+                field: TENANT_HEADER,
+                value: field.to_string(),
+            })
+    }
+
+    /// parse_timestamp reads the timestamp column, if there is one. Empty fields are treated as
+    /// having no timestamp.
+    fn parse_timestamp(&self, record: &StringRecord) -> Result<Option<DateTime<Utc>>, InputError> {
+        let field = match self.timestamp.and_then(|i| record.get(i)) {
+            Some(field) if self.config.strict => field,
+            Some(field) => field.trim(),
+            None => return Ok(None),
+        };
+        if field.is_empty() {
+            return Ok(None);
+        }
+        DateTime::parse_from_rfc3339(field)
+            .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+            .map_err(|_| InputError::InvalidField {
+                field: TIMESTAMP_HEADER,
+                value: field.to_string(),
+            })
+    }
+
+    /// parse_status reads the status column, if there is one, making deposits & withdrawals which
+    /// are pending into their pending kinds. Only deposits & withdrawals can be pending.
+    fn parse_status(
+        &self,
+        record: &StringRecord,
+        kind: TransactionKind,
+    ) -> Result<TransactionKind, InputError> {
+        let field = match self.status.and_then(|i| record.get(i)) {
+            Some(field) if self.config.strict => field,
+            Some(field) => field.trim(),
+            None => return Ok(kind),
+        };
+        match (field, kind) {
+            ("" | "settled", _) => Ok(kind),
+            ("pending", TransactionKind::Deposit { amount }) => {
+                Ok(TransactionKind::PendingDeposit { amount })
+            }
+            ("pending", TransactionKind::Withdrawal { amount }) => {
+                Ok(TransactionKind::PendingWithdrawal { amount })
+            }
+            _ => Err(InputError::InvalidField {
+                field: STATUS_HEADER,
+                value: field.to_string(),
+            }),
+        }
+    }
+}
+
+/// parse_number parses an unsigned integer field
+fn parse_number<T: FromStr>(field: &str, name: &'static str) -> Result<T, InputError> {
+    if field.is_empty() {
+        return Err(InputError::MissingField(name));
+    }
+    field.parse().map_err(|_| InputError::InvalidField {
+        field: name,
+        value: field.to_string(),
+    })
+}
+
+impl<R: io::Read> Iterator for CommandReader<R> {
+    type Item = Result<Row, RowError>;
+
+    /// Yields the next row, or an error if it couldn't be parsed. Errors which leave the row
+    /// unreadable, such as I/O errors, have no record and shouldn't be skipped over.
+    fn next(&mut self) -> Option<Result<Row, RowError>> {
+        // rows are read into a buffer which is reused between rows, so that only the record
+        // handed out with the row is allocated, at its exact size
+        match self.reader.read_byte_record(&mut self.buffer) {
+            Ok(true) => {
+                let bytes = self.buffer.clone();
+                let position = bytes
+                    .position()
+                    .map(|position| self.locate(position))
+                    .unwrap_or_default();
+                let parsed = StringRecord::from_byte_record(bytes).map_err(|e| {
+                    let error = anyhow::Error::from(e.utf8_error().clone());
+                    let bytes = e.into_byte_record();
+                    let record: StringRecord = bytes.iter().map(String::from_utf8_lossy).collect();
+                    (record, error)
+                });
+                Some(match parsed {
+                    Ok(record) => match self.parse_record(&record) {
+                        Ok(command) => Ok(Row {
+                            command,
+                            position,
+                            record,
+                        }),
+                        Err(error) => Err(RowError {
+                            position,
+                            record: Some(record),
+                            error,
+                        }),
+                    },
+                    Err((record, error)) => Err(RowError {
+                        position,
+                        record: Some(record),
+                        error,
+                    }),
+                })
+            }
+            Ok(false) => None,
+            Err(e) => Some(Err(RowError {
+                position: e
+                    .position()
+                    .map(|position| self.locate(position))
+                    .unwrap_or_default(),
+                record: None,
+                error: e.into(),
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str, config: InputConfig) -> Result<Vec<TransactionCommand>> {
+        let mut commands = vec![];
+        for row in CommandReader::from_reader(input.as_bytes(), config)? {
+            commands.push(row?.command);
+        }
+        Ok(commands)
+    }
+
+    #[test]
+    fn test_read() -> Result<()> {
+        let commands = read(
+            "type,client,tx,amount\ndeposit,1,1,1.5\ndispute,1,1,\n",
+            InputConfig::default(),
+        )?;
+        assert_eq!(
+            commands,
+            vec![
+                TransactionCommand {
+                    kind: TransactionKind::Deposit {
+                        amount: Decimal::new(15, 1)
+                    },
+                    client: 1,
+                    tx: 1,
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                },
+                TransactionCommand {
+                    kind: TransactionKind::Dispute,
+                    client: 1,
+                    tx: 1,
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_no_headers() -> Result<()> {
+        let commands = read(
+            "deposit,1,1,1.5\n",
+            InputConfig {
+                has_headers: false,
+                ..InputConfig::default()
+            },
+        )?;
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].tx, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_delimiter_and_decimal_comma() -> Result<()> {
+        let commands = read(
+            "type;client;tx;amount\nwithdrawal;2;3;\"1,25\"\n",
+            InputConfig {
+                delimiter: b';',
+                decimal_comma: true,
+                ..InputConfig::default()
+            },
+        )?;
+        assert_eq!(
+            commands[0].kind,
+            TransactionKind::Withdrawal {
+                amount: Decimal::new(125, 2)
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_lenient() -> Result<()> {
+        let input = " tx , amount,type,client,note\n1,  1.0 , deposit ,1,first\n1,,dispute,1\n";
+        let commands = read(input, InputConfig::default())?;
+        assert_eq!(
+            commands,
+            vec![
+                TransactionCommand {
+                    kind: TransactionKind::Deposit {
+                        amount: Decimal::new(10, 1)
+                    },
+                    client: 1,
+                    tx: 1,
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                },
+                TransactionCommand {
+                    kind: TransactionKind::Dispute,
+                    client: 1,
+                    tx: 1,
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_strict() -> Result<()> {
+        let strict = InputConfig {
+            strict: true,
+            ..InputConfig::default()
+        };
+        let res = read("type,client,tx,amount,note\ndeposit,1,1,1.0,x\n", strict);
+        assert_eq!(
+            res.unwrap_err().downcast::<InputError>()?,
+            InputError::UnknownColumn("note".to_string())
+        );
+        assert!(read("type,client,tx,amount\ndeposit,1,1, 1.0\n", strict).is_err());
+        assert!(read("type,client,tx,amount\ndispute,1,1\n", strict).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_type_aliases() -> Result<()> {
+        let input = "type,client,tx,amount\nDEPOSIT,1,1,1.0\nCharge_Back,1,1,\nwithdraw,1,2,1.0\n";
+        let kinds: Vec<String> = read(input, InputConfig::default())?
+            .iter()
+            .map(|c| c.kind.to_string())
+            .collect();
+        assert_eq!(kinds, vec!["deposit", "chargeback", "withdrawal"]);
+
+        let strict = InputConfig {
+            strict: true,
+            ..InputConfig::default()
+        };
+        assert!(read(input, strict).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_fast() -> Result<()> {
+        let input = "type,client,tx,amount
+deposit,1,1,1.5
+ Withdraw , 1 , 2 , 1e-1
+dispute,1,1,
+deposit,1,x,1.0
+deposit,1,3,
+transfer,1,4,1.0
+";
+        let fast = InputConfig {
+            fast: true,
+            ..InputConfig::default()
+        };
+        let read_all = |config| -> Result<Vec<Result<TransactionCommand, String>>> {
+            Ok(CommandReader::from_reader(input.as_bytes(), config)?
+                .map(|row| row.map(|row| row.command).map_err(|e| e.to_string()))
+                .collect())
+        };
+        let rows = read_all(fast)?;
+        let oks = |rows: &[Result<TransactionCommand, String>]| -> Vec<TransactionCommand> {
+            rows.iter().filter_map(|row| row.clone().ok()).collect()
+        };
+        assert_eq!(oks(&rows), oks(&read_all(InputConfig::default())?));
+        assert_eq!(oks(&rows).len(), 3);
+        assert_eq!(
+            rows[3..],
+            [
+                Err(r#"line 5: invalid tx "x""#.to_string()),
+                Err("line 6: missing field `amount`".to_string()),
+                Err(r#"line 7: unknown transaction type "transfer""#.to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_payout() -> Result<()> {
+        let input = "type,client,tx,amount,beneficiary
+deposit,1,1,5.0,
+payout,1,2,1.5,10
+payout,1,3,1.5,
+";
+        for config in [
+            InputConfig::default(),
+            InputConfig {
+                fast: true,
+                ..InputConfig::default()
+            },
+            InputConfig {
+                strict: true,
+                ..InputConfig::default()
+            },
+        ] {
+            let rows: Vec<Result<Row, RowError>> =
+                CommandReader::from_reader(input.as_bytes(), config)?.collect();
+            assert!(rows[0].is_ok());
+            assert_eq!(
+                rows[1].as_ref().ok().map(|row| row.command.kind),
+                Some(TransactionKind::Payout {
+                    amount: Decimal::new(15, 1),
+                    beneficiary: 10
+                })
+            );
+            assert!(rows[2].is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_status() -> Result<()> {
+        let input = "type,client,tx,amount,status
+deposit,1,1,1.0,pending
+withdrawal,1,2,1.0,pending
+deposit,1,3,1.0,settled
+deposit,1,4,1.0,
+settle,1,1,,
+dispute,1,3,,pending
+deposit,1,5,1.0,cleared
+";
+        let amount = Decimal::from(1);
+        for config in [
+            InputConfig::default(),
+            InputConfig {
+                fast: true,
+                ..InputConfig::default()
+            },
+            InputConfig {
+                strict: true,
+                ..InputConfig::default()
+            },
+        ] {
+            let rows: Vec<Result<Row, RowError>> =
+                CommandReader::from_reader(input.as_bytes(), config)?.collect();
+            let kind = |i: usize| rows[i].as_ref().ok().map(|row| row.command.kind);
+            assert_eq!(kind(0), Some(TransactionKind::PendingDeposit { amount }));
+            assert_eq!(kind(1), Some(TransactionKind::PendingWithdrawal { amount }));
+            assert_eq!(kind(2), Some(TransactionKind::Deposit { amount }));
+            assert_eq!(kind(3), Some(TransactionKind::Deposit { amount }));
+            assert_eq!(kind(4), Some(TransactionKind::Settle { withdrawal: false }));
+            assert!(rows[5].is_err());
+            assert!(rows[6].is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_engine_types() -> Result<()> {
+        let input = "type,client,tx,amount
+pending_deposit,1,1,1.0
+pending_withdrawal,1,2,1.0
+escrow,1,3,1.0
+adjustment,1,4,1.0
+lock,1,5,
+unlock,1,6,
+force_resolve,1,7,
+";
+        for config in [
+            InputConfig::default(),
+            InputConfig {
+                fast: true,
+                ..InputConfig::default()
+            },
+            InputConfig {
+                strict: true,
+                ..InputConfig::default()
+            },
+        ] {
+            for row in CommandReader::from_reader(input.as_bytes(), config)? {
+                let error = row.unwrap_err().error;
+                assert_eq!(
+                    error.downcast_ref::<InputError>().map(InputError::code),
+                    Some("unknown_type"),
+                    "{:#}",
+                    error
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_timestamp() -> Result<()> {
+        let input = "type,client,tx,amount,timestamp
+deposit,1,1,1.0,2024-01-31T09:30:00Z
+deposit,1,2,1.0,
+deposit,1,3,1.0,2024-01-31T10:30:00+01:00
+deposit,1,4,1.0,yesterday
+";
+        for config in [
+            InputConfig::default(),
+            InputConfig {
+                fast: true,
+                ..InputConfig::default()
+            },
+            InputConfig {
+                strict: true,
+                ..InputConfig::default()
+            },
+        ] {
+            let rows: Vec<Result<Row, RowError>> =
+                CommandReader::from_reader(input.as_bytes(), config)?.collect();
+            let timestamp = |i: usize| rows[i].as_ref().ok().map(|row| row.command.timestamp);
+            let expected = "2024-01-31T09:30:00Z".parse().ok();
+            assert_eq!(timestamp(0), Some(expected));
+            assert_eq!(timestamp(1), Some(None));
+            assert_eq!(timestamp(2), Some(expected));
+            assert!(rows[3].is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_correlation() -> Result<()> {
+        let input = "type,client,tx,amount,correlation_id
+deposit,1,1,1.0,abc-123
+deposit,1,2,1.0,
+dispute,1,1,,12345
+deposit,1,3,1.0,0123456789012345678901234567890123456789
+";
+        for config in [
+            InputConfig::default(),
+            InputConfig {
+                fast: true,
+                ..InputConfig::default()
+            },
+            InputConfig {
+                strict: true,
+                ..InputConfig::default()
+            },
+        ] {
+            let rows: Vec<Result<Row, RowError>> =
+                CommandReader::from_reader(input.as_bytes(), config)?.collect();
+            let correlation = |i: usize| -> Option<String> {
+                let row = rows[i].as_ref().ok()?;
+                row.command.correlation.map(|c| c.to_string())
+            };
+            assert_eq!(correlation(0), Some("abc-123".to_string()));
+            assert_eq!(correlation(1), None);
+            assert_eq!(correlation(2), Some("12345".to_string()));
+            let err = rows[3].as_ref().unwrap_err();
+            assert_eq!(
+                err.error.downcast_ref::<InputError>().map(|e| e.code()),
+                Some("invalid_field")
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_tenant() -> Result<()> {
+        let input = "type,client,tx,amount,tenant
+deposit,1,1,1.0,acme
+deposit,1,2,1.0,
+deposit,1,3,1.0,acme/other
+";
+        for config in [
+            InputConfig::default(),
+            InputConfig {
+                strict: true,
+                ..InputConfig::default()
+            },
+        ] {
+            let rows: Vec<Result<Row, RowError>> =
+                CommandReader::from_reader(input.as_bytes(), config)?.collect();
+            let tenant = |i: usize| -> Option<String> {
+                let row = rows[i].as_ref().ok()?;
+                row.command.tenant.map(|t| t.to_string())
+            };
+            assert_eq!(tenant(0), Some("acme".to_string()));
+            assert_eq!(tenant(1), None);
+            assert!(rows[1].is_ok());
+            let err = rows[2].as_ref().unwrap_err();
+            assert_eq!(
+                err.error.downcast_ref::<InputError>().map(|e| e.code()),
+                Some("invalid_field")
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_positions() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,x,\" 2.0\"\n";
+        let mut reader = CommandReader::from_reader(input.as_bytes(), InputConfig::default())?;
+
+        let row = reader.next().unwrap()?;
+        assert_eq!(row.position, Position { line: 2, byte: 22 });
+        assert_eq!(raw(&row.record), "deposit,1,1,1.0");
+
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.position, Position { line: 3, byte: 38 });
+        assert_eq!(raw(err.record.as_ref().unwrap()), "deposit,1,x, 2.0");
+        assert!(err.to_string().starts_with("line 3: "));
+
+        assert!(reader.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_utf8() -> Result<()> {
+        let input = b"type,client,tx,amount\ndeposit,1,1,1.\xff\ndeposit,1,2,1.0\n";
+        let mut reader = CommandReader::from_reader(&input[..], InputConfig::default())?;
+
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.position.line, 2);
+        assert_eq!(err.record.unwrap().get(3), Some("1.\u{fffd}"));
+        assert_eq!(reader.next().unwrap()?.command.tx, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::io;
+
+use anyhow::{bail, Result};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::chargebacks::Ratio;
+use crate::events::EventsRepo;
+use crate::transactions::TransactionKind;
+
+/// InterchangeKind is the type of transaction an interchange rate applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterchangeKind {
+    Deposit,
+    Withdrawal,
+    Payout,
+    Chargeback,
+}
+
+fn percentage<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse::<Ratio>()
+        .map(|ratio| ratio.0)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Rate is the interchange charged on a type of transaction, as a percentage of its amount plus a
+/// fixed amount, e.g.
+/// `{"network": "visa", "type": "deposit", "percentage": "1.5%", "fixed": "0.1"}`. A rate without
+/// a network applies to every network which doesn't declare its own.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Rate {
+    #[serde(default)]
+    pub network: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: InterchangeKind,
+    #[serde(default, deserialize_with = "percentage")]
+    pub percentage: Decimal,
+    #[serde(default)]
+    pub fixed: Decimal,
+}
+
+/// InterchangeModel is every rate declared in the interchange config, along with the network each
+/// client's transactions are acquired through
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct InterchangeModel {
+    pub rates: Vec<Rate>,
+    /// The network of each client, by ID
+    #[serde(default)]
+    pub networks: HashMap<u64, String>,
+    /// The network of clients which aren't listed in `networks`
+    #[serde(default)]
+    pub default_network: Option<String>,
+}
+
+impl InterchangeModel {
+    /// network is the network a client's transactions are acquired through, if it's known
+    pub fn network(&self, client: u64) -> Option<&str> {
+        self.networks
+            .get(&client)
+            .or(self.default_network.as_ref())
+            .map(String::as_str)
+    }
+
+    /// rate is the rate charged on a type of transaction through a network, preferring the
+    /// network's own rate over one for every network
+    pub fn rate(&self, network: Option<&str>, kind: InterchangeKind) -> Option<&Rate> {
+        let rates = || self.rates.iter().filter(move |r| r.kind == kind);
+        rates()
+            .find(|r| network.is_some() && r.network.as_deref() == network)
+            .or_else(|| rates().find(|r| r.network.is_none()))
+    }
+}
+
+/// read_interchange reads a JSON interchange config
+pub fn read_interchange<R: io::Read>(reader: R) -> Result<InterchangeModel> {
+    let model: InterchangeModel = serde_json::from_reader(io::BufReader::new(reader))?;
+    for (i, rate) in model.rates.iter().enumerate() {
+        if model.rates[..i]
+            .iter()
+            .any(|r| r.network == rate.network && r.kind == rate.kind)
+        {
+            bail!(
+                "rate for {:?} on network {:?} is declared more than once",
+                rate.kind,
+                rate.network.as_deref().unwrap_or("*")
+            );
+        }
+    }
+    Ok(model)
+}
+
+/// InterchangeFee is the interchange charged on a single transaction
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InterchangeFee {
+    pub tx: u64,
+    pub client: u64,
+    #[serde(rename = "type")]
+    pub kind: InterchangeKind,
+    pub network: Option<String>,
+    pub amount: Decimal,
+    pub percentage: Decimal,
+    pub fixed: Decimal,
+    pub fee: Decimal,
+}
+
+/// interchange_fees computes the interchange of every transaction in the event log which has a
+/// rate, in the order they were made. Pending & escrow deposits are charged as deposits, and
+/// pending withdrawals as withdrawals, when they're made.
+pub fn interchange_fees(
+    events: &dyn EventsRepo,
+    model: &InterchangeModel,
+) -> Result<Vec<InterchangeFee>> {
+    let mut fees = Vec::new();
+    for event in events.get_all()? {
+        let kind = match event.kind {
+            TransactionKind::Deposit { .. }
+            | TransactionKind::PendingDeposit { .. }
+            | TransactionKind::Escrow { .. } => InterchangeKind::Deposit,
+            TransactionKind::Withdrawal { .. } | TransactionKind::PendingWithdrawal { .. } => {
+                InterchangeKind::Withdrawal
+            }
+            TransactionKind::Payout { .. } => InterchangeKind::Payout,
+            TransactionKind::ChargeBack => InterchangeKind::Chargeback,
+            _ => continue,
+        };
+        let network = model.network(event.client);
+        let rate = match model.rate(network, kind) {
+            Some(rate) => rate,
+            None => continue,
+        };
+        fees.push(InterchangeFee {
+            tx: event.tx,
+            client: event.client,
+            kind,
+            network: network.map(str::to_string),
+            amount: event.amount,
+            percentage: rate.percentage,
+            fixed: rate.fixed,
+            fee: (event.amount * rate.percentage + rate.fixed).normalize(),
+        });
+    }
+    Ok(fees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::MemoryRepo;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_read_interchange() -> Result<()> {
+        let model = read_interchange(
+            r#"{
+                "rates": [
+                    {"network": "visa", "type": "deposit", "percentage": "1.5%", "fixed": "0.1"},
+                    {"type": "deposit", "percentage": "0.02"},
+                    {"type": "chargeback", "fixed": "15"}
+                ],
+                "networks": {"1": "visa", "2": "amex"}
+            }"#
+            .as_bytes(),
+        )?;
+        assert_eq!(model.network(1), Some("visa"));
+        assert_eq!(model.network(3), None);
+        let rate = model.rate(Some("visa"), InterchangeKind::Deposit).unwrap();
+        assert_eq!(rate.percentage, Decimal::new(15, 3));
+        assert_eq!(rate.fixed, Decimal::new(1, 1));
+        let rate = model.rate(Some("amex"), InterchangeKind::Deposit).unwrap();
+        assert_eq!(rate.percentage, Decimal::new(2, 2));
+        assert_eq!(model.rate(None, InterchangeKind::Payout), None);
+
+        let duplicate = r#"{"rates": [{"type": "payout"}, {"type": "payout", "fixed": "1"}]}"#;
+        assert!(read_interchange(duplicate.as_bytes()).is_err());
+        let invalid = r#"{"rates": [{"type": "payout", "percentage": "150%"}]}"#;
+        assert!(read_interchange(invalid.as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_interchange_fees() -> Result<()> {
+        let events = MemoryRepo::new();
+        let event = |tx, client, kind, amount: i64| Transaction {
+            tx,
+            client,
+            amount: Decimal::from(amount),
+            kind,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+        let amount = Decimal::from(100);
+        events.append(event(1, 1, TransactionKind::Deposit { amount }, 100))?;
+        events.append(event(2, 2, TransactionKind::Deposit { amount }, 100))?;
+        events.append(event(3, 1, TransactionKind::Withdrawal { amount }, 100))?;
+        events.append(event(2, 2, TransactionKind::Dispute, 100))?;
+        events.append(event(2, 2, TransactionKind::ChargeBack, 100))?;
+
+        let model = InterchangeModel {
+            rates: vec![
+                Rate {
+                    network: Some("visa".to_string()),
+                    kind: InterchangeKind::Deposit,
+                    percentage: Decimal::new(15, 3),
+                    fixed: Decimal::new(1, 1),
+                },
+                Rate {
+                    network: None,
+                    kind: InterchangeKind::Deposit,
+                    percentage: Decimal::new(2, 2),
+                    fixed: Decimal::from(0),
+                },
+                Rate {
+                    network: None,
+                    kind: InterchangeKind::Chargeback,
+                    percentage: Decimal::from(0),
+                    fixed: Decimal::from(15),
+                },
+            ],
+            networks: HashMap::from([(1, "visa".to_string())]),
+            default_network: None,
+        };
+        let fees = interchange_fees(&events, &model)?;
+        let summary: Vec<(u64, InterchangeKind, Option<&str>, Decimal)> = fees
+            .iter()
+            .map(|f| (f.tx, f.kind, f.network.as_deref(), f.fee))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    1,
+                    InterchangeKind::Deposit,
+                    Some("visa"),
+                    Decimal::new(16, 1)
+                ),
+                (2, InterchangeKind::Deposit, None, Decimal::from(2)),
+                (2, InterchangeKind::Chargeback, None, Decimal::from(15)),
+            ]
+        );
+        Ok(())
+    }
+}
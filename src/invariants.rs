@@ -0,0 +1,192 @@
+use std::cell::Cell;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::accounts::Account;
+use crate::transactions::{Transaction, TransactionKind};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum InvariantError {
+    #[error("tx {tx} would leave client {client}'s held balance negative ({held})")]
+    NegativeHeld { tx: u64, client: u64, held: Decimal },
+    #[error(
+        "tx {tx} would leave accounts changed by {totals} in total this run, but deposits less \
+         withdrawals & chargebacks came to {flows}"
+    )]
+    Unbalanced {
+        tx: u64,
+        totals: Decimal,
+        flows: Decimal,
+    },
+}
+
+impl InvariantError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvariantError::NegativeHeld { .. } => "negative_held",
+            InvariantError::Unbalanced { .. } => "unbalanced",
+        }
+    }
+}
+
+/// flow is how much a transaction moves into (positive) or out of (negative) the accounts in
+/// total, going by its kind alone rather than its effect on the account
+fn flow(transaction: &Transaction) -> Decimal {
+    let amount = transaction.amount;
+    match transaction.kind {
+        TransactionKind::Deposit { .. }
+        | TransactionKind::Escrow { .. }
+        | TransactionKind::PendingDeposit { .. }
+        | TransactionKind::Adjustment { .. } => amount,
+        TransactionKind::Withdrawal { .. }
+        | TransactionKind::Payout { .. }
+        | TransactionKind::ChargeBack
+        | TransactionKind::Refund
+        | TransactionKind::Settle { withdrawal: true }
+        | TransactionKind::Fail { withdrawal: false } => -amount,
+        TransactionKind::PendingWithdrawal { .. }
+        | TransactionKind::Dispute
+        | TransactionKind::Resolve
+        | TransactionKind::ForceResolve
+        | TransactionKind::Release
+        | TransactionKind::Settle { withdrawal: false }
+        | TransactionKind::Fail { withdrawal: true }
+        | TransactionKind::Lock
+        | TransactionKind::Unlock => Decimal::from(0),
+    }
+}
+
+/// Invariants checks every transaction an engine applies against the invariants of the accounts:
+/// that held balances are never negative, and that the accounts' totals change by exactly the
+/// deposits less withdrawals, chargebacks etc. of the run. An account's total is always its
+/// available plus held balance, since accounts don't store it.
+pub struct Invariants {
+    totals: Cell<Decimal>,
+    flows: Cell<Decimal>,
+}
+
+impl Default for Invariants {
+    fn default() -> Invariants {
+        Invariants::new()
+    }
+}
+
+impl Invariants {
+    pub fn new() -> Invariants {
+        Invariants {
+            totals: Cell::new(Decimal::from(0)),
+            flows: Cell::new(Decimal::from(0)),
+        }
+    }
+
+    /// check fails if applying the transaction to the account, which was `previous` before it (or
+    /// didn't exist), would break an invariant
+    pub fn check(
+        &self,
+        transaction: &Transaction,
+        previous: Option<&Account>,
+        updated: &Account,
+    ) -> Result<(), InvariantError> {
+        if updated.held() < Decimal::from(0) {
+            return Err(InvariantError::NegativeHeld {
+                tx: transaction.tx,
+                client: updated.client(),
+                held: updated.held(),
+            });
+        }
+        let (totals, flows) = self.sums(transaction, previous, updated);
+        if totals != flows {
+            return Err(InvariantError::Unbalanced {
+                tx: transaction.tx,
+                totals,
+                flows,
+            });
+        }
+        Ok(())
+    }
+
+    /// record adds an applied transaction to the run's totals
+    pub fn record(&self, transaction: &Transaction, previous: Option<&Account>, updated: &Account) {
+        let (totals, flows) = self.sums(transaction, previous, updated);
+        self.totals.set(totals);
+        self.flows.set(flows);
+    }
+
+    fn sums(
+        &self,
+        transaction: &Transaction,
+        previous: Option<&Account>,
+        updated: &Account,
+    ) -> (Decimal, Decimal) {
+        let previous = previous.map(Account::total).unwrap_or_default();
+        (
+            self.totals.get() + updated.total() - previous,
+            self.flows.get() + flow(transaction),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(tx: u64, kind: TransactionKind, amount: i64) -> Transaction {
+        Transaction {
+            tx,
+            client: 1,
+            amount: Decimal::from(amount),
+            kind,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_invariants() -> anyhow::Result<()> {
+        let invariants = Invariants::new();
+        let amount = Decimal::from(10);
+        let deposit = transaction(1, TransactionKind::Deposit { amount }, 10);
+        let account = Account::new(deposit)?;
+        invariants.check(&deposit, None, &account)?;
+        invariants.record(&deposit, None, &account);
+
+        let dispute = transaction(1, TransactionKind::Dispute, 10);
+        let disputed = account.apply(dispute)?;
+        assert_eq!(
+            invariants.check(&dispute, Some(&account), &disputed),
+            Ok(())
+        );
+
+        // resolving more than is held
+        let resolve = transaction(1, TransactionKind::Resolve, 10);
+        let resolved = account.apply(resolve)?;
+        assert_eq!(
+            invariants.check(&resolve, Some(&account), &resolved),
+            Err(InvariantError::NegativeHeld {
+                tx: 1,
+                client: 1,
+                held: Decimal::from(-10),
+            })
+        );
+
+        // a transaction whose effect on the account doesn't match its kind
+        let withdrawal = transaction(2, TransactionKind::Withdrawal { amount }, 10);
+        assert_eq!(
+            invariants.check(&withdrawal, Some(&account), &account),
+            Err(InvariantError::Unbalanced {
+                tx: 2,
+                totals: amount,
+                flows: Decimal::from(0),
+            })
+        );
+        let withdrawn = account.apply(withdrawal)?;
+        assert_eq!(
+            invariants.check(&withdrawal, Some(&account), &withdrawn),
+            Ok(())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::correlation::CorrelationId;
+use crate::transactions::{TransactionCommand, TransactionKind};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum Iso8583Error {
+    #[error("message isn't ASCII")]
+    NotAscii,
+    #[error("invalid message type indicator {0:?}")]
+    InvalidMti(String),
+    #[error("invalid bitmap")]
+    InvalidBitmap,
+    #[error("message ends within field {0}")]
+    Truncated(u8),
+    #[error("invalid length of field {0}")]
+    InvalidLength(u8),
+    #[error("missing field {0}")]
+    MissingField(u8),
+    #[error("invalid field {field} {value:?}")]
+    InvalidField { field: u8, value: String },
+    #[error("unsupported processing code {0:?}")]
+    UnsupportedProcessingCode(String),
+}
+
+impl Iso8583Error {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            Iso8583Error::NotAscii => "invalid_encoding",
+            Iso8583Error::InvalidMti(_) => "invalid_mti",
+            Iso8583Error::InvalidBitmap => "invalid_bitmap",
+            Iso8583Error::Truncated(_) => "truncated_message",
+            Iso8583Error::InvalidLength(_) => "invalid_field_length",
+            Iso8583Error::MissingField(_) => "missing_field",
+            Iso8583Error::InvalidField { .. } => "invalid_field",
+            Iso8583Error::UnsupportedProcessingCode(_) => "unsupported_processing_code",
+        }
+    }
+}
+
+/// Length is how the length of a data element is encoded
+#[derive(Debug, Clone, Copy)]
+enum Length {
+    Fixed(usize),
+    /// Prefixed by a 2 digit length
+    LlVar,
+    /// Prefixed by a 3 digit length
+    LllVar,
+}
+
+/// length is the encoding of a data element's length in ISO 8583:1987, with binary elements
+/// written as hex, as switches log them
+fn length(field: u8) -> Length {
+    use Length::*;
+    match field {
+        2 | 32 | 33 | 34 | 35 | 44 | 45 | 99..=103 => LlVar,
+        36 | 46..=48 | 54..=63 | 104..=127 => LllVar,
+        3 | 11 | 12 | 38 | 73 => Fixed(6),
+        4 | 5 | 6 | 37 | 82..=85 => Fixed(12),
+        7 | 74..=81 => Fixed(10),
+        8..=10 => Fixed(8),
+        13..=18 | 71 | 72 => Fixed(4),
+        19..=24 | 40 | 49..=51 | 68..=70 => Fixed(3),
+        25 | 26 | 39 | 67 | 92 => Fixed(2),
+        27 | 65 | 66 | 91 => Fixed(1),
+        28..=31 => Fixed(9),
+        41 => Fixed(8),
+        42 => Fixed(15),
+        43 => Fixed(40),
+        52 | 53 | 64 | 86..=89 | 96 | 128 => Fixed(16),
+        90 | 95 => Fixed(42),
+        93 => Fixed(5),
+        94 => Fixed(7),
+        97 => Fixed(17),
+        98 => Fixed(25),
+        // field 1 is the secondary bitmap, which is read along with the primary
+        _ => Fixed(16),
+    }
+}
+
+/// Message is an ISO 8583 message: its message type indicator (MTI) and data elements
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub mti: String,
+    pub fields: BTreeMap<u8, String>,
+}
+
+impl Message {
+    /// parse parses an ASCII encoded ISO 8583:1987 message, whose bitmaps are hex encoded
+    pub fn parse(line: &str) -> Result<Message, Iso8583Error> {
+        if !line.is_ascii() {
+            return Err(Iso8583Error::NotAscii);
+        }
+        let mti = line.get(..4).unwrap_or(line);
+        if mti.len() != 4 || !mti.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Iso8583Error::InvalidMti(mti.to_string()));
+        }
+        let bitmap = |at: usize| {
+            line.get(at..at + 16)
+                .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+                .ok_or(Iso8583Error::InvalidBitmap)
+        };
+        let mut pos = 4;
+        let mut bitmaps = vec![bitmap(pos)?];
+        pos += 16;
+        if bitmaps[0] >> 63 == 1 {
+            bitmaps.push(bitmap(pos)?);
+            pos += 16;
+        }
+
+        let mut fields = BTreeMap::new();
+        for field in 2..=(bitmaps.len() as u8 * 64) {
+            let (map, bit) = (bitmaps[(field as usize - 1) / 64], (field - 1) % 64);
+            if map >> (63 - bit) & 1 == 0 {
+                continue;
+            }
+            let take = |pos: usize, len: usize| {
+                line.get(pos..pos + len)
+                    .ok_or(Iso8583Error::Truncated(field))
+            };
+            let digits = match length(field) {
+                Length::Fixed(_) => 0,
+                Length::LlVar => 2,
+                Length::LllVar => 3,
+            };
+            let len = match length(field) {
+                Length::Fixed(len) => len,
+                _ => take(pos, digits)?
+                    .parse()
+                    .map_err(|_| Iso8583Error::InvalidLength(field))?,
+            };
+            pos += digits;
+            fields.insert(field, take(pos, len)?.to_string());
+            pos += len;
+        }
+        Ok(Message {
+            mti: mti.to_string(),
+            fields,
+        })
+    }
+
+    fn field(&self, field: u8) -> Result<&str, Iso8583Error> {
+        self.fields
+            .get(&field)
+            .map(|value| value.trim())
+            .ok_or(Iso8583Error::MissingField(field))
+    }
+
+    fn number(&self, field: u8) -> Result<u64, Iso8583Error> {
+        let value = self.field(field)?;
+        value.parse().map_err(|_| Iso8583Error::InvalidField {
+            field,
+            value: value.to_string(),
+        })
+    }
+
+    /// amount is the transaction amount (field 4, in minor units) in the currency's major units
+    fn amount(&self) -> Result<Decimal, Iso8583Error> {
+        let minor = self.number(4)?;
+        let exponent = match self.fields.get(&49) {
+            Some(currency) => exponent(currency.trim()),
+            None => 2,
+        };
+        Ok(Decimal::new(minor as i64, exponent))
+    }
+}
+
+/// exponent is the number of minor units of an ISO 4217 numeric currency code, e.g. 2 for cents
+fn exponent(currency: &str) -> u32 {
+    match currency {
+        "108" | "152" | "174" | "262" | "324" | "352" | "392" | "410" | "548" | "600" | "646"
+        | "704" | "800" | "950" | "952" | "953" => 0,
+        "048" | "368" | "400" | "414" | "434" | "512" | "788" => 3,
+        _ => 2,
+    }
+}
+
+/// APPROVED is the response code (field 39) of an approved transaction
+const APPROVED: &str = "00";
+
+/// commands converts a message into the transactions it represents, which may be none: only
+/// approved financial responses (0210) and financial advices (0220, 0221) move funds, along with
+/// issuer reversal advices (0422, 0423), which are chargebacks. Requests are skipped in favour of
+/// their responses, as are authorizations, which only reserve funds at the card issuer.
+///
+/// The transaction's ID is its retrieval reference number (field 37), which chargebacks share with
+/// the transaction they reverse, and which is also kept as its correlation ID for tracing it back
+/// to the switch. Its client is the account identification (field 102). The processing code's
+/// transaction type (field 3) decides whether it's a deposit or a withdrawal.
+pub fn commands(message: &Message) -> Result<Vec<TransactionCommand>, Iso8583Error> {
+    let chargeback = match message.mti.as_str() {
+        "0210" if message.field(39)? != APPROVED => return Ok(vec![]),
+        "0210" | "0220" | "0221" => false,
+        "0422" | "0423" => true,
+        _ => return Ok(vec![]),
+    };
+    let command = |kind| -> Result<TransactionCommand, Iso8583Error> {
+        Ok(TransactionCommand {
+            kind,
+            tx: message.number(37)?,
+            client: message.number(102)?,
+            correlation: CorrelationId::from_str(message.field(37)?).ok(),
+            tenant: None,
+            timestamp: None,
+        })
+    };
+    if chargeback {
+        return Ok(vec![
+            command(TransactionKind::Dispute)?,
+            command(TransactionKind::ChargeBack)?,
+        ]);
+    }
+    let processing = message.field(3)?;
+    let amount = message.amount()?;
+    let kind = match processing.get(..2) {
+        // goods & services, cash withdrawals and purchases with cash back
+        Some("00" | "01" | "09") => TransactionKind::Withdrawal { amount },
+        // refunds, deposits and original credits
+        Some("20" | "21" | "26") => TransactionKind::Deposit { amount },
+        _ => {
+            return Err(Iso8583Error::UnsupportedProcessingCode(
+                processing.to_string(),
+            ))
+        }
+    };
+    Ok(vec![command(kind)?])
+}
+
+/// Iso8583Record is the CSV representation of a converted transaction, matching the input format
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Iso8583Record {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub client: u64,
+    pub tx: u64,
+    pub amount: Option<Decimal>,
+    pub correlation_id: Option<CorrelationId>,
+}
+
+impl Iso8583Record {
+    /// HEADERS is the header row, for output which has no records to write it
+    pub const HEADERS: [&'static str; 5] = ["type", "client", "tx", "amount", "correlation_id"];
+}
+
+impl From<TransactionCommand> for Iso8583Record {
+    fn from(command: TransactionCommand) -> Iso8583Record {
+        let amount = match command.kind {
+            TransactionKind::Deposit { amount } | TransactionKind::Withdrawal { amount } => {
+                Some(amount)
+            }
+            _ => None,
+        };
+        Iso8583Record {
+            kind: command.kind.to_string(),
+            client: command.client,
+            tx: command.tx,
+            amount,
+            correlation_id: command.correlation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// message builds an ASCII message from its MTI and (field, value) pairs, prefixing variable
+    /// length fields with their length
+    fn message(mti: &str, fields: &[(u8, &str)]) -> String {
+        let mut bitmaps = [0u64; 2];
+        let mut data = String::new();
+        for (field, value) in fields {
+            let field = *field as usize - 1;
+            bitmaps[field / 64] |= 1 << (63 - field % 64);
+            match length(field as u8 + 1) {
+                Length::Fixed(_) => {}
+                Length::LlVar => data.push_str(&format!("{:02}", value.len())),
+                Length::LllVar => data.push_str(&format!("{:03}", value.len())),
+            }
+            data.push_str(value);
+        }
+        if bitmaps[1] != 0 {
+            bitmaps[0] |= 1 << 63;
+            format!("{}{:016X}{:016X}{}", mti, bitmaps[0], bitmaps[1], data)
+        } else {
+            format!("{}{:016X}{}", mti, bitmaps[0], data)
+        }
+    }
+
+    #[test]
+    fn test_parse() -> Result<(), Iso8583Error> {
+        let line = message(
+            "0210",
+            &[
+                (2, "4111111111111111"),
+                (3, "000000"),
+                (4, "000000001050"),
+                (37, "000000000042"),
+                (39, "00"),
+                (49, "826"),
+                (102, "7"),
+            ],
+        );
+        let parsed = Message::parse(&line)?;
+        assert_eq!(parsed.mti, "0210");
+        assert_eq!(parsed.fields[&2], "4111111111111111");
+        assert_eq!(parsed.fields[&102], "7");
+        assert_eq!(parsed.amount()?, Decimal::new(1050, 2));
+
+        assert_eq!(
+            Message::parse(&line[..line.len() - 1]),
+            Err(Iso8583Error::Truncated(102))
+        );
+        assert_eq!(
+            Message::parse("02X0"),
+            Err(Iso8583Error::InvalidMti("02X0".to_string()))
+        );
+        assert_eq!(Message::parse("0210ZZ"), Err(Iso8583Error::InvalidBitmap));
+        Ok(())
+    }
+
+    #[test]
+    fn test_commands() -> Result<(), Iso8583Error> {
+        let convert =
+            |mti, fields: &[(u8, &str)]| commands(&Message::parse(&message(mti, fields))?);
+        let purchase = [
+            (3, "000000"),
+            (4, "000000001050"),
+            (37, "000000000042"),
+            (39, "00"),
+            (102, "7"),
+        ];
+        let converted = convert("0210", &purchase)?;
+        assert_eq!(converted.len(), 1);
+        assert_eq!((converted[0].tx, converted[0].client), (42, 7));
+        assert_eq!(
+            converted[0].kind,
+            TransactionKind::Withdrawal {
+                amount: Decimal::new(1050, 2)
+            }
+        );
+
+        // declined responses, requests & authorizations don't move funds
+        let mut declined = purchase;
+        declined[3] = (39, "51");
+        assert!(convert("0210", &declined)?.is_empty());
+        assert!(convert("0200", &purchase)?.is_empty());
+        assert!(convert("0110", &purchase)?.is_empty());
+
+        // a refund in yen, which has no minor units
+        let refund = [
+            (3, "200000"),
+            (4, "000000001050"),
+            (37, "000000000043"),
+            (49, "392"),
+            (102, "7"),
+        ];
+        assert_eq!(
+            convert("0220", &refund)?[0].kind,
+            TransactionKind::Deposit {
+                amount: Decimal::from(1050)
+            }
+        );
+
+        let chargeback = [(37, "000000000043"), (102, "7")];
+        let kinds: Vec<TransactionKind> = convert("0422", &chargeback)?
+            .into_iter()
+            .map(|c| c.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![TransactionKind::Dispute, TransactionKind::ChargeBack]
+        );
+
+        let balance_inquiry = [
+            (3, "300000"),
+            (4, "000000000000"),
+            (37, "000000000001"),
+            (102, "7"),
+        ];
+        assert_eq!(
+            convert("0220", &balance_inquiry),
+            Err(Iso8583Error::UnsupportedProcessingCode(
+                "300000".to_string()
+            ))
+        );
+        assert_eq!(
+            convert("0220", &purchase[..2]),
+            Err(Iso8583Error::MissingField(37))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::ledger::{ChartOfAccounts, Posting};
+
+/// JournalLine is a line of a journal entry in the general ledger import format: the total debits
+/// & credits of a GL account over the run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JournalLine {
+    pub journal: String,
+    pub date: NaiveDate,
+    pub line: u64,
+    pub account: String,
+    pub description: String,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+/// journal summarizes the postings as a single journal entry for the general ledger, with a line
+/// per GL account code in the chart, ordered by code. Internal accounts which the chart maps to the
+/// same code are combined into one line, described by the name of the first. Accounts without any
+/// postings are left out.
+pub fn journal(
+    postings: &[Posting],
+    chart: &ChartOfAccounts,
+    id: &str,
+    date: NaiveDate,
+) -> Vec<JournalLine> {
+    let mut accounts: BTreeMap<&str, (&str, Decimal, Decimal)> = BTreeMap::new();
+    for posting in postings {
+        let entry = chart.entry(posting.account);
+        let (_, debit, credit) = accounts.entry(&entry.code).or_insert((
+            &entry.name,
+            Decimal::from(0),
+            Decimal::from(0),
+        ));
+        *debit += posting.debit;
+        *credit += posting.credit;
+    }
+    accounts
+        .into_iter()
+        .zip(1..)
+        .map(|((code, (name, debit, credit)), line)| JournalLine {
+            journal: id.to_string(),
+            date,
+            line,
+            account: code.to_string(),
+            description: name.to_string(),
+            debit,
+            credit,
+        })
+        .collect()
+}
+
+/// write_journal writes journal lines as CSV
+pub fn write_journal<W: io::Write>(writer: W, lines: &[JournalLine]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for line in lines {
+        writer.serialize(line)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{read_chart, LedgerAccount};
+
+    #[test]
+    fn test_journal() -> Result<()> {
+        let d = Decimal::from;
+        let posting = |entry, account, debit: i64, credit: i64| Posting {
+            entry,
+            tx: entry,
+            client: Some(1),
+            erasure: None,
+            account,
+            debit: d(debit),
+            credit: d(credit),
+        };
+        let postings = [
+            posting(1, LedgerAccount::Cash, 10, 0),
+            posting(1, LedgerAccount::ClientFunds, 0, 10),
+            posting(2, LedgerAccount::ClientFunds, 4, 0),
+            posting(2, LedgerAccount::ClientHeld, 0, 4),
+        ];
+        // client funds & held funds are both booked to the ERP's client liabilities account
+        let chart = read_chart(
+            "account,code,name\nclient_funds,GL-2000,Client liabilities\nclient_held,GL-2000,Client liabilities\ncash,GL-1000,Bank\n"
+                .as_bytes(),
+        )?;
+        let date = NaiveDate::from_ymd(2024, 1, 31);
+        let lines = journal(&postings, &chart, "PAY-1", date);
+
+        let mut out = Vec::new();
+        write_journal(&mut out, &lines)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "journal,date,line,account,description,debit,credit
+PAY-1,2024-01-31,1,GL-1000,Bank,10,0
+PAY-1,2024-01-31,2,GL-2000,Client liabilities,4,14
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::transactions::{TransactionCommand, TransactionKind};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum KycError {
+    #[error("client {client} is blocked")]
+    Blocked { client: u64 },
+    #[error("client {client} is unverified, so can't withdraw")]
+    UnverifiedWithdrawal { client: u64 },
+    #[error("client {client} is unverified, so can't deposit more than {limit} in total")]
+    UnverifiedDeposit { client: u64, limit: Decimal },
+}
+
+impl KycError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        "kyc_rejected"
+    }
+}
+
+/// KycStatus is how far a client has got through identity verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KycStatus {
+    Verified,
+    Pending,
+    Blocked,
+}
+
+impl fmt::Display for KycStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KycStatus::Verified => write!(f, "verified"),
+            KycStatus::Pending => write!(f, "pending"),
+            KycStatus::Blocked => write!(f, "blocked"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusRecord {
+    client: u64,
+    status: KycStatus,
+}
+
+/// read_statuses reads a CSV of each client's KYC status, with `client` & `status` columns
+pub fn read_statuses<R: io::Read>(reader: R) -> Result<HashMap<u64, KycStatus>> {
+    let mut statuses = HashMap::new();
+    for result in csv::Reader::from_reader(reader).deserialize() {
+        let StatusRecord { client, status } = result?;
+        if statuses.insert(client, status).is_some() {
+            bail!("client {} has more than one status", client);
+        }
+    }
+    Ok(statuses)
+}
+
+/// KycPolicy is what clients may do depending on their KYC status. Verified clients are
+/// unrestricted, blocked clients can't deposit or withdraw at all, and unverified clients (those
+/// pending verification or missing from the status file) can deposit up to a limit in total but
+/// can't withdraw. Disputes, resolves & chargebacks are initiated by the partner rather than the
+/// client, so they're never restricted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KycPolicy {
+    pub statuses: HashMap<u64, KycStatus>,
+    /// The total unverified clients may deposit
+    pub unverified_deposit_limit: Decimal,
+}
+
+impl KycPolicy {
+    /// status is a client's KYC status, treating clients missing from the status file as pending
+    pub fn status(&self, client: u64) -> KycStatus {
+        self.statuses
+            .get(&client)
+            .copied()
+            .unwrap_or(KycStatus::Pending)
+    }
+}
+
+/// Kyc checks transactions against a KYC policy, keeping track of the total each unverified client
+/// has deposited so far
+pub struct Kyc {
+    policy: Arc<KycPolicy>,
+    deposited: RefCell<HashMap<u64, Decimal>>,
+}
+
+impl Kyc {
+    pub fn new(policy: Arc<KycPolicy>) -> Kyc {
+        Kyc {
+            policy,
+            deposited: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// check fails if the policy doesn't allow the client to make the transaction
+    pub fn check(&self, command: &TransactionCommand) -> Result<(), KycError> {
+        let client = command.client;
+        match (self.policy.status(client), command.kind) {
+            (KycStatus::Verified, _) => Ok(()),
+            (
+                KycStatus::Blocked,
+                TransactionKind::Deposit { .. }
+                | TransactionKind::PendingDeposit { .. }
+                | TransactionKind::Withdrawal { .. }
+                | TransactionKind::PendingWithdrawal { .. }
+                | TransactionKind::Payout { .. },
+            ) => Err(KycError::Blocked { client }),
+            (
+                KycStatus::Pending,
+                TransactionKind::Withdrawal { .. }
+                | TransactionKind::PendingWithdrawal { .. }
+                | TransactionKind::Payout { .. },
+            ) => Err(KycError::UnverifiedWithdrawal { client }),
+            (
+                KycStatus::Pending,
+                TransactionKind::Deposit { amount } | TransactionKind::PendingDeposit { amount },
+            ) => {
+                let deposited = self
+                    .deposited
+                    .borrow()
+                    .get(&client)
+                    .copied()
+                    .unwrap_or_default();
+                let limit = self.policy.unverified_deposit_limit;
+                if deposited + amount > limit {
+                    return Err(KycError::UnverifiedDeposit { client, limit });
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// record adds an accepted deposit to its client's total
+    pub fn record(&self, command: &TransactionCommand) {
+        if let TransactionKind::Deposit { amount } | TransactionKind::PendingDeposit { amount } =
+            command.kind
+        {
+            *self
+                .deposited
+                .borrow_mut()
+                .entry(command.client)
+                .or_default() += amount;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(client: u64, kind: TransactionKind) -> TransactionCommand {
+        TransactionCommand {
+            kind,
+            tx: 1,
+            client,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_read_statuses() -> Result<()> {
+        let statuses =
+            read_statuses("client,status\n1,verified\n2,pending\n3,blocked\n".as_bytes())?;
+        assert_eq!(statuses.get(&1), Some(&KycStatus::Verified));
+        assert_eq!(statuses.get(&3), Some(&KycStatus::Blocked));
+        assert!(read_statuses("client,status\n1,verified\n1,blocked\n".as_bytes()).is_err());
+        assert!(read_statuses("client,status\n1,unknown\n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() {
+        let kyc = Kyc::new(Arc::new(KycPolicy {
+            statuses: HashMap::from([
+                (1, KycStatus::Verified),
+                (2, KycStatus::Pending),
+                (3, KycStatus::Blocked),
+            ]),
+            unverified_deposit_limit: Decimal::from(100),
+        }));
+        let deposit = |client, amount: i64| {
+            command(
+                client,
+                TransactionKind::Deposit {
+                    amount: Decimal::from(amount),
+                },
+            )
+        };
+        let withdrawal = |client| {
+            command(
+                client,
+                TransactionKind::Withdrawal {
+                    amount: Decimal::from(1),
+                },
+            )
+        };
+
+        assert_eq!(kyc.check(&deposit(1, 1000)), Ok(()));
+        assert_eq!(kyc.check(&withdrawal(1)), Ok(()));
+
+        assert_eq!(kyc.check(&deposit(2, 60)), Ok(()));
+        kyc.record(&deposit(2, 60));
+        let limit = Decimal::from(100);
+        assert_eq!(
+            kyc.check(&deposit(2, 60)),
+            Err(KycError::UnverifiedDeposit { client: 2, limit })
+        );
+        assert_eq!(kyc.check(&deposit(2, 40)), Ok(()));
+        assert_eq!(
+            kyc.check(&withdrawal(2)),
+            Err(KycError::UnverifiedWithdrawal { client: 2 })
+        );
+        // missing clients are unverified
+        assert_eq!(
+            kyc.check(&withdrawal(4)),
+            Err(KycError::UnverifiedWithdrawal { client: 4 })
+        );
+
+        assert_eq!(
+            kyc.check(&deposit(3, 1)),
+            Err(KycError::Blocked { client: 3 })
+        );
+        assert_eq!(kyc.check(&command(3, TransactionKind::Dispute)), Ok(()));
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::erasure::Tombstone;
+use crate::events::EventsRepo;
+use crate::transactions::{Transaction, TransactionKind};
+
+/// LedgerAccount is an internal account which postings are made against. Client funds & held
+/// funds are liabilities kept per client, so the postings against them name the client (or the
+/// erasure, once the client's been forgotten).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    /// Funds we hold at the bank (an asset)
+    Cash,
+    /// Funds in transit from pending deposits, which we'll hold once they settle (an asset)
+    Clearing,
+    /// What we owe clients which is available to them (a liability)
+    ClientFunds,
+    /// What we owe clients which is held, e.g. under dispute or pending (a liability)
+    ClientHeld,
+    /// Fees charged to clients by adjustments, net of any refunded (income)
+    Fees,
+    /// Chargebacks which clients no longer had the funds to cover (an expense)
+    ChargebackLoss,
+}
+
+impl LedgerAccount {
+    pub const ALL: [LedgerAccount; 6] = [
+        LedgerAccount::Cash,
+        LedgerAccount::Clearing,
+        LedgerAccount::ClientFunds,
+        LedgerAccount::ClientHeld,
+        LedgerAccount::Fees,
+        LedgerAccount::ChargebackLoss,
+    ];
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LedgerAccount::Cash => "cash",
+            LedgerAccount::Clearing => "clearing",
+            LedgerAccount::ClientFunds => "client_funds",
+            LedgerAccount::ClientHeld => "client_held",
+            LedgerAccount::Fees => "fees",
+            LedgerAccount::ChargebackLoss => "chargeback_loss",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<LedgerAccount> {
+        match LedgerAccount::ALL.into_iter().find(|a| a.to_string() == s) {
+            Some(account) => Ok(account),
+            None => bail!("unknown ledger account {:?}", s),
+        }
+    }
+}
+
+impl Serialize for LedgerAccount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// ChartEntry is the code & name an internal account is known by in the books
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChartEntry {
+    pub code: String,
+    pub name: String,
+}
+
+/// ChartOfAccounts maps every internal account to its code & name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChartOfAccounts {
+    entries: HashMap<LedgerAccount, ChartEntry>,
+}
+
+impl ChartOfAccounts {
+    pub fn entry(&self, account: LedgerAccount) -> &ChartEntry {
+        &self.entries[&account]
+    }
+}
+
+impl Default for ChartOfAccounts {
+    fn default() -> ChartOfAccounts {
+        let entry = |code: &str, name: &str| ChartEntry {
+            code: code.to_string(),
+            name: name.to_string(),
+        };
+        ChartOfAccounts {
+            entries: HashMap::from([
+                (LedgerAccount::Cash, entry("1000", "Cash")),
+                (LedgerAccount::Clearing, entry("1100", "Clearing")),
+                (LedgerAccount::ClientFunds, entry("2000", "Client funds")),
+                (
+                    LedgerAccount::ClientHeld,
+                    entry("2100", "Client held funds"),
+                ),
+                (LedgerAccount::Fees, entry("4000", "Fee income")),
+                (
+                    LedgerAccount::ChargebackLoss,
+                    entry("5000", "Chargeback losses"),
+                ),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChartRecord {
+    account: String,
+    code: String,
+    name: String,
+}
+
+/// read_chart reads a CSV with `account` (e.g. `client_funds`), `code` & `name` columns, which
+/// override the default codes & names of the accounts it lists
+pub fn read_chart<R: io::Read>(reader: R) -> Result<ChartOfAccounts> {
+    let mut chart = ChartOfAccounts::default();
+    let mut seen = Vec::new();
+    for result in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+    {
+        let ChartRecord {
+            account,
+            code,
+            name,
+        } = result?;
+        let account: LedgerAccount = account.parse()?;
+        if seen.contains(&account) {
+            bail!("ledger account {} is listed more than once", account);
+        }
+        seen.push(account);
+        chart.entries.insert(account, ChartEntry { code, name });
+    }
+    Ok(chart)
+}
+
+/// Posting is one side of a journal entry: a debit or a credit of an internal account. Every
+/// event in the log is recorded as a journal entry whose debits & credits balance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    /// The journal entry the posting belongs to, numbered in the order of the event log
+    pub entry: u64,
+    pub tx: u64,
+    /// The client the event belonged to, unless they've been erased
+    pub client: Option<u64>,
+    /// The erasure which forgot the client the event belonged to, if any
+    pub erasure: Option<u64>,
+    pub account: LedgerAccount,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+/// Holder is who a client liability is owed to: a client, or an erasure standing in for a
+/// forgotten client
+type Holder = (Option<u64>, Option<u64>);
+
+/// Ledger records events as double-entry postings. Every journal entry moves an amount from the
+/// credited account(s) to the debited one(s):
+///
+/// - deposits (& escrow deposits) debit cash & credit the client, as funds or held funds
+/// - withdrawals & payouts debit the client's funds & credit cash
+/// - disputes, resolves, releases & pending withdrawals move funds between the client's funds &
+///   held funds
+/// - pending deposits are held against clearing until they settle into cash, or fail
+/// - chargebacks & refunds pay the client's held funds back out of cash
+/// - adjustments are fees (or fee refunds) between the client's funds & fee income
+///
+/// A chargeback which leaves the client owing us, because they'd already withdrawn the funds, is
+/// written off to chargeback losses. Their client funds in the ledger are then zero, although
+/// their account's available balance is negative.
+pub struct Ledger {
+    postings: Vec<Posting>,
+    /// Each client's funds plus held funds, net of write offs
+    owed: HashMap<Holder, Decimal>,
+    entries: u64,
+}
+
+impl Default for Ledger {
+    fn default() -> Ledger {
+        Ledger::new()
+    }
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        Ledger {
+            postings: Vec::new(),
+            owed: HashMap::new(),
+            entries: 0,
+        }
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    /// post records an event as a journal entry
+    pub fn post(&mut self, event: &Transaction) -> Result<()> {
+        self.record(
+            (Some(event.client), None),
+            event.tx,
+            event.amount,
+            event.kind,
+        )
+    }
+
+    /// post_erased records an erased event as a journal entry under its erasure, so that the
+    /// forgotten client's amounts stay in the books without naming them
+    pub fn post_erased(&mut self, erasure: u64, tombstone: &Tombstone) -> Result<()> {
+        self.record(
+            (None, Some(erasure)),
+            tombstone.tx,
+            tombstone.amount,
+            tombstone.kind,
+        )
+    }
+
+    /// record records an event of a holder's as a journal entry, failing if what they're owed
+    /// overflows
+    fn record(
+        &mut self,
+        holder: Holder,
+        tx: u64,
+        amount: Decimal,
+        kind: TransactionKind,
+    ) -> Result<()> {
+        use LedgerAccount::*;
+
+        let mut legs: Vec<(LedgerAccount, LedgerAccount, Decimal)> = match kind {
+            TransactionKind::Deposit { .. } => vec![(Cash, ClientFunds, amount)],
+            TransactionKind::Escrow { .. } => vec![(Cash, ClientHeld, amount)],
+            TransactionKind::Withdrawal { .. } | TransactionKind::Payout { .. } => {
+                vec![(ClientFunds, Cash, amount)]
+            }
+            TransactionKind::Dispute | TransactionKind::PendingWithdrawal { .. } => {
+                vec![(ClientFunds, ClientHeld, amount)]
+            }
+            TransactionKind::Resolve
+            | TransactionKind::ForceResolve
+            | TransactionKind::Release
+            | TransactionKind::Fail { withdrawal: true } => {
+                vec![(ClientHeld, ClientFunds, amount)]
+            }
+            TransactionKind::ChargeBack
+            | TransactionKind::Refund
+            | TransactionKind::Settle { withdrawal: true } => vec![(ClientHeld, Cash, amount)],
+            TransactionKind::PendingDeposit { .. } => vec![(Clearing, ClientHeld, amount)],
+            TransactionKind::Settle { withdrawal: false } => {
+                vec![(Cash, Clearing, amount), (ClientHeld, ClientFunds, amount)]
+            }
+            TransactionKind::Fail { withdrawal: false } => vec![(ClientHeld, Clearing, amount)],
+            TransactionKind::Adjustment { .. } if amount < Decimal::from(0) => {
+                vec![(ClientFunds, Fees, -amount)]
+            }
+            TransactionKind::Adjustment { .. } => vec![(Fees, ClientFunds, amount)],
+            TransactionKind::Lock | TransactionKind::Unlock => vec![],
+        };
+
+        let owed = self.owed.entry(holder).or_default();
+        for (debit, credit, amount) in &legs {
+            for (account, sign) in [(debit, -1), (credit, 1)] {
+                if matches!(account, ClientFunds | ClientHeld) {
+                    *owed = owed
+                        .checked_add(amount * Decimal::from(sign))
+                        .ok_or_else(|| anyhow!("ledger balance overflowed posting tx {}", tx))?;
+                }
+            }
+        }
+        if kind == TransactionKind::ChargeBack && *owed < Decimal::from(0) {
+            let loss = (-*owed).min(amount);
+            *owed = owed
+                .checked_add(loss)
+                .ok_or_else(|| anyhow!("ledger balance overflowed posting tx {}", tx))?;
+            legs.push((ChargebackLoss, ClientFunds, loss));
+        }
+
+        self.entries += 1;
+        for (debit, credit, amount) in legs.into_iter().filter(|(_, _, a)| !a.is_zero()) {
+            for (account, debit, credit) in [
+                (debit, amount, Decimal::from(0)),
+                (credit, Decimal::from(0), amount),
+            ] {
+                self.postings.push(Posting {
+                    entry: self.entries,
+                    tx,
+                    client: holder.0,
+                    erasure: holder.1,
+                    account,
+                    debit,
+                    credit,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ledger records every event in the log, in order, followed by the events erased from it (see
+/// erasure::erased_events). Each erased client's events are recorded together, in the order they
+/// were logged, so their balances are the same as if they'd never been erased.
+pub fn ledger(events: &dyn EventsRepo, erased: &[(u64, Tombstone)]) -> Result<Ledger> {
+    let mut ledger = Ledger::new();
+    for event in events.get_all()? {
+        ledger.post(&event)?;
+    }
+    for (erasure, tombstone) in erased {
+        ledger.post_erased(*erasure, tombstone)?;
+    }
+    Ok(ledger)
+}
+
+/// PostingRecord is the CSV representation of a posting, with its account's code & name from the
+/// chart of accounts
+#[derive(Debug, Serialize)]
+pub struct PostingRecord<'a> {
+    pub entry: u64,
+    pub tx: u64,
+    pub client: Option<u64>,
+    pub erasure: Option<u64>,
+    pub account: LedgerAccount,
+    pub code: &'a str,
+    pub name: &'a str,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+/// write_postings writes postings as CSV
+pub fn write_postings<W: io::Write>(
+    writer: W,
+    postings: &[Posting],
+    chart: &ChartOfAccounts,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for posting in postings {
+        let entry = chart.entry(posting.account);
+        writer.serialize(PostingRecord {
+            entry: posting.entry,
+            tx: posting.tx,
+            client: posting.client,
+            erasure: posting.erasure,
+            account: posting.account,
+            code: &entry.code,
+            name: &entry.name,
+            debit: posting.debit,
+            credit: posting.credit,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::MemoryRepo as AccountsMemoryRepo;
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::payments::PaymentsEngine;
+    use crate::testkit::command;
+    use crate::transactions::MemoryRepo as TransactionsMemoryRepo;
+
+    #[test]
+    fn test_ledger() -> Result<()> {
+        let transactions = TransactionsMemoryRepo::new();
+        let accounts = AccountsMemoryRepo::new();
+        let events = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+        let process = |kind, tx, client| engine.process_transaction(command(kind, client, tx));
+        let d = Decimal::from;
+        process(TransactionKind::Deposit { amount: d(10) }, 1, 1)?;
+        process(TransactionKind::Withdrawal { amount: d(6) }, 2, 1)?;
+        process(TransactionKind::Dispute, 1, 1)?;
+        process(TransactionKind::ChargeBack, 1, 1)?;
+        process(TransactionKind::PendingDeposit { amount: d(5) }, 3, 2)?;
+        process(TransactionKind::Settle { withdrawal: false }, 3, 2)?;
+
+        let ledger = ledger(&events, &[])?;
+        let postings = ledger.postings();
+        // every entry balances
+        for entry in 1..=6 {
+            let (debits, credits) = postings
+                .iter()
+                .filter(|p| p.entry == entry)
+                .fold((d(0), d(0)), |(d, c), p| (d + p.debit, c + p.credit));
+            assert_eq!(debits, credits, "entry {}", entry);
+        }
+        let balance = |account| -> Decimal {
+            postings
+                .iter()
+                .filter(|p| p.account == account)
+                .map(|p| p.debit - p.credit)
+                .sum()
+        };
+        // client 1 withdrew 6 of the 10 which was charged back, so 6 is lost
+        assert_eq!(balance(LedgerAccount::ChargebackLoss), d(6));
+        assert_eq!(balance(LedgerAccount::Cash), d(-1));
+        assert_eq!(balance(LedgerAccount::Clearing), d(0));
+        assert_eq!(balance(LedgerAccount::ClientFunds), d(-5));
+        assert_eq!(balance(LedgerAccount::ClientHeld), d(0));
+        assert_eq!(
+            postings[6..8],
+            [
+                Posting {
+                    entry: 4,
+                    tx: 1,
+                    client: Some(1),
+                    erasure: None,
+                    account: LedgerAccount::ClientHeld,
+                    debit: d(10),
+                    credit: d(0),
+                },
+                Posting {
+                    entry: 4,
+                    tx: 1,
+                    client: Some(1),
+                    erasure: None,
+                    account: LedgerAccount::Cash,
+                    debit: d(0),
+                    credit: d(10),
+                },
+            ]
+        );
+
+        let chart = read_chart("account,code,name\ncash,1010,Operating account\n".as_bytes())?;
+        assert_eq!(chart.entry(LedgerAccount::Cash).code, "1010");
+        assert_eq!(chart.entry(LedgerAccount::Fees).code, "4000");
+
+        // balances which overflow are an error rather than a panic
+        let max = Decimal::max_value();
+        let mut overflowing = Ledger::new();
+        let deposit = |tx| Transaction {
+            tx,
+            client: 1,
+            amount: max,
+            kind: TransactionKind::Deposit { amount: max },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+        overflowing.post(&deposit(1))?;
+        assert!(overflowing.post(&deposit(2)).is_err());
+        assert!(read_chart("account,code,name\nbank,1010,Bank\n".as_bytes()).is_err());
+        assert!(read_chart("account,code,name\ncash,1,A\ncash,2,B\n".as_bytes()).is_err());
+
+        let mut out = Vec::new();
+        write_postings(&mut out, &postings[..2], &chart)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "entry,tx,client,erasure,account,code,name,debit,credit
+1,1,1,,cash,1010,Operating account,10,0
+1,1,1,,client_funds,2000,Client funds,0,10
+"
+        );
+        Ok(())
+    }
+}
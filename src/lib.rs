@@ -0,0 +1,75 @@
+//! The payments engine, which applies a stream of transactions to client accounts, along with the
+//! storage backends, input formats & reports built around it. The `payments` binary is the CLI over
+//! this library.
+
+pub mod accounts;
+pub mod activity;
+pub mod aml;
+pub mod archive;
+pub mod audit;
+pub mod auth;
+pub mod bench;
+pub mod beneficiaries;
+pub mod cache;
+pub mod chaos;
+pub mod chargebacks;
+pub mod chunked;
+pub mod clients;
+pub mod correlation;
+pub mod diff;
+pub mod domain;
+pub mod encryption;
+pub mod erasure;
+pub mod errorlog;
+pub mod events;
+pub mod exitcode;
+pub mod ffi;
+pub mod fuzz;
+pub mod generate;
+pub mod input;
+pub mod interchange;
+pub mod invariants;
+pub mod iso8583;
+pub mod journal;
+pub mod kyc;
+pub mod ledger;
+pub mod memory;
+pub mod merge;
+pub mod migrate;
+pub mod netting;
+pub mod openbanking;
+pub mod ownership;
+pub mod parallel;
+pub mod payments;
+pub mod periods;
+pub mod pipeline;
+pub mod progress;
+pub mod quarantine;
+pub mod ratelimit;
+pub mod reconcile;
+pub mod redact;
+pub mod rejections;
+pub mod repos;
+pub mod retention;
+pub mod rules;
+pub mod scenarios;
+pub mod schedules;
+pub mod schema;
+pub mod server;
+pub mod settlement;
+pub mod signature;
+pub mod simulation;
+pub mod snapshot;
+pub mod soak;
+pub mod spill;
+pub mod summary;
+pub mod suspense;
+pub mod tenant;
+pub mod testkit;
+pub mod threshold;
+pub mod timestamps;
+pub mod timings;
+pub mod transactions;
+pub mod trialbalance;
+pub mod usage;
+pub mod verify;
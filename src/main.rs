@@ -1,87 +1,2078 @@
 extern crate proc_macro;
 
-use anyhow::Result;
-use clap::Clap;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{ArgEnum, Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use rust_decimal::prelude::*;
 use serde::Serialize;
-use std::io;
-use tracing::{debug, error};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io::{self, IsTerminal};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn, Level};
+use tracing_subscriber::{fmt::time::ChronoUtc, EnvFilter};
 
-mod accounts;
-mod payments;
-mod transactions;
+use payments::{
+    accounts, activity, aml, audit, auth, bench, beneficiaries, chaos, chargebacks, chunked,
+    clients, correlation, diff, encryption, erasure, errorlog, events, exitcode, generate, input,
+    interchange, iso8583, journal, kyc, ledger, memory, merge, migrate, netting, openbanking,
+    ownership, parallel, periods, pipeline, progress, quarantine, ratelimit, reconcile, redact,
+    rejections, repos, rules, scenarios, schedules, schema, server, settlement, signature,
+    simulation, snapshot, soak, spill, summary, suspense, tenant, threshold, timestamps, timings,
+    transactions, trialbalance, usage, verify,
+};
 
-use accounts::{AccountsRepo, MemoryRepo as AccountsMemoryRepo};
-use payments::PaymentsEngine;
-use transactions::MemoryRepo as TransactionsMemoryRepo;
+use accounts::{Account, AccountStatement, AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+use aml::AmlConfig;
+use bench::{Backend, RowCount};
+use beneficiaries::Beneficiaries;
+use chaos::Faults;
+use chargebacks::Ratio;
+use chunked::ChunkedReader;
+use clients::ClientInfo;
+use correlation::CorrelationId;
+use encryption::Cipher;
+use errorlog::{ErrorLog, RejectionReport};
+use generate::{CommandRecord, Generator, GeneratorConfig};
+use input::{CommandReader, InputConfig, Row, RowError};
+use kyc::KycPolicy;
+use memory::MemoryConfig;
+use ownership::Ownership;
+use payments::payments::{EngineOptions, PaymentsEngine};
+use periods::Period;
+use progress::Progress;
+use quarantine::Quarantine;
+use ratelimit::{Rate, RateLimit};
+use reconcile::ReconcileError;
+use rejections::Rejections;
+use repos::Repos;
+use scenarios::{ScenarioDiff, ScenarioError, Spec};
+use schedules::ScheduledRecord;
+use settlement::SettlementFormat;
+use signature::{Signer, Verified};
+use simulation::{SimulationConfig, SimulationError};
+use snapshot::Snapshot;
+use soak::SoakConfig;
+use spill::MemoryLimit;
+use summary::Summary;
+use suspense::Suspense;
+use tenant::TenantId;
+use threshold::{ErrorRate, ErrorThreshold};
+use timestamps::{OutOfOrder, Reorder, TimestampPolicy};
+use timings::{Stage, Timed, Timings};
+use transactions::{MemoryRepo as TransactionsMemoryRepo, TransactionsRepo};
+use trialbalance::TrialBalanceFormat;
+use usage::MemoryUsage;
+use verify::{Until, VerifyError};
 
-#[derive(Clap)]
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[derive(Parser)]
 #[clap(version = "0.1.0", author = "Vance Longwill <vancelongwill@gmail.com>")]
 struct Opts {
+    #[clap(flatten)]
+    log: LogOpts,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct LogOpts {
+    /// Log at this level and above (error, warn, info, debug or trace), instead of the directives
+    /// in RUST_LOG
+    #[clap(long, global = true, conflicts_with = "verbose")]
+    log_level: Option<Level>,
+    /// Log more verbosely: -v for debug (including every rejected row), -vv for trace
+    #[clap(short, long, global = true, parse(from_occurrences))]
+    verbose: u8,
+    /// Log as human readable text, or as newline delimited JSON for ingestion by log pipelines
+    #[clap(long, global = true, arg_enum, default_value = "pretty")]
+    log_format: LogFormat,
+    /// Redact logs so that they're safe to ship to a third party: client IDs are replaced by a
+    /// hash keyed with PAYMENTS_REDACT_KEY (or a random key per run, if unset) and amounts by
+    /// their order of magnitude. The error log, statements & database keep full detail.
+    #[clap(long, global = true)]
+    redact_logs: bool,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl LogOpts {
+    fn filter(&self) -> EnvFilter {
+        let level = match (self.log_level, self.verbose) {
+            (Some(level), _) => level,
+            (None, 0) => return EnvFilter::from_default_env(),
+            (None, 1) => Level::DEBUG,
+            (None, _) => Level::TRACE,
+        };
+        // dependencies (e.g. sled) are kept quiet unless explicitly enabled through RUST_LOG
+        EnvFilter::new(format!("error,payments={}", level))
+    }
+
+    fn init(&self) {
+        if self.redact_logs {
+            redact::enable(redact::Redactor::new(&redact_key()));
+        }
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(self.filter())
+            .with_writer(io::stderr);
+        match self.log_format {
+            LogFormat::Pretty => subscriber.init(),
+            LogFormat::Json => subscriber
+                .json()
+                .with_current_span(true)
+                .with_timer(ChronoUtc::rfc3339())
+                .init(),
+        }
+    }
+}
+
+/// redact_key is the key client IDs are hashed with in redacted logs, so that they can be
+/// correlated across runs with the same key
+fn redact_key() -> Vec<u8> {
+    match std::env::var("PAYMENTS_REDACT_KEY") {
+        Ok(key) if !key.is_empty() => key.into_bytes(),
+        _ => {
+            let mut key = vec![0; 32];
+            ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut key)
+                .expect("system random number generator");
+            key
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process a transactions file and output the resulting account statements
+    Process(Box<ProcessOpts>),
+    /// Process a (fixed) quarantine file on top of the state produced by a previous run
+    Reprocess(ReprocessOpts),
+    /// Query the state of a single client
+    Query(QueryOpts),
+    /// Generate a reproducible transactions file for load testing & demos
+    Generate(GenerateOpts),
+    /// Write the transactions of the standing orders which are due on a date, to be processed
+    /// before that day's transactions file
+    RunSchedules(RunSchedulesOpts),
+    /// Convert a card switch's dump of ISO 8583 messages into a transactions file
+    Iso8583(Iso8583Opts),
+    /// Serve a gRPC API for submitting transactions & querying accounts one at a time
+    Serve(ServeOpts),
+    /// Measure throughput of each pipeline configuration on generated data
+    Bench(BenchOpts),
+    /// Check the engine against a reference model on seeded pseudo-random command sequences,
+    /// writing a minimal reproduction of the first divergence as a transactions file
+    Simulate(SimulateOpts),
+    /// Stream generated transactions at a steady rate for a long time, writing latency
+    /// percentiles & memory use every interval, to check that a long-running deployment stays
+    /// stable
+    Soak(SoakOpts),
+    /// Run every scenario (a directory with an input.csv & the expected.csv statements it should
+    /// produce) under a directory, writing how the statements of those which fail differ
+    TestScenarios(TestScenariosOpts),
+    /// Run scenario spec files (the accounts to start from, commands to submit & the balances they
+    /// should leave), writing every command & account whose outcome differs from its spec's
+    RunScenario(RunScenarioOpts),
+    /// Compare two statements files, reporting per-client balance deltas and lock changes
+    Diff(DiffOpts),
+    /// Process a transactions file and compare the resulting balances to an expected balances file
+    Reconcile(ReconcileOpts),
+    /// Replay the event log of a database or snapshot from scratch and verify that it reproduces
+    /// the persisted state, reporting every record which diverges
+    Audit(AuditOpts),
+    /// Rebuild account state from the event log of a database or snapshot, up to a transaction or
+    /// point in time, writing a statement per account
+    Replay(ReplayOpts),
+    /// Erase a client from a database, keeping their transactions' amounts as anonymous tombstones
+    Forget(ForgetOpts),
+    /// Merge one client's account into another's, e.g. when the same person was onboarded twice
+    Merge(MergeOpts),
+    /// Generate a shell completion script
+    Completions(CompletionsOpts),
+    /// Generate a man page in roff format
+    Man,
+    /// Print the JSON Schema of a transactions file row or a statements row, for partners to
+    /// validate their feeds against
+    Schema(SchemaOpts),
+}
+
+#[derive(Args)]
+struct ProcessOpts {
+    file: String,
+    #[clap(flatten)]
+    input: InputOpts,
+    #[clap(flatten)]
+    errors: ErrorOpts,
+    /// Persist state to the sled database at this path instead of keeping it in memory
+    #[clap(long)]
+    db: Option<String>,
+    /// Process transactions for this tenant (program), whose state is kept isolated from every
+    /// other tenant's in the database. Rows whose tenant column names another tenant are rejected.
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    /// Apply the transactions of joint owners to the accounts they own, as listed in this CSV
+    /// file (with account & client columns, where the account is its primary client's ID)
+    #[clap(long)]
+    owners: Option<String>,
+    /// Open accounts for these clients (comma separated) as escrow accounts, where deposits are
+    /// held until a release or refund row for the deposit's transaction
+    #[clap(long, use_value_delimiter = true)]
+    escrow: Vec<u64>,
+    /// Accept payouts only to the beneficiaries registered in this CSV file (with id & client
+    /// columns, where the client is the account the beneficiary is registered to)
+    #[clap(long)]
+    beneficiaries: Option<String>,
+    #[clap(flatten)]
+    timestamps: TimestampOpts,
+    /// Check every transaction against the invariants of the accounts before applying it,
+    /// rejecting (as negative_held or unbalanced) any which would leave a held balance negative or
+    /// change the accounts' total by other than the deposits less withdrawals & chargebacks
+    #[clap(long)]
+    check_invariants: bool,
+    /// Run the full pipeline without persisting anything to the database
+    #[clap(long)]
+    dry_run: bool,
+    /// Don't output the account statements
+    #[clap(long)]
+    no_statements: bool,
+    /// Move accounts which had no activity in this run and hold no funds in dispute to an archive,
+    /// which excludes them from statements until they see activity again
+    #[clap(long, requires = "db")]
+    archive_dormant: bool,
+    /// Restore every archived account before processing, so they're included in statements again
+    #[clap(long, requires = "db", conflicts_with = "archive-dormant")]
+    include_archived: bool,
+    /// Join the client metadata in this CSV file (with id, name, email & segment columns) into
+    /// the statements, as extra name, email & segment columns after the balances
+    #[clap(long)]
+    clients: Option<String>,
+    /// Write a snapshot of the resulting state to this file, which can later be resumed from
+    #[clap(long)]
+    snapshot: Option<String>,
+    /// Write each client's balance timeline to this file as CSV
+    #[clap(long)]
+    balance_history: Option<String>,
+    /// Split the event log into statement periods by the transactions' timestamps, daily or
+    /// monthly, writing each period's statements (opening balance, activity & closing balance per
+    /// client) to its own file in `--period-dir`
+    #[clap(long, requires = "period-dir")]
+    period: Option<Period>,
+    /// The directory to write each period's statements file to
+    #[clap(long, requires = "period")]
+    period_dir: Option<String>,
+    /// Write a summary of each client's activity (the count & volume of their transactions by
+    /// kind, their fees, and what became of their disputes) to this file as CSV
+    #[clap(long)]
+    activity: Option<String>,
+    /// Write the net amount owed to or by each client for the run to this settlement file, with
+    /// the batch totals & a control record, for the funds to be moved by the bank
+    #[clap(long)]
+    settlement: Option<String>,
+    /// The layout of the settlement file: csv, or fixed-width for 50 character records with
+    /// amounts in ten-thousandths
+    #[clap(long, default_value = "csv", requires = "settlement")]
+    settlement_format: SettlementFormat,
+    /// Net what each client is owed or owes for the run into instructions for clients to pay
+    /// each other directly, with the platform taking up any difference, and write them to this
+    /// file as CSV
+    #[clap(long)]
+    netting: Option<String>,
+    /// Write every event in the log as double-entry postings against our internal accounts to
+    /// this file as CSV
+    #[clap(long)]
+    ledger: Option<String>,
+    /// Write the total debits & credits, and closing balance, of each internal account in the
+    /// ledger to this file. The run fails if they don't balance.
+    #[clap(long)]
+    trial_balance: Option<String>,
+    /// The layout of the trial balance: csv, or json
+    #[clap(long, default_value = "csv", requires = "trial-balance")]
+    trial_balance_format: TrialBalanceFormat,
+    /// A CSV of the codes & names to post each internal account under in the ledger, trial
+    /// balance & GL export, with `account`, `code` & `name` columns. Accounts it doesn't list keep
+    /// their default code & name.
+    #[clap(long)]
+    chart_of_accounts: Option<String>,
+    /// Write the ledger as a journal entry for import into the general ledger to this file as CSV,
+    /// with a line of the total debits & credits per GL account code in the chart of accounts
+    #[clap(long)]
+    gl_export: Option<String>,
+    /// The ID of the journal entry in the GL export
+    #[clap(long, default_value = "payments", requires = "gl-export")]
+    gl_journal: String,
+    /// The date of the journal entry in the GL export (YYYY-MM-DD), today by default
+    #[clap(long, requires = "gl-export")]
+    gl_date: Option<NaiveDate>,
+    /// Process the file across this many threads, partitioned by client. Transaction IDs must be
+    /// unique across all clients.
+    #[clap(long, default_value = "1", conflicts_with = "db")]
+    workers: usize,
+    /// Hash IDs in memory with FxHash, which is faster than the default but not resistant to
+    /// maliciously crafted input
+    #[clap(long, conflicts_with = "db")]
+    fast_hash: bool,
+    /// Store transactions in memory in a vector indexed by ID, which uses less memory when IDs
+    /// are mostly sequential
+    #[clap(long, conflicts_with = "db")]
+    dense_transactions: bool,
+    /// Bound the memory used for transactions & events (e.g. 2G), spilling the least recently
+    /// used to disk
+    #[clap(long, conflicts_with = "db")]
+    memory_limit: Option<MemoryLimit>,
+    /// Cache this many of the most recently used transactions & accounts in memory, in front of
+    /// the database
+    #[clap(long, requires = "db")]
+    cache_size: Option<usize>,
+    /// Measure the time spent parsing, looking up & saving state, applying transactions and
+    /// writing statements, and include it in the summary. Adds a small overhead per row.
+    #[clap(long)]
+    timings: bool,
+    /// Evaluate every deposit & withdrawal against the limits declared in this JSON rules file,
+    /// rejecting or flagging transactions which exceed them
+    #[clap(long)]
+    rules: Option<String>,
+    /// Write the transactions flagged by rules, or as out of order, to this file as CSV
+    #[clap(long)]
+    flagged: Option<String>,
+    /// Check every deposit & withdrawal against the clients' KYC status in this CSV file (with
+    /// client & status columns, where status is verified, pending or blocked). Clients which
+    /// aren't verified can't withdraw, and can only deposit up to --unverified-deposit-limit.
+    #[clap(long)]
+    kyc: Option<String>,
+    /// The total clients which aren't verified may deposit
+    #[clap(long, default_value = "1000", requires = "kyc")]
+    unverified_deposit_limit: Decimal,
+    #[clap(flatten)]
+    aml: AmlOpts,
+    /// Write every client's chargebacks as a ratio of their deposits, by count & value, to this
+    /// file as CSV
+    #[clap(long)]
+    chargeback_report: Option<String>,
+    /// Flag clients in the chargeback report whose chargeback ratio exceeds this, as a percentage
+    /// (e.g. 0.9%) or a ratio (e.g. 0.009)
+    #[clap(long, default_value = "1%", requires = "chargeback-report")]
+    max_chargeback_ratio: Ratio,
+    /// Write the interchange charged on every deposit, withdrawal, payout & chargeback, as given
+    /// by the --interchange-model, to this file as CSV
+    #[clap(long, requires = "interchange-model")]
+    interchange: Option<String>,
+    /// A JSON interchange config, with the rates (a percentage plus a fixed amount) charged per
+    /// network & type of transaction, and the network of each client
+    #[clap(long, requires = "interchange")]
+    interchange_model: Option<String>,
+    /// Write every account's closing balances to this file, as a UK Open Banking balances
+    /// response (JSON)
+    #[clap(long)]
+    open_banking_balances: Option<String>,
+    /// Write every change to each account's balance to this file, as a UK Open Banking
+    /// transactions response (JSON)
+    #[clap(long)]
+    open_banking_transactions: Option<String>,
+    /// The ISO 4217 currency of amounts in the Open Banking output
+    #[clap(long, default_value = "GBP")]
+    open_banking_currency: String,
+}
+
+impl ProcessOpts {
+    fn memory(&self) -> MemoryConfig {
+        MemoryConfig {
+            fast_hash: self.fast_hash,
+            dense_transactions: self.dense_transactions,
+            limit: self.memory_limit,
+        }
+    }
+}
+
+/// TimestampOpts controls how rows which are out of order are handled, when the input has a
+/// timestamp column
+#[derive(Args)]
+struct TimestampOpts {
+    /// Treat rows as out of order when their timestamp is more than this many seconds earlier than
+    /// the latest row of the same client
+    #[clap(long, default_value = "0")]
+    timestamp_tolerance: u32,
+    /// What to do with rows which are out of order: reject them, flag them (accepting them) or
+    /// reorder them, buffering rows for --reorder-window and rejecting rows later than that
+    #[clap(long, default_value = "reject", possible_values = &["reject", "flag", "reorder"])]
+    out_of_order: OutOfOrder,
+    /// How many seconds to buffer rows for when reordering them
+    #[clap(long, default_value = "60")]
+    reorder_window: u32,
+}
+
+impl TimestampOpts {
+    fn policy(&self) -> TimestampPolicy {
+        TimestampPolicy {
+            tolerance: chrono::Duration::seconds(self.timestamp_tolerance.into()),
+            out_of_order: self.out_of_order,
+            window: chrono::Duration::seconds(self.reorder_window.into()),
+        }
+    }
+}
+
+#[derive(Args)]
+struct AmlOpts {
+    /// Write the clients whose activity looks suspicious (deposits structured just under the
+    /// threshold, or large movements in total) to this file as CSV, for review by compliance
+    #[clap(long)]
+    sar: Option<String>,
+    /// The reporting threshold which structured deposits are kept just under
+    #[clap(long, default_value = "10000", requires = "sar")]
+    aml_threshold: Decimal,
+    /// How far under the threshold a deposit counts as structured, as a proportion of it
+    #[clap(long, default_value = "0.1", requires = "sar")]
+    aml_margin: Decimal,
+    /// The number of structured deposits which makes a client suspicious
+    #[clap(long, default_value = "3", requires = "sar")]
+    aml_min_structured: u64,
+    /// The total deposited & withdrawn which makes a client suspicious
+    #[clap(long, default_value = "50000", requires = "sar")]
+    aml_large_total: Decimal,
+}
+
+impl AmlOpts {
+    fn config(&self) -> AmlConfig {
+        AmlConfig {
+            threshold: self.aml_threshold,
+            margin: self.aml_margin,
+            min_structured: self.aml_min_structured,
+            large_total: self.aml_large_total,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ReprocessOpts {
+    file: String,
+    /// The state to apply the rows on top of, either a sled database directory or a snapshot file
+    #[clap(long)]
+    resume: String,
+    /// Process transactions for this tenant (program), whose state is kept isolated from every
+    /// other tenant's in the database. Rows whose tenant column names another tenant are rejected.
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    /// Apply the transactions of joint owners to the accounts they own, as listed in this CSV
+    /// file (with account & client columns, where the account is its primary client's ID)
+    #[clap(long)]
+    owners: Option<String>,
+    /// Open accounts for these clients (comma separated) as escrow accounts, where deposits are
+    /// held until a release or refund row for the deposit's transaction
+    #[clap(long, use_value_delimiter = true)]
+    escrow: Vec<u64>,
+    /// Accept payouts only to the beneficiaries registered in this CSV file (with id & client
+    /// columns, where the client is the account the beneficiary is registered to)
+    #[clap(long)]
+    beneficiaries: Option<String>,
+    #[clap(flatten)]
+    timestamps: TimestampOpts,
+    /// Check every transaction against the invariants of the accounts before applying it,
+    /// rejecting (as negative_held or unbalanced) any which would leave a held balance negative or
+    /// change the accounts' total by other than the deposits less withdrawals & chargebacks
+    #[clap(long)]
+    check_invariants: bool,
+    #[clap(flatten)]
+    input: InputOpts,
+    #[clap(flatten)]
+    errors: ErrorOpts,
+    /// Write a snapshot of the resulting state to this file
+    #[clap(long)]
+    snapshot: Option<String>,
+    /// Join the client metadata in this CSV file (with id, name, email & segment columns) into
+    /// the statements, as extra name, email & segment columns after the balances
+    #[clap(long)]
+    clients: Option<String>,
+}
+
+#[derive(Args)]
+struct GenerateOpts {
+    #[clap(long, default_value = "1000")]
+    clients: u64,
+    #[clap(long, default_value = "10000")]
+    rows: u64,
+    /// Probability of a row disputing an earlier deposit
+    #[clap(long, default_value = "0.01")]
+    dispute_rate: f64,
+    /// Probability of a row being invalid
+    #[clap(long, default_value = "0.001")]
+    invalid_rate: f64,
+    #[clap(long, default_value = "0")]
+    seed: u64,
+    /// Write to this file instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+    /// Sign every row with the key in PAYMENTS_SIGNING_KEY, adding a signature column
+    #[clap(long)]
+    sign: bool,
+}
+
+#[derive(Args)]
+struct RunSchedulesOpts {
+    /// The standing orders, with id, client, amount, frequency (daily, weekly or monthly), start
+    /// (YYYY-MM-DD) & optionally beneficiary columns. Orders naming a beneficiary are payouts.
+    schedules: String,
+    /// The date (YYYY-MM-DD) to write the due transactions of
+    #[clap(long)]
+    as_of: NaiveDate,
+    /// Write to this file instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+}
+
+#[derive(Args)]
+struct Iso8583Opts {
+    /// The dump, with one ASCII encoded ISO 8583:1987 message per line and hex encoded bitmaps
+    dump: String,
+    /// Write to this file instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+}
+
+#[derive(Args)]
+struct ServeOpts {
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:50051")]
+    addr: SocketAddr,
+    /// Persist state to the sled database at this path instead of keeping it in memory
+    #[clap(long)]
+    db: Option<String>,
+    /// Serve this tenant (program), whose state is kept isolated from every other tenant's in the
+    /// database
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    /// Restore in-memory state from this snapshot file at startup if it exists, and save it back
+    /// on shutdown
+    #[clap(long, conflicts_with = "db")]
+    snapshot: Option<String>,
+    /// Also save the snapshot every this many seconds while running, so that less is lost if the
+    /// process is killed without a chance to shut down
+    #[clap(long, requires = "snapshot")]
+    snapshot_interval: Option<u64>,
+    /// Evict transactions once they've been settled (i.e. not under dispute) for this many days,
+    /// after which they can no longer be disputed. Evicted transaction IDs are still rejected as
+    /// duplicates.
+    #[clap(long)]
+    retention_days: Option<u64>,
+    /// Require an API key on every request, read from a CSV file with `name`, `key` and `scope`
+    /// (read, submit or admin) columns
+    #[clap(long)]
+    api_keys: Option<String>,
+    /// Serve over TLS with this PEM encoded certificate (chain)
+    #[clap(long, requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM encoded private key for the TLS certificate
+    #[clap(long, requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+    /// Require clients to present a certificate signed by this PEM encoded CA (mutual TLS)
+    #[clap(long, requires = "tls-cert")]
+    tls_client_ca: Option<PathBuf>,
+    /// Limit each client to this many submitted transactions per second on average, rejecting
+    /// the excess with RESOURCE_EXHAUSTED
+    #[clap(long)]
+    rate_limit: Option<Rate>,
+    /// Number of transactions a client can submit in a burst above --rate-limit
+    #[clap(long, requires = "rate-limit", default_value_t = 100)]
+    burst: u32,
+}
+
+#[derive(Args)]
+struct BenchOpts {
+    /// Number of rows to generate, optionally with a K or M suffix (e.g. 10M)
+    #[clap(long, default_value = "1M")]
+    rows: RowCount,
+    #[clap(long, default_value = "1000")]
+    clients: u64,
+    /// Number of threads for the multi-threaded configurations, defaulting to the number of CPUs
+    #[clap(long)]
+    workers: Option<usize>,
+    #[clap(long, default_value = "0")]
+    seed: u64,
+    #[clap(flatten)]
+    chaos: ChaosOpts,
+}
+
+#[derive(Args)]
+struct SoakOpts {
+    /// The target number of transactions per second
+    #[clap(long, default_value = "1000")]
+    rate: u64,
+    /// How many seconds to run for
+    #[clap(long, default_value = "60")]
+    duration: u64,
+    /// Write a sample every this many seconds
+    #[clap(long, default_value = "10")]
+    interval: u64,
+    #[clap(long, default_value = "1000")]
+    clients: u64,
+    #[clap(long, default_value = "0")]
+    seed: u64,
+    /// Where to store state: in memory, or in a temporary sled database
+    #[clap(long, default_value = "memory", possible_values = &["memory", "sled"])]
+    backend: String,
+    /// Bound the memory used for transactions & events with the memory backend (e.g. 2G),
+    /// spilling the least recently used to disk
+    #[clap(long)]
+    memory_limit: Option<MemoryLimit>,
+    /// Cache up to this many accounts & transactions in memory with the sled backend
+    #[clap(long)]
+    cache: Option<usize>,
+}
+
+/// ChaosOpts injects storage faults, to check how the engine copes with misbehaving storage. Only
+/// the single-threaded configurations are benchmarked in chaos mode.
+#[derive(Args)]
+struct ChaosOpts {
+    /// Fail this fraction of repository gets & saves (chaos mode)
+    #[clap(long)]
+    chaos_rate: Option<f64>,
+    /// Delay every repository get & save by this many milliseconds (chaos mode)
+    #[clap(long)]
+    chaos_latency_ms: Option<u64>,
+}
+
+impl ChaosOpts {
+    /// faults is the faults to inject with the given seed, if chaos mode is enabled
+    fn faults(&self, seed: u64) -> Option<Faults> {
+        if self.chaos_rate.is_none() && self.chaos_latency_ms.is_none() {
+            return None;
+        }
+        Some(Faults {
+            rate: self.chaos_rate.unwrap_or(0.0),
+            latency: self.chaos_latency_ms.map(Duration::from_millis),
+            seed,
+        })
+    }
+}
+
+#[derive(Args)]
+struct SimulateOpts {
+    /// Seed of the first sequence
+    #[clap(long, default_value = "0")]
+    seed: u64,
+    /// Number of sequences to check, each with the next seed
+    #[clap(long, default_value = "100")]
+    runs: u64,
+    /// Number of commands in each sequence
+    #[clap(long, default_value = "200")]
+    steps: usize,
+    #[clap(long, default_value = "3")]
+    clients: u64,
+    #[clap(flatten)]
+    chaos: ChaosOpts,
+}
+
+#[derive(Args)]
+struct TestScenariosOpts {
+    dir: PathBuf,
+}
+
+#[derive(Args)]
+struct RunScenarioOpts {
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct DiffOpts {
+    old: String,
+    new: String,
+}
+
+#[derive(Args)]
+struct AuditOpts {
+    /// The state to verify, either a sled database directory or a snapshot file
+    state: String,
+    /// Verify the state of this tenant (program)
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    /// Also verify the statements written by the run which produced the state
+    #[clap(long)]
+    statements: Option<String>,
+}
+
+#[derive(Args)]
+struct ReplayOpts {
+    /// The state whose event log to replay, either a sled database directory or a snapshot file
+    state: String,
+    /// Replay the event log of this tenant (program)
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    /// Stop after the event creating this transaction ID, or after the last event at or before
+    /// this RFC 3339 timestamp
+    #[clap(long)]
+    until: Option<Until>,
+    /// Verify that the replayed accounts & transactions match this snapshot file, e.g. one saved
+    /// at the point replayed until
+    #[clap(long)]
+    expect: Option<String>,
+}
+
+#[derive(Args)]
+struct ReconcileOpts {
     file: String,
+    #[clap(flatten)]
+    input: InputOpts,
+    #[clap(flatten)]
+    errors: ErrorOpts,
+    /// CSV file of expected balances, with `client` and `total` columns
+    #[clap(long)]
+    expected: String,
+    /// Persist state to the sled database at this path instead of keeping it in memory
+    #[clap(long)]
+    db: Option<String>,
+    /// Process transactions for this tenant (program), whose state is kept isolated from every
+    /// other tenant's in the database. Rows whose tenant column names another tenant are rejected.
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    /// Run the full pipeline without persisting anything to the database
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct ForgetOpts {
+    /// The client to erase
+    #[clap(long)]
+    client: u64,
+    /// The sled database to erase the client from
+    #[clap(long)]
+    db: String,
+    /// The tenant the client belongs to
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    /// Append the erasure receipt to this audit log, as a JSON object
+    #[clap(long)]
+    audit_log: PathBuf,
+}
+
+#[derive(Args)]
+struct MergeOpts {
+    /// The client whose account is merged, and removed
+    #[clap(long)]
+    from: u64,
+    /// The client whose account the balances & transactions are merged into
+    #[clap(long)]
+    into: u64,
+    /// The sled database holding both accounts
+    #[clap(long)]
+    db: String,
+    /// The tenant the clients belong to
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    /// Append the merge receipt to this audit log, as a JSON object
+    #[clap(long)]
+    audit_log: PathBuf,
+}
+
+#[derive(Args)]
+struct CompletionsOpts {
+    #[clap(arg_enum)]
+    shell: Shell,
+}
+
+#[derive(Args)]
+struct SchemaOpts {
+    #[clap(arg_enum, default_value = "input")]
+    format: SchemaFormat,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum SchemaFormat {
+    /// A row of a transactions file
+    Input,
+    /// A row of the statements output
+    Statement,
 }
 
+#[derive(Args)]
+struct QueryOpts {
+    #[clap(subcommand)]
+    command: QueryCommand,
+}
+
+#[derive(Subcommand)]
+enum QueryCommand {
+    /// Output a client's account statement
+    Account(AccountQueryOpts),
+    /// List a client's stored transactions
+    History(HistoryQueryOpts),
+}
+
+/// InputOpts controls how a transactions file is read
+#[derive(Args)]
+struct InputOpts {
+    /// The input has no header row, columns are read in the order: type, client, tx, amount
+    #[clap(long)]
+    no_headers: bool,
+    /// Field delimiter
+    #[clap(long, default_value = ",")]
+    delimiter: char,
+    /// Quote character
+    #[clap(long, default_value = "\"")]
+    quote: char,
+    /// Escape character for quotes within quoted fields, instead of doubling them
+    #[clap(long)]
+    escape: Option<char>,
+    /// Amounts use a comma as their decimal mark, e.g. `1,5`
+    #[clap(long)]
+    decimal_comma: bool,
+    /// Reject fields with surrounding whitespace, unknown columns, rows with a differing number
+    /// of fields and transaction types which aren't lowercase, rather than tolerating them
+    #[clap(long)]
+    strict_parsing: bool,
+    /// Parse fields directly instead of deserializing each row, which is faster on large files
+    #[clap(long)]
+    fast_parse: bool,
+    /// Memory-map the file & parse chunks of it across this many threads. Quoted fields may
+    /// contain line breaks, but quotes must enclose whole fields.
+    #[clap(long, default_value = "1")]
+    parse_threads: usize,
+    /// Reject every row which isn't signed with the key in PAYMENTS_SIGNING_KEY, i.e. whose
+    /// signature column isn't the hex encoded HMAC-SHA256 of its other fields, each preceded by
+    /// its header and all encoded as netstrings (e.g. `4:type,7:deposit,`)
+    #[clap(long)]
+    verify_signatures: bool,
+}
+
+/// Rows is every row of a transactions file, in order
+type Rows = Box<dyn Iterator<Item = Result<Row, RowError>> + Send>;
+
+impl InputOpts {
+    fn config(&self) -> Result<InputConfig> {
+        let byte = |c: char| -> Result<u8> {
+            if !c.is_ascii() {
+                return Err(anyhow!("'{}' must be a single ASCII character", c));
+            }
+            Ok(c as u8)
+        };
+        Ok(InputConfig {
+            has_headers: !self.no_headers,
+            delimiter: byte(self.delimiter)?,
+            quote: byte(self.quote)?,
+            escape: self.escape.map(byte).transpose()?,
+            decimal_comma: self.decimal_comma,
+            strict: self.strict_parsing,
+            fast: self.fast_parse,
+        })
+    }
+
+    /// open opens the transactions file at `path`, returning its headers & rows, with a progress
+    /// bar when stderr is a terminal
+    fn open(&self, path: &str) -> Result<(csv::StringRecord, Rows)> {
+        let config = self.config()?;
+        let (headers, rows): (_, Rows) = if self.parse_threads > 1 {
+            let reader = ChunkedReader::from_path(path, config, self.parse_threads)?;
+            (reader.headers().clone(), Box::new(reader.rows()))
+        } else {
+            let reader = CommandReader::from_path(path, config)?;
+            (reader.headers().clone(), Box::new(reader))
+        };
+        let rows: Rows = if self.verify_signatures {
+            Box::new(Verified::new(rows, &headers, Signer::from_env()?))
+        } else {
+            rows
+        };
+        if !io::stderr().is_terminal() {
+            return Ok((headers, rows));
+        }
+        let len = fs::metadata(path)?.len();
+        Ok((headers, Box::new(Progress::new(rows, len))))
+    }
+}
+
+/// ErrorOpts controls how rows which can't be parsed or processed are handled
+#[derive(Args, Default)]
+struct ErrorOpts {
+    /// Write every rejected row to this file, verbatim with an additional error column
+    #[clap(long)]
+    quarantine: Option<String>,
+    /// Write a JSON object describing every rejected row to this file, one per line
+    #[clap(long)]
+    error_log: Option<String>,
+    /// Write every rejected row to this file as CSV, with its line, tx, client, correlation ID and
+    /// a stable code classifying why it was rejected (e.g. duplicate_tx or unknown_tx)
+    #[clap(long)]
+    rejection_report: Option<String>,
+    /// Abort with a nonzero exit code on the first row which can't be parsed or processed,
+    /// instead of skipping it
+    #[clap(long)]
+    strict: bool,
+    /// Abort with a nonzero exit code once more than this many rows have been rejected
+    #[clap(long)]
+    max_errors: Option<u64>,
+    /// Fail with a nonzero exit code if more than this proportion of rows are rejected, either as
+    /// a percentage (e.g. 0.5%) or a ratio (e.g. 0.005)
+    #[clap(long)]
+    max_error_rate: Option<ErrorRate>,
+    /// Hold disputes, resolves & chargebacks of transactions we have no record of in this suspense
+    /// file as CSV, with the amount given on their row (if any), instead of rejecting them
+    #[clap(long)]
+    suspense: Option<String>,
+}
+
+impl ErrorOpts {
+    /// rejections creates the handler for rejected rows, creating the quarantine file with the
+    /// same columns & delimiter as the input
+    fn rejections(&self, headers: &csv::StringRecord, delimiter: u8) -> Result<Rejections> {
+        let quarantine = match &self.quarantine {
+            Some(path) => Some(Quarantine::create(path, headers, delimiter)?),
+            None => None,
+        };
+        let error_log = match &self.error_log {
+            Some(path) => Some(ErrorLog::create(path)?),
+            None => None,
+        };
+        let threshold = ErrorThreshold {
+            max_errors: self.max_errors,
+            max_rate: self.max_error_rate,
+        };
+        let mut rejections = Rejections::new(quarantine, error_log, threshold, self.strict);
+        if let Some(path) = &self.rejection_report {
+            rejections = rejections.with_report(RejectionReport::create(path)?);
+        }
+        Ok(match &self.suspense {
+            Some(path) => rejections.with_suspense(Suspense::create(path, headers)?),
+            None => rejections,
+        })
+    }
+}
+
+/// SourceOpts selects where a query reads its state from
+#[derive(Args)]
+struct SourceOpts {
+    /// Process this transactions file in memory before querying
+    #[clap(long, required_unless_present = "db", conflicts_with = "db")]
+    file: Option<String>,
+    /// Query the state persisted in the sled database at this path
+    #[clap(long)]
+    db: Option<String>,
+    /// Query the state of this tenant (program)
+    #[clap(long)]
+    tenant: Option<TenantId>,
+    #[clap(flatten)]
+    input: InputOpts,
+}
+
+#[derive(Args)]
+struct AccountQueryOpts {
+    client: u64,
+    #[clap(flatten)]
+    source: SourceOpts,
+    /// Reconstruct the client's account as it was immediately after this transaction id, or at
+    /// this RFC 3339 timestamp
+    #[clap(long)]
+    as_of: Option<Until>,
+    /// Write the client's transactions which are currently under dispute to this file as CSV
+    #[clap(long)]
+    disputes: Option<String>,
+}
+
+#[derive(Args)]
+struct HistoryQueryOpts {
+    client: u64,
+    #[clap(flatten)]
+    source: SourceOpts,
+    /// Only list transactions which were created as this kind
+    #[clap(
+        long,
+        possible_values = &[
+            "deposit", "withdrawal", "payout", "escrow", "pending_deposit", "pending_withdrawal"
+        ]
+    )]
+    kind: Option<String>,
+    /// Only list transactions which are currently in this state
+    #[clap(
+        long,
+        possible_values = &[
+            "deposit", "withdrawal", "payout", "dispute", "resolve", "chargeback", "escrow", "release",
+            "refund", "pending_deposit", "pending_withdrawal", "settle", "fail"
+        ]
+    )]
+    state: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryStatement {
+    tx: u64,
+    #[serde(rename = "type")]
+    kind: String,
+    state: String,
+    amount: Decimal,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+struct DisputeStatement {
+    tx: u64,
+    amount: Decimal,
+}
+
+/// ExtendedStatement is an account statement joined with the client's metadata. Clients missing
+/// from the metadata file have empty metadata columns.
 #[derive(Debug, Serialize)]
-struct AccountStatement {
-    client: u16,
+struct ExtendedStatement {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<TenantId>,
+    client: u64,
     available: Decimal,
     held: Decimal,
     total: Decimal,
     locked: bool,
+    name: Option<String>,
+    email: Option<String>,
+    segment: Option<String>,
 }
 
-fn run() -> Result<()> {
-    let opts: Opts = Opts::parse();
+impl ExtendedStatement {
+    fn new(acc: Account, info: Option<&ClientInfo>) -> ExtendedStatement {
+        let AccountStatement {
+            tenant,
+            client,
+            available,
+            held,
+            total,
+            locked,
+        } = AccountStatement::from(acc);
+        ExtendedStatement {
+            tenant,
+            client,
+            available,
+            held,
+            total,
+            locked,
+            name: info.map(|info| info.name.clone()),
+            email: info.map(|info| info.email.clone()),
+            segment: info.map(|info| info.segment.clone()),
+        }
+    }
+}
+
+/// process_file feeds every transaction command in the CSV file at `path` through the engine.
+/// Rows which can't be parsed or processed are skipped, unless running in strict mode or too many
+/// of them are rejected. Returns a summary of the rows read, accepted & rejected.
+fn process_file(
+    engine: &PaymentsEngine,
+    path: &str,
+    input: &InputOpts,
+    errors: &ErrorOpts,
+) -> Result<Summary> {
+    let (headers, mut rows) = input.open(path)?;
+    let mut rejections = errors.rejections(&headers, input.config()?.delimiter)?;
+    if let Some(timings) = engine.timings() {
+        rows = Box::new(Timed::new(rows, timings.clone(), Stage::Parse));
+    }
+    if let Some(window) = engine.reorder_window() {
+        rows = Box::new(Reorder::new(rows, window));
+    }
 
-    let mut reader = csv::Reader::from_path(opts.file)?;
-    // @TODO: as we scale, the in-memory repositories might no longer be suitable due to
-    // memory constraints & cold start (loading all transactions that ever occurred into memory
-    // from CSV vs snapshotting the state at a known point in time).
-    //
-    // To mitigate this, the in-memory implementations can be easily swapped out for ones
-    // utilising a db with a higher capacity & more durable storage backend (e.g. sqlite, redis, postgres or dynamodb).
-    // Migrating to one of the above storage backends is as simple as implementing the
-    // AccountsRepo/TransactionsRepo traits respectively.
-    let transactions_repo = TransactionsMemoryRepo::new();
-    let accounts_repo = AccountsMemoryRepo::new();
-    let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo);
-
-    for result in reader.deserialize() {
-        let command = result?;
-        match engine.process_transaction(command) {
-            Ok(()) => debug!(
-                tx = command.tx,
-                client = command.client,
-                "Processed transaction"
-            ),
-            Err(e) => debug!(
-                error = e.to_string(),
-                tx = command.tx,
-                client = command.client,
-                "Unable to process transaction"
-            ),
+    let mut summary = Summary::default();
+    for result in pipeline::read_ahead(rows) {
+        summary.rows += 1;
+        match result {
+            Ok(Row {
+                command,
+                position,
+                record,
+            }) => match engine.process_transaction(command) {
+                Ok(flags) => {
+                    summary.accept(&command);
+                    summary.flagged.extend(flags);
+                    debug!(
+                        tx = command.tx,
+                        client = %redact::client(command.client),
+                        correlation_id = command.correlation.as_ref().map(CorrelationId::as_str),
+                        "Processed transaction"
+                    )
+                }
+                Err(e) => rejections.reject(e, &record, position, Some(command))?,
+            },
+            Err(e @ RowError { record: None, .. }) => return Err(e.into()),
+            Err(RowError {
+                position,
+                record: Some(record),
+                error,
+            }) => rejections.reject(error, &record, position, None)?,
         }
     }
 
+    rejections.finish(summary.rows)?;
+    summary.suspend(rejections.suspense.as_ref());
+    summary.rejected = rejections.reasons;
+    Ok(summary)
+}
+
+/// process_file_partitioned processes the transactions file across multiple threads, each
+/// owning a partition of the clients, then merges the state of every partition into `repos`
+fn process_file_partitioned(
+    repos: &Repos,
+    opts: &ProcessOpts,
+    options: EngineOptions,
+) -> Result<Summary> {
+    let (headers, mut rows) = opts.input.open(&opts.file)?;
+    if let Some(timings) = &options.timings {
+        rows = Box::new(Timed::new(rows, timings.clone(), Stage::Parse));
+    }
+    if let Some(window) = options
+        .timestamps
+        .as_ref()
+        .and_then(TimestampPolicy::reorder_window)
+    {
+        rows = Box::new(Reorder::new(rows, window));
+    }
+    let delimiter = opts.input.config()?.delimiter;
+    let rejections = Mutex::new(opts.errors.rejections(&headers, delimiter)?);
+
+    let (snapshot, mut summary) = parallel::process_partitioned(
+        pipeline::read_ahead(rows),
+        opts.workers,
+        opts.memory(),
+        &rejections,
+        options,
+    )?;
+    snapshot.restore(
+        repos.transactions.as_ref(),
+        repos.accounts.as_ref(),
+        repos.events.as_ref(),
+    )?;
+    let rejections = rejections
+        .into_inner()
+        .map_err(|_| anyhow!("rejections lock poisoned"))?;
+    summary.suspend(rejections.suspense.as_ref());
+    summary.rejected = rejections.reasons;
+    Ok(summary)
+}
+
+/// write_statements writes a statement per account to stdout, joined with each client's metadata
+/// if there is any
+fn write_statements(
+    accounts: Vec<Account>,
+    clients: Option<&HashMap<u64, ClientInfo>>,
+) -> Result<()> {
+    match clients {
+        Some(clients) => write_rows(accounts.into_iter().map(|acc| {
+            let info = clients.get(&acc.client());
+            ExtendedStatement::new(acc, info)
+        })),
+        None => write_rows(accounts.into_iter().map(AccountStatement::from)),
+    }
+}
+
+/// write_rows writes rows to stdout as CSV, on a separate thread to the one producing them
+fn write_rows<T: Serialize + Send + 'static>(rows: impl Iterator<Item = T>) -> Result<()> {
+    let mut writer = pipeline::write_behind(|rows: &mut dyn Iterator<Item = T>| {
+        let mut writer = csv::Writer::from_writer(io::stdout());
+        for row in rows {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    });
+    for row in rows {
+        writer.send(row)?;
+    }
+    writer.finish()
+}
+
+/// read_owners reads the joint owners file at `path`, if there is one
+fn read_owners(path: Option<&str>) -> Result<Option<Arc<Ownership>>> {
+    path.map(|path| {
+        let ownership = ownership::read_owners(File::open(path)?)
+            .with_context(|| format!("unable to read joint owners from {}", path))?;
+        Ok(Arc::new(ownership))
+    })
+    .transpose()
+}
+
+/// read_beneficiaries reads the registered beneficiaries file at `path`, if there is one
+fn read_beneficiaries(path: Option<&str>) -> Result<Option<Arc<Beneficiaries>>> {
+    path.map(|path| {
+        let beneficiaries = beneficiaries::read_beneficiaries(File::open(path)?)
+            .with_context(|| format!("unable to read beneficiaries from {}", path))?;
+        Ok(Arc::new(beneficiaries))
+    })
+    .transpose()
+}
+
+/// escrow_accounts is the set of accounts to open as escrow accounts, if there are any
+fn escrow_accounts(clients: &[u64]) -> Option<Arc<HashSet<u64>>> {
+    (!clients.is_empty()).then(|| Arc::new(clients.iter().copied().collect()))
+}
+
+/// read_clients reads the client metadata file at `path`, if there is one
+fn read_clients(path: Option<&str>) -> Result<Option<HashMap<u64, ClientInfo>>> {
+    path.map(|path| {
+        clients::read_clients(File::open(path)?)
+            .with_context(|| format!("unable to read client metadata from {}", path))
+    })
+    .transpose()
+}
+
+fn process(opts: ProcessOpts) -> Result<()> {
+    let start = Instant::now();
+    let mut repos = match &opts.db {
+        Some(_) if opts.dry_run => Repos::open_unmigrated(opts.db.as_deref(), opts.tenant)?,
+        Some(_) => Repos::open(opts.db.as_deref(), opts.tenant)?,
+        None => Repos::memory(opts.memory(), opts.tenant)?,
+    };
+    if let Some(capacity) = opts.cache_size {
+        repos = repos.cached(capacity);
+    }
+    let archive = repos.archive.clone();
+    // erased events are read from the database, even on a dry run
+    let db = repos.db.clone();
+    if opts.dry_run {
+        repos = repos.dry_run()?;
+    }
+    if opts.include_archived {
+        if let Some(archive) = &archive {
+            let restored = if opts.dry_run {
+                // restored into the dry run's overlay, leaving the archive itself untouched
+                let archived = archive.get_all()?;
+                for account in &archived {
+                    repos.accounts.save(*account)?;
+                }
+                archived.len()
+            } else {
+                archive.restore_all()?
+            };
+            info!(accounts = restored, "Restored archived accounts");
+        }
+    }
+    let timings = opts.timings.then(|| Arc::new(Timings::default()));
+    let rules = match &opts.rules {
+        Some(path) => Some(Arc::new(
+            rules::read_rules(File::open(path)?)
+                .with_context(|| format!("unable to read rules from {}", path))?,
+        )),
+        None => None,
+    };
+    let clients = read_clients(opts.clients.as_deref())?;
+    let kyc = match &opts.kyc {
+        Some(path) => Some(Arc::new(KycPolicy {
+            statuses: kyc::read_statuses(File::open(path)?)
+                .with_context(|| format!("unable to read KYC statuses from {}", path))?,
+            unverified_deposit_limit: opts.unverified_deposit_limit,
+        })),
+        None => None,
+    };
+    let options = EngineOptions {
+        timings: timings.clone(),
+        rules,
+        kyc,
+        tenant: repos.tenant,
+        ownership: read_owners(opts.owners.as_deref())?,
+        escrow: escrow_accounts(&opts.escrow),
+        beneficiaries: read_beneficiaries(opts.beneficiaries.as_deref())?,
+        timestamps: Some(opts.timestamps.policy()),
+        invariants: opts.check_invariants,
+    };
+    // archived accounts may be resurrected, so their balances are opening balances too
+    let mut accounts = repos.accounts.get_all()?;
+    if let (Some(archive), false) = (&archive, opts.include_archived) {
+        accounts = archive.including_archived(accounts)?;
+    }
+    let mut summary = if opts.workers > 1 {
+        process_file_partitioned(&repos, &opts, options)?
+    } else {
+        let engine = repos.engine().configure(options);
+        process_file(&engine, &opts.file, &opts.input, &opts.errors)?
+    };
+    repos.flush()?;
+    let after = repos.accounts.get_all()?;
+    summary.count_accounts(&accounts, &after);
+    let owed = settlement::settlement(&accounts, &after);
+    if let Some(path) = &opts.settlement {
+        settlement::write_settlement(File::create(path)?, &owed, opts.settlement_format)?;
+    }
+    if let Some(path) = &opts.netting {
+        let mut writer = csv::Writer::from_path(path)?;
+        for instruction in netting::net(&owed) {
+            writer.serialize(instruction)?;
+        }
+        writer.flush()?;
+    }
+    if opts.ledger.is_some() || opts.trial_balance.is_some() || opts.gl_export.is_some() {
+        let chart = match &opts.chart_of_accounts {
+            Some(path) => ledger::read_chart(File::open(path)?)
+                .with_context(|| format!("unable to read chart of accounts from {}", path))?,
+            None => ledger::ChartOfAccounts::default(),
+        };
+        let erased = match &db {
+            Some(db) => erasure::erased_events(db, repos.tenant, repos.cipher.as_ref())?,
+            None => Vec::new(),
+        };
+        let ledger = ledger::ledger(repos.events.as_ref(), &erased)?;
+        if let Some(path) = &opts.ledger {
+            ledger::write_postings(File::create(path)?, ledger.postings(), &chart)?;
+        }
+        if let Some(path) = &opts.gl_export {
+            let date = opts
+                .gl_date
+                .unwrap_or_else(|| Utc::now().naive_utc().date());
+            let lines = journal::journal(ledger.postings(), &chart, &opts.gl_journal, date);
+            journal::write_journal(File::create(path)?, &lines)?;
+        }
+        if let Some(path) = &opts.trial_balance {
+            let trial_balance = trialbalance::trial_balance(ledger.postings(), &chart);
+            trialbalance::write_trial_balance(
+                File::create(path)?,
+                &trial_balance,
+                opts.trial_balance_format,
+            )?;
+            if !trial_balance.balanced {
+                return Err(anyhow!(
+                    "trial balance doesn't balance: debits {} != credits {}",
+                    trial_balance.debits,
+                    trial_balance.credits
+                ));
+            }
+        }
+    }
+    if opts.archive_dormant {
+        if let Some(archive) = &repos.archive {
+            summary.accounts_archived = archive.archive_dormant()? as u64;
+        }
+    }
+
+    if let Some(path) = &opts.snapshot {
+        repos.snapshot()?.save(path, repos.cipher.as_ref())?;
+    }
+
+    if let Some(path) = &opts.flagged {
+        let mut writer = csv::Writer::from_path(path)?;
+        for flag in &summary.flagged {
+            writer.serialize(flag)?;
+        }
+        writer.flush()?;
+    }
+
+    if let Some(path) = &opts.aml.sar {
+        let mut writer = csv::Writer::from_path(path)?;
+        for candidate in aml::sar_candidates(repos.events.as_ref(), &opts.aml.config())? {
+            writer.serialize(candidate)?;
+        }
+        writer.flush()?;
+    }
+
+    if let Some(path) = &opts.chargeback_report {
+        let mut writer = csv::Writer::from_path(path)?;
+        let max = opts.max_chargeback_ratio;
+        for ratio in chargebacks::chargeback_ratios(repos.events.as_ref(), max)? {
+            if ratio.flagged {
+                warn!(
+                    client = %redact::client(ratio.client),
+                    count_ratio = %ratio.count_ratio,
+                    value_ratio = %ratio.value_ratio,
+                    max = %max,
+                    "Chargeback ratio exceeded"
+                );
+            }
+            writer.serialize(ratio)?;
+        }
+        writer.flush()?;
+    }
+
+    if let (Some(path), Some(model)) = (&opts.interchange, &opts.interchange_model) {
+        let model = interchange::read_interchange(File::open(model)?)
+            .with_context(|| format!("unable to read interchange model from {}", model))?;
+        let mut writer = csv::Writer::from_path(path)?;
+        for fee in interchange::interchange_fees(repos.events.as_ref(), &model)? {
+            writer.serialize(fee)?;
+        }
+        writer.flush()?;
+    }
+
+    let now = Utc::now();
+    let currency = &opts.open_banking_currency;
+    if let Some(path) = &opts.open_banking_balances {
+        let balances = openbanking::balances(&repos.accounts.get_all()?, currency, now);
+        openbanking::write_response(File::create(path)?, &balances)?;
+    }
+    if let Some(path) = &opts.open_banking_transactions {
+        let transactions = openbanking::transactions(repos.events.as_ref(), currency, now)?;
+        openbanking::write_response(File::create(path)?, &transactions)?;
+    }
+
+    if let Some(path) = &opts.activity {
+        let mut writer = csv::Writer::from_path(path)?;
+        for activity in activity::client_activity(repos.events.as_ref())? {
+            writer.serialize(activity)?;
+        }
+        writer.flush()?;
+    }
+
+    if let Some(path) = &opts.balance_history {
+        let mut writer = csv::Writer::from_path(path)?;
+        for change in events::balance_history(repos.events.as_ref())? {
+            writer.serialize(change)?;
+        }
+        writer.flush()?;
+    }
+
+    if let (Some(period), Some(dir)) = (opts.period, &opts.period_dir) {
+        let periods = periods::period_statements(repos.events.as_ref(), period)?;
+        let written = periods::write_period_statements(Path::new(dir), &periods)?;
+        info!(periods = written, "Wrote period statements");
+    }
+
+    if !opts.no_statements {
+        let accounts = repos.accounts.get_all()?;
+        let clients = clients.as_ref();
+        match &timings {
+            Some(timings) => timings.time(Stage::Output, || write_statements(accounts, clients))?,
+            None => write_statements(accounts, clients)?,
+        }
+    }
+    if let Some(timings) = &timings {
+        summary.stages = timings.stages();
+    }
+    report(summary, start);
+    Ok(())
+}
+
+/// report prints the summary of a run to stderr, so it's kept separate from the statements
+fn report(mut summary: Summary, start: Instant) {
+    summary.elapsed = start.elapsed();
+    summary.memory = MemoryUsage::measure();
+    eprintln!("{}", summary);
+}
+
+fn reprocess(opts: ReprocessOpts) -> Result<()> {
+    let start = Instant::now();
+    let clients = read_clients(opts.clients.as_deref())?;
+    let repos = Repos::resume(&opts.resume, opts.tenant)?;
+    let engine = repos.engine().configure(EngineOptions {
+        ownership: read_owners(opts.owners.as_deref())?,
+        escrow: escrow_accounts(&opts.escrow),
+        beneficiaries: read_beneficiaries(opts.beneficiaries.as_deref())?,
+        timestamps: Some(opts.timestamps.policy()),
+        invariants: opts.check_invariants,
+        ..EngineOptions::default()
+    });
+
+    let accounts = repos.accounts.get_all()?;
+    let mut summary = process_file(&engine, &opts.file, &opts.input, &opts.errors)?;
+    repos.flush()?;
+    summary.count_accounts(&accounts, &repos.accounts.get_all()?);
+
+    if let Some(path) = &opts.snapshot {
+        repos.snapshot()?.save(path, repos.cipher.as_ref())?;
+    }
+
+    write_statements(repos.accounts.get_all()?, clients.as_ref())?;
+    report(summary, start);
+    Ok(())
+}
+
+fn query_account(opts: AccountQueryOpts) -> Result<()> {
+    let repos = Repos::open(opts.source.db.as_deref(), opts.source.tenant)?;
+    let engine = repos.engine();
+
+    if let Some(path) = &opts.source.file {
+        process_file(&engine, path, &opts.source.input, &ErrorOpts::default())?;
+    }
+
+    let account = match opts.as_of {
+        Some(as_of) => events::account_as_of(repos.events.as_ref(), opts.client, as_of)?,
+        None => repos.accounts.get(opts.client)?,
+    };
+    write_statements(account.into_iter().collect(), None)?;
+
+    if let Some(path) = &opts.disputes {
+        let mut writer = csv::Writer::from_path(path)?;
+        for transaction in engine.open_disputes(opts.client)? {
+            writer.serialize(DisputeStatement {
+                tx: transaction.tx,
+                amount: transaction.amount,
+            })?;
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+fn query_history(opts: HistoryQueryOpts) -> Result<()> {
+    let repos = Repos::open(opts.source.db.as_deref(), opts.source.tenant)?;
+    let engine = repos.engine();
+
+    if let Some(path) = &opts.source.file {
+        process_file(&engine, path, &opts.source.input, &ErrorOpts::default())?;
+    }
+
     let mut writer = csv::Writer::from_writer(io::stdout());
-    for acc in accounts_repo.get_all()? {
-        writer.serialize(AccountStatement {
-            client: acc.client(),
-            available: acc.available(),
-            held: acc.held(),
-            total: acc.total(),
-            locked: acc.is_locked(),
+    for entry in engine.history(opts.client)? {
+        let kind = entry.origin.to_string();
+        let state = entry.transaction.kind.to_string();
+        if opts.kind.iter().any(|k| *k != kind) || opts.state.iter().any(|s| *s != state) {
+            continue;
+        }
+        writer.serialize(HistoryStatement {
+            tx: entry.transaction.tx,
+            kind,
+            state,
+            amount: entry.transaction.amount,
+            timestamp: entry.transaction.timestamp,
         })?;
     }
     writer.flush()?;
+    Ok(())
+}
+
+fn run_schedules(opts: RunSchedulesOpts) -> Result<()> {
+    let schedules = schedules::read_schedules(File::open(&opts.schedules)?)
+        .with_context(|| format!("unable to read schedules from {}", opts.schedules))?;
+    let output: Box<dyn io::Write> = match &opts.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(io::BufWriter::new(output));
+    let due = schedules::due(&schedules, opts.as_of);
+    if due.is_empty() {
+        writer.write_record(ScheduledRecord::HEADERS)?;
+    }
+    for command in due {
+        writer.serialize(ScheduledRecord::from(command))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn convert_iso8583(opts: Iso8583Opts) -> Result<()> {
+    let dump = io::BufReader::new(File::open(&opts.dump)?);
+    let output: Box<dyn io::Write> = match &opts.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(io::BufWriter::new(output));
+    let (mut messages, mut converted, mut invalid) = (0, 0, 0);
+    for (i, line) in io::BufRead::lines(dump).enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        messages += 1;
+        let commands = iso8583::Message::parse(line.trim()).and_then(|m| iso8583::commands(&m));
+        match commands {
+            Ok(commands) => {
+                for command in commands {
+                    converted += 1;
+                    writer.serialize(iso8583::Iso8583Record::from(command))?;
+                }
+            }
+            Err(e) => {
+                invalid += 1;
+                warn!(
+                    line = i + 1,
+                    code = e.code(),
+                    "Unable to convert message: {}",
+                    e
+                );
+            }
+        }
+    }
+    if converted == 0 {
+        writer.write_record(iso8583::Iso8583Record::HEADERS)?;
+    }
+    writer.flush()?;
+    info!(messages, converted, invalid, "Converted ISO 8583 dump");
+    Ok(())
+}
+
+fn generate(opts: GenerateOpts) -> Result<()> {
+    let output: Box<dyn io::Write> = match &opts.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(io::BufWriter::new(output));
+    let generator = Generator::new(GeneratorConfig {
+        clients: opts.clients,
+        rows: opts.rows,
+        dispute_rate: opts.dispute_rate,
+        invalid_rate: opts.invalid_rate,
+        seed: opts.seed,
+    });
+    if !opts.sign {
+        for command in generator {
+            writer.serialize(CommandRecord::from(command))?;
+        }
+        writer.flush()?;
+        return Ok(());
+    }
+
+    let signer = Signer::from_env()?;
+    let mut headers = csv::StringRecord::from(input::HEADERS.to_vec());
+    headers.push_field(input::SIGNATURE_HEADER);
+    writer.write_record(&headers)?;
+    for command in generator {
+        let command = CommandRecord::from(command);
+        let amount = command.amount.map(|a| a.to_string()).unwrap_or_default();
+        let mut record = csv::StringRecord::from(vec![
+            command.kind,
+            command.client.to_string(),
+            command.tx.to_string(),
+            amount,
+        ]);
+        record.push_field(&signer.sign(&headers, &record, None));
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn serve(opts: ServeOpts) -> Result<()> {
+    let ServeOpts {
+        addr,
+        db,
+        tenant,
+        snapshot,
+        snapshot_interval,
+        retention_days,
+        api_keys,
+        tls_cert,
+        tls_key,
+        tls_client_ca,
+        rate_limit,
+        burst,
+    } = opts;
+    let keys = match api_keys {
+        Some(path) => Some(
+            auth::read_keys(File::open(&path)?)
+                .with_context(|| format!("unable to read API keys from {}", path))?,
+        ),
+        None => None,
+    };
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => Some(server::TlsConfig {
+            cert,
+            key,
+            client_ca: tls_client_ca,
+        }),
+        _ => None,
+    };
+    let restore = snapshot.clone().filter(|path| Path::new(path).exists());
+    server::serve(
+        server::ServerConfig {
+            addr,
+            keys,
+            tls,
+            rate_limit: rate_limit.map(|rate| RateLimit {
+                per_second: rate.per_second(),
+                burst,
+            }),
+            checkpoint_interval: snapshot_interval.map(Duration::from_secs),
+            retention: retention_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        },
+        move || match restore {
+            Some(path) => Repos::resume(&path, tenant),
+            None => Repos::open(db.as_deref(), tenant),
+        },
+        move |repos| {
+            if let Some(path) = &snapshot {
+                repos.snapshot()?.save(path, repos.cipher.as_ref())?;
+                info!(path = path.as_str(), "Saved snapshot");
+            }
+            Ok(())
+        },
+    )
+}
+
+fn bench(opts: BenchOpts) -> Result<()> {
+    let RowCount(rows) = opts.rows;
+    let workers = match opts.workers {
+        Some(workers) => workers,
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    info!(rows, "Generating input");
+    let input = bench::generate(rows, opts.clients, opts.seed)?;
+
+    let faults = opts.chaos.faults(opts.seed);
+    let mut measurements = vec![];
+    for case in bench::cases(workers.max(2)) {
+        if faults.is_some() && case.workers > 1 {
+            continue;
+        }
+        info!(backend = %case.backend, fast_parse = case.fast_parse, workers = case.workers, "Running benchmark");
+        measurements.push(bench::run(&input, rows, case, faults)?);
+    }
+    bench::write_table(io::stdout(), &mut measurements)?;
+    Ok(())
+}
+
+fn simulate(opts: SimulateOpts) -> Result<()> {
+    let counterexample = match simulation::run(SimulationConfig {
+        seed: opts.seed,
+        runs: opts.runs,
+        steps: opts.steps,
+        clients: opts.clients,
+        faults: opts.chaos.faults(opts.seed),
+    })? {
+        Some(counterexample) => counterexample,
+        None => {
+            info!(
+                runs = opts.runs,
+                steps = opts.steps,
+                "Engine matched the model"
+            );
+            return Ok(());
+        }
+    };
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for command in counterexample.commands.iter().copied() {
+        writer.serialize(CommandRecord::from(command))?;
+    }
+    writer.flush()?;
+    Err(SimulationError::from(&counterexample).into())
+}
 
+fn soak(opts: SoakOpts) -> Result<()> {
+    let backend = match opts.backend.as_str() {
+        "sled" => Backend::Sled(opts.cache),
+        _ => Backend::Memory(MemoryConfig {
+            limit: opts.memory_limit,
+            ..MemoryConfig::default()
+        }),
+    };
+    info!(rate = opts.rate, duration = opts.duration, backend = %backend, "Soaking");
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    let soak = soak::run(
+        SoakConfig {
+            rate: opts.rate,
+            duration: Duration::from_secs(opts.duration),
+            interval: Duration::from_secs(opts.interval.max(1)),
+            clients: opts.clients,
+            seed: opts.seed,
+            backend,
+        },
+        |sample| {
+            writer.serialize(sample)?;
+            writer.flush()?;
+            Ok(())
+        },
+    )?;
+    info!(
+        rows = soak.total.rows,
+        rejected = soak.total.rejected,
+        rows_per_sec = soak.total.rows_per_sec,
+        p50_ms = soak.total.p50_ms,
+        p99_ms = soak.total.p99_ms,
+        p999_ms = soak.total.p999_ms,
+        max_ms = soak.total.max_ms,
+        rss_growth = soak.growth(),
+        "Soak complete"
+    );
     Ok(())
 }
 
+fn test_scenarios(opts: TestScenariosOpts) -> Result<()> {
+    let scenarios = scenarios::discover(&opts.dir)?;
+    if scenarios.is_empty() {
+        return Err(ScenarioError::NoScenarios(opts.dir.display().to_string()).into());
+    }
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    let mut failed = 0;
+    for scenario in &scenarios {
+        let diffs = scenarios::run(scenario)
+            .with_context(|| format!("unable to run scenario {}", scenario.name))?;
+        if diffs.is_empty() {
+            info!(scenario = scenario.name.as_str(), "Scenario passed");
+            continue;
+        }
+        warn!(
+            scenario = scenario.name.as_str(),
+            clients = diffs.len(),
+            "Scenario failed"
+        );
+        failed += 1;
+        for diff in diffs {
+            writer.serialize(ScenarioDiff::new(scenario, diff))?;
+        }
+    }
+    writer.flush()?;
+    if failed > 0 {
+        return Err(ScenarioError::Failed {
+            failed,
+            total: scenarios.len(),
+        }
+        .into());
+    }
+    info!(total = scenarios.len(), "All scenarios passed");
+    Ok(())
+}
+
+fn run_scenario(opts: RunScenarioOpts) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    let mut failed = 0;
+    for path in &opts.files {
+        let name = path.display().to_string();
+        let spec = Spec::read(File::open(path)?)
+            .with_context(|| format!("unable to read scenario {}", name))?;
+        let mismatches = scenarios::run_spec(&name, &spec)?;
+        if mismatches.is_empty() {
+            info!(scenario = name.as_str(), "Scenario passed");
+            continue;
+        }
+        warn!(
+            scenario = name.as_str(),
+            mismatches = mismatches.len(),
+            "Scenario failed"
+        );
+        failed += 1;
+        for mismatch in mismatches {
+            writer.serialize(mismatch)?;
+        }
+    }
+    writer.flush()?;
+    if failed > 0 {
+        return Err(ScenarioError::Failed {
+            failed,
+            total: opts.files.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn diff(opts: DiffOpts) -> Result<()> {
+    let old = diff::read_statements(File::open(&opts.old)?)?;
+    let new = diff::read_statements(File::open(&opts.new)?)?;
+
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for client in diff::diff(&old, &new) {
+        writer.serialize(client)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn audit(opts: AuditOpts) -> Result<()> {
+    let repos = Repos::resume(&opts.state, opts.tenant)?;
+    let snapshot = repos.snapshot()?;
+    let (accounts, mut divergences) = verify::verify(&snapshot)?;
+    if let Some(path) = &opts.statements {
+        let statements = diff::read_statements(File::open(path)?)?;
+        divergences.extend(verify::verify_statements(&statements, &accounts));
+    }
+
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for divergence in &divergences {
+        writer.serialize(divergence)?;
+    }
+    writer.flush()?;
+    info!(
+        events = snapshot.events.len(),
+        accounts = snapshot.accounts.len(),
+        transactions = snapshot.transactions.len(),
+        divergences = divergences.len(),
+        "Audited state"
+    );
+
+    if !divergences.is_empty() {
+        return Err(VerifyError::Diverged(divergences.len()).into());
+    }
+    Ok(())
+}
+
+fn replay(opts: ReplayOpts) -> Result<()> {
+    let repos = Repos::resume(&opts.state, opts.tenant)?;
+    let snapshot = repos.snapshot()?;
+    let events = match opts.until {
+        Some(until) => verify::events_until(&snapshot.events, until)?,
+        None => &snapshot.events,
+    };
+    let transactions = TransactionsMemoryRepo::new();
+    let accounts = AccountsMemoryRepo::new();
+    verify::replay(events, &transactions, &accounts)?;
+    let mut replayed = accounts.get_all()?;
+    replayed.sort_by_key(Account::client);
+    info!(
+        events = events.len(),
+        of = snapshot.events.len(),
+        accounts = replayed.len(),
+        "Replayed event log"
+    );
+
+    if let Some(path) = &opts.expect {
+        let expected = Snapshot::load(path, repos.cipher.as_ref())
+            .with_context(|| format!("unable to load snapshot {}", path))?;
+        let divergences = verify::compare(&expected, &replayed, transactions.get_all()?)?;
+        for divergence in &divergences {
+            warn!(
+                record = divergence.record,
+                id = divergence.id,
+                expected = divergence.persisted.as_str(),
+                replayed = divergence.replayed.as_str(),
+                "Replayed state differs from the snapshot"
+            );
+        }
+        write_statements(replayed, None)?;
+        if !divergences.is_empty() {
+            return Err(VerifyError::Diverged(divergences.len()).into());
+        }
+        return Ok(());
+    }
+    write_statements(replayed, None)
+}
+
+fn forget(opts: ForgetOpts) -> Result<()> {
+    // opened before erasing, so that an audit log which can't be written to stops the erasure
+    // before it starts. Recording the receipt can still fail once the client's been erased, in
+    // which case the error names the erasure so that it can be recorded by hand.
+    let mut audit = audit::AuditLog::open(&opts.audit_log)
+        .with_context(|| format!("unable to open audit log {}", opts.audit_log.display()))?;
+    let db = sled::open(&opts.db)?;
+    migrate::migrate(&db)?;
+    let receipt = erasure::forget(&db, opts.client, opts.tenant, Cipher::from_env()?.as_ref())?;
+    audit.record(&receipt).with_context(|| {
+        format!(
+            "erasure {} completed, but its receipt couldn't be recorded",
+            receipt.erasure
+        )
+    })?;
+    info!(
+        erasure = receipt.erasure,
+        transactions = receipt.transactions,
+        events = receipt.events,
+        "Forgot client"
+    );
+    Ok(())
+}
+
+fn reconcile(opts: ReconcileOpts) -> Result<()> {
+    let start = Instant::now();
+    let expected = reconcile::read_expected(File::open(&opts.expected)?)?;
+    let mut repos = if opts.dry_run {
+        Repos::open_unmigrated(opts.db.as_deref(), opts.tenant)?
+    } else {
+        Repos::open(opts.db.as_deref(), opts.tenant)?
+    };
+    let archive = repos.archive.clone();
+    if opts.dry_run {
+        repos = repos.dry_run()?;
+    }
+    let engine = repos.engine();
+
+    let mut accounts = repos.accounts.get_all()?;
+    if let Some(archive) = &archive {
+        accounts = archive.including_archived(accounts)?;
+    }
+    let mut summary = process_file(&engine, &opts.file, &opts.input, &opts.errors)?;
+    repos.flush()?;
+    let accounts_after = repos.accounts.get_all()?;
+    summary.count_accounts(&accounts, &accounts_after);
+
+    let discrepancies = reconcile::reconcile(&expected, &accounts_after);
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for discrepancy in &discrepancies {
+        writer.serialize(discrepancy)?;
+    }
+    writer.flush()?;
+    report(summary, start);
+
+    if !discrepancies.is_empty() {
+        return Err(ReconcileError::Mismatch(discrepancies.len()).into());
+    }
+    Ok(())
+}
+
+fn merge(opts: MergeOpts) -> Result<()> {
+    // opened before merging, so that an audit log which can't be written to stops the merge
+    // before it starts. Recording the receipt can still fail once the accounts have been merged,
+    // in which case the error names the merge so that it can be recorded by hand.
+    let mut audit = audit::AuditLog::open(&opts.audit_log)
+        .with_context(|| format!("unable to open audit log {}", opts.audit_log.display()))?;
+    let db = sled::open(&opts.db)?;
+    migrate::migrate(&db)?;
+    let receipt = merge::merge(
+        &db,
+        opts.from,
+        opts.into,
+        opts.tenant,
+        Cipher::from_env()?.as_ref(),
+    )?;
+    audit.record(&receipt).with_context(|| {
+        format!(
+            "merge {} completed, but its receipt couldn't be recorded",
+            receipt.merge
+        )
+    })?;
+    info!(
+        merge = receipt.merge,
+        transactions = receipt.transactions,
+        events = receipt.events,
+        "Merged accounts"
+    );
+    Ok(())
+}
+
+fn completions(opts: CompletionsOpts) -> Result<()> {
+    let mut command = Opts::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(opts.shell, &mut command, name, &mut io::stdout());
+    Ok(())
+}
+
+fn man() -> Result<()> {
+    clap_mangen::Man::new(Opts::command()).render(&mut io::stdout())?;
+    Ok(())
+}
+
+fn schema(opts: SchemaOpts) -> Result<()> {
+    let schema = match opts.format {
+        SchemaFormat::Input => schema::transaction_command(),
+        SchemaFormat::Statement => schema::account_statement(),
+    };
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+fn run(opts: Opts) -> Result<()> {
+    match opts.command {
+        Command::Process(opts) => process(*opts),
+        Command::Reprocess(opts) => reprocess(opts),
+        Command::Generate(opts) => generate(opts),
+        Command::RunSchedules(opts) => run_schedules(opts),
+        Command::Iso8583(opts) => convert_iso8583(opts),
+        Command::Serve(opts) => serve(opts),
+        Command::Bench(opts) => bench(opts),
+        Command::Simulate(opts) => simulate(opts),
+        Command::Soak(opts) => soak(opts),
+        Command::TestScenarios(opts) => test_scenarios(opts),
+        Command::RunScenario(opts) => run_scenario(opts),
+        Command::Diff(opts) => diff(opts),
+        Command::Reconcile(opts) => reconcile(opts),
+        Command::Audit(opts) => audit(opts),
+        Command::Replay(opts) => replay(opts),
+        Command::Forget(opts) => forget(opts),
+        Command::Merge(opts) => merge(opts),
+        Command::Completions(opts) => completions(opts),
+        Command::Man => man(),
+        Command::Schema(opts) => schema(opts),
+        Command::Query(opts) => match opts.command {
+            QueryCommand::Account(opts) => query_account(opts),
+            QueryCommand::History(opts) => query_history(opts),
+        },
+    }
+}
+
 fn main() {
-    tracing_subscriber::fmt::init();
+    let opts: Opts = Opts::parse();
+    opts.log.init();
 
-    if let Err(e) = run() {
-        error!(error = e.to_string(), "Something went wrong")
+    if let Err(e) = run(opts) {
+        error!(error = %redact::error(&e), "Something went wrong");
+        process::exit(exitcode::exit_code(&e));
     }
 }
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use fxhash::FxBuildHasher;
+
+use crate::accounts::{AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+use crate::events::{EventsRepo, MemoryRepo as EventsMemoryRepo, SledRepo as EventsSledRepo};
+use crate::spill::{MemoryLimit, SpillRepo};
+use crate::transactions::{DenseRepo, MemoryRepo as TransactionsMemoryRepo, TransactionsRepo};
+
+/// MemoryConfig selects the implementations of the in-memory repositories
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryConfig {
+    /// Hash IDs with FxHash rather than SipHash, which is faster but not resistant to maliciously
+    /// crafted input
+    pub fast_hash: bool,
+    /// Store transactions in a vector indexed by ID rather than a map
+    pub dense_transactions: bool,
+    /// Bound the memory used for transactions & events by spilling them to disk
+    pub limit: Option<MemoryLimit>,
+}
+
+impl MemoryConfig {
+    pub fn transactions_repo(&self) -> Result<Box<dyn TransactionsRepo>> {
+        if let Some(limit) = self.limit {
+            return Ok(Box::new(SpillRepo::new(limit.capacity())?));
+        }
+        Ok(match (self.dense_transactions, self.fast_hash) {
+            (true, true) => Box::new(DenseRepo::with_hasher(FxBuildHasher::default())),
+            (true, false) => Box::new(DenseRepo::new()),
+            (false, true) => {
+                Box::new(TransactionsMemoryRepo::with_hasher(FxBuildHasher::default()))
+            }
+            (false, false) => Box::new(TransactionsMemoryRepo::new()),
+        })
+    }
+
+    pub fn accounts_repo(&self) -> Box<dyn AccountsRepo> {
+        if self.fast_hash {
+            return Box::new(AccountsMemoryRepo::with_hasher(FxBuildHasher::default()));
+        }
+        Box::new(AccountsMemoryRepo::new())
+    }
+
+    /// events_repo keeps the event log in memory, unless memory is limited, in which case it's
+    /// written to a temporary database since it grows with every transaction
+    pub fn events_repo(&self) -> Result<Box<dyn EventsRepo>> {
+        if self.limit.is_some() {
+            let db = sled::Config::new().temporary(true).open()?;
+            return Ok(Box::new(EventsSledRepo::new(&db)?));
+        }
+        Ok(Box::new(EventsMemoryRepo::new()))
+    }
+
+    /// partition divides the memory limit between `partitions` sets of repositories
+    pub fn partition(&self, partitions: usize) -> MemoryConfig {
+        MemoryConfig {
+            limit: self
+                .limit
+                .map(|MemoryLimit(bytes)| MemoryLimit(bytes / partitions.max(1) as u64)),
+            ..*self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{Transaction, TransactionKind};
+    use rust_decimal::prelude::*;
+
+    #[test]
+    fn test_limited_repos() -> Result<()> {
+        let config = MemoryConfig {
+            limit: Some(MemoryLimit(1 << 10)),
+            ..MemoryConfig::default()
+        };
+        let amount = Decimal::from(1);
+        let transaction = Transaction {
+            tx: 1,
+            client: 1,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+        let transactions = config.transactions_repo()?;
+        transactions.save(transaction)?;
+        assert!(transactions.get(1)?.is_some());
+
+        // the temporary database must outlive the handle used to open it
+        let events = config.events_repo()?;
+        events.append(transaction)?;
+        assert_eq!(events.get_all()?.len(), 1);
+
+        assert_eq!(config.partition(4).limit, Some(MemoryLimit(1 << 8)));
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountKind};
+use crate::encryption::{self, Cipher};
+use crate::erasure::owned_by;
+use crate::events;
+use crate::tenant::{self, TenantId};
+use crate::transactions::Transaction;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MergeError {
+    #[error("client {0} has no stored account")]
+    UnknownClient(u64),
+    #[error("unable to merge client {0} into itself")]
+    SameClient(u64),
+    #[error("unable to merge a {from:?} account into a {into:?} account")]
+    KindMismatch {
+        from: AccountKind,
+        into: AccountKind,
+    },
+}
+
+/// MergeReceipt records that one client's account was merged into another's, along with what was
+/// moved, so that the merge can be accounted for later
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MergeReceipt {
+    pub event: &'static str,
+    /// Identifies the merge
+    pub merge: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    /// The client whose account was merged, and no longer exists
+    pub from: u64,
+    /// The client whose account the balances & history were merged into
+    pub into: u64,
+    /// Seconds since the Unix epoch
+    pub merged_at: u64,
+    pub transactions: u64,
+    pub events: u64,
+    /// The balances moved from the merged account
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// Whether the resulting account is locked, which it is if either account was
+    pub locked: bool,
+}
+
+/// merge merges the account of client `from` into that of client `into`, e.g. when the same
+/// person was onboarded twice: their balances are summed, and `from`'s stored transactions &
+/// events are re-pointed to `into`, so that its history (and replaying it) includes them. `from`'s
+/// account is removed. All of it happens in a single database transaction, so either everything
+/// is merged or nothing is. Values are encrypted with `cipher`, which the database must have been
+/// encrypted with too, if given.
+pub fn merge(
+    db: &sled::Db,
+    from: u64,
+    into: u64,
+    tenant: Option<TenantId>,
+    cipher: Option<&Cipher>,
+) -> Result<MergeReceipt> {
+    if from == into {
+        return Err(MergeError::SameClient(from).into());
+    }
+    let tree = |name| db.open_tree(tenant::tree_name(tenant, name));
+    let accounts = tree("accounts")?;
+    let transactions = tree("transactions")?;
+    let events = tree("events")?;
+    let events_by_client = tree("events_by_client")?;
+
+    let account = |client: u64| -> Result<Account> {
+        match accounts.get(client.to_be_bytes())? {
+            Some(bytes) => encryption::decode(cipher, &bytes),
+            None => Err(MergeError::UnknownClient(client).into()),
+        }
+    };
+    let (source, target) = (account(from)?, account(into)?);
+    if source.kind() != target.kind() {
+        return Err(MergeError::KindMismatch {
+            from: source.kind(),
+            into: target.kind(),
+        }
+        .into());
+    }
+    let merged = target.merge(&source)?;
+
+    let repoint = |owned: Vec<(sled::IVec, Transaction)>| -> Result<Vec<(sled::IVec, Vec<u8>)>> {
+        owned
+            .into_iter()
+            .map(|(key, transaction)| {
+                let transaction = Transaction {
+                    client: into,
+                    ..transaction
+                };
+                Ok((key, encryption::encode(cipher, &transaction)?))
+            })
+            .collect()
+    };
+    let moved_transactions = repoint(owned_by(&transactions, from, cipher)?)?;
+    let moved_events = repoint(owned_by(&events, from, cipher)?)?;
+    let merged_account = encryption::encode(cipher, &merged)?;
+    let id = db.generate_id()?;
+
+    (&accounts, &transactions, &events, &events_by_client)
+        .transaction(|(accounts, transactions, events, events_by_client)| {
+            accounts.remove(&from.to_be_bytes())?;
+            accounts.insert(&into.to_be_bytes(), merged_account.as_slice())?;
+            for (key, value) in &moved_transactions {
+                transactions.insert(key, value.as_slice())?;
+            }
+            for (key, value) in &moved_events {
+                events.insert(key, value.as_slice())?;
+                events_by_client.remove(events::index_key(from, key))?;
+                events_by_client.insert(events::index_key(into, key), &[])?;
+            }
+            Ok::<_, ConflictableTransactionError<()>>(())
+        })
+        .map_err(|e| match e {
+            TransactionError::Storage(e) => anyhow::Error::from(e),
+            TransactionError::Abort(()) => anyhow::anyhow!("merge aborted"),
+        })?;
+    db.flush()?;
+
+    Ok(MergeReceipt {
+        event: "merge",
+        merge: id,
+        tenant,
+        from,
+        into,
+        merged_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        transactions: moved_transactions.len() as u64,
+        events: moved_events.len() as u64,
+        available: source.available(),
+        held: source.held(),
+        total: source.total(),
+        locked: merged.is_locked(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::{AccountError, AccountsRepo, SledRepo as AccountsSledRepo};
+    use crate::events::{self, EventsRepo, SledRepo as EventsSledRepo};
+    use crate::payments::PaymentsEngine;
+    use crate::transactions::{
+        SledRepo as TransactionsSledRepo, TransactionCommand, TransactionKind, TransactionsRepo,
+    };
+    use crate::verify::Until;
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let transactions = TransactionsSledRepo::new(&db)?;
+        let accounts = AccountsSledRepo::new(&db)?;
+        let events = EventsSledRepo::new(&db)?;
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+        let deposit = |amount: i64| TransactionKind::Deposit {
+            amount: Decimal::from(amount),
+        };
+        for (tx, client, kind) in [
+            (1, 1, deposit(10)),
+            (2, 2, deposit(5)),
+            (3, 2, deposit(2)),
+            (3, 2, TransactionKind::Dispute),
+            (4, 3, deposit(1)),
+        ] {
+            engine.process_transaction(TransactionCommand {
+                kind,
+                tx,
+                client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+
+        let receipt = merge(&db, 2, 1, None, None)?;
+        assert_eq!((receipt.from, receipt.into), (2, 1));
+        assert_eq!(receipt.transactions, 2);
+        assert_eq!(receipt.events, 3);
+        assert_eq!(receipt.available, Decimal::from(5));
+        assert_eq!(receipt.held, Decimal::from(2));
+
+        assert!(accounts.get(2)?.is_none());
+        let account = accounts.get(1)?.unwrap();
+        assert_eq!(account.available(), Decimal::from(15));
+        assert_eq!(account.held(), Decimal::from(2));
+        assert_eq!(transactions.get(3)?.unwrap().client, 1);
+        assert!(events.get_all()?.iter().all(|e| e.client != 2));
+        assert_eq!(events.get_by_client(1)?.len(), 4);
+        assert!(events.get_by_client(2)?.is_empty());
+        // the merged history replays to the merged balances
+        let replayed = events::account_as_of(&events, 1, Until::Tx(4))?.unwrap();
+        assert_eq!(replayed.total(), account.total());
+        // the merged client's disputes can be resolved through the account it was merged into
+        engine.process_transaction(TransactionCommand {
+            kind: TransactionKind::Resolve,
+            tx: 3,
+            client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        let account = accounts.get(1)?.unwrap();
+        assert_eq!(account.available(), Decimal::from(17));
+        assert_eq!(account.held(), Decimal::from(0));
+
+        assert_eq!(
+            merge(&db, 2, 1, None, None)
+                .unwrap_err()
+                .downcast::<MergeError>()?,
+            MergeError::UnknownClient(2)
+        );
+        assert_eq!(
+            merge(&db, 1, 1, None, None)
+                .unwrap_err()
+                .downcast::<MergeError>()?,
+            MergeError::SameClient(1)
+        );
+
+        // balances which would overflow once merged are an error, and nothing is merged
+        let max = TransactionKind::Deposit {
+            amount: Decimal::max_value(),
+        };
+        for (tx, client) in [(5, 4), (6, 5)] {
+            engine.process_transaction(TransactionCommand {
+                kind: max,
+                tx,
+                client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+        assert_eq!(
+            merge(&db, 5, 4, None, None)
+                .unwrap_err()
+                .downcast::<AccountError>()?,
+            AccountError::Overflow
+        );
+        assert!(accounts.get(5)?.is_some());
+        assert_eq!(transactions.get(6)?.unwrap().client, 5);
+        Ok(())
+    }
+}
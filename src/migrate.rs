@@ -0,0 +1,198 @@
+use std::convert::TryInto;
+
+use anyhow::{bail, Result};
+use tracing::info;
+
+use crate::transactions::ERASED_IDS_TREE;
+
+/// SCHEMA_VERSION is the version of the layout of the sled database written by this build
+pub const SCHEMA_VERSION: u64 = 4;
+
+/// VERSION_KEY is where the schema version is recorded, in the database's default tree
+const VERSION_KEY: &[u8] = b"schema_version";
+
+/// migrate upgrades a database to the current schema. The version it's upgraded to is recorded,
+/// so that each migration only runs once; databases without a version predate versioning.
+pub fn migrate(db: &sled::Db) -> Result<()> {
+    let version = version(db)?;
+    if version < 2 {
+        // client IDs were widened from 16 to 64 bits
+        let widened = widen_keys(db, "accounts")?;
+        if widened > 0 {
+            info!(
+                accounts = widened,
+                "Migrated account keys to 64 bit client IDs"
+            );
+        }
+    }
+    if version < 3 {
+        // transaction IDs were widened from 32 to 64 bits
+        let widened = widen_keys(db, "transactions")?;
+        if widened > 0 {
+            info!(
+                transactions = widened,
+                "Migrated transaction keys to 64 bit transaction IDs"
+            );
+        }
+    }
+    if version < 4 {
+        // erased transactions' IDs are kept so that they can't be reused
+        let indexed = index_erased_ids(db)?;
+        if indexed > 0 {
+            info!(
+                transactions = indexed,
+                "Indexed the IDs of erased transactions"
+            );
+        }
+    }
+    db.insert(VERSION_KEY, &SCHEMA_VERSION.to_be_bytes())?;
+    db.flush()?;
+    Ok(())
+}
+
+/// check fails unless a database is already on the current schema (or is new & empty), for
+/// when it's opened without being written to, e.g. on a dry run
+pub fn check(db: &sled::Db) -> Result<()> {
+    let version = version(db)?;
+    if version < SCHEMA_VERSION && db.was_recovered() {
+        bail!(
+            "database schema version {} is older than this build's ({}), so needs migrating by a \
+             run without --dry-run first",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// version is the schema version a database was last migrated to, failing if it's newer than
+/// this build supports
+fn version(db: &sled::Db) -> Result<u64> {
+    let version = match db.get(VERSION_KEY)? {
+        Some(bytes) => u64::from_be_bytes(bytes.as_ref().try_into()?),
+        None => 1,
+    };
+    if version > SCHEMA_VERSION {
+        bail!(
+            "database schema version {} is newer than this build supports ({})",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(version)
+}
+
+/// widen_keys rewrites every key narrower than 8 bytes in the trees called `name`, including
+/// each tenant's, as the same big endian integer in 8 bytes. Returns the number of keys rewritten.
+fn widen_keys(db: &sled::Db, name: &str) -> Result<usize> {
+    let suffix = format!("/{}", name);
+    let mut widened = 0;
+    for tree_name in db.tree_names() {
+        if tree_name != name.as_bytes() && !tree_name.ends_with(suffix.as_bytes()) {
+            continue;
+        }
+        let tree = db.open_tree(&tree_name)?;
+        let mut batch = sled::Batch::default();
+        for entry in tree.iter() {
+            let (key, value) = entry?;
+            if key.len() >= 8 {
+                continue;
+            }
+            let mut wide = [0; 8];
+            wide[8 - key.len()..].copy_from_slice(&key);
+            batch.remove(key);
+            batch.insert(&wide, value);
+            widened += 1;
+        }
+        tree.apply_batch(batch)?;
+    }
+    Ok(widened)
+}
+
+/// index_erased_ids adds the ID of every erased transaction, including each tenant's, to the
+/// tree of erased IDs alongside its tombstone. Tombstones are keyed by their erasure followed by
+/// the transaction's ID. Returns the number of IDs indexed.
+fn index_erased_ids(db: &sled::Db) -> Result<usize> {
+    let name = "erased_transactions";
+    let suffix = format!("/{}", name);
+    let mut indexed = 0;
+    for tree_name in db.tree_names() {
+        if tree_name != name.as_bytes() && !tree_name.ends_with(suffix.as_bytes()) {
+            continue;
+        }
+        let prefix = &tree_name[..tree_name.len() - name.len()];
+        let mut ids_name = prefix.to_vec();
+        ids_name.extend_from_slice(ERASED_IDS_TREE.as_bytes());
+        let ids = db.open_tree(ids_name)?;
+        let mut batch = sled::Batch::default();
+        for key in db.open_tree(&tree_name)?.iter().keys() {
+            let key = key?;
+            if key.len() != 16 {
+                bail!("erased transaction key is {} bytes, expected 16", key.len());
+            }
+            batch.insert(&key[8..], &[]);
+            indexed += 1;
+        }
+        ids.apply_batch(batch)?;
+    }
+    Ok(indexed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let accounts = db.open_tree("accounts")?;
+        accounts.insert(7u16.to_be_bytes(), "a")?;
+        let tenant = db.open_tree("tenant/acme/accounts")?;
+        tenant.insert(300u16.to_be_bytes(), "b")?;
+        let transactions = db.open_tree("transactions")?;
+        transactions.insert(1u32.to_be_bytes(), "c")?;
+        let mut tombstone_key = 5u64.to_be_bytes().to_vec();
+        tombstone_key.extend_from_slice(&9u64.to_be_bytes());
+        db.open_tree("tenant/acme/erased_transactions")?
+            .insert(tombstone_key, "e")?;
+
+        migrate(&db)?;
+        assert_eq!(accounts.get(7u64.to_be_bytes())?, Some("a".into()));
+        assert!(accounts.get(7u16.to_be_bytes())?.is_none());
+        assert_eq!(tenant.get(300u64.to_be_bytes())?, Some("b".into()));
+        assert_eq!(transactions.get(1u64.to_be_bytes())?, Some("c".into()));
+        let erased_ids = db.open_tree(format!("tenant/acme/{}", ERASED_IDS_TREE))?;
+        assert!(erased_ids.contains_key(9u64.to_be_bytes())?);
+
+        // already migrated, so narrow keys written since are left alone
+        accounts.insert(8u16.to_be_bytes(), "d")?;
+        migrate(&db)?;
+        assert_eq!(accounts.get(8u16.to_be_bytes())?, Some("d".into()));
+
+        db.insert(VERSION_KEY, &(SCHEMA_VERSION + 1).to_be_bytes())?;
+        assert!(migrate(&db).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("payments-migrate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        {
+            // a new database has nothing to migrate
+            let db = sled::open(&path)?;
+            check(&db)?;
+            db.open_tree("accounts")?.insert(7u16.to_be_bytes(), "a")?;
+            db.flush()?;
+        }
+        let db = sled::open(&path)?;
+        assert!(check(&db).is_err());
+        // and checking doesn't migrate it
+        assert!(db.get(VERSION_KEY)?.is_none());
+        migrate(&db)?;
+        check(&db)?;
+        drop(db);
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+
+use rust_decimal::prelude::*;
+use serde::{Serialize, Serializer};
+
+/// Counterparty is a party to a settlement: either a client, or the platform itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Counterparty {
+    Platform,
+    Client(u64),
+}
+
+impl fmt::Display for Counterparty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Counterparty::Platform => write!(f, "platform"),
+            Counterparty::Client(client) => write!(f, "{}", client),
+        }
+    }
+}
+
+impl Serialize for Counterparty {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Instruction is an instruction to move funds from one counterparty to another
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Instruction {
+    pub from: Counterparty,
+    pub to: Counterparty,
+    pub amount: Decimal,
+}
+
+/// net nets the amounts owed to (positive) or by (negative) each client, as computed by
+/// [crate::settlement::settlement], into instructions for debtors to pay creditors directly
+/// rather than each settling with the platform. The platform takes whatever position balances
+/// the clients'. The largest debtor pays the largest creditor until either is settled, which
+/// takes at most one fewer instruction than there are counterparties.
+pub fn net(owed: &[(u64, Decimal)]) -> Vec<Instruction> {
+    let mut positions: Vec<(Counterparty, Decimal)> = owed
+        .iter()
+        .map(|(client, amount)| (Counterparty::Client(*client), *amount))
+        .collect();
+    positions.push((
+        Counterparty::Platform,
+        -owed.iter().map(|(_, a)| *a).sum::<Decimal>(),
+    ));
+
+    let (mut creditors, mut debtors): (Vec<_>, Vec<_>) = positions
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(party, amount)| (party, amount.abs(), amount > Decimal::from(0)))
+        .partition(|(_, _, creditor)| *creditor);
+    // sorted ascending, so the largest is popped first, & by party for a stable order
+    let by_amount = |a: &(Counterparty, Decimal, bool), b: &(Counterparty, Decimal, bool)| {
+        a.1.cmp(&b.1).then(b.0.cmp(&a.0))
+    };
+    creditors.sort_by(by_amount);
+    debtors.sort_by(by_amount);
+
+    let mut instructions = Vec::new();
+    while let (Some(mut creditor), Some(mut debtor)) = (creditors.pop(), debtors.pop()) {
+        let amount = creditor.1.min(debtor.1);
+        instructions.push(Instruction {
+            from: debtor.0,
+            to: creditor.0,
+            amount,
+        });
+        creditor.1 -= amount;
+        debtor.1 -= amount;
+        // whatever remains of a position is settled by later instructions
+        if !creditor.1.is_zero() {
+            creditors.push(creditor);
+            creditors.sort_by(by_amount);
+        }
+        if !debtor.1.is_zero() {
+            debtors.push(debtor);
+            debtors.sort_by(by_amount);
+        }
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_net() {
+        let d = Decimal::from;
+        let instruction = |from, to, amount| Instruction { from, to, amount };
+        let client = Counterparty::Client;
+
+        // client 1 deposited 100 & clients 2 & 3 withdrew 60 & 30: client 1 pays them directly,
+        // and the platform the rest
+        assert_eq!(
+            net(&[(1, d(-100)), (2, d(60)), (3, d(30))]),
+            vec![
+                instruction(client(1), client(2), d(60)),
+                instruction(client(1), client(3), d(30)),
+                instruction(client(1), Counterparty::Platform, d(10)),
+            ]
+        );
+        // positions which balance need nothing from the platform
+        let instructions = net(&[(1, d(-50)), (2, d(-20)), (3, d(40)), (4, d(30))]);
+        assert_eq!(
+            instructions,
+            vec![
+                instruction(client(1), client(3), d(40)),
+                instruction(client(2), client(4), d(20)),
+                instruction(client(1), client(4), d(10)),
+            ]
+        );
+        assert!(instructions.len() < 4);
+        assert_eq!(
+            net(&[(1, d(25))]),
+            vec![instruction(Counterparty::Platform, client(1), d(25))]
+        );
+        assert!(net(&[]).is_empty());
+    }
+}
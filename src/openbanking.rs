@@ -0,0 +1,275 @@
+use std::io;
+
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::accounts::Account;
+use crate::events::{self, EventsRepo};
+
+/// CreditDebitIndicator is whether an amount is paid into (credit) or out of (debit) an account
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum CreditDebitIndicator {
+    Credit,
+    Debit,
+}
+
+/// Amount is an amount & its currency. Open Banking amounts are never negative, the direction is
+/// given by a credit/debit indicator alongside them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Amount {
+    pub amount: String,
+    pub currency: String,
+}
+
+fn amount(amount: Decimal, currency: &str) -> (Amount, CreditDebitIndicator) {
+    let indicator = if amount < Decimal::from(0) {
+        CreditDebitIndicator::Debit
+    } else {
+        CreditDebitIndicator::Credit
+    };
+    let amount = Amount {
+        amount: amount.abs().to_string(),
+        currency: currency.to_string(),
+    };
+    (amount, indicator)
+}
+
+fn date_time(date_time: DateTime<Utc>) -> String {
+    date_time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Balance {
+    pub account_id: String,
+    pub credit_debit_indicator: CreditDebitIndicator,
+    /// ClosingAvailable for the available balance, ClosingBooked for the total
+    #[serde(rename = "Type")]
+    pub kind: &'static str,
+    pub date_time: String,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TransactionBalance {
+    pub credit_debit_indicator: CreditDebitIndicator,
+    #[serde(rename = "Type")]
+    pub kind: &'static str,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Transaction {
+    pub account_id: String,
+    pub transaction_id: String,
+    pub credit_debit_indicator: CreditDebitIndicator,
+    /// Booked, or Pending for deposits & withdrawals which haven't settled
+    pub status: &'static str,
+    pub booking_date_time: String,
+    pub transaction_information: String,
+    pub amount: Amount,
+    /// The account's booked balance after the transaction
+    pub balance: TransactionBalance,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Links {
+    #[serde(rename = "Self")]
+    pub self_link: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Meta {
+    pub total_pages: u64,
+}
+
+/// Response is an Open Banking read API response: its data, wrapped in the resource's name (e.g.
+/// `{"Data": {"Balance": [...]}}`), along with links & metadata
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Response<T> {
+    pub data: T,
+    pub links: Links,
+    pub meta: Meta,
+}
+
+impl<T> Response<T> {
+    fn new(data: T, path: &str) -> Response<T> {
+        Response {
+            data,
+            links: Links {
+                self_link: path.to_string(),
+            },
+            meta: Meta { total_pages: 1 },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Balances {
+    pub balance: Vec<Balance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Transactions {
+    pub transaction: Vec<Transaction>,
+}
+
+/// balances is every account's closing available & booked (total) balances, as the response of
+/// the UK Open Banking bulk balances endpoint (`GET /balances`). The engine has no notion of
+/// currency, so every amount is in the given one.
+pub fn balances(accounts: &[Account], currency: &str, at: DateTime<Utc>) -> Response<Balances> {
+    let mut balances = Vec::new();
+    for account in accounts {
+        for (kind, balance) in [
+            ("ClosingAvailable", account.available()),
+            ("ClosingBooked", account.total()),
+        ] {
+            let (amount, credit_debit_indicator) = amount(balance, currency);
+            balances.push(Balance {
+                account_id: account.client().to_string(),
+                credit_debit_indicator,
+                kind,
+                date_time: date_time(at),
+                amount,
+            });
+        }
+    }
+    Response::new(Balances { balance: balances }, "/balances")
+}
+
+/// transactions is every change to an account's booked (total) balance, as the response of the
+/// UK Open Banking bulk transactions endpoint (`GET /transactions`), grouped by account. Disputes
+/// & resolves only move funds between available & held, so they aren't listed. Transactions
+/// without a timestamp are booked at `at`.
+///
+/// Transaction IDs are the engine's, suffixed by the type of the event for events other than the
+/// one which created the transaction (e.g. `7-chargeback`), so they're unique.
+pub fn transactions(
+    events: &dyn EventsRepo,
+    currency: &str,
+    at: DateTime<Utc>,
+) -> Result<Response<Transactions>> {
+    let mut transactions = Vec::new();
+    for change in events::balance_history(events)? {
+        if change.delta == Decimal::from(0) {
+            continue;
+        }
+        let (delta, credit_debit_indicator) = amount(change.delta, currency);
+        let (total, total_indicator) = amount(change.available + change.held, currency);
+        let (transaction_id, status) = match change.kind.as_str() {
+            "pending_deposit" | "pending_withdrawal" => (change.tx.to_string(), "Pending"),
+            "deposit" | "withdrawal" | "payout" | "escrow" => (change.tx.to_string(), "Booked"),
+            kind => (format!("{}-{}", change.tx, kind), "Booked"),
+        };
+        transactions.push(Transaction {
+            account_id: change.client.to_string(),
+            transaction_id,
+            credit_debit_indicator,
+            status,
+            booking_date_time: date_time(change.timestamp.unwrap_or(at)),
+            transaction_information: change.kind,
+            amount: delta,
+            balance: TransactionBalance {
+                credit_debit_indicator: total_indicator,
+                kind: "InterimBooked",
+                amount: total,
+            },
+        });
+    }
+    Ok(Response::new(
+        Transactions {
+            transaction: transactions,
+        },
+        "/transactions",
+    ))
+}
+
+/// write_response writes a response as pretty printed JSON
+pub fn write_response<W: io::Write, T: Serialize>(writer: W, response: &Response<T>) -> Result<()> {
+    serde_json::to_writer_pretty(writer, response)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::AccountsRepo;
+    use crate::accounts::MemoryRepo as AccountsMemoryRepo;
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::payments::PaymentsEngine;
+    use crate::testkit::command;
+    use crate::transactions::{MemoryRepo as TransactionsMemoryRepo, TransactionKind};
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn test_open_banking() -> Result<()> {
+        let transactions = TransactionsMemoryRepo::new();
+        let accounts = AccountsMemoryRepo::new();
+        let events = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+        let process = |kind, tx| engine.process_transaction(command(kind, 1, tx));
+        let d = Decimal::from;
+        process(TransactionKind::Deposit { amount: d(10) }, 1)?;
+        process(TransactionKind::Withdrawal { amount: d(4) }, 2)?;
+        process(TransactionKind::Dispute, 1)?;
+        process(TransactionKind::ChargeBack, 1)?;
+
+        let at = Utc.ymd(2024, 1, 31).and_hms(17, 0, 0);
+        let response = balances(&accounts.get_all()?, "GBP", at);
+        assert_eq!(
+            serde_json::to_value(&response.data.balance[1])?,
+            json!({
+                "AccountId": "1",
+                "CreditDebitIndicator": "Debit",
+                "Type": "ClosingBooked",
+                "DateTime": "2024-01-31T17:00:00Z",
+                "Amount": {"Amount": "4", "Currency": "GBP"}
+            })
+        );
+        assert_eq!(response.links.self_link, "/balances");
+
+        let response = super::transactions(&events, "GBP", at)?;
+        let summary: Vec<(&str, CreditDebitIndicator, &str)> = response
+            .data
+            .transaction
+            .iter()
+            .map(|t| {
+                (
+                    t.transaction_id.as_str(),
+                    t.credit_debit_indicator,
+                    t.amount.amount.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("1", CreditDebitIndicator::Credit, "10"),
+                ("2", CreditDebitIndicator::Debit, "4"),
+                ("1-chargeback", CreditDebitIndicator::Debit, "10"),
+            ]
+        );
+        let chargeback = serde_json::to_value(&response.data.transaction[2])?;
+        assert_eq!(chargeback["Status"], "Booked");
+        assert_eq!(
+            chargeback["Balance"],
+            json!({
+                "CreditDebitIndicator": "Debit",
+                "Type": "InterimBooked",
+                "Amount": {"Amount": "4", "Currency": "GBP"}
+            })
+        );
+        Ok(())
+    }
+}
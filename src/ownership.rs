@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::io;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+/// Ownership maps the clients which jointly own another client's account to that account.
+/// Accounts are identified by their primary client's ID, and every client which isn't a joint
+/// owner of another account owns only their own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ownership {
+    accounts: HashMap<u64, u64>,
+}
+
+impl Ownership {
+    /// account is the ID of the account a client's transactions apply to
+    pub fn account(&self, client: u64) -> u64 {
+        self.accounts.get(&client).copied().unwrap_or(client)
+    }
+
+    /// owns is whether a client is one of the owners of an account
+    pub fn owns(&self, client: u64, account: u64) -> bool {
+        self.account(client) == account
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OwnerRecord {
+    account: u64,
+    client: u64,
+}
+
+/// read_owners reads a CSV of the joint owners of accounts, with `account` & `client` columns.
+/// An account's primary client always owns it, so only the additional owners need to be listed.
+pub fn read_owners<R: io::Read>(reader: R) -> Result<Ownership> {
+    let mut accounts = HashMap::new();
+    for result in csv::Reader::from_reader(reader).deserialize() {
+        let OwnerRecord { account, client } = result?;
+        if client == account {
+            continue;
+        }
+        match accounts.insert(client, account) {
+            Some(other) if other != account => {
+                bail!("client {} owns more than one account", client)
+            }
+            _ => {}
+        }
+    }
+    // a client's transactions can only apply to a single account, so the primary client of a
+    // joint account can't also be a joint owner of another
+    if let Some((client, account)) = accounts
+        .iter()
+        .find(|(c, _)| accounts.values().any(|account| account == *c))
+    {
+        bail!(
+            "client {} has joint owners of their own account, so can't jointly own account {}",
+            client,
+            account
+        );
+    }
+    Ok(Ownership { accounts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_owners() -> Result<()> {
+        let ownership = read_owners("account,client\n1,1\n1,2\n1,3\n4,5\n1,2\n".as_bytes())?;
+        assert_eq!(ownership.account(1), 1);
+        assert_eq!(ownership.account(2), 1);
+        assert_eq!(ownership.account(3), 1);
+        assert_eq!(ownership.account(5), 4);
+        assert_eq!(ownership.account(6), 6);
+        assert!(ownership.owns(3, 1));
+        assert!(!ownership.owns(4, 1));
+        assert!(!ownership.owns(1, 4));
+
+        assert!(read_owners("account,client\n1,2\n3,2\n".as_bytes()).is_err());
+        assert!(read_owners("account,client\n1,2\n2,3\n".as_bytes()).is_err());
+        assert_eq!(Ownership::default().account(7), 7);
+        Ok(())
+    }
+}
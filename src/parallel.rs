@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{anyhow, bail, Result};
+use tracing::debug;
+
+use crate::correlation::CorrelationId;
+use crate::input::{Row, RowError};
+use crate::memory::MemoryConfig;
+use crate::payments::{EngineOptions, PaymentsEngine};
+use crate::redact;
+use crate::rejections::Rejections;
+use crate::snapshot::Snapshot;
+use crate::summary::Summary;
+use crate::transactions::TransactionError;
+
+/// BATCH_SIZE is the number of rows sent to a worker at once, amortising the cost of the channel
+const BATCH_SIZE: usize = 1024;
+
+/// CHANNEL_CAPACITY is the number of batches which can be queued for each worker before reading
+/// blocks, bounding memory usage when the workers can't keep up
+const CHANNEL_CAPACITY: usize = 16;
+
+/// process_partitioned processes rows across `workers` threads. Every state transition is keyed
+/// on the account, so each worker owns the accounts where `account % workers` is its index, along
+/// with their own in-memory repositories. Once all rows have been processed, the partitions are
+/// merged into a single snapshot, with their events in the order their rows were read, along with
+/// a summary of the transactions accepted across every partition.
+///
+/// Transaction IDs are claimed by the partition of the first row to use them, so a transaction
+/// reusing an ID from another partition is rejected as a duplicate, even if the first row was
+/// itself rejected. A transaction referencing another client's transaction ID is rejected as
+/// unknown, rather than as belonging to a different client.
+pub fn process_partitioned<I>(
+    rows: I,
+    workers: usize,
+    memory: MemoryConfig,
+    rejections: &Mutex<Rejections>,
+    options: EngineOptions,
+) -> Result<(Snapshot, Summary)>
+where
+    I: Iterator<Item = Result<Row, RowError>>,
+{
+    let workers = workers.max(1);
+    let memory = memory.partition(workers);
+    thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(workers);
+        let mut handles = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let (sender, receiver) = mpsc::sync_channel::<Vec<(u64, Row)>>(CHANNEL_CAPACITY);
+            senders.push(sender);
+            let options = options.clone();
+            handles.push(
+                scope.spawn(move || process_partition(receiver, memory, rejections, options)),
+            );
+        }
+
+        let mut total = 0;
+        let read = || -> Result<()> {
+            let mut batches: Vec<Vec<(u64, Row)>> = (0..workers).map(|_| Vec::new()).collect();
+            // the partition which first used each transaction ID
+            let mut claims: HashMap<u64, usize> = HashMap::new();
+            for result in rows {
+                let sequence = total;
+                total += 1;
+                match result {
+                    Ok(row) => {
+                        let account = match &options.ownership {
+                            Some(ownership) => ownership.account(row.command.client),
+                            None => row.command.client,
+                        };
+                        let partition = (account % workers as u64) as usize;
+                        if row.command.kind.is_initial()
+                            && *claims.entry(row.command.tx).or_insert(partition) != partition
+                        {
+                            let error = TransactionError::Duplicate { tx: row.command.tx };
+                            lock(rejections)?.reject(
+                                error.into(),
+                                &row.record,
+                                row.position,
+                                Some(row.command),
+                            )?;
+                            continue;
+                        }
+                        batches[partition].push((sequence, row));
+                        if batches[partition].len() < BATCH_SIZE {
+                            continue;
+                        }
+                        let batch = std::mem::take(&mut batches[partition]);
+                        // the worker has stopped early due to an error, which is returned when
+                        // it's joined below
+                        if senders[partition].send(batch).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Err(e @ RowError { record: None, .. }) => return Err(e.into()),
+                    Err(RowError {
+                        position,
+                        record: Some(record),
+                        error,
+                    }) => lock(rejections)?.reject(error, &record, position, None)?,
+                }
+            }
+            for (sender, batch) in senders.iter().zip(batches) {
+                if sender.send(batch).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        };
+        let res = read();
+        drop(senders);
+
+        let mut partitions = Vec::with_capacity(workers);
+        let mut summary = Summary {
+            rows: total,
+            ..Summary::default()
+        };
+        for handle in handles {
+            let (snapshot, sequences, partition) = handle
+                .join()
+                .map_err(|_| anyhow!("worker thread panicked"))??;
+            partitions.push((snapshot, sequences));
+            summary.merge(&partition);
+        }
+        res?;
+        lock(rejections)?.finish(total)?;
+        Ok((merge(partitions)?, summary))
+    })
+}
+
+/// process_partition processes every row received by a single worker, returning the state of the
+/// partition along with the sequence number of the row behind each of its events
+fn process_partition(
+    receiver: mpsc::Receiver<Vec<(u64, Row)>>,
+    memory: MemoryConfig,
+    rejections: &Mutex<Rejections>,
+    options: EngineOptions,
+) -> Result<(Snapshot, Vec<u64>, Summary)> {
+    let transactions_repo = memory.transactions_repo()?;
+    let accounts_repo = memory.accounts_repo();
+    let events_repo = memory.events_repo()?;
+    // clients are partitioned, so each partition's rules & KYC checks see all of its clients'
+    // transactions
+    let engine = PaymentsEngine::new(
+        transactions_repo.as_ref(),
+        accounts_repo.as_ref(),
+        events_repo.as_ref(),
+    )
+    .configure(options);
+
+    let mut summary = Summary::default();
+    let mut sequences = Vec::new();
+    for (
+        sequence,
+        Row {
+            command,
+            position,
+            record,
+        },
+    ) in receiver.into_iter().flatten()
+    {
+        match engine.process_transaction(command) {
+            Ok(flags) => {
+                // every accepted transaction appends exactly one event
+                sequences.push(sequence);
+                summary.accept(&command);
+                summary.flagged.extend(flags);
+                debug!(
+                    tx = command.tx,
+                    client = %redact::client(command.client),
+                    correlation_id = command.correlation.as_ref().map(CorrelationId::as_str),
+                    "Processed transaction"
+                )
+            }
+            Err(e) => lock(rejections)?.reject(e, &record, position, Some(command))?,
+        }
+    }
+
+    let snapshot = Snapshot::capture(
+        transactions_repo.as_ref(),
+        accounts_repo.as_ref(),
+        events_repo.as_ref(),
+    )?;
+    Ok((snapshot, sequences, summary))
+}
+
+/// merge combines the state of every partition into a single snapshot. Each partition owns
+/// distinct accounts & transaction IDs, so those are simply combined, while the events are
+/// interleaved by the sequence number of the row behind each of them.
+fn merge(partitions: Vec<(Snapshot, Vec<u64>)>) -> Result<Snapshot> {
+    let mut merged = Snapshot {
+        accounts: Vec::new(),
+        transactions: Vec::new(),
+        events: Vec::new(),
+    };
+    let mut events = Vec::new();
+    for (snapshot, sequences) in partitions {
+        if sequences.len() != snapshot.events.len() {
+            bail!(
+                "partition logged {} events for {} accepted transactions",
+                snapshot.events.len(),
+                sequences.len()
+            );
+        }
+        merged.accounts.extend(snapshot.accounts);
+        merged.transactions.extend(snapshot.transactions);
+        events.extend(sequences.into_iter().zip(snapshot.events));
+    }
+    events.sort_unstable_by_key(|(sequence, _)| *sequence);
+    merged.events = events.into_iter().map(|(_, event)| event).collect();
+    Ok(merged)
+}
+
+fn lock(rejections: &Mutex<Rejections>) -> Result<std::sync::MutexGuard<'_, Rejections>> {
+    rejections
+        .lock()
+        .map_err(|_| anyhow!("rejections lock poisoned"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::{AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+    use crate::events::{EventsRepo, MemoryRepo as EventsMemoryRepo};
+    use crate::input::{CommandReader, InputConfig};
+    use crate::threshold::ErrorThreshold;
+    use crate::transactions::{MemoryRepo as TransactionsMemoryRepo, TransactionsRepo};
+
+    #[test]
+    fn test_process_partitioned() -> Result<()> {
+        let input = "type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,5
+deposit,3,3,1
+withdrawal,2,4,6
+dispute,1,1,
+deposit,4,x,1
+withdrawal,3,5,1
+";
+        let reader = CommandReader::from_reader(input.as_bytes(), InputConfig::default())?;
+        let rejections = Mutex::new(Rejections::new(
+            None,
+            None,
+            ErrorThreshold::default(),
+            false,
+        ));
+        let (snapshot, summary) = process_partitioned(
+            reader,
+            2,
+            MemoryConfig::default(),
+            &rejections,
+            EngineOptions::default(),
+        )?;
+        assert_eq!(lock(&rejections)?.rejected, 2);
+        assert_eq!(summary.rows, 7);
+        assert_eq!(summary.accepted, 5);
+        assert_eq!(summary.deposits, 16.into());
+        assert_eq!(summary.withdrawals, 1.into());
+
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        snapshot.restore(&transactions_repo, &accounts_repo, &events_repo)?;
+
+        let mut accounts = accounts_repo.get_all()?;
+        accounts.sort_by_key(|acc| acc.client());
+        let balances: Vec<(u64, String, String)> = accounts
+            .iter()
+            .map(|acc| {
+                (
+                    acc.client(),
+                    acc.available().to_string(),
+                    acc.held().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            balances,
+            vec![
+                (1, "0".to_string(), "10".to_string()),
+                (2, "5".to_string(), "0".to_string()),
+                (3, "0".to_string(), "0".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_partitioned_duplicate() -> Result<()> {
+        // clients 1 & 2 are in different partitions, and client 2 reuses client 1's tx 1
+        let input = "type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,5
+deposit,2,1,7
+deposit,1,3,1
+";
+        let reader = CommandReader::from_reader(input.as_bytes(), InputConfig::default())?;
+        let rejections = Mutex::new(Rejections::new(
+            None,
+            None,
+            ErrorThreshold::default(),
+            false,
+        ));
+        let (snapshot, summary) = process_partitioned(
+            reader,
+            2,
+            MemoryConfig::default(),
+            &rejections,
+            EngineOptions::default(),
+        )?;
+        assert_eq!(lock(&rejections)?.rejected, 1);
+        assert_eq!(summary.accepted, 3);
+
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        snapshot.restore(&transactions_repo, &accounts_repo, &events_repo)?;
+        assert_eq!(transactions_repo.get(1)?.map(|t| t.client), Some(1));
+        assert_eq!(
+            accounts_repo.get(2)?.map(|acc| acc.available()),
+            Some(5.into())
+        );
+        // the events are in input order, rather than grouped by partition
+        let events: Vec<u64> = events_repo.get_all()?.iter().map(|e| e.tx).collect();
+        assert_eq!(events, vec![1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_partitioned_strict() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,1\nwithdrawal,2,2,1\ndeposit,3,3,1\n";
+        let reader = CommandReader::from_reader(input.as_bytes(), InputConfig::default())?;
+        let rejections = Mutex::new(Rejections::new(None, None, ErrorThreshold::default(), true));
+        assert!(process_partitioned(
+            reader,
+            3,
+            MemoryConfig {
+                fast_hash: true,
+                dense_transactions: true,
+                limit: None,
+            },
+            &rejections,
+            EngineOptions::default(),
+        )
+        .is_err());
+        Ok(())
+    }
+}
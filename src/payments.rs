@@ -1,48 +1,573 @@
 use anyhow::Result;
+use chrono::Duration;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{debug_span, trace};
 
-use crate::accounts::{Account, AccountsRepo};
-use crate::transactions::{Transaction, TransactionCommand, TransactionsRepo};
+use crate::accounts::{Account, AccountKind, AccountsRepo};
+use crate::beneficiaries::Beneficiaries;
+use crate::correlation::CorrelationId;
+use crate::events::EventsRepo;
+use crate::invariants::Invariants;
+use crate::kyc::{Kyc, KycPolicy};
+use crate::ownership::Ownership;
+use crate::redact;
+use crate::rules::{Flag, RuleSet, Rules};
+use crate::snapshot::Snapshot;
+use crate::tenant::TenantId;
+use crate::timestamps::{TimestampOrder, TimestampPolicy};
+use crate::timings::{Stage, Timings};
+use crate::transactions::{
+    Transaction, TransactionCommand, TransactionError, TransactionKind, TransactionsRepo,
+};
 
-pub struct PaymentsEngine<'a, 'b> {
+/// HistoryEntry pairs a stored transaction with the kind it was originally created as, since a
+/// transaction's kind moves on to dispute, resolve or chargeback as it is disputed
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryEntry {
+    pub origin: TransactionKind,
+    pub transaction: Transaction,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum AdminError {
+    #[error("client {0} has no account")]
+    UnknownAccount(u64),
+    #[error("transaction {tx} not found for client {client}")]
+    UnknownTransaction { tx: u64, client: u64 },
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SaveError {
+    #[error("unable to undo the partial save of tx {tx} ({reason}), so the accounts, transactions & event log may disagree")]
+    Inconsistent { tx: u64, reason: String },
+}
+
+impl SaveError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            SaveError::Inconsistent { .. } => "inconsistent_state",
+        }
+    }
+}
+
+/// AdminAction is an operation performed by an operator on a client's account, rather than a
+/// transaction submitted by the client. Apart from forced resolves, which act on an existing
+/// dispute, each action is given a new transaction ID so that it's recorded like a transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdminAction {
+    Lock {
+        client: u64,
+        tx: u64,
+    },
+    Unlock {
+        client: u64,
+        tx: u64,
+    },
+    Adjust {
+        client: u64,
+        tx: u64,
+        amount: Decimal,
+    },
+    ForceResolve {
+        client: u64,
+        tx: u64,
+    },
+}
+
+impl AdminAction {
+    pub fn client(&self) -> u64 {
+        match *self {
+            AdminAction::Lock { client, .. }
+            | AdminAction::Unlock { client, .. }
+            | AdminAction::Adjust { client, .. }
+            | AdminAction::ForceResolve { client, .. } => client,
+        }
+    }
+    pub fn tx(&self) -> u64 {
+        match *self {
+            AdminAction::Lock { tx, .. }
+            | AdminAction::Unlock { tx, .. }
+            | AdminAction::Adjust { tx, .. }
+            | AdminAction::ForceResolve { tx, .. } => tx,
+        }
+    }
+    pub fn kind(&self) -> TransactionKind {
+        match *self {
+            AdminAction::Lock { .. } => TransactionKind::Lock,
+            AdminAction::Unlock { .. } => TransactionKind::Unlock,
+            AdminAction::Adjust { amount, .. } => TransactionKind::Adjustment { amount },
+            AdminAction::ForceResolve { .. } => TransactionKind::ForceResolve,
+        }
+    }
+}
+
+/// EngineOptions is the optional configuration of an engine, shared by every engine of a run
+/// when processing is partitioned across several of them
+#[derive(Clone, Default)]
+pub struct EngineOptions {
+    pub timings: Option<Arc<Timings>>,
+    pub rules: Option<Arc<RuleSet>>,
+    pub kyc: Option<Arc<KycPolicy>>,
+    pub tenant: Option<TenantId>,
+    pub ownership: Option<Arc<Ownership>>,
+    pub escrow: Option<Arc<HashSet<u64>>>,
+    pub beneficiaries: Option<Arc<Beneficiaries>>,
+    pub timestamps: Option<TimestampPolicy>,
+    pub invariants: bool,
+}
+
+pub struct PaymentsEngine<'a, 'b, 'c> {
     transactions: &'a dyn TransactionsRepo,
     accounts: &'b dyn AccountsRepo,
+    events: &'c dyn EventsRepo,
+    timings: Option<Arc<Timings>>,
+    rules: Option<Rules>,
+    kyc: Option<Kyc>,
+    timestamps: Option<TimestampOrder>,
+    invariants: Option<Invariants>,
+    tenant: Option<TenantId>,
+    ownership: Arc<Ownership>,
+    escrow: Arc<HashSet<u64>>,
+    beneficiaries: Arc<Beneficiaries>,
 }
 
-impl<'a, 'b> PaymentsEngine<'a, 'b> {
+impl<'a, 'b, 'c> PaymentsEngine<'a, 'b, 'c> {
     pub fn new(
         transactions: &'a dyn TransactionsRepo,
         accounts: &'b dyn AccountsRepo,
-    ) -> PaymentsEngine<'a, 'b> {
+        events: &'c dyn EventsRepo,
+    ) -> PaymentsEngine<'a, 'b, 'c> {
         PaymentsEngine {
             transactions,
             accounts,
+            events,
+            timings: None,
+            rules: None,
+            kyc: None,
+            timestamps: None,
+            invariants: None,
+            tenant: None,
+            ownership: Arc::default(),
+            escrow: Arc::default(),
+            beneficiaries: Arc::default(),
+        }
+    }
+    /// with_timings makes the engine add the time spent in each stage of processing to `timings`
+    pub fn with_timings(self, timings: Arc<Timings>) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine {
+            timings: Some(timings),
+            ..self
+        }
+    }
+    pub fn timings(&self) -> Option<&Arc<Timings>> {
+        self.timings.as_ref()
+    }
+    /// with_rules makes the engine evaluate every transaction against the rules before applying it
+    pub fn with_rules(self, rules: Rules) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine {
+            rules: Some(rules),
+            ..self
+        }
+    }
+    /// with_kyc makes the engine check every transaction against the client's KYC status before
+    /// applying it
+    pub fn with_kyc(self, kyc: Kyc) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine {
+            kyc: Some(kyc),
+            ..self
+        }
+    }
+    /// with_timestamp_order makes the engine reject or flag rows which are earlier than the latest
+    /// of their client's rows by more than the policy's tolerance
+    pub fn with_timestamp_order(self, policy: TimestampPolicy) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine {
+            timestamps: Some(TimestampOrder::new(policy)),
+            ..self
+        }
+    }
+    /// reorder_window is how long rows should be buffered for to be applied in the order they
+    /// happened, if they should be
+    pub fn reorder_window(&self) -> Option<Duration> {
+        self.timestamps.as_ref()?.reorder_window()
+    }
+    /// with_invariants makes the engine reject any transaction which would break an invariant of
+    /// the accounts, which would be a bug
+    pub fn with_invariants(self) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine {
+            invariants: Some(Invariants::new()),
+            ..self
+        }
+    }
+    /// with_tenant makes the engine process transactions for a tenant, whose repositories are
+    /// expected to hold only that tenant's records. Commands which name a different tenant are
+    /// rejected.
+    pub fn with_tenant(self, tenant: TenantId) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine {
+            tenant: Some(tenant),
+            ..self
+        }
+    }
+    /// with_ownership makes the engine apply transactions from the joint owners of an account to
+    /// that account, rather than to their own
+    pub fn with_ownership(self, ownership: Arc<Ownership>) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine { ownership, ..self }
+    }
+    /// with_escrow makes the engine open the given accounts as escrow accounts, holding deposits
+    /// into them until they're released or refunded
+    pub fn with_escrow(self, escrow: Arc<HashSet<u64>>) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine { escrow, ..self }
+    }
+    /// with_beneficiaries makes the engine accept payouts to the given registered beneficiaries.
+    /// Without it every payout is rejected.
+    pub fn with_beneficiaries(
+        self,
+        beneficiaries: Arc<Beneficiaries>,
+    ) -> PaymentsEngine<'a, 'b, 'c> {
+        PaymentsEngine {
+            beneficiaries,
+            ..self
+        }
+    }
+    /// configure applies every option which is set. Each engine tracks the rules, KYC, timestamp
+    /// order & invariant state of its own clients.
+    pub fn configure(self, options: EngineOptions) -> PaymentsEngine<'a, 'b, 'c> {
+        let mut engine = self;
+        if let Some(timings) = options.timings {
+            engine = engine.with_timings(timings);
+        }
+        if let Some(rules) = options.rules {
+            engine = engine.with_rules(Rules::new(rules));
+        }
+        if let Some(kyc) = options.kyc {
+            engine = engine.with_kyc(Kyc::new(kyc));
+        }
+        if let Some(policy) = options.timestamps {
+            engine = engine.with_timestamp_order(policy);
+        }
+        if options.invariants {
+            engine = engine.with_invariants();
+        }
+        if let Some(tenant) = options.tenant {
+            engine = engine.with_tenant(tenant);
+        }
+        if let Some(ownership) = options.ownership {
+            engine = engine.with_ownership(ownership);
+        }
+        if let Some(escrow) = options.escrow {
+            engine = engine.with_escrow(escrow);
+        }
+        if let Some(beneficiaries) = options.beneficiaries {
+            engine = engine.with_beneficiaries(beneficiaries);
+        }
+        engine
+    }
+    fn timed<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        match &self.timings {
+            Some(timings) => timings.time(stage, f),
+            None => f(),
         }
     }
     /// process_transaction attempts to create a transaction event and apply that transaction to
-    /// the client account it references
-    pub fn process_transaction(&self, t: TransactionCommand) -> Result<()> {
-        let transaction = match self.transactions.get(t.tx)? {
-            Some(prev) => prev.apply(t)?,
-            None => Transaction::try_from(t)?,
+    /// the client account it references, within a span identifying the transaction. Returns the
+    /// rules the transaction was flagged by, if any.
+    pub fn process_transaction(&self, t: TransactionCommand) -> Result<Vec<Flag>> {
+        let _span = debug_span!(
+            "transaction",
+            tx = t.tx,
+            client = %redact::client(t.client),
+            kind = %t.kind,
+            correlation_id = t.correlation.as_ref().map(CorrelationId::as_str),
+        )
+        .entered();
+        let t = t.for_tenant(self.tenant)?;
+        let late = match &self.timestamps {
+            Some(timestamps) => timestamps.check(&t)?,
+            None => None,
         };
-
-        let updated = match self.accounts.get(transaction.client)? {
-            Some(acc) => acc.apply(transaction)?,
-            None => Account::new(transaction)?,
+        if let Some(kyc) = &self.kyc {
+            self.timed(Stage::Apply, || kyc.check(&t))?;
+        }
+        let mut flags = match &self.rules {
+            Some(rules) => self.timed(Stage::Apply, || rules.evaluate(&t))?,
+            None => Vec::new(),
+        };
+        flags.extend(late);
+        let client = self.ownership.account(t.client);
+        if let TransactionKind::Payout { beneficiary, .. } = t.kind {
+            self.beneficiaries.check(client, beneficiary)?;
+        }
+        let account = self.timed(Stage::Lookup, || self.accounts.get(client))?;
+        let previous = self.timed(Stage::Lookup, || self.transactions.get(t.tx))?;
+        let transaction = match previous {
+            Some(_) if t.kind.is_initial() => {
+                return Err(TransactionError::Duplicate { tx: t.tx }.into())
+            }
+            Some(prev) => self.timed(Stage::Apply, || prev.apply(t, &self.ownership))?,
+            None if !t.kind.is_initial() => {
+                return Err(TransactionError::Unknown { tx: t.tx }.into())
+            }
+            // an erased transaction's ID stays taken, although the transaction itself is gone
+            None if self.timed(Stage::Lookup, || self.transactions.erased(t.tx))? => {
+                return Err(TransactionError::Duplicate { tx: t.tx }.into())
+            }
+            None => self.timed(Stage::Apply, || {
+                let transaction = Transaction::try_from(TransactionCommand { client, ..t })?;
+                let escrow = match &account {
+                    Some(acc) => acc.kind() == AccountKind::Escrow,
+                    None => self.escrow.contains(&client),
+                };
+                Ok::<_, TransactionError>(match transaction.kind {
+                    TransactionKind::Deposit { amount } if escrow => Transaction {
+                        kind: TransactionKind::Escrow { amount },
+                        ..transaction
+                    },
+                    _ => transaction,
+                })
+            })?,
         };
 
-        self.accounts.save(updated)?;
-        self.transactions.save(transaction)?;
+        let updated = self.timed(Stage::Apply, || match account {
+            Some(acc) => acc.apply(transaction),
+            None => Account::new(transaction),
+        })?;
+        if let Some(invariants) = &self.invariants {
+            invariants.check(&transaction, account.as_ref(), &updated)?;
+        }
 
+        self.timed(Stage::Save, || {
+            self.save(account, previous, updated, transaction, transaction)
+        })?;
+        if let Some(invariants) = &self.invariants {
+            invariants.record(&transaction, account.as_ref(), &updated);
+        }
+        if let Some(rules) = &self.rules {
+            rules.record(&t)?;
+        }
+        if let Some(kyc) = &self.kyc {
+            kyc.record(&t);
+        }
+        if let Some(timestamps) = &self.timestamps {
+            timestamps.record(&t);
+        }
+        trace!(available = %redact::amount(updated.available()), held = %redact::amount(updated.held()), "Applied transaction");
+
+        Ok(flags)
+    }
+    /// process_admin applies an admin action to an existing account, recording it in the event
+    /// log, and returns the updated account
+    pub fn process_admin(&self, action: AdminAction) -> Result<Account> {
+        let (client, tx, kind) = (action.client(), action.tx(), action.kind());
+        let _span =
+            debug_span!("admin", tx, client = %redact::client(client), kind = %kind).entered();
+        let account = self
+            .accounts
+            .get(client)?
+            .ok_or(AdminError::UnknownAccount(client))?;
+        let existing = self.transactions.get(tx)?;
+        let (transaction, event) = match (action, existing) {
+            (AdminAction::ForceResolve { .. }, Some(prev)) if prev.client == client => {
+                if prev.kind != TransactionKind::Dispute {
+                    return Err(TransactionError::InvalidState {
+                        from: prev.kind,
+                        to: kind,
+                    }
+                    .into());
+                }
+                // the transaction ends up resolved as usual, while the event records that the
+                // resolve was forced
+                let transaction = Transaction {
+                    kind: TransactionKind::Resolve,
+                    correlation: None,
+                    ..prev
+                };
+                (
+                    transaction,
+                    Transaction {
+                        kind,
+                        ..transaction
+                    },
+                )
+            }
+            (AdminAction::ForceResolve { .. }, _) => {
+                return Err(AdminError::UnknownTransaction { tx, client }.into())
+            }
+            // admin actions mustn't reuse the ID of a transaction
+            (_, Some(prev)) => {
+                return Err(TransactionError::InvalidState {
+                    from: prev.kind,
+                    to: kind,
+                }
+                .into())
+            }
+            (_, None) => {
+                let amount = match kind {
+                    TransactionKind::Adjustment { amount } => amount,
+                    _ => Decimal::from(0),
+                };
+                let transaction = Transaction {
+                    tx,
+                    client,
+                    amount,
+                    kind,
+                    correlation: None,
+                    tenant: self.tenant,
+                    timestamp: None,
+                };
+                (transaction, transaction)
+            }
+        };
+        let updated = account.apply(event)?;
+        if let Some(invariants) = &self.invariants {
+            invariants.check(&event, Some(&account), &updated)?;
+        }
+        self.save(Some(account), existing, updated, transaction, event)?;
+        if let Some(invariants) = &self.invariants {
+            invariants.record(&event, Some(&account), &updated);
+        }
+        trace!(available = %redact::amount(updated.available()), held = %redact::amount(updated.held()), locked = updated.is_locked(), "Applied admin action");
+        Ok(updated)
+    }
+    /// save writes the effects of a transaction as a unit: the transaction, the updated account &
+    /// the event. If a write fails, the ones before it are undone by restoring the previous
+    /// transaction & account, so that nothing is applied twice if it's retried. The event is
+    /// written last, since the log can't be undone.
+    fn save(
+        &self,
+        account: Option<Account>,
+        previous: Option<Transaction>,
+        updated: Account,
+        transaction: Transaction,
+        event: Transaction,
+    ) -> Result<()> {
+        self.transactions.save(transaction)?;
+        if let Err(e) = self.accounts.save(updated) {
+            return Err(self.undo(e, transaction.tx, previous, None));
+        }
+        if let Err(e) = self.events.append(event) {
+            let account = (updated.client(), account);
+            return Err(self.undo(e, transaction.tx, previous, Some(account)));
+        }
+        Ok(())
+    }
+    /// undo restores the previous state of a partially saved transaction & its account, returning
+    /// the error which caused the save to fail, with a SaveError if the state couldn't be restored
+    fn undo(
+        &self,
+        error: anyhow::Error,
+        tx: u64,
+        previous: Option<Transaction>,
+        account: Option<(u64, Option<Account>)>,
+    ) -> anyhow::Error {
+        let restored = (|| -> Result<()> {
+            match account {
+                Some((_, Some(account))) => {
+                    self.accounts.save(account)?;
+                }
+                Some((client, None)) => {
+                    self.accounts.remove(client)?;
+                }
+                None => {}
+            }
+            match previous {
+                Some(transaction) => self.transactions.save(transaction).map(|_| ()),
+                None => self.transactions.remove(tx).map(|_| ()),
+            }
+        })();
+        match restored {
+            Ok(()) => error,
+            Err(e) => error.context(SaveError::Inconsistent {
+                tx,
+                reason: e.to_string(),
+            }),
+        }
+    }
+    /// history returns all of the client's stored transactions, ordered by ID. Only the client's
+    /// own events are read, to find the kind each transaction was created as.
+    pub fn history(&self, client: u64) -> Result<Vec<HistoryEntry>> {
+        let mut origins = HashMap::new();
+        for event in self.events.get_by_client(client)? {
+            origins.entry(event.tx).or_insert(event.kind);
+        }
+        Ok(self
+            .transactions
+            .get_by_client(client)?
+            .into_iter()
+            .map(|transaction| HistoryEntry {
+                origin: origins
+                    .get(&transaction.tx)
+                    .cloned()
+                    .unwrap_or(transaction.kind),
+                transaction,
+            })
+            .collect())
+    }
+    /// open_disputes returns the client's transactions which are currently under dispute, ordered
+    /// by ID
+    pub fn open_disputes(&self, client: u64) -> Result<Vec<Transaction>> {
+        Ok(self
+            .transactions
+            .get_by_client(client)?
+            .into_iter()
+            .filter(|transaction| transaction.kind == TransactionKind::Dispute)
+            .collect())
+    }
+    /// snapshot captures the state of the engine's repositories, to `restore` later, e.g. to try a
+    /// sequence of commands from a known state and roll back. The state kept in memory by rules,
+    /// KYC limits & timestamp ordering (e.g. a client's deposits so far) isn't captured.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        Snapshot::capture(self.transactions, self.accounts, self.events)
+    }
+    /// restore rolls the engine's repositories back to a snapshot: accounts & transactions which
+    /// aren't in it are removed, and the rest are saved as they were. The event log is expected to
+    /// have grown from the snapshot's, as it has when branching from it, so it's truncated back to
+    /// the snapshot's length (or, if it's shorter, the snapshot's later events are appended).
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<()> {
+        let clients: HashSet<u64> = snapshot.accounts.iter().map(Account::client).collect();
+        for account in self.accounts.get_all()? {
+            if !clients.contains(&account.client()) {
+                self.accounts.remove(account.client())?;
+            }
+        }
+        let txs: HashSet<u64> = snapshot.transactions.iter().map(|t| t.tx).collect();
+        for transaction in self.transactions.get_all()? {
+            if !txs.contains(&transaction.tx) {
+                self.transactions.remove(transaction.tx)?;
+            }
+        }
+        for account in &snapshot.accounts {
+            self.accounts.save(*account)?;
+        }
+        for transaction in &snapshot.transactions {
+            self.transactions.save(*transaction)?;
+        }
+        let len = self.events.get_all()?.len();
+        if len >= snapshot.events.len() {
+            self.events.truncate(snapshot.events.len() as u64)?;
+        } else {
+            for event in &snapshot.events[len..] {
+                self.events.append(*event)?;
+            }
+        }
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::accounts::MemoryRepo as AccountsMemoryRepo;
-    use crate::transactions::{MemoryRepo as TransactionsMemoryRepo, TransactionKind};
+    use crate::accounts::{MemoryRepo as AccountsMemoryRepo, SledRepo as AccountsSledRepo};
+    use crate::beneficiaries::PayoutError;
+    use crate::errorlog;
+    use crate::events::{MemoryRepo as EventsMemoryRepo, SledRepo as EventsSledRepo};
+    use crate::testkit::command;
+    use crate::transactions::{
+        MemoryRepo as TransactionsMemoryRepo, SledRepo as TransactionsSledRepo,
+    };
+    use crate::verify::Until;
     use rust_decimal::prelude::*;
 
     use super::*;
@@ -51,14 +576,485 @@ mod tests {
     fn test_process() -> Result<()> {
         let transactions_repo = TransactionsMemoryRepo::new();
         let accounts_repo = AccountsMemoryRepo::new();
-        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo);
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
         let amount = Decimal::from(99);
         let command = TransactionCommand {
             kind: TransactionKind::Deposit { amount },
             tx: 1,
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         };
         engine.process_transaction(command)?;
         Ok(())
     }
+
+    #[test]
+    fn test_process_correlation() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        let amount = Decimal::from(5);
+        for (kind, correlation) in [
+            (
+                TransactionKind::Deposit { amount },
+                Some("payment-1".parse()?),
+            ),
+            (TransactionKind::Dispute, None),
+        ] {
+            engine.process_transaction(TransactionCommand {
+                kind,
+                tx: 1,
+                client: 1,
+                correlation,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+        let correlations: Vec<Option<String>> = events_repo
+            .get_all()?
+            .iter()
+            .map(|e| e.correlation.map(|c| c.to_string()))
+            .collect();
+        assert_eq!(correlations, vec![Some("payment-1".to_string()), None]);
+        assert!(transactions_repo.get(1)?.unwrap().correlation.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_tenants() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let (acme, globex) = ("acme".parse()?, "globex".parse()?);
+        let deposit = |tx, tenant| TransactionCommand {
+            kind: TransactionKind::Deposit {
+                amount: Decimal::from(tx),
+            },
+            tx,
+            client: 1,
+            correlation: None,
+            tenant,
+            timestamp: None,
+        };
+        for (tenant, tx) in [(acme, 1), (globex, 2)] {
+            let transactions = TransactionsSledRepo::for_tenant(&db, Some(tenant))?;
+            let accounts = AccountsSledRepo::for_tenant(&db, Some(tenant))?;
+            let events = EventsSledRepo::for_tenant(&db, Some(tenant))?;
+            let engine = PaymentsEngine::new(&transactions, &accounts, &events).with_tenant(tenant);
+            engine.process_transaction(deposit(tx, None))?;
+            engine.process_transaction(deposit(tx + 10, Some(tenant)))?;
+            let other = if tenant == acme { globex } else { acme };
+            assert_eq!(
+                engine
+                    .process_transaction(deposit(tx + 20, Some(other)))
+                    .unwrap_err()
+                    .downcast::<TransactionError>()?,
+                TransactionError::UnexpectedTenant {
+                    expected: Some(tenant),
+                    got: Some(other),
+                }
+            );
+        }
+
+        // the same client is kept apart in each tenant, and neither touches the default trees
+        let balance = |tenant| -> Result<Option<(Option<TenantId>, Decimal)>> {
+            Ok(AccountsSledRepo::for_tenant(&db, tenant)?
+                .get(1)?
+                .map(|acc| (acc.tenant(), acc.available())))
+        };
+        assert_eq!(balance(Some(acme))?, Some((Some(acme), Decimal::from(12))));
+        assert_eq!(
+            balance(Some(globex))?,
+            Some((Some(globex), Decimal::from(14)))
+        );
+        assert_eq!(balance(None)?, None);
+        let transactions = TransactionsSledRepo::for_tenant(&db, Some(acme))?;
+        assert_eq!(transactions.get(1)?.and_then(|t| t.tenant), Some(acme));
+        assert!(transactions.get(2)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_joint_account() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let ownership = crate::ownership::read_owners("account,client\n1,2\n".as_bytes())?;
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo)
+            .with_ownership(Arc::new(ownership));
+        let amount = Decimal::from(10);
+        // the joint owner opens the account, which the primary client then draws on
+        engine.process_transaction(command(TransactionKind::Deposit { amount }, 2, 1))?;
+        engine.process_transaction(command(TransactionKind::Deposit { amount }, 1, 2))?;
+        engine.process_transaction(command(
+            TransactionKind::Withdrawal {
+                amount: Decimal::from(15),
+            },
+            1,
+            3,
+        ))?;
+        // either owner may dispute the other's deposit, but nobody else can
+        engine.process_transaction(command(TransactionKind::Dispute, 2, 2))?;
+        assert_eq!(
+            engine
+                .process_transaction(command(TransactionKind::Dispute, 3, 1))
+                .unwrap_err()
+                .downcast::<TransactionError>()?,
+            TransactionError::UnexpectedClient {
+                expected: 1,
+                got: 3
+            }
+        );
+
+        let account = accounts_repo.get(1)?.unwrap();
+        assert_eq!(account.available(), Decimal::from(-5));
+        assert_eq!(account.held(), Decimal::from(10));
+        assert!(accounts_repo.get(2)?.is_none());
+        assert_eq!(transactions_repo.get(1)?.unwrap().client, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_escrow() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo)
+            .with_escrow(Arc::new([1].into_iter().collect()));
+        let amount = Decimal::from(10);
+        for (kind, tx, client) in [
+            (TransactionKind::Deposit { amount }, 1, 1),
+            (TransactionKind::Deposit { amount }, 2, 1),
+            (TransactionKind::Deposit { amount }, 3, 2),
+            (TransactionKind::Release, 1, 1),
+            (TransactionKind::Refund, 2, 1),
+        ] {
+            engine.process_transaction(command(kind, client, tx))?;
+        }
+        // deposits into other accounts are available straight away, so can't be released
+        assert!(engine
+            .process_transaction(command(TransactionKind::Release, 2, 3))
+            .is_err());
+
+        let escrow = accounts_repo.get(1)?.unwrap();
+        assert_eq!(escrow.kind(), AccountKind::Escrow);
+        assert_eq!(escrow.available(), amount);
+        assert_eq!(escrow.held(), Decimal::from(0));
+        assert_eq!(accounts_repo.get(2)?.unwrap().kind(), AccountKind::Standard);
+        assert_eq!(
+            events_repo.get_all()?[1].kind,
+            TransactionKind::Escrow { amount }
+        );
+        assert_eq!(
+            transactions_repo.get(2)?.unwrap().kind,
+            TransactionKind::Refund
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_payout() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let beneficiaries =
+            crate::beneficiaries::read_beneficiaries("id,client\n10,1\n".as_bytes())?;
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo)
+            .with_beneficiaries(Arc::new(beneficiaries));
+        let payout = |beneficiary| TransactionKind::Payout {
+            amount: Decimal::from(3),
+            beneficiary,
+        };
+        engine.process_transaction(command(
+            TransactionKind::Deposit {
+                amount: Decimal::from(10),
+            },
+            1,
+            1,
+        ))?;
+        engine.process_transaction(command(payout(10), 1, 2))?;
+        for (tx, client, beneficiary) in [(3, 1, 11), (4, 2, 10)] {
+            assert_eq!(
+                engine
+                    .process_transaction(command(payout(beneficiary), client, tx))
+                    .unwrap_err()
+                    .downcast::<PayoutError>()?,
+                PayoutError::UnregisteredBeneficiary {
+                    client,
+                    beneficiary
+                }
+            );
+        }
+        assert_eq!(accounts_repo.get(1)?.unwrap().available(), Decimal::from(7));
+        assert!(transactions_repo.get(3)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_pending() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        let amount = Decimal::from(10);
+        let settle = TransactionKind::Settle { withdrawal: false };
+        let fail = TransactionKind::Fail { withdrawal: false };
+        let balances = || -> Result<(Decimal, Decimal)> {
+            let account = accounts_repo.get(1)?.unwrap();
+            Ok((account.available(), account.held()))
+        };
+
+        // pending deposits are held, and can't be withdrawn until they settle
+        engine.process_transaction(command(TransactionKind::PendingDeposit { amount }, 1, 1))?;
+        engine.process_transaction(command(TransactionKind::PendingDeposit { amount }, 1, 2))?;
+        assert_eq!(balances()?, (Decimal::from(0), Decimal::from(20)));
+        assert!(engine
+            .process_transaction(command(TransactionKind::Withdrawal { amount }, 1, 3))
+            .is_err());
+        engine.process_transaction(command(settle, 1, 1))?;
+        engine.process_transaction(command(fail, 1, 2))?;
+        assert_eq!(balances()?, (amount, Decimal::from(0)));
+        assert!(engine.process_transaction(command(settle, 1, 2)).is_err());
+
+        // pending withdrawals are held until they're paid out, or returned if they fail
+        let withdrawal = TransactionKind::PendingWithdrawal {
+            amount: Decimal::from(4),
+        };
+        engine.process_transaction(command(withdrawal, 1, 4))?;
+        engine.process_transaction(command(withdrawal, 1, 5))?;
+        assert_eq!(balances()?, (Decimal::from(2), Decimal::from(8)));
+        engine.process_transaction(command(settle, 1, 4))?;
+        engine.process_transaction(command(fail, 1, 5))?;
+        assert_eq!(balances()?, (Decimal::from(6), Decimal::from(0)));
+        assert_eq!(
+            transactions_repo.get(5)?.unwrap().kind,
+            TransactionKind::Fail { withdrawal: true }
+        );
+
+        // settled deposits can be disputed like any other
+        engine.process_transaction(command(TransactionKind::Dispute, 1, 1))?;
+        assert_eq!(balances()?, (Decimal::from(-4), amount));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_rejection_codes() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        let process = |kind, tx| engine.process_transaction(command(kind, 1, tx));
+        let code = |res: Result<Vec<Flag>>| errorlog::code(&res.unwrap_err());
+        let amount = Decimal::from(10);
+        process(TransactionKind::Deposit { amount }, 1)?;
+        assert_eq!(
+            code(process(TransactionKind::Deposit { amount }, 1)),
+            "duplicate_tx"
+        );
+        assert_eq!(code(process(TransactionKind::Dispute, 2)), "unknown_tx");
+        assert_eq!(code(process(TransactionKind::Resolve, 1)), "invalid_state");
+        assert_eq!(
+            code(process(
+                TransactionKind::Withdrawal { amount: 11.into() },
+                3
+            )),
+            "insufficient_funds"
+        );
+        process(TransactionKind::Dispute, 1)?;
+        process(TransactionKind::ChargeBack, 1)?;
+        assert_eq!(
+            code(process(TransactionKind::Deposit { amount }, 4)),
+            "account_locked"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_disputes() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        let amount = Decimal::from(10);
+        for (kind, tx) in [
+            (TransactionKind::Deposit { amount }, 1),
+            (TransactionKind::Deposit { amount }, 2),
+            (TransactionKind::Deposit { amount }, 3),
+            (TransactionKind::Dispute, 1),
+            (TransactionKind::Dispute, 2),
+            (TransactionKind::Resolve, 2),
+        ] {
+            engine.process_transaction(TransactionCommand {
+                kind,
+                tx,
+                client: 1,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+
+        let disputes = engine.open_disputes(1)?;
+        assert_eq!(disputes.len(), 1);
+        assert_eq!(disputes[0].tx, 1);
+        assert!(engine.open_disputes(2)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_history() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        let amount = Decimal::from(10);
+        for (kind, tx, client) in [
+            (TransactionKind::Deposit { amount }, 1, 1),
+            (TransactionKind::Deposit { amount }, 2, 2),
+            (TransactionKind::Withdrawal { amount }, 3, 1),
+            (TransactionKind::Dispute, 1, 1),
+        ] {
+            engine.process_transaction(TransactionCommand {
+                kind,
+                tx,
+                client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+
+        let history = engine.history(1)?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].origin, TransactionKind::Deposit { amount });
+        assert_eq!(history[0].transaction.kind, TransactionKind::Dispute);
+        assert_eq!(history[1].origin, TransactionKind::Withdrawal { amount });
+        assert_eq!(
+            history[1].transaction.kind,
+            TransactionKind::Withdrawal { amount }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_admin() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        let amount = Decimal::from(10);
+        for (kind, tx) in [
+            (TransactionKind::Deposit { amount }, 1),
+            (TransactionKind::Deposit { amount }, 2),
+            (TransactionKind::Dispute, 1),
+            (TransactionKind::Dispute, 2),
+            (TransactionKind::ChargeBack, 2),
+        ] {
+            engine.process_transaction(TransactionCommand {
+                kind,
+                tx,
+                client: 1,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+        // the chargeback locked the account, so the other dispute can only be resolved by force
+        assert!(engine
+            .process_transaction(TransactionCommand {
+                kind: TransactionKind::Resolve,
+                tx: 1,
+                client: 1,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })
+            .is_err());
+        let acc = engine.process_admin(AdminAction::ForceResolve { client: 1, tx: 1 })?;
+        assert_eq!(acc.available(), Decimal::from(10));
+        assert_eq!(acc.held(), Decimal::from(0));
+        assert_eq!(
+            transactions_repo.get(1)?.map(|t| t.kind),
+            Some(TransactionKind::Resolve)
+        );
+
+        let acc = engine.process_admin(AdminAction::Adjust {
+            client: 1,
+            tx: 3,
+            amount: Decimal::from(-4),
+        })?;
+        assert_eq!(acc.available(), Decimal::from(6));
+        let acc = engine.process_admin(AdminAction::Unlock { client: 1, tx: 4 })?;
+        assert!(!acc.is_locked());
+
+        let err = engine
+            .process_admin(AdminAction::Lock { client: 1, tx: 3 })
+            .unwrap_err();
+        assert!(err.is::<TransactionError>());
+        let err = engine
+            .process_admin(AdminAction::Lock { client: 2, tx: 5 })
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::UnknownAccount(2))
+        );
+
+        // replaying the event log reproduces the admin actions
+        let replayed = crate::events::account_as_of(&events_repo, 1, Until::Tx(4))?.unwrap();
+        assert_eq!(replayed.available(), acc.available());
+        assert!(!replayed.is_locked());
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_restore() -> Result<()> {
+        use crate::testkit::{chargeback, deposit, dispute, withdrawal};
+
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        engine.process_transaction(deposit(1, 1, "10"))?;
+        engine.process_transaction(deposit(1, 2, "5"))?;
+        let snapshot = engine.snapshot()?;
+        let state = |engine: &PaymentsEngine| -> Result<String> {
+            let mut state = engine.snapshot()?;
+            state.accounts.sort_by_key(|a| a.client());
+            state.transactions.sort_by_key(|t| t.tx);
+            Ok(serde_json::to_string(&state)?)
+        };
+        let before = state(&engine)?;
+
+        // a branch which locks the account & opens another is rolled back
+        for command in [
+            dispute(1, 1),
+            chargeback(1, 1),
+            deposit(2, 3, "1"),
+            withdrawal(1, 4, "1"),
+        ] {
+            let _ = engine.process_transaction(command);
+        }
+        assert!(accounts_repo.get(1)?.unwrap().is_locked());
+        engine.restore(&snapshot)?;
+        assert_eq!(state(&engine)?, before);
+        assert!(accounts_repo.get(2)?.is_none());
+
+        // so another branch can be tried from the same state
+        engine.process_transaction(withdrawal(1, 4, "1"))?;
+        assert_eq!(
+            accounts_repo.get(1)?.map(|a| a.available()),
+            Some(Decimal::from(14))
+        );
+
+        // & the snapshot can be restored into another engine's empty repositories
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let other = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        other.restore(&snapshot)?;
+        assert_eq!(state(&other)?, before);
+        Ok(())
+    }
 }
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::accounts::Account;
+use crate::events::EventsRepo;
+
+/// Period is how long each statement period lasts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Daily,
+    Monthly,
+}
+
+impl FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Period> {
+        match s {
+            "daily" => Ok(Period::Daily),
+            "monthly" => Ok(Period::Monthly),
+            _ => bail!("expected daily or monthly, got {:?}", s),
+        }
+    }
+}
+
+impl Period {
+    /// start is the first day of the period which a timestamp falls in
+    fn start(self, timestamp: DateTime<Utc>) -> NaiveDate {
+        let date = timestamp.naive_utc().date();
+        match self {
+            Period::Daily => date,
+            Period::Monthly => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    /// label names the period starting on a day, e.g. 2024-01-31 or 2024-01
+    fn label(self, start: NaiveDate) -> String {
+        match self {
+            Period::Daily => start.format("%Y-%m-%d").to_string(),
+            Period::Monthly => start.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// PeriodStatement is a client's statement for a period: their balances when it opened, the
+/// activity which changed their total balance during it, and their balances when it closed
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct PeriodStatement {
+    pub client: u64,
+    pub opening_available: Decimal,
+    pub opening_held: Decimal,
+    pub opening_total: Decimal,
+    /// The number of the client's events in the period
+    pub transactions: u64,
+    /// The increases to the client's total balance, e.g. deposits
+    pub credits: Decimal,
+    /// The decreases to the client's total balance, e.g. withdrawals & chargebacks
+    pub debits: Decimal,
+    pub closing_available: Decimal,
+    pub closing_held: Decimal,
+    pub closing_total: Decimal,
+    pub locked: bool,
+}
+
+/// PeriodStatements are every client's statements for a period, ordered by client
+#[derive(Debug, PartialEq)]
+pub struct PeriodStatements {
+    /// The period's label, e.g. 2024-01-31 for a daily period or 2024-01 for a monthly one
+    pub period: String,
+    pub statements: Vec<PeriodStatement>,
+}
+
+/// period_statements replays the event log and splits it into periods by the events' timestamps,
+/// returning the statements of each period which had any activity, in order. Each period's
+/// statements include every client with an account by its close, so balances carry over from one
+/// period to the next. Events without a timestamp fall in the period of the event before them
+/// (or the first period), as do events timestamped earlier than the period they're logged in.
+pub fn period_statements(events: &dyn EventsRepo, period: Period) -> Result<Vec<PeriodStatements>> {
+    let mut accounts: BTreeMap<u64, Account> = BTreeMap::new();
+    let mut opening: BTreeMap<u64, Account> = BTreeMap::new();
+    let mut activity: BTreeMap<u64, PeriodStatement> = BTreeMap::new();
+    let mut current: Option<NaiveDate> = None;
+    let mut periods = Vec::new();
+    for event in events.get_all()? {
+        if let Some(start) = event.timestamp.map(|t| period.start(t)) {
+            match current {
+                Some(current) if start <= current => (),
+                Some(current) => {
+                    periods.push(close(period.label(current), &opening, &accounts, &activity));
+                    opening = accounts.clone();
+                    activity.clear();
+                }
+                None => (),
+            }
+            current = current.max(Some(start));
+        }
+
+        let (previous, updated) = match accounts.get(&event.client) {
+            Some(acc) => (acc.total(), acc.apply(event)?),
+            None => (Decimal::from(0), Account::new(event)?),
+        };
+        let change = updated.total() - previous;
+        let statement = activity.entry(event.client).or_default();
+        statement.transactions += 1;
+        if change > Decimal::from(0) {
+            statement.credits += change;
+        } else {
+            statement.debits -= change;
+        }
+        accounts.insert(event.client, updated);
+    }
+    if !activity.is_empty() {
+        // a log without any timestamps is a single period, labelled by when it's closed
+        let current = current.unwrap_or_else(|| period.start(Utc::now()));
+        periods.push(close(period.label(current), &opening, &accounts, &activity));
+    }
+    Ok(periods)
+}
+
+/// close is the statements of a period, from the accounts when it opened & closed and the
+/// activity during it
+fn close(
+    period: String,
+    opening: &BTreeMap<u64, Account>,
+    closing: &BTreeMap<u64, Account>,
+    activity: &BTreeMap<u64, PeriodStatement>,
+) -> PeriodStatements {
+    let statements = closing
+        .iter()
+        .map(|(&client, account)| {
+            let activity = activity.get(&client).cloned().unwrap_or_default();
+            let open = opening.get(&client);
+            PeriodStatement {
+                client,
+                opening_available: open.map(Account::available).unwrap_or_default(),
+                opening_held: open.map(Account::held).unwrap_or_default(),
+                opening_total: open.map(Account::total).unwrap_or_default(),
+                closing_available: account.available(),
+                closing_held: account.held(),
+                closing_total: account.total(),
+                locked: account.is_locked(),
+                ..activity
+            }
+        })
+        .collect();
+    PeriodStatements { period, statements }
+}
+
+/// write_period_statements writes each period's statements to its own CSV file in a directory,
+/// named after the period (e.g. statements-2024-01.csv), creating the directory if need be.
+/// Returns the number of files written.
+pub fn write_period_statements(dir: &Path, periods: &[PeriodStatements]) -> Result<usize> {
+    fs::create_dir_all(dir)?;
+    for period in periods {
+        let path = dir.join(format!("statements-{}.csv", period.period));
+        let mut writer = csv::Writer::from_path(path)?;
+        for statement in &period.statements {
+            writer.serialize(statement)?;
+        }
+        writer.flush()?;
+    }
+    Ok(periods.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::MemoryRepo;
+    use crate::transactions::{Transaction, TransactionKind};
+
+    fn event(kind: TransactionKind, tx: u64, client: u64, timestamp: &str) -> Transaction {
+        Transaction {
+            tx,
+            client,
+            amount: Decimal::from(10),
+            kind,
+            correlation: None,
+            tenant: None,
+            timestamp: (!timestamp.is_empty()).then(|| timestamp.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_period_statements() -> Result<()> {
+        let repo = MemoryRepo::new();
+        let amount = Decimal::from(10);
+        let deposit = TransactionKind::Deposit { amount };
+        repo.append(event(deposit, 1, 1, "2024-01-31T09:00:00Z"))?;
+        repo.append(event(deposit, 2, 1, ""))?;
+        repo.append(event(deposit, 3, 2, "2024-02-01T10:00:00Z"))?;
+        repo.append(event(
+            TransactionKind::Dispute,
+            1,
+            1,
+            "2024-02-01T11:00:00Z",
+        ))?;
+        repo.append(event(
+            TransactionKind::ChargeBack,
+            1,
+            1,
+            "2024-02-03T09:00:00Z",
+        ))?;
+
+        let daily = period_statements(&repo, Period::Daily)?;
+        let periods: Vec<&str> = daily.iter().map(|p| p.period.as_str()).collect();
+        assert_eq!(periods, vec!["2024-01-31", "2024-02-01", "2024-02-03"]);
+        assert_eq!(
+            daily[0].statements,
+            vec![PeriodStatement {
+                client: 1,
+                transactions: 2,
+                credits: Decimal::from(20),
+                closing_available: Decimal::from(20),
+                closing_total: Decimal::from(20),
+                ..PeriodStatement::default()
+            }]
+        );
+        // client 1's dispute moves funds to held without changing the total
+        assert_eq!(
+            daily[1].statements[0],
+            PeriodStatement {
+                client: 1,
+                opening_available: Decimal::from(20),
+                opening_total: Decimal::from(20),
+                transactions: 1,
+                closing_available: Decimal::from(10),
+                closing_held: Decimal::from(10),
+                closing_total: Decimal::from(20),
+                ..PeriodStatement::default()
+            }
+        );
+        assert_eq!(daily[1].statements[1].opening_total, Decimal::from(0));
+        assert_eq!(daily[1].statements[1].closing_total, Decimal::from(10));
+        // client 2 had no activity on the 3rd, but their balance carries over
+        let third = &daily[2].statements;
+        assert_eq!((third[0].debits, third[0].locked), (amount, true));
+        assert_eq!((third[1].transactions, third[1].closing_total), (0, amount));
+
+        let monthly = period_statements(&repo, Period::Monthly)?;
+        let periods: Vec<&str> = monthly.iter().map(|p| p.period.as_str()).collect();
+        assert_eq!(periods, vec!["2024-01", "2024-02"]);
+        assert_eq!(monthly[1].statements[0].transactions, 2);
+
+        assert!(period_statements(&MemoryRepo::new(), Period::Daily)?.is_empty());
+        assert!("weekly".parse::<Period>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::mem;
+use std::panic;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Result};
+
+/// BATCH_SIZE is the number of items passed between stages at once, amortising the cost of the
+/// channel
+const BATCH_SIZE: usize = 1024;
+
+/// CHANNEL_CAPACITY is the number of batches which can be queued between stages before the
+/// producing stage blocks, bounding memory usage under backpressure
+const CHANNEL_CAPACITY: usize = 16;
+
+/// ReadAhead drives an iterator on its own thread, e.g. reading & parsing CSV rows, so that it
+/// overlaps with whatever consumes the items
+pub struct ReadAhead<T> {
+    receiver: mpsc::Receiver<Vec<T>>,
+    batch: std::vec::IntoIter<T>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// read_ahead spawns a thread which eagerly pulls items from `iter`, up to the channel's capacity
+pub fn read_ahead<I>(iter: I) -> ReadAhead<I::Item>
+where
+    I: Iterator + Send + 'static,
+    I::Item: Send + 'static,
+{
+    let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    let handle = thread::spawn(move || {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        for item in iter {
+            batch.push(item);
+            if batch.len() == BATCH_SIZE {
+                let full = mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                // the consumer has stopped reading
+                if sender.send(full).is_err() {
+                    return;
+                }
+            }
+        }
+        let _ = sender.send(batch);
+    });
+    ReadAhead {
+        receiver,
+        batch: Vec::new().into_iter(),
+        handle: Some(handle),
+    }
+}
+
+impl<T> Iterator for ReadAhead<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some(item) = self.batch.next() {
+                return Some(item);
+            }
+            match self.receiver.recv() {
+                Ok(batch) => self.batch = batch.into_iter(),
+                Err(_) => {
+                    // the reading thread has finished, re-raise its panic rather than silently
+                    // truncating the input
+                    if let Some(handle) = self.handle.take() {
+                        if let Err(e) = handle.join() {
+                            panic::resume_unwind(e);
+                        }
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// WriteBehind writes items on its own thread, e.g. serializing account statements, so that
+/// producing them isn't blocked on I/O
+pub struct WriteBehind<T> {
+    sender: Option<mpsc::SyncSender<Vec<T>>>,
+    batch: Vec<T>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+/// write_behind spawns a thread which passes an iterator over every item sent to `write`
+pub fn write_behind<T, W>(write: W) -> WriteBehind<T>
+where
+    T: Send + 'static,
+    W: FnOnce(&mut dyn Iterator<Item = T>) -> Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::sync_channel::<Vec<T>>(CHANNEL_CAPACITY);
+    let handle = thread::spawn(move || write(&mut receiver.into_iter().flatten()));
+    WriteBehind {
+        sender: Some(sender),
+        batch: Vec::with_capacity(BATCH_SIZE),
+        handle: Some(handle),
+    }
+}
+
+impl<T> WriteBehind<T> {
+    pub fn send(&mut self, item: T) -> Result<()> {
+        self.batch.push(item);
+        if self.batch.len() < BATCH_SIZE {
+            return Ok(());
+        }
+        let full = mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+        let sent = match &self.sender {
+            Some(sender) => sender.send(full).is_ok(),
+            None => false,
+        };
+        if !sent {
+            // the writing thread has stopped early, its error is returned when joined
+            return self.join();
+        }
+        Ok(())
+    }
+
+    /// finish waits for every item to be written
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(mem::take(&mut self.batch));
+        }
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.sender = None;
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| anyhow!("writer thread panicked"))?,
+            None => Err(anyhow!("writer thread has already stopped")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_read_ahead() {
+        let items: Vec<u32> = read_ahead(0..5000).collect();
+        assert_eq!(items, (0..5000).collect::<Vec<u32>>());
+        assert_eq!(read_ahead(0..0).count(), 0);
+    }
+
+    #[test]
+    fn test_write_behind() -> Result<()> {
+        let written = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&written);
+        let mut writer = write_behind(move |items: &mut dyn Iterator<Item = u32>| {
+            sink.lock().unwrap().extend(items);
+            Ok(())
+        });
+        for item in 0..5000 {
+            writer.send(item)?;
+        }
+        writer.finish()?;
+        assert_eq!(*written.lock().unwrap(), (0..5000).collect::<Vec<u32>>());
+
+        let mut failing =
+            write_behind(|_: &mut dyn Iterator<Item = u32>| Err(anyhow!("disk full")));
+        let mut res = Ok(());
+        for item in 0..5000 {
+            res = failing.send(item);
+            if res.is_err() {
+                break;
+            }
+        }
+        assert!(res.is_err() || failing.finish().is_err());
+        Ok(())
+    }
+}
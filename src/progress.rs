@@ -0,0 +1,94 @@
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::input::{Row, RowError};
+
+/// UPDATE_INTERVAL is the number of rows between updates of the progress bar's position
+const UPDATE_INTERVAL: u64 = 4096;
+
+/// Progress is an iterator of rows which shows how far through the file they are on a progress
+/// bar, along with the throughput & estimated time remaining
+pub struct Progress<I> {
+    inner: I,
+    bar: ProgressBar,
+    rows: u64,
+}
+
+impl<I> Progress<I> {
+    /// new creates a progress bar drawn to stderr, for a file of `len` bytes
+    pub fn new(inner: I, len: u64) -> Progress<I> {
+        Progress::with_bar(inner, ProgressBar::new(len))
+    }
+
+    fn with_bar(inner: I, bar: ProgressBar) -> Progress<I> {
+        bar.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {bar:40} {bytes}/{total_bytes} ({bytes_per_sec}) ETA {eta}",
+            )
+            .expect("valid progress template"),
+        );
+        Progress {
+            inner,
+            bar,
+            rows: 0,
+        }
+    }
+}
+
+impl<I> Iterator for Progress<I>
+where
+    I: Iterator<Item = Result<Row, RowError>>,
+{
+    type Item = Result<Row, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next();
+        match &item {
+            Some(result) => {
+                self.rows += 1;
+                if self.rows.is_multiple_of(UPDATE_INTERVAL) {
+                    let position = match result {
+                        Ok(row) => row.position,
+                        Err(e) => e.position,
+                    };
+                    self.bar.set_position(position.byte);
+                }
+            }
+            None => self.bar.finish_and_clear(),
+        }
+        item
+    }
+}
+
+impl<I> Drop for Progress<I> {
+    fn drop(&mut self) {
+        // clears the bar when processing is aborted part way through the file
+        if !self.bar.is_finished() {
+            self.bar.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{CommandReader, InputConfig};
+    use anyhow::Result;
+
+    #[test]
+    fn test_progress() -> Result<()> {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=UPDATE_INTERVAL {
+            input.push_str(&format!("deposit,1,{},1\n", tx));
+        }
+        let reader = CommandReader::from_reader(input.as_bytes(), InputConfig::default())?;
+        let mut progress = Progress::with_bar(reader, ProgressBar::hidden());
+        progress.bar.set_length(input.len() as u64);
+
+        assert_eq!(progress.by_ref().take(10).count(), 10);
+        assert_eq!(progress.bar.position(), 0);
+        assert_eq!(progress.by_ref().count() as u64, UPDATE_INTERVAL - 10);
+        assert!(progress.bar.position() > 0);
+        assert!(progress.bar.is_finished());
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
+use csv::StringRecord;
+
+/// Quarantine writes rows which couldn't be parsed or processed, verbatim and with the reason
+/// they were rejected in an additional `error` column. The output uses the same columns and
+/// delimiter as the input, so once fixed it can be reprocessed like any other transactions file.
+pub struct Quarantine<W: io::Write> {
+    writer: csv::Writer<W>,
+    width: usize,
+}
+
+impl Quarantine<File> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        headers: &StringRecord,
+        delimiter: u8,
+    ) -> Result<Quarantine<File>> {
+        Quarantine::new(File::create(path)?, headers, delimiter)
+    }
+}
+
+impl<W: io::Write> Quarantine<W> {
+    pub fn new(writer: W, headers: &StringRecord, delimiter: u8) -> Result<Quarantine<W>> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_writer(writer);
+        let mut header = headers.clone();
+        header.push_field("error");
+        writer.write_record(&header)?;
+        Ok(Quarantine {
+            writer,
+            width: headers.len(),
+        })
+    }
+
+    /// reject writes a row along with the reason it was rejected. Short rows are padded so that
+    /// the reason always lands in the `error` column.
+    pub fn reject(&mut self, record: &StringRecord, reason: &str) -> Result<()> {
+        let mut row = record.clone();
+        while row.len() < self.width {
+            row.push_field("");
+        }
+        row.push_field(reason);
+        self.writer.write_record(&row)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reject() -> Result<()> {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let mut quarantine = Quarantine::new(vec![], &headers, b';')?;
+        quarantine.reject(
+            &StringRecord::from(vec!["withdrawal", "2", "5", " 3,0"]),
+            "insufficient funds",
+        )?;
+        quarantine.reject(
+            &StringRecord::from(vec!["dispute", "2", "x"]),
+            "invalid digit",
+        )?;
+
+        let output = String::from_utf8(quarantine.writer.into_inner()?)?;
+        assert_eq!(
+            output,
+            "type;client;tx;amount;error
+withdrawal;2;5; 3,0;insufficient funds
+dispute;2;x;;invalid digit
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RateLimitError {
+    #[error("rate limit exceeded for client {client}, retry in {retry_after:?}")]
+    Exceeded { client: u64, retry_after: Duration },
+}
+
+/// Rate is a number of requests per second, which must be finite & positive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate(f64);
+
+impl Rate {
+    pub fn per_second(self) -> f64 {
+        self.0
+    }
+}
+
+impl FromStr for Rate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Rate> {
+        let rate = s.trim().parse::<f64>()?;
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(anyhow!("rate must be a positive number, got {}", s));
+        }
+        Ok(Rate(rate))
+    }
+}
+
+/// RateLimit is the sustained rate of requests allowed per client, along with the size of the
+/// burst allowed above it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Bucket is the tokens available to a client as of the last time it was checked
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Buckets are the clients' buckets, along with when they were last pruned
+struct Buckets {
+    clients: HashMap<u64, Bucket>,
+    pruned: Instant,
+}
+
+/// RateLimiter is a token bucket per client. Each bucket holds up to `burst` tokens and refills at
+/// `per_second`, with every request taking a token, so that a single client flooding the service
+/// is throttled without affecting anyone else. A full bucket is no different to a new one, so
+/// buckets are dropped once they've had time to refill, keeping the map to the recent clients.
+pub struct RateLimiter {
+    limit: RateLimit,
+    /// How long an empty bucket takes to refill
+    refill: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> RateLimiter {
+        let burst = f64::from(limit.burst.max(1));
+        RateLimiter {
+            limit,
+            refill: Duration::try_from_secs_f64(burst / limit.per_second).unwrap_or(Duration::MAX),
+            buckets: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// check takes a token from the client's bucket, or fails if it's empty
+    pub fn check(&self, client: u64) -> Result<(), RateLimitError> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: u64, now: Instant) -> Result<(), RateLimitError> {
+        let burst = f64::from(self.limit.burst.max(1));
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if now.saturating_duration_since(buckets.pruned) >= self.refill {
+            let refill = self.refill;
+            buckets
+                .clients
+                .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < refill);
+            buckets.pruned = now;
+        }
+        let bucket = buckets.clients.entry(client).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.limit.per_second).min(burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            let retry_after =
+                Duration::try_from_secs_f64((1.0 - bucket.tokens) / self.limit.per_second)
+                    .unwrap_or(Duration::MAX);
+            return Err(RateLimitError::Exceeded {
+                client,
+                retry_after,
+            });
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 10.0,
+            burst: 3,
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(1, start), Ok(()));
+        }
+        assert_eq!(
+            limiter.check_at(1, start),
+            Err(RateLimitError::Exceeded {
+                client: 1,
+                retry_after: Duration::from_millis(100)
+            })
+        );
+        // other clients have their own buckets
+        assert_eq!(limiter.check_at(2, start), Ok(()));
+
+        // refills at the sustained rate, up to the burst
+        let later = start + Duration::from_millis(150);
+        assert_eq!(limiter.check_at(1, later), Ok(()));
+        assert!(limiter.check_at(1, later).is_err());
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(1, much_later), Ok(()));
+        }
+        assert!(limiter.check_at(1, much_later).is_err());
+    }
+
+    #[test]
+    fn test_prune() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 10.0,
+            burst: 3,
+        });
+        let start = Instant::now();
+        for client in 0..100 {
+            assert_eq!(limiter.check_at(client, start), Ok(()));
+        }
+        // every bucket has refilled after 300ms, so only the latest client's is kept
+        let later = start + Duration::from_millis(300);
+        assert_eq!(limiter.check_at(1, later), Ok(()));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.clients.keys().collect::<Vec<_>>(), vec![&1]);
+    }
+
+    #[test]
+    fn test_parse_rate() -> Result<()> {
+        assert_eq!("2.5".parse::<Rate>()?.per_second(), 2.5);
+        for invalid in ["0", "-1", "NaN", "inf", "fast"] {
+            assert!(invalid.parse::<Rate>().is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+}
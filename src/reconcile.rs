@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use anyhow::Result;
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::accounts::Account;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ReconcileError {
+    #[error("{0} client balance(s) do not match the expected balances")]
+    Mismatch(usize),
+}
+
+/// ExpectedBalance is a single row of an externally supplied balances file. Any columns other
+/// than `client` and `total` are ignored.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ExpectedBalance {
+    pub client: u64,
+    pub total: Decimal,
+}
+
+pub fn read_expected<R: io::Read>(reader: R) -> Result<BTreeMap<u64, Decimal>> {
+    let mut expected = BTreeMap::new();
+    for result in csv::Reader::from_reader(reader).deserialize() {
+        let ExpectedBalance { client, total }: ExpectedBalance = result?;
+        expected.insert(client, total);
+    }
+    Ok(expected)
+}
+
+/// Discrepancy describes a client whose total balance differs from the expected balance. Clients
+/// missing from either side are treated as having a zero balance.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Discrepancy {
+    pub client: u64,
+    pub expected: Decimal,
+    pub actual: Decimal,
+    pub difference: Decimal,
+}
+
+/// reconcile compares the accounts' total balances against the expected balances, returning a
+/// discrepancy for every client which doesn't match, ordered by client
+pub fn reconcile(expected: &BTreeMap<u64, Decimal>, accounts: &[Account]) -> Vec<Discrepancy> {
+    let mut balances: BTreeMap<u64, (Decimal, Decimal)> = expected
+        .iter()
+        .map(|(client, total)| (*client, (*total, Decimal::from(0))))
+        .collect();
+    for acc in accounts {
+        balances
+            .entry(acc.client())
+            .or_insert((Decimal::from(0), Decimal::from(0)))
+            .1 = acc.total();
+    }
+
+    balances
+        .into_iter()
+        .filter(|(_, (expected, actual))| expected != actual)
+        .map(|(client, (expected, actual))| Discrepancy {
+            client,
+            expected,
+            actual,
+            difference: actual - expected,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{Transaction, TransactionKind};
+
+    fn account(client: u64, amount: i64) -> Account {
+        let amount = Decimal::from(amount);
+        Account::new(Transaction {
+            tx: client,
+            client,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_reconcile() -> Result<()> {
+        let expected = read_expected(
+            "client,total,locked
+1,10,false
+2,5.0,false
+3,1,false
+"
+            .as_bytes(),
+        )?;
+        let accounts = vec![account(1, 10), account(2, 6), account(4, 2)];
+
+        assert_eq!(
+            reconcile(&expected, &accounts),
+            vec![
+                Discrepancy {
+                    client: 2,
+                    expected: Decimal::from(5),
+                    actual: Decimal::from(6),
+                    difference: Decimal::from(1),
+                },
+                Discrepancy {
+                    client: 3,
+                    expected: Decimal::from(1),
+                    actual: Decimal::from(0),
+                    difference: Decimal::from(-1),
+                },
+                Discrepancy {
+                    client: 4,
+                    expected: Decimal::from(0),
+                    actual: Decimal::from(2),
+                    difference: Decimal::from(2),
+                },
+            ]
+        );
+        Ok(())
+    }
+}
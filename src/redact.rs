@@ -0,0 +1,203 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+use ring::hmac;
+use rust_decimal::prelude::*;
+
+/// REDACTOR is set when logs are redacted, in which case the values wrapped by [client], [amount]
+/// & [error] are displayed redacted
+static REDACTOR: OnceLock<Redactor> = OnceLock::new();
+
+/// Redactor makes client IDs & amounts safe to include in logs shipped outside of the secured
+/// environment. Client IDs are replaced by a keyed hash, so that a client's log lines can still be
+/// correlated without revealing who they are, and amounts by their order of magnitude.
+pub struct Redactor {
+    key: hmac::Key,
+    numbers: Regex,
+}
+
+impl Redactor {
+    /// new creates a redactor which hashes client IDs with `key`. Client IDs are hashed to the same
+    /// value across runs using the same key.
+    pub fn new(key: &[u8]) -> Redactor {
+        Redactor {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key),
+            // client IDs are hashed & transaction IDs, positions and OS error codes left as is,
+            // since they can only be linked to a client through the secured logs, while any other
+            // number could be an amount
+            numbers: Regex::new(r"\b(client|tx|line|byte|error)([ =])(\d+)|-?\d+(?:\.\d+)?")
+                .expect("valid regex"),
+        }
+    }
+
+    /// client is the pseudonym for a client ID
+    pub fn client(&self, client: u64) -> String {
+        let tag = hmac::sign(&self.key, &client.to_be_bytes());
+        tag.as_ref()[..6]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// message redacts a free text message, e.g. an error, hashing the IDs of clients it refers to
+    /// and bucketing every other number
+    pub fn message(&self, message: &str) -> String {
+        self.numbers
+            .replace_all(message, |caps: &Captures| {
+                match (&caps.get(1), &caps.get(3)) {
+                    (Some(label), Some(id)) if label.as_str() == "client" => {
+                        match id.as_str().parse() {
+                            Ok(client) => format!("client{}{}", &caps[2], self.client(client)),
+                            Err(_) => format!("client{}#", &caps[2]),
+                        }
+                    }
+                    (Some(_), Some(_)) => caps[0].to_string(),
+                    _ => match Decimal::from_str(&caps[0]) {
+                        Ok(amount) => bucket(amount),
+                        Err(_) => "#".to_string(),
+                    },
+                }
+            })
+            .into_owned()
+    }
+}
+
+/// bucket is the order of magnitude of an amount, e.g. `100..1000` for 512.34 or `-1..0` for
+/// -0.5
+pub fn bucket(amount: Decimal) -> String {
+    if amount.is_zero() {
+        return "0".to_string();
+    }
+    let whole = amount.abs().trunc();
+    let (lower, upper) = if whole.is_zero() {
+        (Decimal::from(0), Decimal::from(1))
+    } else {
+        let lower = Decimal::from(10).powi(whole.to_string().len() as u64 - 1);
+        (lower, lower * Decimal::from(10))
+    };
+    if amount.is_sign_negative() && lower.is_zero() {
+        format!("-{}..0", upper)
+    } else if amount.is_sign_negative() {
+        format!("-{}..-{}", upper, lower)
+    } else {
+        format!("{}..{}", lower, upper)
+    }
+}
+
+/// enable makes every value logged through this module redacted from now on
+pub fn enable(redactor: Redactor) {
+    // only fails if already enabled, in which case the original redactor is kept so that
+    // client IDs are hashed consistently
+    let _ = REDACTOR.set(redactor);
+}
+
+/// Client displays a client ID for logging, redacted if enabled
+pub struct Client(u64);
+
+/// client wraps a client ID to be logged
+pub fn client(client: u64) -> Client {
+    Client(client)
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match REDACTOR.get() {
+            Some(redactor) => write!(f, "{}", redactor.client(self.0)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Amount displays an amount for logging, redacted if enabled
+pub struct Amount(Decimal);
+
+/// amount wraps an amount to be logged
+pub fn amount(amount: Decimal) -> Amount {
+    Amount(amount)
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match REDACTOR.get() {
+            Some(_) => write!(f, "{}", bucket(self.0)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Message displays an error or other free text for logging, redacted if enabled
+pub struct Message(String);
+
+/// error wraps an error, along with its causes, to be logged
+pub fn error(error: &anyhow::Error) -> Message {
+    Message(format!("{:#}", error))
+}
+
+/// message wraps free text which may refer to clients or amounts, to be logged
+pub fn message(message: impl Into<String>) -> Message {
+    Message(message.into())
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match REDACTOR.get() {
+            Some(redactor) => write!(f, "{}", redactor.message(&self.0)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Row displays a raw input row for logging, which is omitted entirely if redaction is enabled
+/// since its columns can't be told apart
+pub struct Row(String);
+
+/// row wraps a raw input row to be logged
+pub fn row(row: String) -> Row {
+    Row(row)
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match REDACTOR.get() {
+            Some(_) => write!(f, "[redacted]"),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(Decimal::from(0)), "0");
+        assert_eq!(bucket(Decimal::new(5, 1)), "0..1");
+        assert_eq!(bucket(Decimal::new(-5, 1)), "-1..0");
+        assert_eq!(bucket(Decimal::new(51234, 2)), "100..1000");
+        assert_eq!(bucket(Decimal::from(1000)), "1000..10000");
+        assert_eq!(bucket(Decimal::from(-42)), "-100..-10");
+    }
+
+    #[test]
+    fn test_redactor() {
+        let redactor = Redactor::new(b"secret");
+        let client = redactor.client(1);
+        assert_eq!(client.len(), 12);
+        assert_eq!(client, Redactor::new(b"secret").client(1));
+        assert_ne!(client, Redactor::new(b"other").client(1));
+        assert_ne!(client, redactor.client(2));
+
+        let message = "unable to process transaction tx=7 client=1 at line 3: \
+                       client 1 is unverified, so can't deposit more than 1000.50 in total";
+        assert_eq!(
+            redactor.message(message),
+            format!(
+                "unable to process transaction tx=7 client={0} at line 3: \
+                 client {0} is unverified, so can't deposit more than 1000..10000 in total",
+                client
+            )
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+
+use anyhow::Result;
+use csv::StringRecord;
+use tracing::debug;
+
+use crate::correlation::CorrelationId;
+use crate::errorlog::{self, ErrorEntry, ErrorLog, RejectionReport};
+use crate::input::{self, Position};
+use crate::quarantine::Quarantine;
+use crate::redact;
+use crate::suspense::{self, Suspense};
+use crate::threshold::ErrorThreshold;
+use crate::transactions::TransactionCommand;
+
+/// Rejections handles rows which couldn't be parsed or processed: logging them, writing them to
+/// the quarantine file & error log, and deciding whether the run should be aborted
+pub struct Rejections {
+    pub quarantine: Option<Quarantine<File>>,
+    pub error_log: Option<ErrorLog<File>>,
+    pub threshold: ErrorThreshold,
+    /// Abort on the first rejected row
+    pub strict: bool,
+    /// The number of rows rejected so far
+    pub rejected: u64,
+    /// The number of rows rejected so far, by error code
+    pub reasons: BTreeMap<&'static str, u64>,
+    /// Holds unmatched disputes, resolves & chargebacks instead of rejecting them, if set
+    pub suspense: Option<Suspense<File>>,
+    pub report: Option<RejectionReport<File>>,
+}
+
+impl Rejections {
+    pub fn new(
+        quarantine: Option<Quarantine<File>>,
+        error_log: Option<ErrorLog<File>>,
+        threshold: ErrorThreshold,
+        strict: bool,
+    ) -> Rejections {
+        Rejections {
+            quarantine,
+            error_log,
+            threshold,
+            strict,
+            rejected: 0,
+            reasons: BTreeMap::new(),
+            suspense: None,
+            report: None,
+        }
+    }
+
+    /// with_report also writes every rejected row to a CSV rejection report
+    pub fn with_report(self, report: RejectionReport<File>) -> Rejections {
+        Rejections {
+            report: Some(report),
+            ..self
+        }
+    }
+
+    /// with_suspense holds disputes, resolves & chargebacks of unknown transactions in `suspense`,
+    /// rather than rejecting them
+    pub fn with_suspense(self, suspense: Suspense<File>) -> Rejections {
+        Rejections {
+            suspense: Some(suspense),
+            ..self
+        }
+    }
+
+    /// reject records a row which couldn't be parsed (no command) or processed, returning an
+    /// error if the run should be aborted
+    pub fn reject(
+        &mut self,
+        error: anyhow::Error,
+        record: &StringRecord,
+        position: Position,
+        command: Option<TransactionCommand>,
+    ) -> Result<()> {
+        if let (Some(suspense), Some(command)) = (&mut self.suspense, command) {
+            if suspense::unmatched(&error, &command) {
+                debug!(
+                    tx = command.tx,
+                    client = %redact::client(command.client),
+                    line = position.line,
+                    "Holding unmatched transaction in suspense"
+                );
+                return suspense.hold(&command, record, position.line);
+            }
+        }
+        match command {
+            Some(command) => debug!(
+                error = %redact::error(&error),
+                tx = command.tx,
+                client = %redact::client(command.client),
+                correlation_id = command.correlation.as_ref().map(CorrelationId::as_str),
+                line = position.line,
+                byte = position.byte,
+                row = %redact::row(input::raw(record)),
+                "Unable to process transaction"
+            ),
+            None => debug!(
+                error = %redact::error(&error),
+                line = position.line,
+                byte = position.byte,
+                row = %redact::row(input::raw(record)),
+                "Unable to parse row"
+            ),
+        }
+
+        self.rejected += 1;
+        let code = errorlog::code(&error);
+        *self.reasons.entry(code).or_default() += 1;
+        if let Some(quarantine) = &mut self.quarantine {
+            quarantine.reject(record, &error.to_string())?;
+        }
+        let entry = ErrorEntry {
+            line: position.line,
+            tx: command.map(|c| c.tx),
+            client: command.map(|c| c.client),
+            correlation_id: command.and_then(|c| c.correlation),
+            code,
+            message: error.to_string(),
+        };
+        if let Some(error_log) = &mut self.error_log {
+            error_log.log(&entry)?;
+        }
+        if let Some(report) = &mut self.report {
+            report.log(&entry)?;
+        }
+
+        let res = if self.strict {
+            let context = match command {
+                Some(command) => format!(
+                    "unable to process transaction tx={} client={} at line {}",
+                    command.tx, command.client, position.line
+                ),
+                None => format!("unable to parse row at line {}", position.line),
+            };
+            Err(error.context(context))
+        } else {
+            self.threshold
+                .check_count(self.rejected)
+                .map_err(anyhow::Error::from)
+        };
+        if res.is_err() {
+            self.flush()?;
+        }
+        res
+    }
+
+    /// finish flushes the output files & checks the error rate once all `total` rows have been
+    /// seen
+    pub fn finish(&mut self, total: u64) -> Result<()> {
+        self.flush()?;
+        self.threshold.check_rate(self.rejected, total)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(quarantine) = &mut self.quarantine {
+            quarantine.flush()?;
+        }
+        if let Some(error_log) = &mut self.error_log {
+            error_log.flush()?;
+        }
+        if let Some(suspense) = &mut self.suspense {
+            suspense.flush()?;
+        }
+        if let Some(report) = &mut self.report {
+            report.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::AccountError;
+
+    #[test]
+    fn test_reject() -> Result<()> {
+        let record = StringRecord::from(vec!["withdrawal", "1", "1", "5.0"]);
+        let threshold = ErrorThreshold {
+            max_errors: Some(1),
+            max_rate: Some("50%".parse()?),
+        };
+        let mut rejections = Rejections::new(None, None, threshold, false);
+        rejections.reject(
+            AccountError::InsufficientFunds.into(),
+            &record,
+            Position::default(),
+            None,
+        )?;
+        assert_eq!(rejections.rejected, 1);
+        assert!(rejections.finish(2).is_ok());
+        assert!(rejections.finish(1).is_err());
+        assert!(rejections
+            .reject(
+                AccountError::InsufficientFunds.into(),
+                &record,
+                Position::default(),
+                None
+            )
+            .is_err());
+
+        let mut strict = Rejections::new(None, None, ErrorThreshold::default(), true);
+        let err = strict
+            .reject(
+                AccountError::InsufficientFunds.into(),
+                &record,
+                Position { line: 7, byte: 0 },
+                None,
+            )
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "unable to parse row at line 7: insufficient funds"
+        );
+        Ok(())
+    }
+}
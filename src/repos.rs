@@ -0,0 +1,175 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::accounts::{
+    AccountsRepo, OverlayRepo as AccountsOverlayRepo, SledRepo as AccountsSledRepo,
+};
+use crate::archive::{Archive, ArchiveRepo};
+use crate::cache::CachedRepo;
+use crate::encryption::Cipher;
+use crate::events::{EventsRepo, OverlayRepo as EventsOverlayRepo, SledRepo as EventsSledRepo};
+use crate::memory::MemoryConfig;
+use crate::migrate;
+use crate::payments::{EngineOptions, PaymentsEngine};
+use crate::snapshot::Snapshot;
+use crate::tenant::TenantId;
+use crate::transactions::{
+    OverlayRepo as TransactionsOverlayRepo, SledRepo as TransactionsSledRepo, TransactionsRepo,
+};
+
+/// Repos holds the repositories backing a run, either in memory or persisted to a sled database
+pub struct Repos {
+    pub transactions: Box<dyn TransactionsRepo>,
+    pub accounts: Box<dyn AccountsRepo>,
+    pub events: Box<dyn EventsRepo>,
+    pub db: Option<sled::Db>,
+    /// Encrypts the database & snapshots, if PAYMENTS_ENCRYPTION_KEY is set
+    pub cipher: Option<Cipher>,
+    /// The tenant whose state the repositories hold
+    pub tenant: Option<TenantId>,
+    /// Holds the database's dormant accounts
+    pub archive: Option<Arc<Archive>>,
+}
+
+impl Repos {
+    // @TODO: as we scale, the in-memory repositories might no longer be suitable due to
+    // memory constraints & cold start (loading all transactions that ever occurred into memory
+    // from CSV vs snapshotting the state at a known point in time).
+    //
+    // To mitigate this, the in-memory implementations can be swapped out for ones utilising a db
+    // with a higher capacity & more durable storage backend, as is done for sled below. Adding
+    // another backend (e.g. sqlite, redis, postgres or dynamodb) is as simple as implementing the
+    // AccountsRepo/TransactionsRepo/EventsRepo traits respectively.
+    pub fn open(db: Option<&str>, tenant: Option<TenantId>) -> Result<Repos> {
+        Repos::open_db(db, tenant, true)
+    }
+
+    /// open_unmigrated opens the repositories like open, but without migrating the database,
+    /// which must already be on the current schema. It's for runs which mustn't write to the
+    /// database, e.g. dry runs.
+    pub fn open_unmigrated(db: Option<&str>, tenant: Option<TenantId>) -> Result<Repos> {
+        Repos::open_db(db, tenant, false)
+    }
+
+    fn open_db(db: Option<&str>, tenant: Option<TenantId>, migrate: bool) -> Result<Repos> {
+        match db {
+            Some(path) => {
+                let db = sled::open(path)?;
+                if migrate {
+                    migrate::migrate(&db)?;
+                } else {
+                    migrate::check(&db)?;
+                }
+                let cipher = Cipher::from_env()?;
+                let (mut transactions, mut accounts, mut events) = (
+                    TransactionsSledRepo::for_tenant(&db, tenant)?,
+                    AccountsSledRepo::for_tenant(&db, tenant)?,
+                    EventsSledRepo::for_tenant(&db, tenant)?,
+                );
+                if let Some(cipher) = &cipher {
+                    transactions = transactions.encrypted(cipher.clone());
+                    accounts = accounts.encrypted(cipher.clone());
+                    events = events.encrypted(cipher.clone());
+                }
+                let archive = Arc::new(Archive::open(&db, tenant, cipher.clone())?);
+                Ok(Repos {
+                    transactions: Box::new(transactions),
+                    accounts: Box::new(ArchiveRepo::new(accounts, archive.clone())),
+                    events: Box::new(events),
+                    db: Some(db),
+                    cipher,
+                    tenant,
+                    archive: Some(archive),
+                })
+            }
+            None => Repos::memory(MemoryConfig::default(), tenant),
+        }
+    }
+
+    /// memory creates empty in-memory repositories
+    pub fn memory(config: MemoryConfig, tenant: Option<TenantId>) -> Result<Repos> {
+        Ok(Repos {
+            transactions: config.transactions_repo()?,
+            accounts: config.accounts_repo(),
+            events: config.events_repo()?,
+            db: None,
+            cipher: Cipher::from_env()?,
+            tenant,
+            archive: None,
+        })
+    }
+
+    /// cached wraps the transactions & accounts repositories with LRU caches of `capacity`
+    /// records each, saving a round trip to the database for recently used records
+    pub fn cached(self, capacity: usize) -> Repos {
+        Repos {
+            transactions: Box::new(CachedRepo::new(self.transactions, capacity)),
+            accounts: Box::new(CachedRepo::new(self.accounts, capacity)),
+            ..self
+        }
+    }
+
+    /// dry_run wraps the repositories so that state is still read from the configured backend,
+    /// but every write is kept in memory and discarded at the end of the run
+    pub fn dry_run(self) -> Result<Repos> {
+        Ok(Repos {
+            transactions: Box::new(TransactionsOverlayRepo::new(self.transactions)),
+            accounts: Box::new(AccountsOverlayRepo::new(self.accounts)),
+            events: Box::new(EventsOverlayRepo::new(self.events)?),
+            db: None,
+            cipher: self.cipher,
+            tenant: self.tenant,
+            archive: None,
+        })
+    }
+
+    /// resume opens the state produced by a previous run, either a sled database directory or a
+    /// snapshot file which is restored into memory
+    pub fn resume(path: &str, tenant: Option<TenantId>) -> Result<Repos> {
+        if Path::new(path).is_dir() {
+            return Repos::open(Some(path), tenant);
+        }
+        let repos = Repos::open(None, tenant)?;
+        Snapshot::load(path, repos.cipher.as_ref())?.restore(
+            repos.transactions.as_ref(),
+            repos.accounts.as_ref(),
+            repos.events.as_ref(),
+        )?;
+        Ok(repos)
+    }
+
+    /// snapshot captures the repositories' state, including any archived accounts
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let mut snapshot = Snapshot::capture(
+            self.transactions.as_ref(),
+            self.accounts.as_ref(),
+            self.events.as_ref(),
+        )?;
+        if let Some(archive) = &self.archive {
+            snapshot.accounts.extend(archive.get_all()?);
+        }
+        Ok(snapshot)
+    }
+
+    pub fn engine(&self) -> PaymentsEngine<'_, '_, '_> {
+        PaymentsEngine::new(
+            self.transactions.as_ref(),
+            self.accounts.as_ref(),
+            self.events.as_ref(),
+        )
+        .configure(EngineOptions {
+            tenant: self.tenant,
+            ..EngineOptions::default()
+        })
+    }
+
+    /// flush ensures any persisted state has been written to disk
+    pub fn flush(&self) -> Result<()> {
+        if let Some(db) = &self.db {
+            db.flush()?;
+        }
+        Ok(())
+    }
+}
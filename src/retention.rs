@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::transactions::{TransactionCommand, TransactionError, TransactionsRepo};
+
+/// Retention evicts transactions from the transactions repo once they've been settled for longer
+/// than the TTL, keeping it bounded for long running deployments. A transaction is settled unless
+/// it's under dispute, so disputed transactions are kept until they're resolved or charged back.
+/// Once evicted, a transaction can no longer be disputed, so the TTL is effectively the window in
+/// which disputes are accepted. The event log is unaffected, since it's the record of everything
+/// which happened. The IDs of evicted transactions are kept, so that replaying one is still
+/// rejected as a duplicate rather than applied again.
+pub struct Retention {
+    ttl: Duration,
+    /// Every transaction in the order it was last saved, which may include earlier saves of the
+    /// same transaction
+    queue: VecDeque<(Instant, u64)>,
+    /// When each transaction was last saved, to tell whether an entry in the queue is its latest
+    saved: HashMap<u64, Instant>,
+    /// The IDs of the transactions which have been evicted
+    evicted: HashSet<u64>,
+}
+
+impl Retention {
+    pub fn new(ttl: Duration) -> Retention {
+        Retention {
+            ttl,
+            queue: VecDeque::new(),
+            saved: HashMap::new(),
+            evicted: HashSet::new(),
+        }
+    }
+
+    /// evicted notes that a transaction was evicted before retention started tracking it, e.g. by
+    /// an earlier run
+    pub fn evicted(&mut self, tx: u64) {
+        self.evicted.insert(tx);
+    }
+
+    /// check rejects a command which would create a transaction that's since been evicted, since
+    /// the engine can no longer tell that it's a duplicate
+    pub fn check(&self, command: &TransactionCommand) -> Result<(), TransactionError> {
+        if command.kind.is_initial() && self.evicted.contains(&command.tx) {
+            return Err(TransactionError::Duplicate { tx: command.tx });
+        }
+        Ok(())
+    }
+
+    /// record notes that a transaction was saved at `now`, restarting its TTL
+    pub fn record(&mut self, tx: u64, now: Instant) {
+        self.queue.push_back((now, tx));
+        self.saved.insert(tx, now);
+    }
+
+    /// evict removes every settled transaction last saved more than the TTL before `now`,
+    /// returning the number removed
+    pub fn evict(&mut self, transactions: &dyn TransactionsRepo, now: Instant) -> Result<usize> {
+        let mut evicted = 0;
+        while let Some(&(saved, tx)) = self.queue.front() {
+            if now.saturating_duration_since(saved) < self.ttl {
+                break;
+            }
+            self.queue.pop_front();
+            // a later save of the same transaction is further back in the queue
+            if self.saved.get(&tx) != Some(&saved) {
+                continue;
+            }
+            self.saved.remove(&tx);
+            // unsettled (e.g. disputed or pending) transactions are recorded again once settled
+            match transactions.get(tx)? {
+                Some(t) if t.kind.is_settled() => {
+                    transactions.remove(tx)?;
+                    self.evicted.insert(tx);
+                    evicted += 1;
+                }
+                _ => (),
+            }
+        }
+        Ok(evicted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{MemoryRepo, Transaction, TransactionKind};
+    use rust_decimal::prelude::*;
+
+    #[test]
+    fn test_evict() -> Result<()> {
+        let repo = MemoryRepo::new();
+        let save = |tx, kind| {
+            repo.save(Transaction {
+                tx,
+                client: 1,
+                amount: Decimal::from(1),
+                kind,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })
+        };
+        let deposit = TransactionKind::Deposit {
+            amount: Decimal::from(1),
+        };
+        let mut retention = Retention::new(Duration::from_secs(60));
+        let start = Instant::now();
+        for tx in 1..=3 {
+            save(tx, deposit)?;
+            retention.record(tx, start);
+        }
+        // 2 is disputed & 3 saved again later, so neither is evicted with 1
+        save(2, TransactionKind::Dispute)?;
+        let later = start + Duration::from_secs(30);
+        save(3, deposit)?;
+        retention.record(3, later);
+
+        assert_eq!(retention.evict(&repo, start + Duration::from_secs(59))?, 0);
+        assert_eq!(retention.evict(&repo, start + Duration::from_secs(60))?, 1);
+        let remaining: Vec<u64> = repo.get_all()?.iter().map(|t| t.tx).collect();
+        assert_eq!(remaining, vec![2, 3]);
+
+        // once resolved, the disputed transaction is evicted after the TTL too
+        save(2, TransactionKind::Resolve)?;
+        retention.record(2, later);
+        assert_eq!(retention.evict(&repo, later + Duration::from_secs(60))?, 2);
+        assert!(repo.get_all()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_evicted_duplicate() -> Result<()> {
+        let repo = MemoryRepo::new();
+        let amount = Decimal::from(1);
+        let command = |kind, tx| TransactionCommand {
+            tx,
+            client: 1,
+            kind,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+        let mut retention = Retention::new(Duration::from_secs(60));
+        let start = Instant::now();
+        repo.save(Transaction {
+            tx: 1,
+            client: 1,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        retention.record(1, start);
+        let deposit = command(TransactionKind::Deposit { amount }, 1);
+        assert!(retention.check(&deposit).is_ok());
+        assert_eq!(retention.evict(&repo, start + Duration::from_secs(60))?, 1);
+
+        // replaying the evicted deposit would credit it again
+        assert_eq!(
+            retention.check(&deposit),
+            Err(TransactionError::Duplicate { tx: 1 })
+        );
+        assert!(retention
+            .check(&command(TransactionKind::Dispute, 1))
+            .is_ok());
+        retention.evicted(2);
+        assert!(retention
+            .check(&command(TransactionKind::Deposit { amount }, 2))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_evict_unsettled() -> Result<()> {
+        let repo = MemoryRepo::new();
+        let amount = Decimal::from(1);
+        let kinds = [
+            TransactionKind::Escrow { amount },
+            TransactionKind::PendingDeposit { amount },
+            TransactionKind::PendingWithdrawal { amount },
+        ];
+        let mut retention = Retention::new(Duration::from_secs(60));
+        let start = Instant::now();
+        for (tx, kind) in (1..).zip(kinds) {
+            repo.save(Transaction {
+                tx,
+                client: 1,
+                amount,
+                kind,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+            retention.record(tx, start);
+        }
+        // escrowed & pending funds are still held, so they can be released or settled later
+        assert_eq!(retention.evict(&repo, start + Duration::from_secs(120))?, 0);
+        assert_eq!(repo.get_all()?.len(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::transactions::{TransactionCommand, TransactionKind};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RuleError {
+    #[error("rejected by rule {rule:?}")]
+    Rejected { rule: String },
+    #[error("client's running total overflowed")]
+    Overflow,
+}
+
+impl RuleError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuleError::Rejected { .. } => "rule_rejected",
+            RuleError::Overflow => "rule_total_overflow",
+        }
+    }
+}
+
+/// RuleKind is the type of transaction a rule applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// Limit is the condition a transaction must stay within. Counts & totals are per client, across
+/// every accepted transaction of the rule's type so far (or within the rule's window), including
+/// the one being evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Limit {
+    /// The maximum number of transactions
+    #[serde(rename = "max_count")]
+    Count(u64),
+    /// The maximum sum of transaction amounts
+    #[serde(rename = "max_total")]
+    Total(Decimal),
+    /// The maximum amount of a single transaction
+    #[serde(rename = "max_amount")]
+    Amount(Decimal),
+}
+
+/// Action is what happens to a transaction which exceeds a rule's limit
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Reject the transaction, as if it were invalid
+    Reject,
+    /// Accept the transaction, but record that it broke the rule
+    Flag,
+}
+
+/// Rule is a single limit declared in the rules config, e.g.
+/// `{"name": "large_deposit", "type": "deposit", "max_amount": "10000", "action": "flag"}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: RuleKind,
+    #[serde(flatten)]
+    pub limit: Limit,
+    pub action: Action,
+    /// Limits the count or total over a rolling window of this many seconds before each
+    /// transaction, by the transactions' timestamps, rather than over the whole run. Transactions
+    /// without a timestamp aren't checked against (or counted towards) windowed limits.
+    #[serde(default)]
+    pub window_secs: Option<u64>,
+}
+
+/// RuleSet is every rule declared in the rules config
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+/// read_rules reads a JSON rules config
+pub fn read_rules<R: io::Read>(reader: R) -> Result<RuleSet> {
+    let set: RuleSet = serde_json::from_reader(io::BufReader::new(reader))?;
+    for (i, rule) in set.rules.iter().enumerate() {
+        if set.rules[..i].iter().any(|r| r.name == rule.name) {
+            bail!("rule {:?} is declared more than once", rule.name);
+        }
+        if let (Some(_), Limit::Amount(_)) = (rule.window_secs, rule.limit) {
+            bail!(
+                "rule {:?} limits single amounts, so can't have a window",
+                rule.name
+            );
+        }
+    }
+    Ok(set)
+}
+
+/// Flag records an accepted transaction which broke a rule with the flag action
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Flag {
+    pub rule: String,
+    pub tx: u64,
+    pub client: u64,
+}
+
+/// Totals is the number & sum of a client's accepted transactions of one type
+#[derive(Debug, Clone, Copy, Default)]
+struct Totals {
+    count: u64,
+    total: Decimal,
+}
+
+/// Recent is a client's accepted transactions of one type with a timestamp, and their amounts,
+/// within the longest window of the rules for the type
+type Recent = Vec<(DateTime<Utc>, Decimal)>;
+
+/// Rules evaluates transactions against a rule set, keeping track of the per client totals the
+/// limits are checked against, and each client's recent timestamped transactions for the limits
+/// over a window
+pub struct Rules {
+    set: Arc<RuleSet>,
+    totals: RefCell<HashMap<(u64, RuleKind), Totals>>,
+    recent: RefCell<HashMap<(u64, RuleKind), Recent>>,
+    /// The longest window of any rule for each type, beyond which recent transactions are dropped
+    windows: HashMap<RuleKind, Duration>,
+}
+
+impl Rules {
+    pub fn new(set: Arc<RuleSet>) -> Rules {
+        let mut windows: HashMap<RuleKind, Duration> = HashMap::new();
+        for rule in &set.rules {
+            if let Some(secs) = rule.window_secs {
+                let window = window(secs);
+                let longest = windows.entry(rule.kind).or_insert(window);
+                *longest = (*longest).max(window);
+            }
+        }
+        Rules {
+            set,
+            totals: RefCell::new(HashMap::new()),
+            recent: RefCell::new(HashMap::new()),
+            windows,
+        }
+    }
+
+    /// windowed is the count & sum of a client's recorded transactions of a type within a window
+    /// before a timestamp
+    fn windowed(
+        &self,
+        client: u64,
+        kind: RuleKind,
+        timestamp: DateTime<Utc>,
+        secs: u64,
+    ) -> Result<Totals, RuleError> {
+        let start = timestamp - window(secs);
+        let recent = self.recent.borrow();
+        let mut totals = Totals::default();
+        for &(at, amount) in recent.get(&(client, kind)).into_iter().flatten() {
+            if at > start && at <= timestamp {
+                totals.count += 1;
+                totals.total = totals
+                    .total
+                    .checked_add(amount)
+                    .ok_or(RuleError::Overflow)?;
+            }
+        }
+        Ok(totals)
+    }
+
+    /// evaluate checks a transaction against every rule for its type, failing on the first one
+    /// it breaks which rejects, or otherwise returning the flags it should be accepted with. It
+    /// also fails if the transaction would overflow its client's running total, so that it can't
+    /// be recorded.
+    pub fn evaluate(&self, command: &TransactionCommand) -> Result<Vec<Flag>, RuleError> {
+        let (kind, amount) = match kind(command) {
+            Some(kind) => kind,
+            None => return Ok(Vec::new()),
+        };
+        let totals = self
+            .totals
+            .borrow()
+            .get(&(command.client, kind))
+            .copied()
+            .unwrap_or_default();
+        totals
+            .total
+            .checked_add(amount)
+            .ok_or(RuleError::Overflow)?;
+        let mut flags = Vec::new();
+        for rule in self.set.rules.iter().filter(|r| r.kind == kind) {
+            let totals = match (rule.window_secs, command.timestamp) {
+                (None, _) => totals,
+                (Some(secs), Some(timestamp)) => {
+                    self.windowed(command.client, kind, timestamp, secs)?
+                }
+                (Some(_), None) => continue,
+            };
+            let exceeded = match rule.limit {
+                Limit::Count(max) => totals.count + 1 > max,
+                Limit::Total(max) => {
+                    totals
+                        .total
+                        .checked_add(amount)
+                        .ok_or(RuleError::Overflow)?
+                        > max
+                }
+                Limit::Amount(max) => amount > max,
+            };
+            if !exceeded {
+                continue;
+            }
+            match rule.action {
+                Action::Reject => {
+                    return Err(RuleError::Rejected {
+                        rule: rule.name.clone(),
+                    })
+                }
+                Action::Flag => flags.push(Flag {
+                    rule: rule.name.clone(),
+                    tx: command.tx,
+                    client: command.client,
+                }),
+            }
+        }
+        Ok(flags)
+    }
+
+    /// record adds an accepted transaction to its client's totals, which evaluate has already
+    /// checked won't overflow
+    pub fn record(&self, command: &TransactionCommand) -> Result<(), RuleError> {
+        if let Some((kind, amount)) = kind(command) {
+            let mut totals = self.totals.borrow_mut();
+            let totals = totals.entry((command.client, kind)).or_default();
+            totals.total = totals
+                .total
+                .checked_add(amount)
+                .ok_or(RuleError::Overflow)?;
+            totals.count += 1;
+
+            if let (Some(&longest), Some(timestamp)) = (self.windows.get(&kind), command.timestamp)
+            {
+                let mut recent = self.recent.borrow_mut();
+                let recent = recent.entry((command.client, kind)).or_default();
+                recent.retain(|&(at, _)| at > timestamp - longest);
+                recent.push((timestamp, amount));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// MAX_WINDOW_SECS caps how far back a window reaches (about a century), so that subtracting it
+/// from a timestamp can't overflow
+const MAX_WINDOW_SECS: u64 = 100 * 366 * 24 * 60 * 60;
+
+/// window is a rule's window as a duration
+fn window(secs: u64) -> Duration {
+    Duration::seconds(secs.min(MAX_WINDOW_SECS) as i64)
+}
+
+fn kind(command: &TransactionCommand) -> Option<(RuleKind, Decimal)> {
+    match command.kind {
+        TransactionKind::Deposit { amount } | TransactionKind::PendingDeposit { amount } => {
+            Some((RuleKind::Deposit, amount))
+        }
+        TransactionKind::Withdrawal { amount }
+        | TransactionKind::PendingWithdrawal { amount }
+        | TransactionKind::Payout { amount, .. } => Some((RuleKind::Withdrawal, amount)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn withdrawal(tx: u64, amount: i64) -> TransactionCommand {
+        TransactionCommand {
+            kind: TransactionKind::Withdrawal {
+                amount: Decimal::from(amount),
+            },
+            tx,
+            client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_read_rules() -> Result<()> {
+        let config = r#"{"rules": [
+            {"name": "max_withdrawals", "type": "withdrawal", "max_count": 10, "action": "reject"},
+            {"name": "large_deposit", "type": "deposit", "max_amount": "10000", "action": "flag"}
+        ]}"#;
+        let set = read_rules(config.as_bytes())?;
+        assert_eq!(set.rules.len(), 2);
+        assert_eq!(set.rules[0].limit, Limit::Count(10));
+        assert_eq!(set.rules[1].limit, Limit::Amount(Decimal::from(10000)));
+        assert_eq!(set.rules[1].action, Action::Flag);
+
+        let duplicate = r#"{"rules": [
+            {"name": "a", "type": "deposit", "max_count": 1, "action": "flag"},
+            {"name": "a", "type": "deposit", "max_count": 2, "action": "flag"}
+        ]}"#;
+        assert!(read_rules(duplicate.as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate() -> Result<()> {
+        let rule = |name: &str, limit, action| Rule {
+            name: name.to_string(),
+            kind: RuleKind::Withdrawal,
+            limit,
+            action,
+            window_secs: None,
+        };
+        let rules = Rules::new(Arc::new(RuleSet {
+            rules: vec![
+                rule("max_withdrawals", Limit::Count(2), Action::Reject),
+                rule(
+                    "max_withdrawn",
+                    Limit::Total(Decimal::from(100)),
+                    Action::Reject,
+                ),
+                rule("large", Limit::Amount(Decimal::from(50)), Action::Flag),
+            ],
+        }));
+
+        assert_eq!(rules.evaluate(&withdrawal(1, 10))?, vec![]);
+        rules.record(&withdrawal(1, 10))?;
+        let flags = rules.evaluate(&withdrawal(2, 60))?;
+        assert_eq!(
+            flags,
+            vec![Flag {
+                rule: "large".to_string(),
+                tx: 2,
+                client: 1
+            }]
+        );
+        // not recorded, so doesn't count towards the totals
+        assert_eq!(
+            rules.evaluate(&withdrawal(3, 95)),
+            Err(RuleError::Rejected {
+                rule: "max_withdrawn".to_string()
+            })
+        );
+        rules.record(&withdrawal(2, 60))?;
+        assert_eq!(
+            rules.evaluate(&withdrawal(3, 1)),
+            Err(RuleError::Rejected {
+                rule: "max_withdrawals".to_string()
+            })
+        );
+        // other clients have their own totals
+        let other = TransactionCommand {
+            client: 2,
+            ..withdrawal(4, 1)
+        };
+        assert_eq!(rules.evaluate(&other)?, vec![]);
+
+        // a running total which would overflow is an error rather than a panic
+        let large = |tx| TransactionCommand {
+            kind: TransactionKind::Withdrawal {
+                amount: Decimal::max_value(),
+            },
+            client: 3,
+            ..withdrawal(tx, 1)
+        };
+        rules.record(&large(5))?;
+        assert_eq!(rules.evaluate(&large(6)), Err(RuleError::Overflow));
+        assert_eq!(rules.record(&large(6)), Err(RuleError::Overflow));
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_window() -> Result<()> {
+        let config = r#"{"rules": [
+            {"name": "hourly_withdrawn", "type": "withdrawal", "max_total": "5000", "window_secs": 3600, "action": "reject"},
+            {"name": "hourly_withdrawals", "type": "withdrawal", "max_count": 2, "window_secs": 3600, "action": "flag"}
+        ]}"#;
+        let rules = Rules::new(Arc::new(read_rules(config.as_bytes())?));
+        let at = |tx, amount, timestamp: &str| TransactionCommand {
+            timestamp: Some(timestamp.parse().unwrap()),
+            ..withdrawal(tx, amount)
+        };
+        let rejected = Err(RuleError::Rejected {
+            rule: "hourly_withdrawn".to_string(),
+        });
+
+        rules.record(&at(1, 3000, "2024-01-31T09:00:00Z"))?;
+        rules.record(&at(2, 1500, "2024-01-31T09:30:00Z"))?;
+        assert_eq!(
+            rules.evaluate(&at(3, 600, "2024-01-31T09:59:59Z")),
+            rejected
+        );
+        let flags = rules.evaluate(&at(3, 500, "2024-01-31T09:59:59Z"))?;
+        assert_eq!(flags[0].rule, "hourly_withdrawals");
+        // an hour after the first withdrawal, it no longer counts
+        assert_eq!(
+            rules.evaluate(&at(3, 3500, "2024-01-31T10:00:00Z"))?,
+            vec![]
+        );
+        rules.record(&at(3, 3500, "2024-01-31T10:00:00Z"))?;
+        assert_eq!(rules.evaluate(&at(4, 1, "2024-01-31T10:10:00Z")), rejected);
+        // transactions without a timestamp aren't checked against windowed limits
+        assert_eq!(rules.evaluate(&withdrawal(5, 10000))?, vec![]);
+
+        let amount = r#"{"rules": [
+            {"name": "a", "type": "deposit", "max_amount": "1", "window_secs": 60, "action": "flag"}
+        ]}"#;
+        assert!(read_rules(amount.as_bytes()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::accounts::{
+    Account, AccountKind, AccountStatement, AccountsRepo, MemoryRepo as AccountsMemoryRepo,
+};
+use crate::diff::{self, Change, ClientDiff, LockChange};
+use crate::errorlog;
+use crate::events::MemoryRepo as EventsMemoryRepo;
+use crate::fuzz;
+use crate::payments::PaymentsEngine;
+use crate::testkit::TestKit;
+use crate::transactions::{
+    MemoryRepo as TransactionsMemoryRepo, Transaction, TransactionCommand, TransactionKind,
+    TransactionsRepo,
+};
+
+/// INPUT & EXPECTED are the file names of a scenario's transactions & expected statements
+pub const INPUT: &str = "input.csv";
+pub const EXPECTED: &str = "expected.csv";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ScenarioError {
+    #[error("no scenarios found in {0}")]
+    NoScenarios(String),
+    #[error("{failed} of {total} scenarios failed")]
+    Failed { failed: usize, total: usize },
+}
+
+impl ScenarioError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScenarioError::NoScenarios(_) => "no_scenarios",
+            ScenarioError::Failed { .. } => "scenarios_failed",
+        }
+    }
+}
+
+/// Scenario is a directory holding a transactions file & the statements processing it should
+/// produce
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    /// The directory's path relative to the directory the scenarios were discovered in
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+/// discover finds every scenario in a directory & its subdirectories, ordered by name. A
+/// directory is a scenario if it has both an `input.csv` & an `expected.csv`.
+pub fn discover(root: &Path) -> Result<Vec<Scenario>> {
+    let mut scenarios = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        if dir.join(INPUT).is_file() && dir.join(EXPECTED).is_file() {
+            let name = dir.strip_prefix(root).unwrap_or(&dir).to_string_lossy();
+            scenarios.push(Scenario {
+                name: if name.is_empty() {
+                    ".".to_string()
+                } else {
+                    name.to_string()
+                },
+                dir: dir.clone(),
+            });
+        }
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("unable to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            }
+        }
+    }
+    scenarios.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(scenarios)
+}
+
+/// run processes a scenario's input through a fresh in-memory engine and compares the resulting
+/// statements to the expected ones, returning how each client's actual statement differs from its
+/// expected one, ordered by client. Balances are compared by value, so `1.5` matches `1.50`.
+pub fn run(scenario: &Scenario) -> Result<Vec<ClientDiff>> {
+    let input = fs::read(scenario.dir.join(INPUT))?;
+    let expected_path = scenario.dir.join(EXPECTED);
+    let expected = diff::read_statements(File::open(&expected_path)?)
+        .with_context(|| format!("unable to read {}", expected_path.display()))?;
+
+    let transactions = TransactionsMemoryRepo::new();
+    let accounts = AccountsMemoryRepo::new();
+    let events = EventsMemoryRepo::new();
+    let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+    fuzz::process_bytes(&input, &engine);
+    let actual: BTreeMap<u64, AccountStatement> = accounts
+        .get_all()?
+        .into_iter()
+        .map(|account| (account.client(), AccountStatement::from(account)))
+        .collect();
+    Ok(diff::diff(&expected, &actual))
+}
+
+/// ScenarioDiff is a row of the report of failed scenarios: how a client's actual statement
+/// differs from the expected one
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ScenarioDiff {
+    pub scenario: String,
+    pub client: u64,
+    /// `added` for a client which isn't expected, `removed` for one which is missing
+    pub change: Change,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: Option<LockChange>,
+}
+
+impl ScenarioDiff {
+    pub fn new(scenario: &Scenario, diff: ClientDiff) -> ScenarioDiff {
+        ScenarioDiff {
+            scenario: scenario.name.clone(),
+            client: diff.client,
+            change: diff.change,
+            available: diff.available,
+            held: diff.held,
+            total: diff.total,
+            locked: diff.locked,
+        }
+    }
+}
+
+/// Spec is a scenario written out as data, so that a tricky case can be encoded as an executable
+/// spec without writing Rust: the accounts & deposits it starts from, the commands submitted in
+/// order, and the balances they should leave every account with
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Accounts which exist before the first command
+    #[serde(default)]
+    pub accounts: Vec<Balances>,
+    /// Deposits which exist before the first command, e.g. to dispute them. They don't credit
+    /// their accounts, whose balances are given by `accounts`.
+    #[serde(default)]
+    pub deposits: Vec<Deposit>,
+    pub commands: Vec<Step>,
+    /// Every account after the last command
+    pub expected: Vec<Balances>,
+}
+
+/// Balances is an account's state, whose total is its available plus held balance
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Balances {
+    pub client: u64,
+    pub available: Decimal,
+    pub held: Decimal,
+    #[serde(default)]
+    pub locked: bool,
+}
+
+impl From<Account> for Balances {
+    fn from(acc: Account) -> Balances {
+        Balances {
+            client: acc.client(),
+            available: acc.available(),
+            held: acc.held(),
+            locked: acc.is_locked(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Deposit {
+    pub client: u64,
+    pub tx: u64,
+    pub amount: Decimal,
+}
+
+/// Step is a command, written like a row of a transactions file, and the code of the error it
+/// should be rejected with, if it should be rejected
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    #[serde(flatten)]
+    pub command: TransactionCommand,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl Spec {
+    /// read reads a spec written as JSON, which is also valid YAML
+    pub fn read<R: io::Read>(reader: R) -> Result<Spec> {
+        Ok(serde_json::from_reader(io::BufReader::new(reader))?)
+    }
+}
+
+/// Mismatch is a way a spec's outcome differed from what it expected, each written as text (or
+/// empty if there's no such account)
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Mismatch {
+    pub scenario: String,
+    /// What was checked: a command, by its position (from 1), or an account, by client
+    pub check: &'static str,
+    pub id: u64,
+    pub expected: String,
+    pub actual: String,
+}
+
+fn outcome(error: Option<&str>) -> String {
+    error.unwrap_or("ok").to_string()
+}
+
+fn balances(balances: Option<&Balances>) -> String {
+    balances
+        .map(|b| {
+            format!(
+                "available={} held={} total={} locked={}",
+                b.available,
+                b.held,
+                b.available + b.held,
+                b.locked
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// run_spec runs a spec through a fresh in-memory engine, returning every command whose outcome
+/// (accepted, or rejected with an error code) & every account whose balances differ from the
+/// spec's. Balances are compared by value, so `1.5` matches `1.50`.
+pub fn run_spec(name: &str, spec: &Spec) -> Result<Vec<Mismatch>> {
+    let kit = TestKit::new();
+    for account in &spec.accounts {
+        kit.accounts.save(Account::from_balances(
+            account.client,
+            account.available,
+            account.held,
+            account.locked,
+            None,
+            AccountKind::Standard,
+        ))?;
+    }
+    for deposit in &spec.deposits {
+        let amount = deposit.amount;
+        kit.transactions.save(Transaction {
+            tx: deposit.tx,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            client: deposit.client,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+    }
+
+    let commands: Vec<TransactionCommand> = spec.commands.iter().map(|s| s.command).collect();
+    let mut mismatches = Vec::new();
+    for (i, (step, result)) in spec.commands.iter().zip(kit.submit(&commands)).enumerate() {
+        let error = result.err().map(|e| errorlog::code(&e));
+        if error != step.error.as_deref() {
+            mismatches.push(Mismatch {
+                scenario: name.to_string(),
+                check: "command",
+                id: i as u64 + 1,
+                expected: outcome(step.error.as_deref()),
+                actual: outcome(error),
+            });
+        }
+    }
+
+    let expected: BTreeMap<u64, Balances> = spec.expected.iter().map(|b| (b.client, *b)).collect();
+    let actual: BTreeMap<u64, Balances> = kit
+        .accounts
+        .get_all()?
+        .into_iter()
+        .map(|acc| (acc.client(), Balances::from(acc)))
+        .collect();
+    let clients: BTreeSet<u64> = expected.keys().chain(actual.keys()).copied().collect();
+    for client in clients {
+        if expected.get(&client) != actual.get(&client) {
+            mismatches.push(Mismatch {
+                scenario: name.to_string(),
+                check: "account",
+                id: client,
+                expected: balances(expected.get(&client)),
+                actual: balances(actual.get(&client)),
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario(root: &Path, name: &str, input: &str, expected: &str) -> Result<()> {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(INPUT), input)?;
+        fs::write(dir.join(EXPECTED), expected)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scenarios() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("payments-scenarios-{}", std::process::id()));
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\nchargeback,1,1,\ndeposit,2,2,1.5\n";
+        scenario(
+            &root,
+            "disputes/chargeback",
+            input,
+            "client,available,held,total,locked\n2,1.50,0,1.5,false\n1,0,0,0,true\n",
+        )?;
+        scenario(
+            &root,
+            "broken",
+            input,
+            "client,available,held,total,locked\n1,10,0,10,false\n3,1,0,1,false\n",
+        )?;
+        fs::create_dir_all(root.join("empty"))?;
+
+        let scenarios = discover(&root)?;
+        let names: Vec<&str> = scenarios.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["broken", "disputes/chargeback"]);
+
+        assert_eq!(run(&scenarios[1])?, vec![]);
+        let diffs: Vec<(u64, Change, Decimal)> = run(&scenarios[0])?
+            .into_iter()
+            .map(|d| (d.client, d.change, d.total))
+            .collect();
+        assert_eq!(
+            diffs,
+            vec![
+                (1, Change::Changed, Decimal::from(-10)),
+                (2, Change::Added, Decimal::new(15, 1)),
+                (3, Change::Removed, Decimal::from(-1)),
+            ]
+        );
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_spec() -> Result<()> {
+        let spec = r#"{
+            "description": "a chargeback of a deposit which was partly withdrawn",
+            "accounts": [{"client": 1, "available": "10", "held": "0"}],
+            "deposits": [{"client": 1, "tx": 1, "amount": "10"}],
+            "commands": [
+                {"type": "withdrawal", "client": 1, "tx": 2, "amount": "4"},
+                {"type": "dispute", "client": 1, "tx": 1},
+                {"type": "withdrawal", "client": 1, "tx": 3, "amount": 1, "error": "insufficient_funds"},
+                {"type": "chargeback", "client": 1, "tx": 1},
+                {"type": "deposit", "client": 1, "tx": 4, "amount": "1", "error": "account_locked"}
+            ],
+            "expected": [{"client": 1, "available": "-4.00", "held": "0", "locked": true}]
+        }"#;
+        let spec = Spec::read(spec.as_bytes())?;
+        assert_eq!(run_spec("chargeback", &spec)?, vec![]);
+
+        let mut wrong = spec;
+        wrong.commands[2].error = None;
+        wrong.expected[0].locked = false;
+        let mismatches = run_spec("wrong", &wrong)?;
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch {
+                    scenario: "wrong".to_string(),
+                    check: "command",
+                    id: 3,
+                    expected: "ok".to_string(),
+                    actual: "insufficient_funds".to_string(),
+                },
+                Mismatch {
+                    scenario: "wrong".to_string(),
+                    check: "account",
+                    id: 1,
+                    expected: "available=-4.00 held=0 total=-4.00 locked=false".to_string(),
+                    actual: "available=-4 held=0 total=-4 locked=true".to_string(),
+                },
+            ]
+        );
+
+        assert!(Spec::read(r#"{"commands": [], "expected": [], "extra": 1}"#.as_bytes()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+use std::io;
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::transactions::{TransactionCommand, TransactionKind};
+
+/// SCHEDULED_TX is set in the ID of every transaction materialized from a schedule, keeping them
+/// clear of the IDs in ordinary transactions files
+const SCHEDULED_TX: u64 = 1 << 63;
+/// DAY_BITS is the number of low bits of a scheduled transaction's ID which hold its due date
+const DAY_BITS: u32 = 24;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// Schedule is a standing order: a withdrawal from a client's account which recurs from its start
+/// date, paid out to a registered beneficiary if it names one
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Schedule {
+    pub id: u64,
+    pub client: u64,
+    pub amount: Decimal,
+    pub frequency: Frequency,
+    pub start: NaiveDate,
+    pub beneficiary: Option<u64>,
+}
+
+impl Schedule {
+    /// is_due is whether the schedule recurs on `date`. Monthly schedules which start on a day
+    /// that some months don't have recur on the last day of those months instead.
+    pub fn is_due(&self, date: NaiveDate) -> bool {
+        if date < self.start {
+            return false;
+        }
+        match self.frequency {
+            Frequency::Daily => true,
+            Frequency::Weekly => (date - self.start).num_days() % 7 == 0,
+            Frequency::Monthly => date.day() == self.start.day().min(days_in_month(date)),
+        }
+    }
+
+    /// command is the transaction the schedule materializes as on `date`. Its ID is derived from
+    /// the schedule & the date, so materializing the same day twice produces duplicate
+    /// transactions, which are rejected, rather than paying out twice.
+    pub fn command(&self, date: NaiveDate) -> TransactionCommand {
+        let kind = match self.beneficiary {
+            Some(beneficiary) => TransactionKind::Payout {
+                amount: self.amount,
+                beneficiary,
+            },
+            None => TransactionKind::Withdrawal {
+                amount: self.amount,
+            },
+        };
+        let day = date.num_days_from_ce() as u64;
+        TransactionCommand {
+            kind,
+            tx: SCHEDULED_TX | self.id << DAY_BITS | day,
+            client: self.client,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        }
+    }
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let (year, month) = match date.month() {
+        12 => (date.year() + 1, 1),
+        month => (date.year(), month + 1),
+    };
+    NaiveDate::from_ymd(year, month, 1).pred().day()
+}
+
+/// read_schedules reads a CSV of standing orders, with `id`, `client`, `amount`, `frequency`
+/// (daily, weekly or monthly), `start` (as YYYY-MM-DD) & optionally `beneficiary` columns
+pub fn read_schedules<R: io::Read>(reader: R) -> Result<Vec<Schedule>> {
+    let mut ids = HashSet::new();
+    let mut schedules = Vec::new();
+    for result in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+    {
+        let schedule: Schedule = result?;
+        if !ids.insert(schedule.id) {
+            bail!("schedule {} is listed more than once", schedule.id);
+        }
+        if schedule.id >= 1 << (63 - DAY_BITS) {
+            bail!("schedule ID {} is too large", schedule.id);
+        }
+        if schedule.amount <= Decimal::from(0) {
+            bail!("schedule {} has a non-positive amount", schedule.id);
+        }
+        schedules.push(schedule);
+    }
+    Ok(schedules)
+}
+
+/// due materializes the transactions of every schedule which is due on `date`
+pub fn due(schedules: &[Schedule], date: NaiveDate) -> Vec<TransactionCommand> {
+    schedules
+        .iter()
+        .filter(|schedule| schedule.is_due(date))
+        .map(|schedule| schedule.command(date))
+        .collect()
+}
+
+/// ScheduledRecord is the CSV representation of a materialized transaction, matching the input
+/// format
+#[derive(Debug, Serialize)]
+pub struct ScheduledRecord {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub client: u64,
+    pub tx: u64,
+    pub amount: Decimal,
+    pub beneficiary: Option<u64>,
+}
+
+impl ScheduledRecord {
+    /// HEADERS is the header row, for output which has no records to write it
+    pub const HEADERS: [&'static str; 5] = ["type", "client", "tx", "amount", "beneficiary"];
+}
+
+impl From<TransactionCommand> for ScheduledRecord {
+    fn from(command: TransactionCommand) -> ScheduledRecord {
+        let (amount, beneficiary) = match command.kind {
+            TransactionKind::Payout {
+                amount,
+                beneficiary,
+            } => (amount, Some(beneficiary)),
+            TransactionKind::Withdrawal { amount } => (amount, None),
+            _ => (Decimal::from(0), None),
+        };
+        ScheduledRecord {
+            kind: command.kind.to_string(),
+            client: command.client,
+            tx: command.tx,
+            amount,
+            beneficiary,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_schedules() -> Result<()> {
+        let schedules = read_schedules(
+            "id,client,amount,frequency,start,beneficiary
+1,1,10,daily,2024-01-01,
+2,2,25.5,weekly,2024-01-03,
+3,3,100,monthly,2024-01-31,7
+"
+            .as_bytes(),
+        )?;
+        let due_clients = |d| -> Vec<u64> {
+            due(&schedules, date(d))
+                .iter()
+                .map(|command| command.client)
+                .collect()
+        };
+        assert_eq!(due_clients("2023-12-31"), Vec::<u64>::new());
+        assert_eq!(due_clients("2024-01-03"), vec![1, 2]);
+        assert_eq!(due_clients("2024-01-10"), vec![1, 2]);
+        assert_eq!(due_clients("2024-01-11"), vec![1]);
+        assert_eq!(due_clients("2024-01-31"), vec![1, 2, 3]);
+        // a monthly schedule starting on the 31st recurs at the end of shorter months
+        assert_eq!(due_clients("2024-02-29"), vec![1, 3]);
+        assert_eq!(due_clients("2024-04-30"), vec![1, 3]);
+        assert_eq!(due_clients("2024-05-30"), vec![1]);
+
+        let payout = schedules[2].command(date("2024-02-29"));
+        assert_eq!(
+            payout.kind,
+            TransactionKind::Payout {
+                amount: Decimal::from(100),
+                beneficiary: 7
+            }
+        );
+        // the same schedule materializes with the same ID on the same day, and a new one on others
+        assert_eq!(payout.tx, schedules[2].command(date("2024-02-29")).tx);
+        assert_ne!(payout.tx, schedules[2].command(date("2024-03-31")).tx);
+        assert_ne!(
+            schedules[0].command(date("2024-01-03")).tx,
+            schedules[1].command(date("2024-01-03")).tx
+        );
+
+        assert!(read_schedules(
+            "id,client,amount,frequency,start\n1,1,10,daily,2024-01-01\n1,2,10,daily,2024-01-01\n"
+                .as_bytes()
+        )
+        .is_err());
+        assert!(read_schedules(
+            "id,client,amount,frequency,start\n1,1,10,yearly,2024-01-01\n".as_bytes()
+        )
+        .is_err());
+        assert!(read_schedules(
+            "id,client,amount,frequency,start\n1,1,-1,daily,2024-01-01\n".as_bytes()
+        )
+        .is_err());
+        Ok(())
+    }
+}
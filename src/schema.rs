@@ -0,0 +1,180 @@
+use serde_json::{json, Value};
+
+use crate::{correlation, tenant};
+
+/// DIALECT is the JSON Schema version the schemas are written against
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// amount is the schema of a non-negative decimal amount, which is accepted as a string (as read from
+/// CSV) or a JSON number
+fn amount() -> Value {
+    json!({
+        "type": ["string", "number"],
+        "pattern": "^[0-9]+(\\.[0-9]+)?$",
+        "minimum": 0
+    })
+}
+
+/// INPUT_TYPES are the transaction types accepted as input, and the fields each requires besides
+/// `type`, `client` & `tx`
+const INPUT_TYPES: [(&str, &[&str]); 10] = [
+    ("deposit", &["amount"]),
+    ("withdrawal", &["amount"]),
+    ("payout", &["amount", "beneficiary"]),
+    ("dispute", &[]),
+    ("resolve", &[]),
+    ("chargeback", &[]),
+    ("release", &[]),
+    ("refund", &[]),
+    ("settle", &[]),
+    ("fail", &[]),
+];
+
+/// transaction_command is the JSON Schema of a row of a transactions file, i.e. a
+/// `TransactionCommand` along with the optional columns read alongside it. Types are the canonical
+/// names, which are also what strict parsing accepts. Admin actions (adjustments, locks, etc.)
+/// aren't accepted as input, so they aren't included.
+pub fn transaction_command() -> Value {
+    let variants: Vec<Value> = INPUT_TYPES
+        .iter()
+        .map(|(kind, required)| {
+            let mut all = vec!["type", "client", "tx"];
+            all.extend_from_slice(required);
+            let mut variant = json!({
+                "properties": {"type": {"const": kind}},
+                "required": all,
+            });
+            if !matches!(*kind, "deposit" | "withdrawal") {
+                // only deposits & withdrawals can be pending
+                variant["properties"]["status"] = json!({"enum": ["", "settled"]});
+            }
+            variant
+        })
+        .collect();
+    json!({
+        "$schema": DIALECT,
+        "title": "TransactionCommand",
+        "description": "A row of a transactions file",
+        "type": "object",
+        "properties": {
+            "type": {"enum": INPUT_TYPES.iter().map(|(kind, _)| *kind).collect::<Vec<_>>()},
+            "client": {"type": "integer", "minimum": 0, "maximum": u64::MAX},
+            "tx": {"type": "integer", "minimum": 0, "maximum": u64::MAX},
+            "amount": amount(),
+            "beneficiary": {
+                "description": "The registered beneficiary of a payout",
+                "type": "integer",
+                "minimum": 0,
+                "maximum": u64::MAX
+            },
+            "status": {
+                "description": "Whether a deposit or withdrawal is held until it's settled or failed",
+                "enum": ["", "settled", "pending"]
+            },
+            "correlation_id": {
+                "description": "Traces the payment across systems",
+                "type": "string",
+                "minLength": 1,
+                "maxLength": correlation::MAX_LEN
+            },
+            "tenant": {
+                "type": "string",
+                "pattern": "^[A-Za-z0-9_-]+$",
+                "maxLength": tenant::MAX_LEN
+            },
+            "timestamp": {"type": "string", "format": "date-time"},
+            "signature": {"description": "The row's HMAC", "type": "string"}
+        },
+        "oneOf": variants,
+    })
+}
+
+/// account_statement is the JSON Schema of an `AccountStatement`, i.e. a row of the statements
+/// output. Balances are output as decimal strings.
+pub fn account_statement() -> Value {
+    let balance = json!({"type": "string", "pattern": "^-?[0-9]+(\\.[0-9]+)?$"});
+    json!({
+        "$schema": DIALECT,
+        "title": "AccountStatement",
+        "description": "An account's balances at the end of a run",
+        "type": "object",
+        "properties": {
+            "tenant": {
+                "description": "Only output when processing for a tenant",
+                "type": "string"
+            },
+            "client": {"type": "integer", "minimum": 0, "maximum": u64::MAX},
+            "available": balance,
+            "held": balance,
+            "total": balance,
+            "locked": {"type": "boolean"}
+        },
+        "required": ["client", "available", "held", "total", "locked"],
+        "additionalProperties": false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::{Account, AccountStatement};
+    use crate::transactions::{Transaction, TransactionCommand, TransactionKind};
+    use anyhow::Result;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_transaction_command() -> Result<()> {
+        let schema = transaction_command();
+        for variant in schema["oneOf"].as_array().unwrap() {
+            let kind = variant["properties"]["type"]["const"].as_str().unwrap();
+            assert_eq!(TransactionKind::canonical_name(kind), Some(kind));
+
+            // a row with only the required fields deserializes, and one without them doesn't
+            let mut row = json!({"type": kind, "client": 1, "tx": 2});
+            for field in variant["required"].as_array().unwrap() {
+                match field.as_str().unwrap() {
+                    "amount" => row["amount"] = json!("1.5"),
+                    "beneficiary" => row["beneficiary"] = json!(3),
+                    _ => {}
+                }
+            }
+            let command: TransactionCommand = serde_json::from_value(row.clone())?;
+            assert_eq!((command.client, command.tx), (1, 2));
+            assert_eq!(command.kind.to_string(), kind);
+            row.as_object_mut().unwrap().remove("tx");
+            assert!(serde_json::from_value::<TransactionCommand>(row).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_statement() -> Result<()> {
+        let amount = Decimal::new(15, 1);
+        let account = Account::new(Transaction {
+            tx: 1,
+            client: 1,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        let statement = serde_json::to_value(AccountStatement::from(account))?;
+        let schema = account_statement();
+        let properties = schema["properties"].as_object().unwrap();
+        for (field, value) in statement.as_object().unwrap() {
+            let property = &properties[field];
+            assert!(!property.is_null(), "{} isn't in the schema", field);
+            match property["type"].as_str().unwrap() {
+                "string" => assert!(value.is_string(), "{}", field),
+                "integer" => assert!(value.is_u64(), "{}", field),
+                "boolean" => assert!(value.is_boolean(), "{}", field),
+                t => panic!("unexpected type {}", t),
+            }
+        }
+        for field in schema["required"].as_array().unwrap() {
+            assert!(!statement[field.as_str().unwrap()].is_null());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,950 @@
+use std::collections::HashSet;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::Decimal;
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+use tracing::{debug, error, info};
+
+use crate::accounts::{Account, AccountError};
+use crate::auth::{self, ApiKeys, Authenticator, Scope};
+use crate::correlation::{CorrelationError, CorrelationId};
+use crate::payments::{AdminAction, AdminError, HistoryEntry};
+use crate::ratelimit::{RateLimit, RateLimitError, RateLimiter};
+use crate::redact;
+use crate::repos::Repos;
+use crate::retention::Retention;
+use crate::transactions::{TransactionCommand, TransactionError, TransactionKind};
+
+pub mod proto {
+    tonic::include_proto!("payments.v1");
+}
+
+use proto::admin_server::{Admin, AdminServer};
+use proto::payments_server::{Payments, PaymentsServer};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RequestError {
+    #[error("invalid amount {0:?}")]
+    InvalidAmount(String),
+    #[error("unknown transaction type {0:?}")]
+    UnknownType(String),
+    #[error(transparent)]
+    InvalidCorrelation(#[from] CorrelationError),
+    #[error("a reason is required")]
+    MissingReason,
+}
+
+impl From<RequestError> for Status {
+    fn from(error: RequestError) -> Status {
+        Status::invalid_argument(error.to_string())
+    }
+}
+
+/// Command is a request for the engine thread, along with where to send the reply
+enum Command {
+    Submit(TransactionCommand, oneshot::Sender<Result<Account>>),
+    GetAccount(u64, oneshot::Sender<Result<Option<Account>>>),
+    ListTransactions(u64, oneshot::Sender<Result<Vec<HistoryEntry>>>),
+    Admin(AdminAction, oneshot::Sender<Result<Account>>),
+}
+
+/// ServerConfig is how the gRPC server is exposed to clients
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    /// API keys required on every request, or `None` to allow anonymous access
+    pub keys: Option<ApiKeys>,
+    /// Serve over TLS rather than plaintext
+    pub tls: Option<TlsConfig>,
+    /// Limit the rate of transactions submitted for each client
+    pub rate_limit: Option<RateLimit>,
+    /// How often to checkpoint the repositories while running, as well as on shutdown
+    pub checkpoint_interval: Option<Duration>,
+    /// Evict transactions from the transactions repo once they've been settled for this long
+    pub retention: Option<Duration>,
+}
+
+/// TlsConfig is the paths of the PEM encoded certificate & private key the server presents, and
+/// optionally a CA certificate which clients must present a certificate signed by (mutual TLS)
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// load reads the certificates & key from disk
+    fn load(&self) -> Result<ServerTlsConfig> {
+        let read = |path: &Path| {
+            fs::read(path).with_context(|| format!("unable to read {}", path.display()))
+        };
+        let identity = Identity::from_pem(read(&self.cert)?, read(&self.key)?);
+        let mut config = ServerTlsConfig::new().identity(identity);
+        if let Some(ca) = &self.client_ca {
+            config = config.client_ca_root(Certificate::from_pem(read(ca)?));
+        }
+        Ok(config)
+    }
+}
+
+/// serve runs the gRPC service until the process receives SIGINT or SIGTERM. The repositories
+/// aren't thread safe, so they're opened & owned by a single engine thread which processes
+/// requests in the order they're received.
+///
+/// The repositories are checkpointed on shutdown, and every checkpoint interval if configured,
+/// by flushing them and then calling `checkpoint` with them (e.g. to save a snapshot). On
+/// shutdown, the server stops accepting connections and waits for in-flight requests to complete,
+/// then the engine thread drains any queued commands before the final checkpoint.
+///
+/// If API keys are configured, every request must carry one of them as a bearer token, with a
+/// scope which allows the call.
+pub fn serve<F, C>(config: ServerConfig, open: F, checkpoint: C) -> Result<()>
+where
+    F: FnOnce() -> Result<Repos> + Send + 'static,
+    C: FnMut(&Repos) -> Result<()> + Send + 'static,
+{
+    let ServerConfig {
+        addr,
+        keys,
+        tls,
+        rate_limit,
+        checkpoint_interval,
+        retention,
+    } = config;
+    let mut builder = tonic::transport::Server::builder();
+    if let Some(tls) = &tls {
+        builder = builder
+            .tls_config(tls.load()?)
+            .context("invalid TLS configuration")?;
+    }
+    let authenticator = Authenticator::new(keys);
+    let (sender, engine) = spawn_engine(open, checkpoint, checkpoint_interval, retention)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let res = runtime.block_on(async {
+        info!(%addr, tls = tls.is_some(), "Listening");
+        builder
+            .add_service(PaymentsServer::with_interceptor(
+                PaymentsService::new(sender.clone(), rate_limit),
+                authenticator.clone(),
+            ))
+            .add_service(AdminServer::with_interceptor(
+                AdminService::new(sender),
+                authenticator,
+            ))
+            .serve_with_shutdown(addr, shutdown_signal())
+            .await
+    });
+    // the service, and with it the last sender, has been dropped, so the engine thread finishes
+    // once every queued command has been handled
+    let closed = engine
+        .join()
+        .map_err(|_| anyhow!("engine thread panicked"))?;
+    res?;
+    closed?;
+    info!("Shut down");
+    Ok(())
+}
+
+/// shutdown_signal completes once the process receives SIGINT (Ctrl-C) or SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!(error = %e, "Unable to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!(error = %e, "Unable to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => (),
+        _ = terminate => (),
+    }
+    info!("Shutting down, waiting for in-flight requests to complete");
+}
+
+type Engine = (mpsc::Sender<Command>, thread::JoinHandle<Result<()>>);
+
+/// spawn_engine starts the engine thread, waiting until the repositories have been opened. The
+/// thread runs until every sender has been dropped, then checkpoints the repositories a final
+/// time.
+fn spawn_engine<F, C>(
+    open: F,
+    mut checkpoint: C,
+    interval: Option<Duration>,
+    retention: Option<Duration>,
+) -> Result<Engine>
+where
+    F: FnOnce() -> Result<Repos> + Send + 'static,
+    C: FnMut(&Repos) -> Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    let (ready, opened) = mpsc::sync_channel(1);
+    let handle = thread::spawn(move || -> Result<()> {
+        let opened = open().and_then(|repos| {
+            let retention = match retention {
+                Some(ttl) => Some(retain(&repos, ttl)?),
+                None => None,
+            };
+            Ok((repos, retention))
+        });
+        let (repos, mut retention) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                let _ = ready.send(Err(e));
+                return Ok(());
+            }
+        };
+        let _ = ready.send(Ok(()));
+        let mut checkpoint = |repos: &Repos| -> Result<()> {
+            repos.flush()?;
+            checkpoint(repos)
+        };
+        run_engine(
+            &repos,
+            receiver,
+            &mut checkpoint,
+            interval,
+            retention.as_mut(),
+        );
+        checkpoint(&repos)
+    });
+    opened
+        .recv()
+        .map_err(|_| anyhow!("engine thread panicked"))??;
+    Ok((sender, handle))
+}
+
+/// retain starts tracking the retention of the transactions already in the repo, as if they'd
+/// just been saved, since when they were saved isn't stored. Transactions in the event log but
+/// not the repo were evicted by an earlier run.
+fn retain(repos: &Repos, ttl: Duration) -> Result<Retention> {
+    let mut retention = Retention::new(ttl);
+    let now = Instant::now();
+    let mut saved = HashSet::new();
+    for transaction in repos.transactions.get_all()? {
+        retention.record(transaction.tx, now);
+        saved.insert(transaction.tx);
+    }
+    for event in repos.events.get_all()? {
+        if !saved.contains(&event.tx) {
+            retention.evicted(event.tx);
+        }
+    }
+    Ok(retention)
+}
+
+/// run_engine handles every request until all of the senders have been dropped, checkpointing
+/// the repositories between requests every `interval` and evicting transactions past their
+/// retention after each request. A failed checkpoint or eviction is logged rather than stopping
+/// the engine, since the next one may well succeed.
+fn run_engine(
+    repos: &Repos,
+    receiver: mpsc::Receiver<Command>,
+    checkpoint: &mut dyn FnMut(&Repos) -> Result<()>,
+    interval: Option<Duration>,
+    mut retention: Option<&mut Retention>,
+) {
+    let engine = repos.engine();
+    let mut next_checkpoint = interval.map(|interval| Instant::now() + interval);
+    loop {
+        let command = match next_checkpoint {
+            Some(deadline) => {
+                match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(command) => Some(command),
+                    Err(mpsc::RecvTimeoutError::Timeout) => None,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            None => match receiver.recv() {
+                Ok(command) => Some(command),
+                Err(mpsc::RecvError) => return,
+            },
+        };
+        if let (Some(deadline), Some(interval)) = (next_checkpoint, interval) {
+            // checked after every command too, so that a steady stream of requests doesn't
+            // postpone the checkpoint indefinitely
+            if Instant::now() >= deadline {
+                let start = Instant::now();
+                match checkpoint(repos) {
+                    Ok(()) => debug!(elapsed = ?start.elapsed(), "Checkpointed"),
+                    Err(e) => error!(error = %redact::error(&e), "Unable to checkpoint"),
+                }
+                next_checkpoint = Some(Instant::now() + interval);
+            }
+        }
+        let command = match command {
+            Some(command) => command,
+            None => continue,
+        };
+        // a closed reply channel means the client has gone away, which isn't an error here
+        match command {
+            Command::Submit(command, reply) => {
+                let res = match retention.as_deref() {
+                    Some(retention) => retention.check(&command).map_err(Into::into),
+                    None => Ok(()),
+                };
+                let res = res
+                    .and_then(|_| engine.process_transaction(command))
+                    .and_then(|_| {
+                        repos
+                            .accounts
+                            .get(command.client)?
+                            .ok_or_else(|| anyhow!("account not found after processing"))
+                    });
+                if let (Ok(_), Some(retention)) = (&res, retention.as_deref_mut()) {
+                    retention.record(command.tx, Instant::now());
+                }
+                let _ = reply.send(res);
+            }
+            Command::GetAccount(client, reply) => {
+                let _ = reply.send(repos.accounts.get(client));
+            }
+            Command::ListTransactions(client, reply) => {
+                let _ = reply.send(engine.history(client));
+            }
+            Command::Admin(action, reply) => {
+                let res = engine.process_admin(action);
+                if let (Ok(_), Some(retention)) = (&res, retention.as_deref_mut()) {
+                    retention.record(action.tx(), Instant::now());
+                }
+                let _ = reply.send(res);
+            }
+        }
+        if let Some(retention) = retention.as_deref_mut() {
+            match retention.evict(repos.transactions.as_ref(), Instant::now()) {
+                Ok(0) => (),
+                Ok(evicted) => debug!(evicted, "Evicted settled transactions"),
+                Err(e) => error!(error = %redact::error(&e), "Unable to evict transactions"),
+            }
+        }
+    }
+}
+
+impl From<RateLimitError> for Status {
+    fn from(error: RateLimitError) -> Status {
+        Status::resource_exhausted(error.to_string())
+    }
+}
+
+/// EngineHandle sends commands to the engine thread, awaiting the replies
+#[derive(Clone)]
+struct EngineHandle(mpsc::Sender<Command>);
+
+impl EngineHandle {
+    async fn call<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<T>>) -> Command,
+    ) -> Result<T, Status> {
+        let (reply, response) = oneshot::channel();
+        self.0
+            .send(command(reply))
+            .map_err(|_| Status::unavailable("engine has stopped"))?;
+        response
+            .await
+            .map_err(|_| Status::unavailable("engine has stopped"))?
+            .map_err(status)
+    }
+}
+
+/// PaymentsService implements the gRPC service by forwarding requests to the engine thread
+pub struct PaymentsService {
+    engine: EngineHandle,
+    limiter: Option<RateLimiter>,
+}
+
+impl PaymentsService {
+    fn new(engine: mpsc::Sender<Command>, rate_limit: Option<RateLimit>) -> PaymentsService {
+        PaymentsService {
+            engine: EngineHandle(engine),
+            limiter: rate_limit.map(RateLimiter::new),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Payments for PaymentsService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::SubmitTransactionRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let caller = auth::authorize(&request, Scope::Submit)?;
+        let command = command(request.into_inner())?;
+        if let Some(limiter) = &self.limiter {
+            limiter.check(command.client).inspect_err(|e| {
+                debug!(
+                    tx = command.tx,
+                    client = %redact::client(command.client),
+                    caller = &*caller.name,
+                    error = %redact::message(e.to_string()),
+                    "Rate limited transaction"
+                )
+            })?;
+        }
+        let account = self
+            .engine
+            .call(|reply| Command::Submit(command, reply))
+            .await
+            .inspect_err(|e| {
+                debug!(
+                    tx = command.tx,
+                    client = %redact::client(command.client),
+                    caller = &*caller.name,
+                    error = %redact::message(e.message()),
+                    "Rejected transaction"
+                )
+            })?;
+        Ok(Response::new(account.into()))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        auth::authorize(&request, Scope::Read)?;
+        let client = request.into_inner().client;
+        match self
+            .engine
+            .call(|reply| Command::GetAccount(client, reply))
+            .await?
+        {
+            Some(account) => Ok(Response::new(account.into())),
+            None => Err(Status::not_found(format!("client {} not found", client))),
+        }
+    }
+
+    async fn list_transactions(
+        &self,
+        request: Request<proto::ListTransactionsRequest>,
+    ) -> Result<Response<proto::ListTransactionsResponse>, Status> {
+        auth::authorize(&request, Scope::Read)?;
+        let client = request.into_inner().client;
+        let history = self
+            .engine
+            .call(|reply| Command::ListTransactions(client, reply))
+            .await?;
+        Ok(Response::new(proto::ListTransactionsResponse {
+            transactions: history.into_iter().map(proto::Transaction::from).collect(),
+        }))
+    }
+}
+
+/// AdminService implements the admin gRPC service, which is only available to admin API keys
+pub struct AdminService {
+    engine: EngineHandle,
+}
+
+impl AdminService {
+    fn new(engine: mpsc::Sender<Command>) -> AdminService {
+        AdminService {
+            engine: EngineHandle(engine),
+        }
+    }
+
+    /// admin performs an action on behalf of the caller, logging who did it and why
+    async fn admin<T>(
+        &self,
+        request: &Request<T>,
+        action: AdminAction,
+        reason: &str,
+    ) -> Result<Response<proto::Account>, Status> {
+        let caller = auth::authorize(request, Scope::Admin)?;
+        let reason = reason.trim();
+        if reason.is_empty() {
+            return Err(RequestError::MissingReason.into());
+        }
+        let account = self
+            .engine
+            .call(|reply| Command::Admin(action, reply))
+            .await?;
+        info!(
+            caller = &*caller.name,
+            action = %action.kind(),
+            client = %redact::client(action.client()),
+            tx = action.tx(),
+            reason,
+            "Admin action"
+        );
+        Ok(Response::new(account.into()))
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn lock_account(
+        &self,
+        request: Request<proto::LockAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let message = request.get_ref();
+        let action = AdminAction::Lock {
+            client: message.client,
+            tx: message.tx,
+        };
+        self.admin(&request, action, &message.reason).await
+    }
+
+    async fn unlock_account(
+        &self,
+        request: Request<proto::LockAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let message = request.get_ref();
+        let action = AdminAction::Unlock {
+            client: message.client,
+            tx: message.tx,
+        };
+        self.admin(&request, action, &message.reason).await
+    }
+
+    async fn post_adjustment(
+        &self,
+        request: Request<proto::PostAdjustmentRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let message = request.get_ref();
+        let action = AdminAction::Adjust {
+            client: message.client,
+            tx: message.tx,
+            amount: amount(&message.amount)?,
+        };
+        self.admin(&request, action, &message.reason).await
+    }
+
+    async fn force_resolve(
+        &self,
+        request: Request<proto::ForceResolveRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let message = request.get_ref();
+        let action = AdminAction::ForceResolve {
+            client: message.client,
+            tx: message.tx,
+        };
+        self.admin(&request, action, &message.reason).await
+    }
+}
+
+/// status maps an error from the engine to a gRPC status, distinguishing transactions which were
+/// rejected from failures of the service itself
+fn status(error: anyhow::Error) -> Status {
+    if error.is::<AdminError>() {
+        return Status::not_found(error.to_string());
+    }
+    if error.is::<AccountError>() || error.is::<TransactionError>() {
+        return Status::failed_precondition(error.to_string());
+    }
+    Status::internal(format!("{:#}", error))
+}
+
+fn amount(amount: &str) -> Result<Decimal, RequestError> {
+    Decimal::from_str(amount.trim()).map_err(|_| RequestError::InvalidAmount(amount.to_string()))
+}
+
+/// command validates a submitted transaction, accepting the same types as the CSV input
+fn command(request: proto::SubmitTransactionRequest) -> Result<TransactionCommand, RequestError> {
+    let amount = || amount(&request.amount);
+    let kind = match TransactionKind::canonical_name(&request.r#type) {
+        Some("deposit") => TransactionKind::Deposit { amount: amount()? },
+        Some("withdrawal") => TransactionKind::Withdrawal { amount: amount()? },
+        Some("dispute") => TransactionKind::Dispute,
+        Some("resolve") => TransactionKind::Resolve,
+        Some("chargeback") => TransactionKind::ChargeBack,
+        Some("release") => TransactionKind::Release,
+        Some("refund") => TransactionKind::Refund,
+        Some("settle") => TransactionKind::Settle { withdrawal: false },
+        Some("fail") => TransactionKind::Fail { withdrawal: false },
+        _ => return Err(RequestError::UnknownType(request.r#type)),
+    };
+    let correlation = match request.correlation_id.as_str() {
+        "" => None,
+        id => Some(CorrelationId::from_str(id)?),
+    };
+    Ok(TransactionCommand {
+        kind,
+        tx: request.tx,
+        client: request.client,
+        correlation,
+        tenant: None,
+        timestamp: None,
+    })
+}
+
+impl From<Account> for proto::Account {
+    fn from(acc: Account) -> proto::Account {
+        proto::Account {
+            client: acc.client(),
+            available: acc.available().to_string(),
+            held: acc.held().to_string(),
+            total: acc.total().to_string(),
+            locked: acc.is_locked(),
+        }
+    }
+}
+
+impl From<HistoryEntry> for proto::Transaction {
+    fn from(entry: HistoryEntry) -> proto::Transaction {
+        proto::Transaction {
+            tx: entry.transaction.tx,
+            r#type: entry.origin.to_string(),
+            state: entry.transaction.kind.to_string(),
+            amount: entry.transaction.amount.to_string(),
+            correlation_id: entry
+                .transaction
+                .correlation
+                .map(|c| c.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Caller;
+    use crate::memory::MemoryConfig;
+    use tonic::Code;
+
+    /// request builds a request as it would be after authentication
+    fn request<T>(message: T, caller: Caller) -> Request<T> {
+        let mut request = Request::new(message);
+        request.extensions_mut().insert(caller);
+        request
+    }
+
+    fn submit(kind: &str, tx: u64, amount: &str) -> Request<proto::SubmitTransactionRequest> {
+        request(
+            proto::SubmitTransactionRequest {
+                r#type: kind.to_string(),
+                client: 1,
+                tx,
+                amount: amount.to_string(),
+                correlation_id: format!("payment-{}", tx),
+            },
+            Caller::anonymous(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_service() -> Result<()> {
+        let (sender, engine) = spawn_engine(
+            || Repos::memory(MemoryConfig::default(), None),
+            |_| Ok(()),
+            None,
+            None,
+        )?;
+        let service = PaymentsService::new(sender, None);
+
+        let account = service
+            .submit_transaction(submit("deposit", 1, "10"))
+            .await?;
+        assert_eq!(account.get_ref().available, "10");
+        let account = service
+            .submit_transaction(submit("Withdraw", 2, "2.5"))
+            .await?;
+        assert_eq!(account.get_ref().total, "7.5");
+        service.submit_transaction(submit("dispute", 1, "")).await?;
+
+        let err = service
+            .submit_transaction(submit("withdrawal", 3, "100"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::FailedPrecondition);
+        assert_eq!(err.message(), "insufficient funds");
+        let err = service
+            .submit_transaction(submit("transfer", 4, "1"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        let account = service
+            .get_account(request(
+                proto::GetAccountRequest { client: 1 },
+                Caller::anonymous(),
+            ))
+            .await?;
+        assert_eq!(account.get_ref().held, "10");
+        let err = service
+            .get_account(request(
+                proto::GetAccountRequest { client: 2 },
+                Caller::anonymous(),
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        let transactions = service
+            .list_transactions(request(
+                proto::ListTransactionsRequest { client: 1 },
+                Caller::anonymous(),
+            ))
+            .await?
+            .into_inner()
+            .transactions;
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].r#type, "deposit");
+        assert_eq!(transactions[0].state, "dispute");
+        assert_eq!(transactions[0].correlation_id, "payment-1");
+
+        let reporting = Caller {
+            name: "reporting".into(),
+            scope: Scope::Read,
+        };
+        let err = service
+            .submit_transaction(request(submit("deposit", 5, "1").into_inner(), reporting))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied);
+
+        drop(service);
+        engine
+            .join()
+            .map_err(|_| anyhow!("engine thread panicked"))??;
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_tls() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("payments-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let tls = |cert: &str| TlsConfig {
+            cert: dir.join(cert),
+            key: dir.join("key.pem"),
+            client_ca: None,
+        };
+        assert!(tls("missing.pem").load().is_err());
+
+        fs::write(dir.join("cert.pem"), "not a certificate")?;
+        fs::write(dir.join("key.pem"), "not a key")?;
+        let config = ServerConfig {
+            addr: "127.0.0.1:0".parse()?,
+            keys: None,
+            tls: Some(tls("cert.pem")),
+            rate_limit: None,
+            checkpoint_interval: None,
+            retention: None,
+        };
+        let res = serve(
+            config,
+            || panic!("repos opened before TLS was configured"),
+            |_| Ok(()),
+        );
+        fs::remove_dir_all(&dir)?;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_admin_service() -> Result<()> {
+        let (sender, engine) = spawn_engine(
+            || Repos::memory(MemoryConfig::default(), None),
+            |_| Ok(()),
+            None,
+            None,
+        )?;
+        let payments = PaymentsService::new(sender.clone(), None);
+        let admin = AdminService::new(sender);
+        payments
+            .submit_transaction(submit("deposit", 1, "10"))
+            .await?;
+
+        let lock = |tx, reason: &str, caller| {
+            request(
+                proto::LockAccountRequest {
+                    client: 1,
+                    tx,
+                    reason: reason.to_string(),
+                },
+                caller,
+            )
+        };
+        let account = admin
+            .lock_account(lock(2, "suspected fraud", Caller::anonymous()))
+            .await?;
+        assert!(account.get_ref().locked);
+        let err = admin
+            .unlock_account(lock(3, " ", Caller::anonymous()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        let ledger = Caller {
+            name: "ledger".into(),
+            scope: Scope::Submit,
+        };
+        let err = admin
+            .unlock_account(lock(3, "investigated", ledger))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied);
+
+        let account = admin
+            .post_adjustment(request(
+                proto::PostAdjustmentRequest {
+                    client: 1,
+                    tx: 3,
+                    amount: "-2.5".to_string(),
+                    reason: "fee refund reversal".to_string(),
+                },
+                Caller::anonymous(),
+            ))
+            .await?;
+        assert_eq!(account.get_ref().available, "7.5");
+        let err = admin
+            .force_resolve(request(
+                proto::ForceResolveRequest {
+                    client: 1,
+                    tx: 9,
+                    reason: "stuck dispute".to_string(),
+                },
+                Caller::anonymous(),
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        drop(payments);
+        drop(admin);
+        engine
+            .join()
+            .map_err(|_| anyhow!("engine thread panicked"))??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() -> Result<()> {
+        let (sender, engine) = spawn_engine(
+            || Repos::memory(MemoryConfig::default(), None),
+            |_| Ok(()),
+            None,
+            None,
+        )?;
+        let limit = RateLimit {
+            per_second: 0.001,
+            burst: 2,
+        };
+        let service = PaymentsService::new(sender, Some(limit));
+        service
+            .submit_transaction(submit("deposit", 1, "1"))
+            .await?;
+        service
+            .submit_transaction(submit("deposit", 2, "1"))
+            .await?;
+        let err = service
+            .submit_transaction(submit("deposit", 3, "1"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::ResourceExhausted);
+
+        drop(service);
+        engine
+            .join()
+            .map_err(|_| anyhow!("engine thread panicked"))??;
+        Ok(())
+    }
+
+    #[test]
+    fn test_periodic_checkpoint() -> Result<()> {
+        let (checkpointed, checkpoints) = mpsc::channel();
+        let (sender, engine) = spawn_engine(
+            || Repos::memory(MemoryConfig::default(), None),
+            move |repos| {
+                checkpointed.send(repos.accounts.get_all()?.len())?;
+                Ok(())
+            },
+            Some(Duration::from_millis(10)),
+            None,
+        )?;
+        let (reply, _) = oneshot::channel();
+        let command = command(submit("deposit", 1, "1").into_inner())?;
+        sender.send(Command::Submit(command, reply))?;
+        // checkpoints while idle, without waiting for the engine to stop
+        assert_eq!(checkpoints.recv_timeout(Duration::from_secs(5))?, 1);
+
+        drop(sender);
+        engine
+            .join()
+            .map_err(|_| anyhow!("engine thread panicked"))??;
+        // along with a final checkpoint on shutdown
+        assert_eq!(checkpoints.iter().last(), Some(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evicted_replay() -> Result<()> {
+        let (sender, engine) = spawn_engine(
+            || Repos::memory(MemoryConfig::default(), None),
+            |_| Ok(()),
+            None,
+            Some(Duration::ZERO),
+        )?;
+        let submit_deposit = || -> Result<_> {
+            let (reply, response) = oneshot::channel();
+            let command = command(submit("deposit", 1, "1").into_inner())?;
+            sender.send(Command::Submit(command, reply))?;
+            Ok(response)
+        };
+        // the deposit is evicted straight after it's processed, but replaying it isn't credited
+        let first = submit_deposit()?.await?;
+        let replayed = submit_deposit()?.await?;
+        assert_eq!(first?.total(), Decimal::from(1));
+        let err = replayed.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::Duplicate { tx: 1 })
+        );
+        drop(sender);
+        engine
+            .join()
+            .map_err(|_| anyhow!("engine thread panicked"))??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_engine_drains_on_shutdown() -> Result<()> {
+        let (closed, accounts) = mpsc::channel();
+        let (sender, engine) = spawn_engine(
+            || Repos::memory(MemoryConfig::default(), None),
+            move |repos| {
+                closed.send(repos.accounts.get_all()?.len())?;
+                Ok(())
+            },
+            None,
+            None,
+        )?;
+        let mut replies = vec![];
+        for tx in 1..=3 {
+            let (reply, response) = oneshot::channel();
+            let command = command(submit("deposit", tx, "1").into_inner())?;
+            sender.send(Command::Submit(command, reply))?;
+            replies.push(response);
+        }
+        // commands queued before the last sender is dropped are still processed
+        drop(sender);
+        engine
+            .join()
+            .map_err(|_| anyhow!("engine thread panicked"))??;
+        for response in replies {
+            assert!(response.await?.is_ok());
+        }
+        assert_eq!(accounts.recv()?, 1);
+        Ok(())
+    }
+}
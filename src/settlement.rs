@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::accounts::Account;
+
+/// SettlementFormat is the layout of a settlement file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettlementFormat {
+    Csv,
+    /// Fixed width records of [RECORD_WIDTH] characters, with amounts in ten-thousandths
+    FixedWidth,
+}
+
+impl FromStr for SettlementFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<SettlementFormat> {
+        match s {
+            "csv" => Ok(SettlementFormat::Csv),
+            "fixed-width" => Ok(SettlementFormat::FixedWidth),
+            _ => bail!("expected csv or fixed-width, got {:?}", s),
+        }
+    }
+}
+
+/// Direction is which way funds move between us and a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Funds owed to the client, e.g. for their withdrawals
+    Credit,
+    /// Funds owed by the client, e.g. for their deposits
+    Debit,
+}
+
+impl Direction {
+    fn of(amount: Decimal) -> Direction {
+        if amount < Decimal::from(0) {
+            Direction::Debit
+        } else {
+            Direction::Credit
+        }
+    }
+}
+
+/// SettlementRecord is a record of a settlement file: a detail record per client with funds to
+/// move, a total record per direction, and a control record with the net of the batch
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SettlementRecord {
+    pub record: &'static str,
+    pub client: Option<u64>,
+    pub direction: Direction,
+    pub amount: Decimal,
+    /// The number of detail records the record covers
+    pub count: u64,
+}
+
+/// RECORD_WIDTH is the width of every line of a fixed width settlement file
+pub const RECORD_WIDTH: usize = 50;
+
+impl SettlementRecord {
+    /// fixed_width formats the record as its record type (1 character), client (20 digits, or
+    /// blank), direction (C or D), amount in ten-thousandths (18 digits) & count (10 digits)
+    fn fixed_width(&self) -> Result<String> {
+        let record = self.record[..1].to_uppercase();
+        let client = match self.client {
+            Some(client) => format!("{:020}", client),
+            None => " ".repeat(20),
+        };
+        let direction = match self.direction {
+            Direction::Credit => "C",
+            Direction::Debit => "D",
+        };
+        let amount = (self.amount * Decimal::from(10_000))
+            .round()
+            .to_u64()
+            .filter(|amount| *amount < 10u64.pow(18))
+            .ok_or_else(|| anyhow!("amount {} is too large to settle", self.amount))?;
+        let line = format!(
+            "{}{}{}{:018}{:010}",
+            record, client, direction, amount, self.count
+        );
+        debug_assert_eq!(line.len(), RECORD_WIDTH);
+        Ok(line)
+    }
+}
+
+/// settlement computes what each client is owed (positive) or owes (negative) for a run, from
+/// their accounts before & after it: every movement of funds in or out of an account (deposits,
+/// withdrawals, payouts, chargebacks & refunds) changes its total, and so is settled by the
+/// opposite movement. Clients with nothing to settle are left out.
+pub fn settlement(before: &[Account], after: &[Account]) -> Vec<(u64, Decimal)> {
+    let totals: HashMap<u64, Decimal> = before.iter().map(|a| (a.client(), a.total())).collect();
+    let mut owed: Vec<(u64, Decimal)> = after
+        .iter()
+        .map(|a| {
+            let opening = totals.get(&a.client()).copied().unwrap_or_default();
+            (a.client(), opening - a.total())
+        })
+        .filter(|(_, amount)| !amount.is_zero())
+        .collect();
+    owed.sort_by_key(|(client, _)| *client);
+    owed
+}
+
+/// records lays out a settlement as a batch of detail records followed by the batch totals &
+/// control record
+pub fn records(owed: &[(u64, Decimal)]) -> Vec<SettlementRecord> {
+    let mut records: Vec<SettlementRecord> = owed
+        .iter()
+        .map(|(client, amount)| SettlementRecord {
+            record: "detail",
+            client: Some(*client),
+            direction: Direction::of(*amount),
+            amount: amount.abs(),
+            count: 1,
+        })
+        .collect();
+    for direction in [Direction::Credit, Direction::Debit] {
+        let details = records
+            .iter()
+            .filter(|r| r.record == "detail" && r.direction == direction);
+        records.push(SettlementRecord {
+            record: "total",
+            client: None,
+            direction,
+            amount: details.clone().map(|r| r.amount).sum(),
+            count: details.count() as u64,
+        });
+    }
+    let net: Decimal = owed.iter().map(|(_, amount)| *amount).sum();
+    records.push(SettlementRecord {
+        record: "control",
+        client: None,
+        direction: Direction::of(net),
+        amount: net.abs(),
+        count: owed.len() as u64,
+    });
+    records
+}
+
+/// write_settlement writes a settlement file in the given layout
+pub fn write_settlement<W: io::Write>(
+    writer: W,
+    owed: &[(u64, Decimal)],
+    format: SettlementFormat,
+) -> Result<()> {
+    match format {
+        SettlementFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for record in records(owed) {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        }
+        SettlementFormat::FixedWidth => {
+            let mut writer = io::BufWriter::new(writer);
+            for record in records(owed) {
+                writeln!(writer, "{}", record.fixed_width()?)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::{AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::payments::PaymentsEngine;
+    use crate::transactions::{
+        MemoryRepo as TransactionsMemoryRepo, TransactionCommand, TransactionKind,
+    };
+
+    #[test]
+    fn test_settlement() -> Result<()> {
+        let transactions = TransactionsMemoryRepo::new();
+        let accounts = AccountsMemoryRepo::new();
+        let events = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+        let process = |kind, tx, client| {
+            engine.process_transaction(TransactionCommand {
+                kind,
+                tx,
+                client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })
+        };
+        let amount = |n: i64| Decimal::new(n, 1);
+        process(
+            TransactionKind::Deposit {
+                amount: amount(100),
+            },
+            1,
+            1,
+        )?;
+        process(TransactionKind::Deposit { amount: amount(50) }, 2, 2)?;
+        process(TransactionKind::Deposit { amount: amount(10) }, 3, 3)?;
+        let before = accounts.get_all()?;
+
+        process(TransactionKind::Withdrawal { amount: amount(40) }, 4, 1)?;
+        process(TransactionKind::Deposit { amount: amount(25) }, 5, 2)?;
+        process(TransactionKind::Dispute, 2, 2)?;
+        process(TransactionKind::ChargeBack, 2, 2)?;
+        process(TransactionKind::Deposit { amount: amount(5) }, 6, 4)?;
+        let owed = settlement(&before, &accounts.get_all()?);
+        assert_eq!(
+            owed,
+            vec![(1, amount(40)), (2, amount(25)), (4, amount(-5))]
+        );
+
+        let records = records(&owed);
+        assert_eq!(records.len(), 6);
+        assert_eq!(
+            records[3],
+            SettlementRecord {
+                record: "total",
+                client: None,
+                direction: Direction::Credit,
+                amount: amount(65),
+                count: 2,
+            }
+        );
+        assert_eq!(records[4].amount, amount(5));
+        assert_eq!(records[5].direction, Direction::Credit);
+        assert_eq!(records[5].amount, amount(60));
+
+        let mut out = Vec::new();
+        write_settlement(&mut out, &owed, SettlementFormat::FixedWidth)?;
+        let out = String::from_utf8(out)?;
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines.iter().all(|line| line.len() == RECORD_WIDTH));
+        assert_eq!(
+            lines[0],
+            "D00000000000000000001C0000000000000400000000000001"
+        );
+        assert_eq!(
+            lines[5],
+            "C                    C0000000000000600000000000003"
+        );
+
+        let mut out = Vec::new();
+        write_settlement(&mut out, &[], SettlementFormat::Csv)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "record,client,direction,amount,count\ntotal,,credit,0,0\ntotal,,debit,0,0\ncontrol,,credit,0,0\n"
+        );
+        Ok(())
+    }
+}
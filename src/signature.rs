@@ -0,0 +1,236 @@
+use std::env;
+
+use anyhow::{anyhow, Context, Result};
+use csv::StringRecord;
+use ring::hmac;
+use thiserror::Error;
+
+use crate::encryption::hex;
+use crate::input::{Row, RowError, SIGNATURE_HEADER};
+
+/// KEY_VAR is the environment variable the signing key is read from, hex encoded
+pub const KEY_VAR: &str = "PAYMENTS_SIGNING_KEY";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SignatureError {
+    #[error("row is unsigned")]
+    Missing,
+    #[error("row signature doesn't match its contents")]
+    Invalid,
+}
+
+impl SignatureError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            SignatureError::Missing => "missing_signature",
+            SignatureError::Invalid => "invalid_signature",
+        }
+    }
+}
+
+/// Signer signs & verifies rows with HMAC-SHA256. A row's signature is the hex encoded HMAC of
+/// every other field of the row, in the order they appear, each preceded by its column's header.
+/// Headers & fields are each encoded as a netstring (its length in bytes, a colon, the field & a
+/// comma) so that fields containing commas can't be shifted into their neighbours, and columns
+/// can't be swapped without changing the signature. Fields are signed as read, after unquoting
+/// but before any whitespace is trimmed.
+pub struct Signer {
+    key: hmac::Key,
+}
+
+impl Signer {
+    pub fn new(key: &[u8]) -> Signer {
+        Signer {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key),
+        }
+    }
+
+    /// from_env creates a signer from the hex encoded key in PAYMENTS_SIGNING_KEY
+    pub fn from_env() -> Result<Signer> {
+        let key = env::var(KEY_VAR)
+            .ok()
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| anyhow!("{} must be set to verify signatures", KEY_VAR))?;
+        let key = hex(key.trim()).with_context(|| format!("invalid {}", KEY_VAR))?;
+        Ok(Signer::new(&key))
+    }
+
+    /// sign returns the signature of a row's fields under `headers`, excluding its signature
+    /// column if it has one
+    pub fn sign(
+        &self,
+        headers: &StringRecord,
+        record: &StringRecord,
+        column: Option<usize>,
+    ) -> String {
+        hmac::sign(&self.key, &message(headers, record, column))
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// verify checks the signature in a row's signature column against the rest of its fields,
+    /// under `headers`
+    pub fn verify(
+        &self,
+        headers: &StringRecord,
+        record: &StringRecord,
+        column: Option<usize>,
+    ) -> Result<(), SignatureError> {
+        let signature = match column.and_then(|i| record.get(i)).map(str::trim) {
+            Some(signature) if !signature.is_empty() => signature,
+            _ => return Err(SignatureError::Missing),
+        };
+        let signature = hex(signature).map_err(|_| SignatureError::Invalid)?;
+        hmac::verify(&self.key, &message(headers, record, column), &signature)
+            .map_err(|_| SignatureError::Invalid)
+    }
+}
+
+/// message is the content of a row which is signed: each of its headers & fields in turn, e.g.
+/// `4:type,7:deposit,6:client,1:1,2:tx,1:1,6:amount,2:10,`. A field without a header is signed
+/// under an empty one.
+fn message(headers: &StringRecord, record: &StringRecord, column: Option<usize>) -> Vec<u8> {
+    let mut message = Vec::new();
+    for (i, field) in record
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| Some(*i) != column)
+    {
+        for s in [headers.get(i).unwrap_or_default(), field] {
+            message.extend_from_slice(s.len().to_string().as_bytes());
+            message.push(b':');
+            message.extend_from_slice(s.as_bytes());
+            message.push(b',');
+        }
+    }
+    message
+}
+
+/// Verified is an iterator of rows which rejects every row that's unsigned or whose signature
+/// doesn't match, before it reaches the engine
+pub struct Verified<I> {
+    inner: I,
+    signer: Signer,
+    headers: StringRecord,
+    column: Option<usize>,
+}
+
+impl<I> Verified<I> {
+    /// new verifies rows against the signature column in `headers`. If there isn't one, every
+    /// row is rejected as unsigned.
+    pub fn new(inner: I, headers: &StringRecord, signer: Signer) -> Verified<I> {
+        Verified {
+            inner,
+            signer,
+            headers: headers.clone(),
+            column: headers.iter().position(|h| h == SIGNATURE_HEADER),
+        }
+    }
+}
+
+impl<I> Iterator for Verified<I>
+where
+    I: Iterator<Item = Result<Row, RowError>>,
+{
+    type Item = Result<Row, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match self.inner.next()? {
+            Ok(row) => match self.signer.verify(&self.headers, &row.record, self.column) {
+                Ok(()) => Ok(row),
+                Err(e) => Err(RowError {
+                    position: row.position,
+                    record: Some(row.record),
+                    error: e.into(),
+                }),
+            },
+            Err(e) => Err(e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{CommandReader, InputConfig};
+
+    #[test]
+    fn test_verified() -> Result<()> {
+        let signer = Signer::new(b"secret");
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "signature"]);
+        let signed = |row: &str| {
+            let record = StringRecord::from(row.split(',').collect::<Vec<&str>>());
+            format!("{},{}\n", row, signer.sign(&headers, &record, None))
+        };
+        let mut input = String::from("type,client,tx,amount,signature\n");
+        input.push_str(&signed("deposit,1,1,10"));
+        // tampered with after signing
+        input.push_str(&signed("deposit,1,2,10").replacen(",10,", ",1000,", 1));
+        input.push_str("deposit,1,3,10,\n");
+        // signature corrupted
+        input.push_str(&signed("withdrawal,1,4,5").replace(",5,", ",5,00"));
+        input.push_str(&signed("withdrawal,1,5,5"));
+
+        let reader = CommandReader::from_reader(input.as_bytes(), InputConfig::default())?;
+        assert_eq!(reader.headers(), &headers);
+        let results: Vec<Result<u64, &'static str>> = Verified::new(reader, &headers, signer)
+            .map(|row| match row {
+                Ok(row) => Ok(row.command.tx),
+                Err(e) => Err(e.error.downcast::<SignatureError>().unwrap().code()),
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok(1),
+                Err("invalid_signature"),
+                Err("missing_signature"),
+                Err("invalid_signature"),
+                Ok(5),
+            ]
+        );
+
+        // signed with a different key
+        let other = Signer::new(b"other");
+        let record = StringRecord::from(vec!["deposit", "1", "1", "10", ""]);
+        let signature = other.sign(&headers, &record, Some(4));
+        let record = StringRecord::from(vec!["deposit", "1", "1", "10", &signature]);
+        assert_eq!(other.verify(&headers, &record, Some(4)), Ok(()));
+        assert_eq!(
+            Signer::new(b"secret").verify(&headers, &record, Some(4)),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            Signer::new(b"secret").verify(&headers, &record, None),
+            Err(SignatureError::Missing)
+        );
+        // swapping the client & tx columns' headers changes what's signed, although their
+        // values still parse
+        let swapped = StringRecord::from(vec!["type", "tx", "client", "amount", "signature"]);
+        assert_eq!(
+            other.verify(&swapped, &record, Some(4)),
+            Err(SignatureError::Invalid)
+        );
+
+        // moving a comma between fields changes what's signed
+        let message_of = |headers: Vec<&str>, fields: Vec<&str>| {
+            message(
+                &StringRecord::from(headers),
+                &StringRecord::from(fields),
+                None,
+            )
+        };
+        assert_eq!(
+            message_of(vec!["type", "tx"], vec!["deposit", "1"]),
+            b"4:type,7:deposit,2:tx,1:1,"
+        );
+        assert_ne!(
+            message_of(vec!["a", "b"], vec!["a,b", "c"]),
+            message_of(vec!["a", "b"], vec!["a", "b,c"])
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::Result;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::accounts::{AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+use crate::chaos::{self, Faults, FaultyRepo};
+use crate::events::MemoryRepo as EventsMemoryRepo;
+use crate::payments::{PaymentsEngine, SaveError};
+use crate::transactions::{
+    MemoryRepo as TransactionsMemoryRepo, TransactionCommand, TransactionKind, TransactionsRepo,
+};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SimulationError {
+    #[error("seed {seed} diverged from the reference model after {steps} commands: {reason}")]
+    Diverged {
+        seed: u64,
+        steps: usize,
+        reason: String,
+    },
+}
+
+impl SimulationError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            SimulationError::Diverged { .. } => "simulation_diverged",
+        }
+    }
+}
+
+/// SimulationConfig controls the shape of the simulated command sequences
+#[derive(Debug, Clone, Copy)]
+pub struct SimulationConfig {
+    pub seed: u64,
+    /// Number of sequences to run, each with the next seed
+    pub runs: u64,
+    /// Number of commands in each sequence
+    pub steps: usize,
+    /// Number of clients the commands are spread over. Few clients make for long dispute chains
+    /// on the same account.
+    pub clients: u64,
+    /// Injects storage faults into the engine's repositories (chaos mode), seeded by each
+    /// sequence's seed
+    pub faults: Option<Faults>,
+}
+
+/// commands is a reproducible sequence of commands for a seed. Unlike the load testing
+/// generator, disputes, resolves & chargebacks reference any earlier transaction in any state, and
+/// some commands are deliberately wrong (duplicates, unknown transactions, another client's
+/// transaction & negative amounts), so that the engine's state machine is exercised rather than
+/// its happy path.
+pub fn commands(seed: u64, steps: usize, clients: u64) -> Vec<TransactionCommand> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut issued: Vec<(u64, u64)> = Vec::new();
+    let mut commands = Vec::with_capacity(steps);
+    let command = |kind, tx, client| TransactionCommand {
+        kind,
+        tx,
+        client,
+        correlation: None,
+        tenant: None,
+        timestamp: None,
+    };
+    for _ in 0..steps {
+        let client = rng.gen_range(1..=clients.max(1));
+        let amount = Decimal::new(rng.gen_range(1..10_000), 2);
+        let next_tx = issued.len() as u64 + 1;
+        let previous = issued.choose(&mut rng).copied();
+        let roll: f64 = rng.gen();
+        let next = match (roll, previous) {
+            (r, _) if r < 0.35 => command(TransactionKind::Deposit { amount }, next_tx, client),
+            (r, _) if r < 0.55 => command(TransactionKind::Withdrawal { amount }, next_tx, client),
+            (r, Some((tx, owner))) if r < 0.90 => {
+                let kind = match rng.gen_range(0..4) {
+                    0 | 1 => TransactionKind::Dispute,
+                    2 => TransactionKind::Resolve,
+                    _ => TransactionKind::ChargeBack,
+                };
+                command(kind, tx, owner)
+            }
+            (_, Some((tx, owner))) => match rng.gen_range(0..4) {
+                0 => command(TransactionKind::Deposit { amount }, tx, owner),
+                1 => command(TransactionKind::Dispute, tx, owner % clients.max(1) + 1),
+                2 => command(TransactionKind::Dispute, next_tx + 1_000, client),
+                _ => command(
+                    TransactionKind::Deposit { amount: -amount },
+                    next_tx,
+                    client,
+                ),
+            },
+            (_, None) => command(TransactionKind::Deposit { amount }, next_tx, client),
+        };
+        if next.kind.is_initial() && next.tx == next_tx {
+            issued.push((next.tx, next.client));
+        }
+        commands.push(next);
+    }
+    commands
+}
+
+/// Balances are an account's balances & whether it's locked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Settled,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl State {
+    /// of is the state of a transaction stored by the engine with the given kind
+    fn of(kind: TransactionKind) -> Option<State> {
+        match kind {
+            TransactionKind::Deposit { .. } | TransactionKind::Withdrawal { .. } => {
+                Some(State::Settled)
+            }
+            TransactionKind::Dispute => Some(State::Disputed),
+            TransactionKind::Resolve => Some(State::Resolved),
+            TransactionKind::ChargeBack => Some(State::ChargedBack),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModelTransaction {
+    client: u64,
+    amount: Decimal,
+    state: State,
+}
+
+/// Model is a deliberately simple reference implementation of deposits, withdrawals & the dispute
+/// lifecycle, written from the rules rather than from the engine, to check the engine against
+#[derive(Debug, Default)]
+pub struct Model {
+    accounts: HashMap<u64, Balances>,
+    transactions: HashMap<u64, ModelTransaction>,
+}
+
+impl Model {
+    pub fn new() -> Model {
+        Model::default()
+    }
+
+    /// account is a client's balances, if they have an account
+    pub fn account(&self, client: u64) -> Option<Balances> {
+        self.accounts.get(&client).copied()
+    }
+
+    /// apply applies a command, returning whether it was accepted. Rejected commands change
+    /// nothing.
+    pub fn apply(&mut self, command: &TransactionCommand) -> bool {
+        let zero = Decimal::from(0);
+        let client = command.client;
+        match command.kind {
+            TransactionKind::Deposit { amount } | TransactionKind::Withdrawal { amount } => {
+                let withdrawal = matches!(command.kind, TransactionKind::Withdrawal { .. });
+                if self.transactions.contains_key(&command.tx) || amount < zero {
+                    return false;
+                }
+                match self.accounts.get_mut(&client) {
+                    Some(account) if account.locked => return false,
+                    Some(account) if withdrawal => {
+                        if account.available < amount {
+                            return false;
+                        }
+                        account.available -= amount;
+                    }
+                    Some(account) => account.available += amount,
+                    // accounts are only opened by deposits
+                    None if withdrawal => return false,
+                    None => {
+                        self.accounts.insert(
+                            client,
+                            Balances {
+                                available: amount,
+                                held: zero,
+                                locked: false,
+                            },
+                        );
+                    }
+                }
+                self.transactions.insert(
+                    command.tx,
+                    ModelTransaction {
+                        client,
+                        amount,
+                        state: State::Settled,
+                    },
+                );
+                true
+            }
+            TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::ChargeBack => {
+                let transaction = match self.transactions.get_mut(&command.tx) {
+                    Some(t) if t.client == client => t,
+                    _ => return false,
+                };
+                let state = match (transaction.state, command.kind) {
+                    (State::Settled, TransactionKind::Dispute) => State::Disputed,
+                    (State::Disputed, TransactionKind::Resolve) => State::Resolved,
+                    (State::Disputed, TransactionKind::ChargeBack) => State::ChargedBack,
+                    _ => return false,
+                };
+                let account = match self.accounts.get_mut(&client) {
+                    Some(account) if !account.locked => account,
+                    _ => return false,
+                };
+                let amount = transaction.amount;
+                match state {
+                    State::Disputed => {
+                        account.available -= amount;
+                        account.held += amount;
+                    }
+                    State::Resolved => {
+                        account.available += amount;
+                        account.held -= amount;
+                    }
+                    _ => {
+                        account.held -= amount;
+                        account.locked = true;
+                    }
+                }
+                transaction.state = state;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Failure is where & how the engine first diverged from the model (or broke an invariant)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    /// The index of the command after which the failure was detected
+    pub step: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}: {}", self.step, self.reason)
+    }
+}
+
+/// check runs commands through a fresh in-memory engine & the model side by side, comparing
+/// whether each command was accepted, the balances of the client it names & the state of the
+/// transaction it names, and that held balances are never negative. Returns the first failure, if
+/// any.
+///
+/// With faults, a command which fails because of an injected fault must leave no trace, so the
+/// model rejects it too. Checking stops early if the engine reports that it couldn't undo a
+/// partial save, since nothing can be expected of the state after that.
+pub fn check(commands: &[TransactionCommand], faults: Option<Faults>) -> Result<Option<Failure>> {
+    let faults = faults.unwrap_or_default();
+    let transactions = FaultyRepo::new(TransactionsMemoryRepo::new(), faults);
+    let accounts = FaultyRepo::new(AccountsMemoryRepo::new(), faults.offset(1));
+    let events = FaultyRepo::new(EventsMemoryRepo::new(), faults.offset(2));
+    let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+    let mut model = Model::new();
+
+    for (step, command) in commands.iter().enumerate() {
+        let fail = |reason: String| Ok(Some(Failure { step, reason }));
+        let (accepted, expected) = match engine.process_transaction(*command) {
+            Ok(_) => (true, model.apply(command)),
+            Err(e) if e.downcast_ref::<SaveError>().is_some() => return Ok(None),
+            Err(e) if chaos::is_injected(&e) => (false, false),
+            Err(_) => (false, model.apply(command)),
+        };
+        if accepted != expected {
+            let verdict = if accepted { "accepted" } else { "rejected" };
+            return fail(format!(
+                "engine {} {} tx={} client={}, the model didn't",
+                verdict, command.kind, command.tx, command.client
+            ));
+        }
+        let actual = accounts.inner().get(command.client)?.map(|a| Balances {
+            available: a.available(),
+            held: a.held(),
+            locked: a.is_locked(),
+        });
+        let expected = model.account(command.client);
+        if actual != expected {
+            return fail(format!(
+                "client {} has {:?}, the model has {:?}",
+                command.client, actual, expected
+            ));
+        }
+        if let Some(balances) = actual.filter(|b| b.held < Decimal::from(0)) {
+            return fail(format!(
+                "client {} has a negative held balance ({})",
+                command.client, balances.held
+            ));
+        }
+        let actual = transactions
+            .inner()
+            .get(command.tx)?
+            .map(|t| (t.client, State::of(t.kind)));
+        let expected = model
+            .transactions
+            .get(&command.tx)
+            .map(|t| (t.client, Some(t.state)));
+        if actual != expected {
+            return fail(format!(
+                "tx {} is {:?}, the model has {:?}",
+                command.tx, actual, expected
+            ));
+        }
+    }
+    Ok(None)
+}
+
+/// shrink removes as many commands as it can while `fails` still holds, first in large chunks
+/// then one at a time, so that what remains is a minimal reproduction: removing any single
+/// command from it makes the failure go away
+pub fn shrink<F>(
+    mut commands: Vec<TransactionCommand>,
+    mut fails: F,
+) -> Result<Vec<TransactionCommand>>
+where
+    F: FnMut(&[TransactionCommand]) -> Result<bool>,
+{
+    let mut chunk = commands.len() / 2;
+    while chunk > 0 {
+        let mut i = 0;
+        while i + chunk <= commands.len() {
+            let mut candidate = commands.clone();
+            candidate.drain(i..i + chunk);
+            if fails(&candidate)? {
+                commands = candidate;
+            } else {
+                i += 1;
+            }
+        }
+        chunk /= 2;
+    }
+    Ok(commands)
+}
+
+/// Counterexample is a minimal command sequence on which the engine diverges from the model
+#[derive(Debug, Clone)]
+pub struct Counterexample {
+    /// The seed of the sequence the counterexample was shrunk from
+    pub seed: u64,
+    pub commands: Vec<TransactionCommand>,
+    pub failure: Failure,
+}
+
+impl From<&Counterexample> for SimulationError {
+    fn from(counterexample: &Counterexample) -> SimulationError {
+        SimulationError::Diverged {
+            seed: counterexample.seed,
+            steps: counterexample.commands.len(),
+            reason: counterexample.failure.reason.clone(),
+        }
+    }
+}
+
+/// run checks the sequences of each seed in turn, stopping at the first which fails, and
+/// returns it shrunk to a minimal counterexample
+pub fn run(config: SimulationConfig) -> Result<Option<Counterexample>> {
+    for seed in config.seed..config.seed.saturating_add(config.runs) {
+        let commands = commands(seed, config.steps, config.clients);
+        let faults = config.faults.map(|faults| Faults { seed, ..faults });
+        let failure = match check(&commands, faults)? {
+            Some(failure) => failure,
+            None => continue,
+        };
+        // nothing after the failing command can matter
+        let commands = commands[..=failure.step].to_vec();
+        let commands = shrink(commands, |c| Ok(check(c, faults)?.is_some()))?;
+        let failure = check(&commands, faults)?.unwrap_or(failure);
+        return Ok(Some(Counterexample {
+            seed,
+            commands,
+            failure,
+        }));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::command;
+
+    #[test]
+    fn test_engine_matches_model() -> Result<()> {
+        let a = commands(7, 200, 3);
+        assert_eq!(a, commands(7, 200, 3));
+        assert_ne!(a, commands(8, 200, 3));
+        let count = |kind: TransactionKind| a.iter().filter(|c| c.kind == kind).count();
+        assert!(count(TransactionKind::Dispute) > 0);
+        assert!(count(TransactionKind::ChargeBack) > 0);
+
+        let config = SimulationConfig {
+            seed: 0,
+            runs: 50,
+            steps: 200,
+            clients: 3,
+            faults: None,
+        };
+        if let Some(counterexample) = run(config)? {
+            panic!(
+                "{}: {:?}",
+                SimulationError::from(&counterexample),
+                counterexample.commands
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_chaos() -> Result<()> {
+        // failed writes are undone, so commands which hit a fault leave no trace
+        let config = SimulationConfig {
+            seed: 0,
+            runs: 50,
+            steps: 200,
+            clients: 3,
+            faults: Some(Faults {
+                rate: 0.05,
+                latency: None,
+                seed: 0,
+            }),
+        };
+        if let Some(counterexample) = run(config)? {
+            panic!(
+                "{}: {:?}",
+                SimulationError::from(&counterexample),
+                counterexample.commands
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_model() {
+        let d = Decimal::from;
+        let mut model = Model::new();
+        assert!(!model.apply(&command(TransactionKind::Withdrawal { amount: d(1) }, 1, 1)));
+        assert!(model.apply(&command(TransactionKind::Deposit { amount: d(10) }, 1, 1)));
+        assert!(!model.apply(&command(TransactionKind::Deposit { amount: d(1) }, 1, 1)));
+        assert!(model.apply(&command(TransactionKind::Withdrawal { amount: d(6) }, 1, 2)));
+        assert!(!model.apply(&command(TransactionKind::Dispute, 2, 2)));
+        assert!(model.apply(&command(TransactionKind::Dispute, 1, 2)));
+        assert!(!model.apply(&command(TransactionKind::Dispute, 1, 2)));
+        assert!(model.apply(&command(TransactionKind::ChargeBack, 1, 2)));
+        assert!(!model.apply(&command(TransactionKind::Deposit { amount: d(1) }, 1, 3)));
+        assert_eq!(
+            model.account(1),
+            Some(Balances {
+                available: d(-2),
+                held: d(0),
+                locked: true
+            })
+        );
+    }
+
+    #[test]
+    fn test_shrink() -> Result<()> {
+        // fails whenever a chargeback follows the deposit it charges back
+        let fails = |commands: &[TransactionCommand]| -> Result<bool> {
+            let deposit = commands
+                .iter()
+                .position(|c| c.tx == 5 && c.kind.is_initial());
+            let chargeback = commands
+                .iter()
+                .rposition(|c| c.tx == 5 && c.kind == TransactionKind::ChargeBack);
+            Ok(matches!((deposit, chargeback), (Some(d), Some(c)) if d < c))
+        };
+        let mut commands = commands(3, 100, 2);
+        commands.insert(
+            10,
+            command(
+                TransactionKind::Deposit {
+                    amount: Decimal::from(1),
+                },
+                1,
+                5,
+            ),
+        );
+        commands.push(command(TransactionKind::ChargeBack, 1, 5));
+        assert!(fails(&commands)?);
+
+        let shrunk = shrink(commands, fails)?;
+        assert_eq!(shrunk.len(), 2);
+        assert!(fails(&shrunk)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::accounts::{Account, AccountsRepo};
+use crate::encryption::Cipher;
+use crate::events::EventsRepo;
+use crate::transactions::{Transaction, TransactionsRepo};
+
+/// Snapshot is the complete state of the repositories at a point in time, which can be written to
+/// a file and later restored into any set of repositories to resume processing from that point.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub accounts: Vec<Account>,
+    pub transactions: Vec<Transaction>,
+    pub events: Vec<Transaction>,
+}
+
+impl Snapshot {
+    pub fn capture(
+        transactions: &dyn TransactionsRepo,
+        accounts: &dyn AccountsRepo,
+        events: &dyn EventsRepo,
+    ) -> Result<Snapshot> {
+        Ok(Snapshot {
+            accounts: accounts.get_all()?,
+            transactions: transactions.get_all()?,
+            events: events.get_all()?,
+        })
+    }
+
+    /// restore saves the snapshot's state into the given repositories, which are expected to be
+    /// empty
+    pub fn restore(
+        &self,
+        transactions: &dyn TransactionsRepo,
+        accounts: &dyn AccountsRepo,
+        events: &dyn EventsRepo,
+    ) -> Result<()> {
+        for account in &self.accounts {
+            accounts.save(*account)?;
+        }
+        for transaction in &self.transactions {
+            transactions.save(*transaction)?;
+        }
+        for event in &self.events {
+            events.append(*event)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: io::Read>(reader: R) -> Result<Snapshot> {
+        Ok(serde_json::from_reader(io::BufReader::new(reader))?)
+    }
+
+    pub fn write<W: io::Write>(&self, writer: W) -> Result<()> {
+        let mut writer = io::BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, self)?;
+        io::Write::flush(&mut writer)?;
+        Ok(())
+    }
+
+    /// load reads a snapshot file, decrypting it with `cipher` if given
+    pub fn load<P: AsRef<Path>>(path: P, cipher: Option<&Cipher>) -> Result<Snapshot> {
+        match cipher {
+            Some(cipher) => Snapshot::read(cipher.open(&fs::read(path)?)?.as_slice()),
+            None => Snapshot::read(File::open(path)?),
+        }
+    }
+
+    /// save writes the snapshot to a temporary file alongside `path` and then renames it into
+    /// place, so that a crash part way through never leaves a truncated snapshot behind. The file
+    /// is encrypted with `cipher` if given.
+    pub fn save<P: AsRef<Path>>(&self, path: P, cipher: Option<&Cipher>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        match cipher {
+            Some(cipher) => {
+                let mut json = Vec::new();
+                self.write(&mut json)?;
+                io::Write::write_all(&mut file, &cipher.seal(&json)?)?;
+            }
+            None => self.write(&file)?,
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::MemoryRepo as AccountsMemoryRepo;
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::payments::PaymentsEngine;
+    use crate::transactions::{
+        MemoryRepo as TransactionsMemoryRepo, TransactionCommand, TransactionKind,
+    };
+    use rust_decimal::prelude::*;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        let amount = Decimal::new(15, 1);
+        for (kind, tx) in [
+            (TransactionKind::Deposit { amount }, 1),
+            (TransactionKind::Dispute, 1),
+        ] {
+            engine.process_transaction(TransactionCommand {
+                kind,
+                tx,
+                client: 1,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+
+        let mut bytes = vec![];
+        Snapshot::capture(&transactions_repo, &accounts_repo, &events_repo)?.write(&mut bytes)?;
+
+        let transactions_repo = TransactionsMemoryRepo::new();
+        let accounts_repo = AccountsMemoryRepo::new();
+        let events_repo = EventsMemoryRepo::new();
+        Snapshot::read(&bytes[..])?.restore(&transactions_repo, &accounts_repo, &events_repo)?;
+
+        let acc = accounts_repo.get(1)?.unwrap();
+        assert_eq!(acc.held(), amount);
+        assert_eq!(
+            transactions_repo.get(1)?.unwrap().kind,
+            TransactionKind::Dispute
+        );
+        assert_eq!(events_repo.get_all()?.len(), 2);
+
+        let engine = PaymentsEngine::new(&transactions_repo, &accounts_repo, &events_repo);
+        engine.process_transaction(TransactionCommand {
+            kind: TransactionKind::Resolve,
+            tx: 1,
+            client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        assert_eq!(accounts_repo.get(1)?.unwrap().available(), amount);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_encrypted() -> Result<()> {
+        let snapshot = Snapshot {
+            accounts: vec![],
+            transactions: vec![],
+            events: vec![],
+        };
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+        let cipher = Cipher::new(&[7; 32])?;
+        snapshot.save(&path, Some(&cipher))?;
+        let loaded = Snapshot::load(&path, Some(&cipher));
+        let unencrypted = Snapshot::load(&path, None);
+        fs::remove_file(&path)?;
+        assert!(loaded?.accounts.is_empty());
+        assert!(unencrypted.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::bench::{self, Backend};
+use crate::generate::{Generator, GeneratorConfig};
+use crate::payments::PaymentsEngine;
+use crate::usage;
+
+/// Histogram counts latencies in buckets which are exact below 16µs and otherwise 1/16th of a
+/// power of two wide, so percentiles are within 6.25% of the true value whatever the range, in
+/// constant memory however long the run
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+const SUB_BUCKETS: u64 = 16;
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            counts: vec![0; Histogram::bucket(u64::MAX) + 1],
+            count: 0,
+            max: 0,
+        }
+    }
+
+    fn bucket(micros: u64) -> usize {
+        if micros < SUB_BUCKETS {
+            return micros as usize;
+        }
+        let exponent = 63 - u64::from(micros.leading_zeros()) - 4;
+        let sub = (micros >> exponent) - SUB_BUCKETS;
+        (SUB_BUCKETS * (exponent + 1) + sub) as usize
+    }
+
+    /// highest is the highest value which falls in a bucket
+    fn highest(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < SUB_BUCKETS {
+            return bucket;
+        }
+        let exponent = bucket / SUB_BUCKETS - 1;
+        let sub = bucket % SUB_BUCKETS;
+        let highest = (u128::from(SUB_BUCKETS + sub + 1) << exponent) - 1;
+        highest.min(u128::from(u64::MAX)) as u64
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().min(u128::from(u64::MAX)) as u64;
+        self.counts[Histogram::bucket(micros)] += 1;
+        self.count += 1;
+        self.max = self.max.max(micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// percentile is the latency which `p` percent of those recorded are at or below, rounded up
+    /// to the top of its bucket
+    pub fn percentile(&self, p: f64) -> Duration {
+        let rank = ((p / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(Histogram::highest(bucket).min(self.max));
+            }
+        }
+        Duration::from_micros(self.max)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max)
+    }
+}
+
+/// SoakConfig controls a soak test: generated transactions streamed at a steady rate for a long
+/// time, to check that latency & memory stay stable
+#[derive(Debug, Clone, Copy)]
+pub struct SoakConfig {
+    /// The target number of transactions per second
+    pub rate: u64,
+    pub duration: Duration,
+    /// How often to report a sample
+    pub interval: Duration,
+    pub clients: u64,
+    pub seed: u64,
+    pub backend: Backend,
+}
+
+/// Sample is what happened during an interval of a soak test (or the whole of it). Latencies are
+/// measured from when each transaction was due, rather than when it was processed, so that time
+/// spent waiting behind slow transactions when the engine can't keep up counts too.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Sample {
+    /// Seconds since the start of the run, at the end of the interval
+    pub elapsed: f64,
+    pub rows: u64,
+    pub rejected: u64,
+    pub rows_per_sec: f64,
+    pub p50_ms: f64,
+    pub p99_ms: f64,
+    pub p999_ms: f64,
+    pub max_ms: f64,
+    /// The resident set size at the end of the interval, where it can be measured
+    pub rss: Option<u64>,
+}
+
+impl Sample {
+    fn new(elapsed: Duration, interval: Duration, rejected: u64, latencies: &Histogram) -> Sample {
+        let ms = |d: Duration| (d.as_secs_f64() * 1e6).round() / 1e3;
+        Sample {
+            elapsed: (elapsed.as_secs_f64() * 1e3).round() / 1e3,
+            rows: latencies.count(),
+            rejected,
+            rows_per_sec: (latencies.count() as f64 / interval.as_secs_f64().max(f64::EPSILON))
+                .round(),
+            p50_ms: ms(latencies.percentile(50.0)),
+            p99_ms: ms(latencies.percentile(99.0)),
+            p999_ms: ms(latencies.percentile(99.9)),
+            max_ms: ms(latencies.max()),
+            rss: usage::current_rss(),
+        }
+    }
+}
+
+/// Soak is the outcome of a soak test
+#[derive(Debug)]
+pub struct Soak {
+    /// The whole run as a single sample
+    pub total: Sample,
+    /// The resident set size at the start of the run, where it can be measured
+    pub rss_start: Option<u64>,
+}
+
+impl Soak {
+    /// growth is how much the resident set size grew (or shrank) over the run
+    pub fn growth(&self) -> Option<i64> {
+        Some(self.total.rss? as i64 - self.rss_start? as i64)
+    }
+}
+
+/// run streams generated transactions through an engine at the configured rate until the duration
+/// is up, passing a sample of each interval to `report` as it ends. Transactions are processed as
+/// soon as they're due, or immediately when the engine has fallen behind.
+pub fn run(config: SoakConfig, mut report: impl FnMut(&Sample) -> Result<()>) -> Result<Soak> {
+    let (transactions, accounts, events) = bench::repos(config.backend)?;
+    let engine = PaymentsEngine::new(transactions.as_ref(), accounts.as_ref(), events.as_ref());
+    let generator = Generator::new(GeneratorConfig {
+        clients: config.clients,
+        rows: u64::MAX,
+        dispute_rate: 0.01,
+        invalid_rate: 0.001,
+        seed: config.seed,
+    });
+
+    let rss_start = usage::current_rss();
+    let period = Duration::from_secs_f64(1.0 / config.rate.max(1) as f64);
+    let (mut interval, mut total) = (Histogram::new(), Histogram::new());
+    let (mut rejected, mut total_rejected) = (0, 0);
+    let start = Instant::now();
+    let mut interval_start = start;
+    for (i, command) in generator.enumerate() {
+        let due = start + period.mul_f64(i as f64);
+        if due.saturating_duration_since(start) >= config.duration {
+            break;
+        }
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        if engine.process_transaction(command).is_err() {
+            rejected += 1;
+        }
+        let latency = due.elapsed();
+        interval.record(latency);
+        total.record(latency);
+
+        let now = Instant::now();
+        if now - interval_start >= config.interval {
+            report(&Sample::new(
+                now - start,
+                now - interval_start,
+                rejected,
+                &interval,
+            ))?;
+            total_rejected += rejected;
+            rejected = 0;
+            interval = Histogram::new();
+            interval_start = now;
+        }
+    }
+    let now = Instant::now();
+    if interval.count() > 0 {
+        report(&Sample::new(
+            now - start,
+            now - interval_start,
+            rejected,
+            &interval,
+        ))?;
+    }
+    Ok(Soak {
+        total: Sample::new(now - start, now - start, total_rejected + rejected, &total),
+        rss_start,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryConfig;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new();
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        assert_eq!(histogram.count(), 1000);
+        assert_eq!(histogram.max(), Duration::from_micros(1000));
+        for (p, exact) in [(50.0, 500.0), (99.0, 990.0), (99.9, 999.0)] {
+            let got = histogram.percentile(p).as_micros() as f64;
+            assert!(got >= exact && got <= exact * 1.0625, "p{} = {}", p, got);
+        }
+        assert_eq!(histogram.percentile(100.0), Duration::from_micros(1000));
+        assert_eq!(Histogram::new().percentile(99.0), Duration::ZERO);
+
+        for micros in [0, 15, 16, 17, 1 << 20, u64::MAX] {
+            let bucket = Histogram::bucket(micros);
+            assert!(Histogram::highest(bucket) >= micros, "{}", micros);
+            assert!(bucket == 0 || Histogram::highest(bucket - 1) < micros);
+        }
+    }
+
+    #[test]
+    fn test_soak() -> Result<()> {
+        let config = SoakConfig {
+            rate: 2000,
+            duration: Duration::from_millis(300),
+            interval: Duration::from_millis(100),
+            clients: 10,
+            seed: 0,
+            backend: Backend::Memory(MemoryConfig::default()),
+        };
+        let mut samples = vec![];
+        let soak = run(config, |sample| {
+            samples.push(sample.rows);
+            Ok(())
+        })?;
+        assert!(samples.len() >= 2, "{:?}", samples);
+        assert_eq!(samples.iter().sum::<u64>(), soak.total.rows);
+        assert_eq!(soak.total.rows, 600);
+        assert!(soak.total.p50_ms <= soak.total.p99_ms && soak.total.p99_ms <= soak.total.max_ms);
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::transactions::{SledRepo, Transaction, TransactionsRepo};
+
+/// ENTRY_SIZE is the approximate number of bytes used per transaction held in memory, including
+/// the map's own overhead
+const ENTRY_SIZE: u64 = 2 * mem::size_of::<(u64, (Transaction, u64))>() as u64;
+
+/// MemoryLimit is a number of bytes, optionally with a K, M or G suffix (powers of 1024)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryLimit(pub u64);
+
+impl FromStr for MemoryLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<MemoryLimit> {
+        let s = s.trim();
+        let (digits, unit) = match s.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+            Some((i, _)) => s.split_at(i),
+            None => (s, ""),
+        };
+        let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches('B') {
+            "" => 1,
+            "K" => 1 << 10,
+            "M" => 1 << 20,
+            "G" => 1 << 30,
+            _ => return Err(anyhow!("unknown unit {:?} in memory limit {:?}", unit, s)),
+        };
+        let value: f64 = digits.trim().parse()?;
+        Ok(MemoryLimit((value * multiplier as f64) as u64))
+    }
+}
+
+impl MemoryLimit {
+    /// capacity is the number of transactions which can be held in memory within the limit
+    pub fn capacity(&self) -> usize {
+        (self.0 / ENTRY_SIZE).max(1) as usize
+    }
+}
+
+/// SpillRepo keeps recently used transactions in memory, spilling the least recently used to disk
+/// once there are more than `capacity` of them, so memory usage is bounded regardless of the
+/// number of transactions. Spilled transactions are brought back into memory when next used.
+pub struct SpillRepo {
+    hot: RefCell<HashMap<u64, (Transaction, u64)>>,
+    cold: SledRepo,
+    capacity: usize,
+    tick: Cell<u64>,
+    // keeps the temporary database open for as long as the repo is
+    _db: sled::Db,
+}
+
+impl SpillRepo {
+    /// new creates a repo which spills to a temporary database, removed once the repo is dropped
+    pub fn new(capacity: usize) -> Result<SpillRepo> {
+        let db = sled::Config::new().temporary(true).open()?;
+        Ok(SpillRepo {
+            hot: RefCell::new(HashMap::new()),
+            cold: SledRepo::new(&db)?,
+            capacity: capacity.max(1),
+            tick: Cell::new(0),
+            _db: db,
+        })
+    }
+
+    fn touch(&self, transaction: Transaction) -> Result<()> {
+        let tick = self.tick.get() + 1;
+        self.tick.set(tick);
+        let mut hot = self.hot.borrow_mut();
+        hot.insert(transaction.tx, (transaction, tick));
+        if hot.len() > self.capacity {
+            self.spill(&mut hot)?;
+        }
+        Ok(())
+    }
+
+    /// spill writes the least recently used half of the transactions in memory to disk
+    fn spill(&self, hot: &mut HashMap<u64, (Transaction, u64)>) -> Result<()> {
+        let mut ticks: Vec<u64> = hot.values().map(|(_, tick)| *tick).collect();
+        let middle = ticks.len() / 2;
+        let (_, cutoff, _) = ticks.select_nth_unstable(middle);
+        let cutoff = *cutoff;
+        let mut spilled = vec![];
+        hot.retain(|_, (transaction, tick)| {
+            if *tick < cutoff {
+                spilled.push(*transaction);
+                return false;
+            }
+            true
+        });
+        for transaction in spilled {
+            self.cold.save(transaction)?;
+        }
+        Ok(())
+    }
+
+    /// merge combines the transactions on disk with those in memory, which are more recent
+    fn merge(
+        &self,
+        cold: Vec<Transaction>,
+        filter: impl Fn(&Transaction) -> bool,
+    ) -> Vec<Transaction> {
+        let hot = self.hot.borrow();
+        let mut transactions: Vec<Transaction> = cold
+            .into_iter()
+            .filter(|t| !hot.contains_key(&t.tx))
+            .chain(hot.values().map(|(t, _)| *t))
+            .filter(filter)
+            .collect();
+        transactions.sort_by_key(|t| t.tx);
+        transactions
+    }
+}
+
+impl TransactionsRepo for SpillRepo {
+    /// Gets a single transaction by ID, from memory if possible
+    fn get(&self, id: u64) -> Result<Option<Transaction>> {
+        if let Some((transaction, _)) = self.hot.borrow().get(&id) {
+            return Ok(Some(*transaction));
+        }
+        let transaction = self.cold.get(id)?;
+        if let Some(transaction) = transaction {
+            self.touch(transaction)?;
+        }
+        Ok(transaction)
+    }
+    /// Upserts a transaction in memory, to be spilled to disk later if unused
+    fn save(&self, transaction: Transaction) -> Result<u64> {
+        self.touch(transaction)?;
+        Ok(transaction.tx)
+    }
+    /// Gets all of a client's transactions, ordered by ID
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        Ok(self.merge(self.cold.get_by_client(client)?, |t| t.client == client))
+    }
+    /// Gets every transaction, ordered by ID
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        Ok(self.merge(self.cold.get_all()?, |_| true))
+    }
+    /// Removes a transaction from both memory & disk, returning the most recent version of it
+    fn remove(&self, id: u64) -> Result<Option<Transaction>> {
+        let cold = self.cold.remove(id)?;
+        Ok(self.hot.borrow_mut().remove(&id).map(|(t, _)| t).or(cold))
+    }
+    fn erased(&self, id: u64) -> Result<bool> {
+        self.cold.erased(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::TransactionKind;
+    use rust_decimal::prelude::*;
+
+    #[test]
+    fn test_parse_memory_limit() -> Result<()> {
+        assert_eq!("2G".parse::<MemoryLimit>()?, MemoryLimit(2 << 30));
+        assert_eq!("512mb".parse::<MemoryLimit>()?, MemoryLimit(512 << 20));
+        assert_eq!("1.5K".parse::<MemoryLimit>()?, MemoryLimit(1536));
+        assert_eq!("100".parse::<MemoryLimit>()?, MemoryLimit(100));
+        assert!("2T".parse::<MemoryLimit>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_repo() -> Result<()> {
+        let repo = SpillRepo::new(4)?;
+        let amount = Decimal::from(1);
+        for tx in 1..=10 {
+            repo.save(Transaction {
+                tx,
+                client: tx % 2,
+                amount,
+                kind: TransactionKind::Deposit { amount },
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+        assert!(repo.hot.borrow().len() <= 4);
+        assert!(repo.cold.get(1)?.is_some());
+
+        repo.save(Transaction {
+            tx: 1,
+            client: 1,
+            amount,
+            kind: TransactionKind::Dispute,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        assert_eq!(repo.get(1)?.unwrap().kind, TransactionKind::Dispute);
+        assert_eq!(repo.get(2)?.unwrap().tx, 2);
+        assert!(repo.get(11)?.is_none());
+
+        let txs: Vec<u64> = repo.get_by_client(1)?.iter().map(|t| t.tx).collect();
+        assert_eq!(txs, vec![1, 3, 5, 7, 9]);
+        let all = repo.get_all()?;
+        assert_eq!(all.len(), 10);
+        assert_eq!(all[0].kind, TransactionKind::Dispute);
+        Ok(())
+    }
+}
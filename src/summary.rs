@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use rust_decimal::prelude::*;
+
+use crate::accounts::Account;
+use crate::rules::Flag;
+use crate::suspense::Suspense;
+use crate::timings::Stage;
+use crate::transactions::{TransactionCommand, TransactionKind};
+use crate::usage::{Bytes, MemoryUsage};
+
+/// saturating_add adds amounts to a total, which saturates rather than overflowing: accounts'
+/// balances are checked for overflow, but totals across every account aren't
+fn saturating_add(total: Decimal, amount: Decimal) -> Decimal {
+    total.checked_add(amount).unwrap_or(Decimal::max_value())
+}
+
+/// Summary describes what happened during a run, to be reported once it's complete
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Summary {
+    /// The number of rows read, including those which were rejected
+    pub rows: u64,
+    pub accepted: u64,
+    /// The number of rejected rows, by error code
+    pub rejected: BTreeMap<&'static str, u64>,
+    /// The number of unmatched rows held in suspense, and the sum of their amounts
+    pub suspended: u64,
+    pub suspended_total: Decimal,
+    /// Accepted transactions which broke a rule with the flag action
+    pub flagged: Vec<Flag>,
+    /// The total amount of accepted deposits
+    pub deposits: Decimal,
+    /// The total amount of accepted withdrawals
+    pub withdrawals: Decimal,
+    pub accounts_created: u64,
+    /// Accounts moved to the archive as dormant
+    pub accounts_archived: u64,
+    pub accounts_locked: u64,
+    pub elapsed: Duration,
+    /// The time spent in each stage, if measured
+    pub stages: Vec<(Stage, Duration)>,
+    pub memory: MemoryUsage,
+}
+
+impl Summary {
+    /// accept records a successfully processed transaction
+    pub fn accept(&mut self, command: &TransactionCommand) {
+        self.accepted += 1;
+        match command.kind {
+            TransactionKind::Deposit { amount } | TransactionKind::PendingDeposit { amount } => {
+                self.deposits = saturating_add(self.deposits, amount)
+            }
+            TransactionKind::Withdrawal { amount }
+            | TransactionKind::PendingWithdrawal { amount }
+            | TransactionKind::Payout { amount, .. } => {
+                self.withdrawals = saturating_add(self.withdrawals, amount)
+            }
+            _ => (),
+        }
+    }
+
+    /// merge adds the transactions accepted by another (e.g. a partition's) summary to this one
+    pub fn merge(&mut self, other: &Summary) {
+        self.accepted += other.accepted;
+        self.flagged.extend(other.flagged.iter().cloned());
+        self.deposits = saturating_add(self.deposits, other.deposits);
+        self.withdrawals = saturating_add(self.withdrawals, other.withdrawals);
+    }
+
+    /// suspend records the rows held in suspense, if there were any
+    pub fn suspend<W: io::Write>(&mut self, suspense: Option<&Suspense<W>>) {
+        if let Some(suspense) = suspense {
+            self.suspended = suspense.count;
+            self.suspended_total = suspense.total;
+        }
+    }
+
+    /// count_accounts records how many accounts were created & locked, given the accounts before
+    /// and after the run. Accounts before the run which aren't after it (e.g. archived ones which
+    /// weren't resurrected) are ignored.
+    pub fn count_accounts(&mut self, before: &[Account], after: &[Account]) {
+        let before: HashMap<u64, &Account> = before.iter().map(|a| (a.client(), a)).collect();
+        self.accounts_created = after
+            .iter()
+            .filter(|a| !before.contains_key(&a.client()))
+            .count() as u64;
+        self.accounts_locked = after
+            .iter()
+            .filter(|a| a.is_locked() && !before.get(&a.client()).is_some_and(|b| b.is_locked()))
+            .count() as u64;
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rejected: u64 = self.rejected.values().sum();
+        writeln!(f, "Rows read:        {}", self.rows)?;
+        writeln!(f, "Accepted:         {}", self.accepted)?;
+        writeln!(f, "Rejected:         {}", rejected)?;
+        for (code, count) in &self.rejected {
+            writeln!(f, "  {:<28} {}", code, count)?;
+        }
+        if self.suspended > 0 {
+            writeln!(
+                f,
+                "Suspense:         {} ({})",
+                self.suspended, self.suspended_total
+            )?;
+        }
+        if !self.flagged.is_empty() {
+            let mut rules: BTreeMap<&str, u64> = BTreeMap::new();
+            for flag in &self.flagged {
+                *rules.entry(flag.rule.as_str()).or_default() += 1;
+            }
+            writeln!(f, "Flagged:          {}", self.flagged.len())?;
+            for (rule, count) in rules {
+                writeln!(f, "  {:<28} {}", rule, count)?;
+            }
+        }
+        writeln!(f, "Deposits:         {}", self.deposits)?;
+        writeln!(f, "Withdrawals:      {}", self.withdrawals)?;
+        writeln!(f, "Accounts created: {}", self.accounts_created)?;
+        writeln!(f, "Accounts locked:  {}", self.accounts_locked)?;
+        if self.accounts_archived > 0 {
+            writeln!(f, "Archived:         {}", self.accounts_archived)?;
+        }
+        write!(f, "Elapsed:          {:.3}s", self.elapsed.as_secs_f64())?;
+        if let Some(peak) = self.memory.peak_rss {
+            write!(f, "\nPeak memory:      {}", Bytes(peak))?;
+        }
+        if let Some(allocated) = self.memory.allocated {
+            write!(f, "\nAllocated:        {}", Bytes(allocated))?;
+        }
+        if let Some(resident) = self.memory.resident {
+            write!(f, "\nResident:         {}", Bytes(resident))?;
+        }
+        if !self.stages.is_empty() {
+            write!(f, "\nTime by stage (summed across threads):")?;
+        }
+        for (stage, elapsed) in &self.stages {
+            write!(f, "\n  {:<28} {:.3}s", stage, elapsed.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::Transaction;
+    use anyhow::Result;
+
+    fn command(kind: TransactionKind, tx: u64) -> TransactionCommand {
+        TransactionCommand {
+            kind,
+            tx,
+            client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let amount = Decimal::from(5);
+        let mut summary = Summary::default();
+        summary.accept(&command(TransactionKind::Deposit { amount }, 1));
+        summary.accept(&command(
+            TransactionKind::Withdrawal {
+                amount: Decimal::from(2),
+            },
+            2,
+        ));
+        summary.accept(&command(TransactionKind::Dispute, 1));
+
+        let mut merged = Summary::default();
+        merged.merge(&summary);
+        merged.merge(&summary);
+        assert_eq!(merged.accepted, 6);
+        assert_eq!(merged.deposits, Decimal::from(10));
+        assert_eq!(merged.withdrawals, Decimal::from(4));
+
+        let acc = Account::new(Transaction::try_from(command(
+            TransactionKind::Deposit { amount },
+            1,
+        ))?)?;
+        summary.count_accounts(&[], &[acc]);
+        assert_eq!(summary.accounts_created, 1);
+        assert_eq!(summary.accounts_locked, 0);
+
+        summary.rows = 4;
+        summary.rejected.insert("insufficient_funds", 1);
+        let report = summary.to_string();
+        assert!(report.contains("Rejected:         1"));
+        assert!(report.contains("  insufficient_funds           1"));
+        assert!(!report.contains("Time by stage"));
+        assert!(!report.contains("Peak memory"));
+        assert!(!report.contains("Flagged"));
+
+        let flag = |tx| Flag {
+            rule: "large_deposit".to_string(),
+            tx,
+            client: 1,
+        };
+        summary.flagged = vec![flag(1), flag(3)];
+        let report = summary.to_string();
+        assert!(report.contains("Flagged:          2\n  large_deposit                2\n"));
+
+        summary.memory.peak_rss = Some(3 << 20);
+        assert!(summary.to_string().ends_with("Peak memory:      3.0 MiB"));
+
+        summary.stages = vec![(Stage::Parse, Duration::from_millis(1500))];
+        assert!(summary.to_string().ends_with(
+            "Time by stage (summed across threads):\n  parsing                      1.500s"
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::transactions::{TransactionCommand, TransactionError, TransactionKind};
+
+/// SuspenseEntry is a dispute, resolve or chargeback which referenced a transaction we have no
+/// record of, along with the amount given on its row (if any)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SuspenseEntry {
+    pub line: u64,
+    pub tx: u64,
+    pub client: u64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub amount: Option<Decimal>,
+}
+
+/// unmatched is whether a command was rejected because it's a dispute, resolve or chargeback of a
+/// transaction which doesn't exist
+pub fn unmatched(error: &anyhow::Error, command: &TransactionCommand) -> bool {
+    matches!(
+        command.kind,
+        TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::ChargeBack
+    ) && matches!(
+        error.downcast_ref::<TransactionError>(),
+        Some(TransactionError::Unknown { .. })
+    )
+}
+
+/// Suspense writes the unmatched dispute-family rows of a run to a suspense file, rather than
+/// rejecting them, so that the amounts they represent are accounted for until the original
+/// transactions are found
+pub struct Suspense<W: io::Write> {
+    writer: csv::Writer<W>,
+    /// The position of the amount column, if the input has one
+    amount: Option<usize>,
+    /// The number of rows held in suspense so far
+    pub count: u64,
+    /// The sum of the amounts held in suspense so far
+    pub total: Decimal,
+}
+
+impl Suspense<File> {
+    pub fn create<P: AsRef<Path>>(path: P, headers: &StringRecord) -> Result<Suspense<File>> {
+        Ok(Suspense::new(File::create(path)?, headers))
+    }
+}
+
+impl<W: io::Write> Suspense<W> {
+    pub fn new(writer: W, headers: &StringRecord) -> Suspense<W> {
+        Suspense {
+            writer: csv::Writer::from_writer(writer),
+            amount: headers.iter().position(|h| h.trim() == "amount"),
+            count: 0,
+            total: Decimal::from(0),
+        }
+    }
+
+    /// hold writes an unmatched row to the suspense file. Amounts which are missing or can't be
+    /// parsed are left empty.
+    pub fn hold(
+        &mut self,
+        command: &TransactionCommand,
+        record: &StringRecord,
+        line: u64,
+    ) -> Result<()> {
+        let amount = self
+            .amount
+            .and_then(|i| record.get(i))
+            .and_then(|field| Decimal::from_str(field.trim()).ok());
+        self.count += 1;
+        self.total += amount.unwrap_or_default();
+        self.writer.serialize(SuspenseEntry {
+            line,
+            tx: command.tx,
+            client: command.client,
+            kind: command.kind.to_string(),
+            amount,
+        })?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::AccountError;
+    use crate::testkit;
+
+    #[test]
+    fn test_hold() -> Result<()> {
+        let command = |kind| testkit::command(kind, 2, 9);
+        let unknown = anyhow::Error::from(TransactionError::Unknown { tx: 9 });
+        assert!(unmatched(&unknown, &command(TransactionKind::ChargeBack)));
+        let amount = Decimal::from(1);
+        assert!(!unmatched(
+            &unknown,
+            &command(TransactionKind::Withdrawal { amount })
+        ));
+        let funds = anyhow::Error::from(AccountError::InsufficientFunds);
+        assert!(!unmatched(&funds, &command(TransactionKind::Dispute)));
+
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let mut suspense = Suspense::new(vec![], &headers);
+        suspense.hold(
+            &command(TransactionKind::ChargeBack),
+            &StringRecord::from(vec!["chargeback", "2", "9", " 12.5"]),
+            3,
+        )?;
+        suspense.hold(
+            &command(TransactionKind::Dispute),
+            &StringRecord::from(vec!["dispute", "2", "9"]),
+            4,
+        )?;
+        assert_eq!((suspense.count, suspense.total), (2, Decimal::new(125, 1)));
+
+        let output = String::from_utf8(suspense.writer.into_inner()?)?;
+        assert_eq!(
+            output,
+            "line,tx,client,type,amount\n3,9,2,chargeback,12.5\n4,9,2,dispute,\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::num::NonZeroU8;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// MAX_LEN is the maximum length of a tenant ID in bytes
+pub const MAX_LEN: usize = 32;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TenantIdError {
+    #[error("tenant id cannot be empty")]
+    Empty,
+    #[error("tenant id is longer than {MAX_LEN} bytes")]
+    TooLong,
+    #[error("tenant id can only contain ASCII letters, digits, '-' and '_', found {0:?}")]
+    InvalidCharacter(char),
+}
+
+/// TenantId identifies a program whose clients are kept isolated from every other program's,
+/// so that programs can be processed by the same deployment without their client IDs colliding.
+/// It's stored inline rather than on the heap, so that transactions remain `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TenantId {
+    len: NonZeroU8,
+    bytes: [u8; MAX_LEN],
+}
+
+impl TenantId {
+    pub fn as_str(&self) -> &str {
+        // only ever constructed from a valid str
+        std::str::from_utf8(&self.bytes[..self.len.get() as usize]).unwrap_or_default()
+    }
+}
+
+impl FromStr for TenantId {
+    type Err = TenantIdError;
+
+    fn from_str(s: &str) -> Result<TenantId, TenantIdError> {
+        if s.len() > MAX_LEN {
+            return Err(TenantIdError::TooLong);
+        }
+        // tenant IDs name database trees, so they're kept to characters which are safe anywhere
+        if let Some(c) = s
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && *c != '-' && *c != '_')
+        {
+            return Err(TenantIdError::InvalidCharacter(c));
+        }
+        let len = NonZeroU8::new(s.len() as u8).ok_or(TenantIdError::Empty)?;
+        let mut bytes = [0; MAX_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(TenantId { len, bytes })
+    }
+}
+
+impl TryFrom<String> for TenantId {
+    type Error = TenantIdError;
+
+    fn try_from(s: String) -> Result<TenantId, TenantIdError> {
+        s.parse()
+    }
+}
+
+impl From<TenantId> for String {
+    fn from(id: TenantId) -> String {
+        id.as_str().to_string()
+    }
+}
+
+impl fmt::Display for TenantId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for TenantId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// tree_name is the name of the database tree holding a tenant's records, e.g.
+/// `tenant/acme/accounts`. Records which don't belong to any tenant are kept in the unprefixed
+/// trees, so databases created before tenants were introduced are read as before.
+pub fn tree_name(tenant: Option<TenantId>, name: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("tenant/{}/{}", tenant, name),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_tenant_id() -> Result<()> {
+        let id: TenantId = "acme-rewards_2".parse()?;
+        assert_eq!(id.as_str(), "acme-rewards_2");
+        assert_eq!("".parse::<TenantId>(), Err(TenantIdError::Empty));
+        assert_eq!(
+            "x".repeat(MAX_LEN + 1).parse::<TenantId>(),
+            Err(TenantIdError::TooLong)
+        );
+        assert_eq!(
+            "acme/other".parse::<TenantId>(),
+            Err(TenantIdError::InvalidCharacter('/'))
+        );
+
+        let json = serde_json::to_string(&id)?;
+        assert_eq!(json, "\"acme-rewards_2\"");
+        assert_eq!(serde_json::from_str::<TenantId>(&json)?, id);
+
+        assert_eq!(
+            tree_name(Some(id), "accounts"),
+            "tenant/acme-rewards_2/accounts"
+        );
+        assert_eq!(tree_name(None, "accounts"), "accounts");
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+
+use crate::accounts::{Account, AccountKind, AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+use crate::events::MemoryRepo as EventsMemoryRepo;
+use crate::fuzz;
+use crate::payments::PaymentsEngine;
+use crate::rules::Flag;
+use crate::summary::Summary;
+use crate::transactions::{
+    MemoryRepo as TransactionsMemoryRepo, Transaction, TransactionCommand, TransactionKind,
+    TransactionsRepo,
+};
+
+/// TestKit owns the in-memory repositories of an engine under test, so that services embedding
+/// the library can write integration tests without setting the repositories up themselves
+#[derive(Default)]
+pub struct TestKit {
+    pub transactions: TransactionsMemoryRepo,
+    pub accounts: AccountsMemoryRepo,
+    pub events: EventsMemoryRepo,
+}
+
+impl TestKit {
+    pub fn new() -> TestKit {
+        TestKit::default()
+    }
+
+    /// with_account seeds an unlocked account with the given balances
+    pub fn with_account(self, client: u64, available: &str, held: &str) -> Result<TestKit> {
+        self.accounts.save(Account::from_balances(
+            client,
+            amount(available),
+            amount(held),
+            false,
+            None,
+            AccountKind::Standard,
+        ))?;
+        Ok(self)
+    }
+
+    /// with_deposit seeds a deposit, e.g. to dispute it, without crediting its account (seed the
+    /// account's balances with `with_account`)
+    pub fn with_deposit(self, client: u64, tx: u64, value: &str) -> Result<TestKit> {
+        self.transactions
+            .save(Transaction::try_from(deposit(client, tx, value))?)?;
+        Ok(self)
+    }
+
+    /// engine is an engine backed by the kit's repositories, which can be configured further
+    /// (e.g. `with_rules`) before submitting transactions to it directly
+    pub fn engine(&self) -> PaymentsEngine<'_, '_, '_> {
+        PaymentsEngine::new(&self.transactions, &self.accounts, &self.events)
+    }
+
+    /// submit processes a sequence of commands in order, returning the result of each
+    pub fn submit(&self, commands: &[TransactionCommand]) -> Vec<Result<Vec<Flag>>> {
+        let engine = self.engine();
+        commands
+            .iter()
+            .map(|&command| engine.process_transaction(command))
+            .collect()
+    }
+
+    /// submit_csv processes a transactions file, e.g. one of the files of a regression scenario,
+    /// counting rejected rows by error code in the summary
+    pub fn submit_csv(&self, input: &str) -> Summary {
+        fuzz::process_bytes(input.as_bytes(), &self.engine())
+    }
+
+    /// account is a client's account, if it exists
+    pub fn account(&self, client: u64) -> Result<Option<Account>> {
+        self.accounts.get(client)
+    }
+
+    /// assert_balances panics unless a client's account exists with the given balances
+    pub fn assert_balances(&self, client: u64, available: &str, held: &str) {
+        let account = self.expect_account(client);
+        assert_eq!(
+            (account.available(), account.held()),
+            (amount(available), amount(held)),
+            "(available, held) balances of client {}",
+            client
+        );
+    }
+
+    /// assert_locked panics unless a client's account exists and is locked (or unlocked)
+    pub fn assert_locked(&self, client: u64, locked: bool) {
+        let account = self.expect_account(client);
+        assert_eq!(
+            account.is_locked(),
+            locked,
+            "locked status of client {}",
+            client
+        );
+    }
+
+    fn expect_account(&self, client: u64) -> Account {
+        match self.account(client) {
+            Ok(Some(account)) => account,
+            Ok(None) => panic!("client {} has no account", client),
+            Err(e) => panic!("unable to get the account of client {}: {:#}", client, e),
+        }
+    }
+}
+
+/// amount parses a decimal amount, panicking if it's invalid
+pub fn amount(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap_or_else(|e| panic!("invalid amount {:?}: {}", value, e))
+}
+
+/// command is a command from a client with no correlation ID, tenant or timestamp
+pub fn command(kind: TransactionKind, client: u64, tx: u64) -> TransactionCommand {
+    TransactionCommand {
+        kind,
+        tx,
+        client,
+        correlation: None,
+        tenant: None,
+        timestamp: None,
+    }
+}
+
+/// deposit is a command depositing an amount into a client's account
+pub fn deposit(client: u64, tx: u64, value: &str) -> TransactionCommand {
+    let amount = amount(value);
+    command(TransactionKind::Deposit { amount }, client, tx)
+}
+
+/// withdrawal is a command withdrawing an amount from a client's account
+pub fn withdrawal(client: u64, tx: u64, value: &str) -> TransactionCommand {
+    let amount = amount(value);
+    command(TransactionKind::Withdrawal { amount }, client, tx)
+}
+
+/// dispute is a command disputing a client's earlier transaction
+pub fn dispute(client: u64, tx: u64) -> TransactionCommand {
+    command(TransactionKind::Dispute, client, tx)
+}
+
+/// resolve is a command resolving a client's disputed transaction
+pub fn resolve(client: u64, tx: u64) -> TransactionCommand {
+    command(TransactionKind::Resolve, client, tx)
+}
+
+/// chargeback is a command charging back a client's disputed transaction
+pub fn chargeback(client: u64, tx: u64) -> TransactionCommand {
+    command(TransactionKind::ChargeBack, client, tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::AccountError;
+    use crate::events::EventsRepo;
+
+    #[test]
+    fn test_testkit() -> Result<()> {
+        let kit = TestKit::new()
+            .with_account(1, "5", "0")?
+            .with_deposit(1, 1, "5")?;
+        let results = kit.submit(&[
+            dispute(1, 1),
+            withdrawal(1, 2, "1"),
+            chargeback(1, 1),
+            deposit(2, 3, "1.50"),
+        ]);
+        assert!(results[0].is_ok() && results[2].is_ok() && results[3].is_ok());
+        assert_eq!(
+            results[1].as_ref().unwrap_err().downcast_ref(),
+            Some(&AccountError::InsufficientFunds)
+        );
+        kit.assert_balances(1, "0", "0");
+        kit.assert_locked(1, true);
+        kit.assert_balances(2, "1.5", "0");
+        assert_eq!(kit.events.get_all()?.len(), 3);
+
+        let summary = kit.submit_csv("type,client,tx,amount\ndeposit,3,4,2\nresolve,3,4,\n");
+        assert_eq!((summary.accepted, summary.rows), (1, 2));
+        kit.assert_balances(3, "2", "0");
+        assert!(kit.account(4)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "(available, held) balances of client 1")]
+    fn test_assert_balances() {
+        let kit = TestKit::new();
+        kit.submit(&[deposit(1, 1, "1")]);
+        kit.assert_balances(1, "2", "0");
+    }
+}
@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ThresholdError {
+    #[error("rejected {rejected} rows, more than the maximum of {max}")]
+    TooManyErrors { rejected: u64, max: u64 },
+    #[error("rejected {rejected} of {total} rows, more than the maximum error rate of {max}")]
+    ErrorRateExceeded {
+        rejected: u64,
+        total: u64,
+        max: ErrorRate,
+    },
+}
+
+/// ErrorRate is a fraction of rows, written either as a percentage (`0.5%`) or a ratio (`0.005`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorRate(f64);
+
+impl FromStr for ErrorRate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<ErrorRate> {
+        let s = s.trim();
+        let rate = match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>()? / 100.0,
+            None => s.parse::<f64>()?,
+        };
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(anyhow!("error rate must be between 0% and 100%, got {}", s));
+        }
+        Ok(ErrorRate(rate))
+    }
+}
+
+impl std::fmt::Display for ErrorRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}%", self.0 * 100.0)
+    }
+}
+
+/// ErrorThreshold fails a run which rejects too many rows. The absolute limit is checked as rows
+/// are rejected, so the run aborts as soon as it's exceeded, whereas the rate can only be
+/// meaningfully checked once every row has been seen.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ErrorThreshold {
+    pub max_errors: Option<u64>,
+    pub max_rate: Option<ErrorRate>,
+}
+
+impl ErrorThreshold {
+    /// check_count fails if more than the maximum number of rows have been rejected
+    pub fn check_count(&self, rejected: u64) -> Result<(), ThresholdError> {
+        match self.max_errors {
+            Some(max) if rejected > max => Err(ThresholdError::TooManyErrors { rejected, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// check_rate fails if the proportion of rejected rows exceeds the maximum error rate
+    pub fn check_rate(&self, rejected: u64, total: u64) -> Result<(), ThresholdError> {
+        match self.max_rate {
+            Some(max) if total > 0 && rejected as f64 / total as f64 > max.0 => {
+                Err(ThresholdError::ErrorRateExceeded {
+                    rejected,
+                    total,
+                    max,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_rate() -> Result<()> {
+        assert_eq!("0.5%".parse::<ErrorRate>()?, ErrorRate(0.005));
+        assert_eq!("0.25".parse::<ErrorRate>()?, ErrorRate(0.25));
+        assert!("150%".parse::<ErrorRate>().is_err());
+        assert!("lots".parse::<ErrorRate>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_threshold() -> Result<()> {
+        let threshold = ErrorThreshold {
+            max_errors: Some(2),
+            max_rate: Some("10%".parse()?),
+        };
+        assert!(threshold.check_count(2).is_ok());
+        assert_eq!(
+            threshold.check_count(3),
+            Err(ThresholdError::TooManyErrors {
+                rejected: 3,
+                max: 2
+            })
+        );
+        assert!(threshold.check_rate(1, 10).is_ok());
+        assert!(threshold.check_rate(2, 10).is_err());
+        assert!(ErrorThreshold::default().check_rate(10, 10).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::str::FromStr;
+
+use anyhow::bail;
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+
+use crate::input::{Row, RowError};
+use crate::rules::Flag;
+use crate::transactions::TransactionCommand;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TimestampError {
+    #[error("client {client}'s row at {timestamp} is earlier than their row at {latest}")]
+    OutOfOrder {
+        client: u64,
+        timestamp: DateTime<Utc>,
+        latest: DateTime<Utc>,
+    },
+}
+
+impl TimestampError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            TimestampError::OutOfOrder { .. } => "out_of_order",
+        }
+    }
+}
+
+/// OutOfOrder is the policy for rows which arrive later than rows which happened after them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutOfOrder {
+    /// Reject them
+    #[default]
+    Reject,
+    /// Accept them, but flag them for review
+    Flag,
+    /// Buffer rows for a window of time, applying them in the order they happened. Rows which
+    /// arrive later than the window are rejected.
+    Reorder,
+}
+
+impl FromStr for OutOfOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<OutOfOrder> {
+        match s {
+            "reject" => Ok(OutOfOrder::Reject),
+            "flag" => Ok(OutOfOrder::Flag),
+            "reorder" => Ok(OutOfOrder::Reorder),
+            _ => bail!("expected reject, flag or reorder, got {:?}", s),
+        }
+    }
+}
+
+/// TimestampPolicy is how rows which are out of order are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampPolicy {
+    /// How much earlier than the latest of their client's rows a row may be without being out of
+    /// order, e.g. to allow for clock skew between the systems the rows come from
+    pub tolerance: Duration,
+    pub out_of_order: OutOfOrder,
+    /// How long rows are buffered for when reordering them
+    pub window: Duration,
+}
+
+impl TimestampPolicy {
+    /// reorder_window is how long rows should be buffered for to be reordered, if they should be
+    pub fn reorder_window(&self) -> Option<Duration> {
+        (self.out_of_order == OutOfOrder::Reorder).then_some(self.window)
+    }
+}
+
+/// OUT_OF_ORDER_RULE is the rule which out of order rows are flagged by
+pub const OUT_OF_ORDER_RULE: &str = "out_of_order";
+
+/// TimestampOrder checks that each client's rows are in the order they happened, within the
+/// policy's tolerance. Rows without a timestamp aren't checked.
+pub struct TimestampOrder {
+    policy: TimestampPolicy,
+    latest: RefCell<HashMap<u64, DateTime<Utc>>>,
+}
+
+impl TimestampOrder {
+    pub fn new(policy: TimestampPolicy) -> TimestampOrder {
+        TimestampOrder {
+            policy,
+            latest: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// reorder_window is how long rows should be buffered for to be reordered, if they should be
+    pub fn reorder_window(&self) -> Option<Duration> {
+        self.policy.reorder_window()
+    }
+
+    /// check fails if the row is further out of order than the tolerance allows, unless the
+    /// policy is to flag such rows, in which case it returns the flag
+    pub fn check(&self, command: &TransactionCommand) -> Result<Option<Flag>, TimestampError> {
+        let timestamp = match command.timestamp {
+            Some(timestamp) => timestamp,
+            None => return Ok(None),
+        };
+        let latest = match self.latest.borrow().get(&command.client) {
+            Some(latest) if timestamp + self.policy.tolerance < *latest => *latest,
+            _ => return Ok(None),
+        };
+        match self.policy.out_of_order {
+            OutOfOrder::Flag => Ok(Some(Flag {
+                rule: OUT_OF_ORDER_RULE.to_string(),
+                tx: command.tx,
+                client: command.client,
+            })),
+            OutOfOrder::Reject | OutOfOrder::Reorder => Err(TimestampError::OutOfOrder {
+                client: command.client,
+                timestamp,
+                latest,
+            }),
+        }
+    }
+
+    /// record notes the timestamp of an applied row
+    pub fn record(&self, command: &TransactionCommand) {
+        if let Some(timestamp) = command.timestamp {
+            let mut latest = self.latest.borrow_mut();
+            let latest = latest.entry(command.client).or_insert(timestamp);
+            *latest = (*latest).max(timestamp);
+        }
+    }
+}
+
+/// Buffered is a row held by [Reorder], ordered by when it happened & then by when it was read
+struct Buffered {
+    timestamp: DateTime<Utc>,
+    seq: u64,
+    row: Row,
+}
+
+impl PartialEq for Buffered {
+    fn eq(&self, other: &Buffered) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Buffered {}
+
+impl PartialOrd for Buffered {
+    fn partial_cmp(&self, other: &Buffered) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Buffered {
+    fn cmp(&self, other: &Buffered) -> Ordering {
+        (self.timestamp, self.seq).cmp(&(other.timestamp, other.seq))
+    }
+}
+
+/// Reorder is an iterator which holds back rows with timestamps until a row at least `window`
+/// later has been read, then yields them in the order they happened. Rows without a timestamp,
+/// and rows which can't be read, are yielded straight away.
+pub struct Reorder<I> {
+    inner: I,
+    window: Duration,
+    buffer: BinaryHeap<Reverse<Buffered>>,
+    latest: Option<DateTime<Utc>>,
+    seq: u64,
+    done: bool,
+}
+
+impl<I> Reorder<I> {
+    pub fn new(inner: I, window: Duration) -> Reorder<I> {
+        Reorder {
+            inner,
+            window,
+            buffer: BinaryHeap::new(),
+            latest: None,
+            seq: 0,
+            done: false,
+        }
+    }
+
+    /// ready pops the earliest buffered row, if the window has passed since it happened
+    fn ready(&mut self) -> Option<Row> {
+        let Reverse(earliest) = self.buffer.peek()?;
+        if !self.done && self.latest? - earliest.timestamp < self.window {
+            return None;
+        }
+        self.buffer.pop().map(|Reverse(buffered)| buffered.row)
+    }
+}
+
+impl<I: Iterator<Item = Result<Row, RowError>>> Iterator for Reorder<I> {
+    type Item = Result<Row, RowError>;
+
+    fn next(&mut self) -> Option<Result<Row, RowError>> {
+        loop {
+            if let Some(row) = self.ready() {
+                return Some(Ok(row));
+            }
+            if self.done {
+                return None;
+            }
+            let row = match self.inner.next() {
+                Some(Ok(row)) => row,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.done = true;
+                    continue;
+                }
+            };
+            let timestamp = match row.command.timestamp {
+                Some(timestamp) => timestamp,
+                None => return Some(Ok(row)),
+            };
+            self.latest = self.latest.max(Some(timestamp));
+            self.seq += 1;
+            self.buffer.push(Reverse(Buffered {
+                timestamp,
+                seq: self.seq,
+                row,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Position;
+    use crate::transactions::TransactionKind;
+
+    fn command(tx: u64, client: u64, timestamp: Option<&str>) -> TransactionCommand {
+        TransactionCommand {
+            kind: TransactionKind::Dispute,
+            tx,
+            client,
+            correlation: None,
+            tenant: None,
+            timestamp: timestamp.map(|t| t.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_timestamp_order() {
+        let policy = |seconds, out_of_order| TimestampPolicy {
+            tolerance: Duration::seconds(seconds),
+            out_of_order,
+            window: Duration::zero(),
+        };
+        let order = TimestampOrder::new(policy(5, OutOfOrder::Reject));
+        let check = |client, timestamp| {
+            let command = command(1, client, Some(timestamp));
+            let flag = order.check(&command)?;
+            order.record(&command);
+            Ok::<_, TimestampError>(flag)
+        };
+
+        assert_eq!(check(1, "2024-01-01T10:00:00Z"), Ok(None));
+        assert_eq!(check(1, "2024-01-01T10:01:00Z"), Ok(None));
+        // within the tolerance
+        assert_eq!(check(1, "2024-01-01T10:00:55Z"), Ok(None));
+        assert_eq!(
+            check(1, "2024-01-01T10:00:54Z"),
+            Err(TimestampError::OutOfOrder {
+                client: 1,
+                timestamp: "2024-01-01T10:00:54Z".parse().unwrap(),
+                latest: "2024-01-01T10:01:00Z".parse().unwrap(),
+            })
+        );
+        // each client is ordered separately, and rows without timestamps aren't checked
+        assert_eq!(check(2, "2024-01-01T09:00:00Z"), Ok(None));
+        assert_eq!(order.check(&command(1, 1, None)), Ok(None));
+
+        let order = TimestampOrder::new(policy(0, OutOfOrder::Flag));
+        order.record(&command(1, 1, Some("2024-01-01T10:00:00Z")));
+        assert_eq!(
+            order.check(&command(2, 1, Some("2024-01-01T09:00:00Z"))),
+            Ok(Some(Flag {
+                rule: OUT_OF_ORDER_RULE.to_string(),
+                tx: 2,
+                client: 1,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_reorder() {
+        let row = |tx, timestamp| {
+            Ok(Row {
+                command: command(tx, 1, timestamp),
+                position: Position::default(),
+                record: csv::StringRecord::new(),
+            })
+        };
+        let rows = vec![
+            row(1, Some("2024-01-01T10:00:10Z")),
+            row(2, Some("2024-01-01T10:00:00Z")),
+            row(3, None),
+            row(4, Some("2024-01-01T10:01:00Z")),
+            // later than the window, so can't be reordered before 1 & 2
+            row(5, Some("2024-01-01T09:59:00Z")),
+            row(6, Some("2024-01-01T10:00:50Z")),
+        ];
+        let txs: Vec<u64> = Reorder::new(rows.into_iter(), Duration::seconds(30))
+            .map(|row| row.unwrap().command.tx)
+            .collect();
+        assert_eq!(txs, vec![3, 2, 1, 5, 6, 4]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Stage is a part of the pipeline which time is spent in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading & parsing rows of the input
+    Parse,
+    /// Getting transactions & accounts from the repositories
+    Lookup,
+    /// Applying transactions to transactions & accounts
+    Apply,
+    /// Saving transactions, accounts & events to the repositories
+    Save,
+    /// Writing the account statements
+    Output,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Parse,
+        Stage::Lookup,
+        Stage::Apply,
+        Stage::Save,
+        Stage::Output,
+    ];
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Parse => "parsing",
+            Stage::Lookup => "repo lookups",
+            Stage::Apply => "applying",
+            Stage::Save => "repo writes",
+            Stage::Output => "output",
+        };
+        f.pad(name)
+    }
+}
+
+/// Timings accumulates the time spent in each stage. It's shared between threads, so the total
+/// across every stage can exceed the elapsed time of the run.
+#[derive(Debug, Default)]
+pub struct Timings {
+    nanos: [AtomicU64; Stage::ALL.len()],
+}
+
+impl Timings {
+    pub fn add(&self, stage: Stage, elapsed: Duration) {
+        self.nanos[stage as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// time runs `f`, adding the time it took to the stage
+    pub fn time<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.add(stage, start.elapsed());
+        res
+    }
+
+    pub fn get(&self, stage: Stage) -> Duration {
+        Duration::from_nanos(self.nanos[stage as usize].load(Ordering::Relaxed))
+    }
+
+    /// stages returns the time spent in every stage, in pipeline order
+    pub fn stages(&self) -> Vec<(Stage, Duration)> {
+        Stage::ALL.iter().map(|s| (*s, self.get(*s))).collect()
+    }
+}
+
+/// Timed is an iterator which adds the time spent getting each item to a stage
+pub struct Timed<I> {
+    inner: I,
+    timings: Arc<Timings>,
+    stage: Stage,
+}
+
+impl<I> Timed<I> {
+    pub fn new(inner: I, timings: Arc<Timings>, stage: Stage) -> Timed<I> {
+        Timed {
+            inner,
+            timings,
+            stage,
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for Timed<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let inner = &mut self.inner;
+        self.timings.time(self.stage, || inner.next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let timings = Arc::new(Timings::default());
+        timings.add(Stage::Save, Duration::from_millis(3));
+        timings.add(Stage::Save, Duration::from_millis(2));
+        assert_eq!(timings.time(Stage::Apply, || 1 + 1), 2);
+
+        let rows: Vec<u32> = Timed::new(0..3, timings.clone(), Stage::Parse).collect();
+        assert_eq!(rows, vec![0, 1, 2]);
+
+        assert_eq!(timings.get(Stage::Save), Duration::from_millis(5));
+        assert_eq!(timings.get(Stage::Output), Duration::ZERO);
+        let stages: Vec<Stage> = timings.stages().into_iter().map(|(s, _)| s).collect();
+        assert_eq!(stages, Stage::ALL.to_vec());
+    }
+}
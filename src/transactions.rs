@@ -1,12 +1,21 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::fmt;
+use std::hash::BuildHasher;
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use rust_decimal::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::correlation::CorrelationId;
+use crate::encryption::{self, Cipher};
+use crate::ownership::Ownership;
+use crate::tenant::{self, TenantId};
+
 #[derive(Error, Debug, Clone, Copy, PartialEq)]
 pub enum TransactionError {
     #[error("unable to move transaction from {from:?} to {to:?}")]
@@ -15,15 +24,40 @@ pub enum TransactionError {
         to: TransactionKind,
     },
     #[error("unable to apply transaction belonging to a different client: expected {expected:?} got {got:?}")]
-    UnexpectedClient { expected: u16, got: u16 },
+    UnexpectedClient { expected: u64, got: u64 },
     #[error(
         "unable to apply transaction with mismatching tx id: expected {expected:?} got {got:?}"
     )]
-    UnexpectedTx { expected: u32, got: u32 },
+    UnexpectedTx { expected: u64, got: u64 },
+    #[error("unable to apply transaction belonging to a different tenant: expected {expected:?} got {got:?}")]
+    UnexpectedTenant {
+        expected: Option<TenantId>,
+        got: Option<TenantId>,
+    },
     #[error("amount cannot be less than zero")]
     NegativeAmount,
     #[error("transaction state must begin with deposit or withdrawal")]
     InvalidInitialState,
+    #[error("transaction {tx} already exists")]
+    Duplicate { tx: u64 },
+    #[error("transaction {tx} not found")]
+    Unknown { tx: u64 },
+}
+
+impl TransactionError {
+    /// code is a stable identifier for the kind of error, for use in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::InvalidState { .. } => "invalid_state",
+            TransactionError::UnexpectedClient { .. } => "unexpected_client",
+            TransactionError::UnexpectedTx { .. } => "unexpected_tx",
+            TransactionError::UnexpectedTenant { .. } => "unexpected_tenant",
+            TransactionError::NegativeAmount => "negative_amount",
+            TransactionError::InvalidInitialState => "invalid_initial_state",
+            TransactionError::Duplicate { .. } => "duplicate_tx",
+            TransactionError::Unknown { .. } => "unknown_tx",
+        }
+    }
 }
 
 /// TransactionCommand represents the minimum fields required for a transaction to be processed.
@@ -33,14 +67,48 @@ pub enum TransactionError {
 pub struct TransactionCommand {
     #[serde(flatten)]
     pub kind: TransactionKind,
-    pub tx: u32,
-    pub client: u16,
+    pub tx: u64,
+    pub client: u64,
+    /// Read from the optional `correlation_id` column, rather than deserialized
+    #[serde(skip)]
+    pub correlation: Option<CorrelationId>,
+    /// Read from the optional `tenant` column, rather than deserialized
+    #[serde(skip)]
+    pub tenant: Option<TenantId>,
+    /// Read from the optional `timestamp` column, rather than deserialized
+    #[serde(skip)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl TransactionCommand {
+    /// for_tenant attributes the command to the tenant being processed, unless it names a
+    /// different tenant
+    pub fn for_tenant(
+        self,
+        tenant: Option<TenantId>,
+    ) -> Result<TransactionCommand, TransactionError> {
+        match self.tenant {
+            None => Ok(TransactionCommand { tenant, ..self }),
+            got if got == tenant => Ok(self),
+            got => Err(TransactionError::UnexpectedTenant {
+                expected: tenant,
+                got,
+            }),
+        }
+    }
 }
 
 impl TryFrom<TransactionCommand> for Transaction {
     type Error = TransactionError;
     fn try_from(
-        TransactionCommand { kind, tx, client }: TransactionCommand,
+        TransactionCommand {
+            kind,
+            tx,
+            client,
+            correlation,
+            tenant,
+            timestamp,
+        }: TransactionCommand,
     ) -> Result<Transaction, Self::Error> {
         match kind {
             TransactionKind::Deposit { amount } => {
@@ -52,9 +120,15 @@ impl TryFrom<TransactionCommand> for Transaction {
                     amount,
                     kind,
                     client,
+                    correlation,
+                    tenant,
+                    timestamp,
                 })
             }
-            TransactionKind::Withdrawal { amount } => {
+            TransactionKind::Withdrawal { amount }
+            | TransactionKind::Payout { amount, .. }
+            | TransactionKind::PendingDeposit { amount }
+            | TransactionKind::PendingWithdrawal { amount } => {
                 if amount < Decimal::from(0) {
                     return Err(TransactionError::NegativeAmount);
                 }
@@ -63,6 +137,9 @@ impl TryFrom<TransactionCommand> for Transaction {
                     amount,
                     kind,
                     client,
+                    correlation,
+                    tenant,
+                    timestamp,
                 })
             }
             _ => Err(TransactionError::InvalidInitialState),
@@ -72,31 +149,199 @@ impl TryFrom<TransactionCommand> for Transaction {
 
 /// TransactionKind represents the type of a transaction, including any specific fields that may
 /// relate to that particular transaction type.
-#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+///
+/// Adjustments, locks, unlocks & forced resolves are admin actions. They're never accepted as
+/// input, but are recorded in the event log alongside ordinary transactions so that replaying the
+/// log reproduces their effect.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase", tag = "type")]
 pub enum TransactionKind {
-    Deposit { amount: Decimal },
-    Withdrawal { amount: Decimal },
+    Deposit {
+        amount: Decimal,
+    },
+    Withdrawal {
+        amount: Decimal,
+    },
+    /// A withdrawal by bank transfer to a registered beneficiary
+    Payout {
+        amount: Decimal,
+        beneficiary: u64,
+    },
     Dispute,
     Resolve,
     ChargeBack,
+    /// Credits (or debits, if negative) the available balance
+    Adjustment {
+        amount: Decimal,
+    },
+    Lock,
+    Unlock,
+    /// Resolves a dispute, even if the account has since been locked
+    #[serde(rename = "force_resolve")]
+    ForceResolve,
+    /// A deposit into an escrow account, which is held until it's released or refunded. Never
+    /// accepted as input, deposits into escrow accounts are recorded as escrows by the engine.
+    Escrow {
+        amount: Decimal,
+    },
+    /// Releases an escrowed deposit, making it available to the account
+    Release,
+    /// Refunds an escrowed deposit, returning it to the payer
+    Refund,
+    /// A deposit which is held until it settles, e.g. an ACH transfer which takes days to clear.
+    /// Read from deposit rows whose status is pending.
+    #[serde(rename = "pending_deposit")]
+    PendingDeposit {
+        amount: Decimal,
+    },
+    /// A withdrawal whose funds are held until it settles. Read from withdrawal rows whose status
+    /// is pending.
+    #[serde(rename = "pending_withdrawal")]
+    PendingWithdrawal {
+        amount: Decimal,
+    },
+    /// Settles a pending deposit, making it available, or a pending withdrawal, paying it out
+    Settle {
+        /// Whether the settled transaction is a withdrawal, which is taken from its pending state
+        #[serde(default, skip_serializing_if = "is_false")]
+        withdrawal: bool,
+    },
+    /// Fails a pending deposit, dropping it, or a pending withdrawal, returning its funds
+    Fail {
+        /// Whether the failed transaction is a withdrawal, which is taken from its pending state
+        #[serde(default, skip_serializing_if = "is_false")]
+        withdrawal: bool,
+    },
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+impl TransactionKind {
+    /// is_initial is whether the kind begins a new transaction, rather than moving an existing
+    /// one on to another state
+    pub fn is_initial(&self) -> bool {
+        matches!(
+            self,
+            TransactionKind::Deposit { .. }
+                | TransactionKind::Withdrawal { .. }
+                | TransactionKind::Payout { .. }
+                | TransactionKind::PendingDeposit { .. }
+                | TransactionKind::PendingWithdrawal { .. }
+        )
+    }
+
+    /// is_settled is whether a transaction in this state no longer holds funds, so nothing more
+    /// can happen to it except (for a deposit or withdrawal) a dispute. Disputed, escrowed &
+    /// pending transactions are unsettled until they're resolved, released, settled etc.
+    pub fn is_settled(&self) -> bool {
+        !matches!(
+            self,
+            TransactionKind::Dispute
+                | TransactionKind::Escrow { .. }
+                | TransactionKind::PendingDeposit { .. }
+                | TransactionKind::PendingWithdrawal { .. }
+        )
+    }
+
+    /// is_admin is whether the kind is an admin action, which applies even to locked accounts
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionKind::Adjustment { .. }
+                | TransactionKind::Lock
+                | TransactionKind::Unlock
+                | TransactionKind::ForceResolve
+        )
+    }
+
+    /// canonical_name maps a loosely written transaction type (any casing, `_`/`-` separators or
+    /// a known alias such as `withdraw`) to the type name expected when deserializing
+    pub fn canonical_name(name: &str) -> Option<&'static str> {
+        let normalized: String = name
+            .chars()
+            .filter(|c| !matches!(c, '_' | '-' | ' '))
+            .collect::<String>()
+            .to_lowercase();
+        match normalized.as_str() {
+            "deposit" => Some("deposit"),
+            "withdrawal" | "withdraw" => Some("withdrawal"),
+            "payout" => Some("payout"),
+            "dispute" => Some("dispute"),
+            "resolve" => Some("resolve"),
+            "chargeback" => Some("chargeback"),
+            "release" => Some("release"),
+            "refund" => Some("refund"),
+            "settle" => Some("settle"),
+            "fail" => Some("fail"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionKind::Deposit { .. } => "deposit",
+            TransactionKind::Withdrawal { .. } => "withdrawal",
+            TransactionKind::Payout { .. } => "payout",
+            TransactionKind::Dispute => "dispute",
+            TransactionKind::Resolve => "resolve",
+            TransactionKind::ChargeBack => "chargeback",
+            TransactionKind::Adjustment { .. } => "adjustment",
+            TransactionKind::Lock => "lock",
+            TransactionKind::Unlock => "unlock",
+            TransactionKind::ForceResolve => "force_resolve",
+            TransactionKind::Escrow { .. } => "escrow",
+            TransactionKind::Release => "release",
+            TransactionKind::Refund => "refund",
+            TransactionKind::PendingDeposit { .. } => "pending_deposit",
+            TransactionKind::PendingWithdrawal { .. } => "pending_withdrawal",
+            TransactionKind::Settle { .. } => "settle",
+            TransactionKind::Fail { .. } => "fail",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 /// Transaction represents a valid, processed transaction event. A transaction always has a valid amount.
 /// For advanced transactions (disputes, resolves, chargebacks), the amount is taken from the
 /// transaction which the advanced transaction acts upon.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Transaction {
-    pub tx: u32,
+    pub tx: u64,
     pub amount: Decimal,
     pub kind: TransactionKind,
-    pub client: u16,
+    /// The ID of the account the transaction belongs to, which for a jointly owned account may
+    /// differ from the client which submitted it
+    pub client: u64,
+    /// The correlation ID of the row which last changed the transaction's state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation: Option<CorrelationId>,
+    /// The tenant the transaction belongs to, if it was processed for one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    /// When the row which last changed the transaction's state happened, if the input has
+    /// timestamps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
 }
 
 impl Transaction {
+    /// apply moves the transaction on to the state given by a command, which must come from one
+    /// of the owners of the account the transaction belongs to
     pub fn apply(
         &self,
-        TransactionCommand { client, kind, tx }: TransactionCommand,
+        TransactionCommand {
+            client,
+            kind,
+            tx,
+            correlation,
+            tenant,
+            timestamp,
+        }: TransactionCommand,
+        ownership: &Ownership,
     ) -> Result<Transaction, TransactionError> {
         if self.tx != tx {
             return Err(TransactionError::UnexpectedTx {
@@ -104,12 +349,18 @@ impl Transaction {
                 got: tx,
             });
         }
-        if self.client != client {
+        if !ownership.owns(client, self.client) {
             return Err(TransactionError::UnexpectedClient {
                 expected: self.client,
                 got: client,
             });
         }
+        if self.tenant != tenant {
+            return Err(TransactionError::UnexpectedTenant {
+                expected: self.tenant,
+                got: tenant,
+            });
+        }
         // using enums to match only the valid state transitions for a transaction
         match (self.kind, kind) {
             (TransactionKind::Deposit { amount }, TransactionKind::Dispute)
@@ -119,6 +370,9 @@ impl Transaction {
                     client: self.client,
                     amount,
                     kind,
+                    correlation,
+                    tenant,
+                    timestamp,
                 })
             }
             (TransactionKind::Dispute, TransactionKind::Resolve) => Ok(Transaction {
@@ -126,13 +380,69 @@ impl Transaction {
                 client: self.client,
                 amount: self.amount,
                 kind,
+                correlation,
+                tenant,
+                timestamp,
             }),
             (TransactionKind::Dispute, TransactionKind::ChargeBack) => Ok(Transaction {
                 tx: self.tx,
                 client: self.client,
                 amount: self.amount,
                 kind,
+                correlation,
+                tenant,
+                timestamp,
             }),
+            (TransactionKind::Escrow { amount }, TransactionKind::Release)
+            | (TransactionKind::Escrow { amount }, TransactionKind::Refund) => Ok(Transaction {
+                tx: self.tx,
+                client: self.client,
+                amount,
+                kind,
+                correlation,
+                tenant,
+                timestamp,
+            }),
+            // settled transactions can be disputed like any other deposit or withdrawal
+            (TransactionKind::Settle { .. }, TransactionKind::Dispute) => Ok(Transaction {
+                tx: self.tx,
+                client: self.client,
+                amount: self.amount,
+                kind,
+                correlation,
+                tenant,
+                timestamp,
+            }),
+            // which way a pending transaction settles or fails depends on its direction, so it's
+            // recorded on the resulting state for replaying the event
+            (TransactionKind::PendingDeposit { amount }, TransactionKind::Settle { .. })
+            | (TransactionKind::PendingWithdrawal { amount }, TransactionKind::Settle { .. }) => {
+                Ok(Transaction {
+                    tx: self.tx,
+                    client: self.client,
+                    amount,
+                    kind: TransactionKind::Settle {
+                        withdrawal: matches!(self.kind, TransactionKind::PendingWithdrawal { .. }),
+                    },
+                    correlation,
+                    tenant,
+                    timestamp,
+                })
+            }
+            (TransactionKind::PendingDeposit { amount }, TransactionKind::Fail { .. })
+            | (TransactionKind::PendingWithdrawal { amount }, TransactionKind::Fail { .. }) => {
+                Ok(Transaction {
+                    tx: self.tx,
+                    client: self.client,
+                    amount,
+                    kind: TransactionKind::Fail {
+                        withdrawal: matches!(self.kind, TransactionKind::PendingWithdrawal { .. }),
+                    },
+                    correlation,
+                    tenant,
+                    timestamp,
+                })
+            }
             _ => Err(TransactionError::InvalidState {
                 from: self.kind,
                 to: kind,
@@ -141,8 +451,8 @@ impl Transaction {
     }
 }
 
-pub struct MemoryRepo {
-    data: RefCell<HashMap<u32, Transaction>>,
+pub struct MemoryRepo<S = RandomState> {
+    data: RefCell<HashMap<u64, Transaction, S>>,
 }
 
 impl MemoryRepo {
@@ -153,21 +463,363 @@ impl MemoryRepo {
     }
 }
 
+impl Default for MemoryRepo {
+    fn default() -> MemoryRepo {
+        MemoryRepo::new()
+    }
+}
+
+impl<S: BuildHasher> MemoryRepo<S> {
+    /// with_hasher creates a repo which hashes IDs with the given hasher, e.g. a faster one than
+    /// the default DoS-resistant SipHash when the input is trusted
+    pub fn with_hasher(hasher: S) -> MemoryRepo<S> {
+        MemoryRepo {
+            data: RefCell::new(HashMap::with_hasher(hasher)),
+        }
+    }
+}
+
 pub trait TransactionsRepo {
-    fn get(&self, id: u32) -> Result<Option<Transaction>>;
-    fn save(&self, transaction: Transaction) -> Result<u32>;
+    fn get(&self, id: u64) -> Result<Option<Transaction>>;
+    fn save(&self, transaction: Transaction) -> Result<u64>;
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>>;
+    fn get_all(&self) -> Result<Vec<Transaction>>;
+    fn remove(&self, id: u64) -> Result<Option<Transaction>>;
+    /// erased is whether a transaction with the ID was erased along with its client, so that
+    /// its ID isn't reused
+    fn erased(&self, id: u64) -> Result<bool>;
 }
 
-impl TransactionsRepo for MemoryRepo {
+impl<T: TransactionsRepo + ?Sized> TransactionsRepo for Box<T> {
+    fn get(&self, id: u64) -> Result<Option<Transaction>> {
+        (**self).get(id)
+    }
+    fn save(&self, transaction: Transaction) -> Result<u64> {
+        (**self).save(transaction)
+    }
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        (**self).get_by_client(client)
+    }
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        (**self).get_all()
+    }
+    fn remove(&self, id: u64) -> Result<Option<Transaction>> {
+        (**self).remove(id)
+    }
+    fn erased(&self, id: u64) -> Result<bool> {
+        (**self).erased(id)
+    }
+}
+
+impl<S: BuildHasher> TransactionsRepo for MemoryRepo<S> {
     /// Gets a single transaction by ID
-    fn get(&self, id: u32) -> Result<Option<Transaction>> {
+    fn get(&self, id: u64) -> Result<Option<Transaction>> {
         Ok(self.data.borrow().get(&id).cloned())
     }
     /// Upserts a transaction
-    fn save(&self, transaction: Transaction) -> Result<u32> {
+    fn save(&self, transaction: Transaction) -> Result<u64> {
         self.data.borrow_mut().insert(transaction.tx, transaction);
         Ok(transaction.tx)
     }
+    /// Gets all of a client's transactions, ordered by ID
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        let mut transactions: Vec<Transaction> = self
+            .data
+            .borrow()
+            .values()
+            .filter(|t| t.client == client)
+            .cloned()
+            .collect();
+        transactions.sort_by_key(|t| t.tx);
+        Ok(transactions)
+    }
+    /// Gets every transaction, ordered by ID
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        let mut transactions: Vec<Transaction> = self.data.borrow().values().cloned().collect();
+        transactions.sort_by_key(|t| t.tx);
+        Ok(transactions)
+    }
+    /// Removes a transaction, returning it if it existed
+    fn remove(&self, id: u64) -> Result<Option<Transaction>> {
+        Ok(self.data.borrow_mut().remove(&id))
+    }
+    /// Clients are only erased from a database, never from memory
+    fn erased(&self, _id: u64) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// MIN_DENSE_LEN is how long a DenseRepo's vector can grow regardless of how few of its slots
+/// are occupied, so that the first IDs needn't be sequential
+const MIN_DENSE_LEN: u64 = 1 << 12;
+
+/// MIN_OCCUPANCY is the fraction (1 in this many) of a DenseRepo's slots which must be occupied
+/// for its vector to grow any further, beyond MIN_DENSE_LEN
+const MIN_OCCUPANCY: u64 = 4;
+
+/// DenseRepo stores transactions in a vector indexed by ID, since IDs are usually allocated
+/// sequentially. This avoids storing the ID & hashing overhead per transaction, and makes lookups
+/// a single index. IDs which would leave too few of the vector's slots occupied are kept in a map
+/// instead, so a sparse or strided range of IDs doesn't allocate a huge, mostly empty vector.
+pub struct DenseRepo<S = RandomState> {
+    dense: RefCell<Vec<Option<Transaction>>>,
+    /// The number of occupied slots in the vector
+    occupied: Cell<u64>,
+    sparse: RefCell<HashMap<u64, Transaction, S>>,
+}
+
+impl DenseRepo {
+    pub fn new() -> DenseRepo {
+        DenseRepo::with_hasher(RandomState::new())
+    }
+}
+
+impl Default for DenseRepo {
+    fn default() -> DenseRepo {
+        DenseRepo::new()
+    }
+}
+
+impl<S: BuildHasher> DenseRepo<S> {
+    /// with_hasher creates a repo which hashes the IDs of sparse transactions with the given
+    /// hasher
+    pub fn with_hasher(hasher: S) -> DenseRepo<S> {
+        DenseRepo {
+            dense: RefCell::new(Vec::new()),
+            occupied: Cell::new(0),
+            sparse: RefCell::new(HashMap::with_hasher(hasher)),
+        }
+    }
+
+    /// is_dense is whether a transaction is (or would be) stored in the vector: if it's within
+    /// the vector, or growing the vector to fit it would still leave enough of it occupied
+    fn is_dense(&self, id: u64) -> bool {
+        if self.sparse.borrow().contains_key(&id) {
+            return false;
+        }
+        let len = self.dense.borrow().len() as u64;
+        if id < len {
+            return true;
+        }
+        let grown = id.saturating_add(1);
+        grown <= MIN_DENSE_LEN || (self.occupied.get() + 1).saturating_mul(MIN_OCCUPANCY) >= grown
+    }
+
+    /// index is the position of a transaction in the vector, if it could be stored there. IDs
+    /// are 64 bit, so may not fit in a usize on 32 bit platforms.
+    fn index(id: u64) -> Option<usize> {
+        usize::try_from(id).ok()
+    }
+}
+
+impl<S: BuildHasher> TransactionsRepo for DenseRepo<S> {
+    /// Gets a single transaction by ID
+    fn get(&self, id: u64) -> Result<Option<Transaction>> {
+        if let Some(Some(transaction)) =
+            Self::index(id).and_then(|i| self.dense.borrow().get(i).copied())
+        {
+            return Ok(Some(transaction));
+        }
+        Ok(self.sparse.borrow().get(&id).cloned())
+    }
+    /// Upserts a transaction
+    fn save(&self, transaction: Transaction) -> Result<u64> {
+        let id = transaction.tx;
+        if !self.is_dense(id) {
+            self.sparse.borrow_mut().insert(id, transaction);
+            return Ok(id);
+        }
+        // fits in a usize, since the vector is (or can be) that long
+        let index = id as usize;
+        let mut dense = self.dense.borrow_mut();
+        if dense.len() <= index {
+            dense.resize(index + 1, None);
+        }
+        if dense[index].replace(transaction).is_none() {
+            self.occupied.set(self.occupied.get() + 1);
+        }
+        Ok(id)
+    }
+    /// Gets all of a client's transactions, ordered by ID
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        Ok(self
+            .get_all()?
+            .into_iter()
+            .filter(|t| t.client == client)
+            .collect())
+    }
+    /// Gets every transaction, ordered by ID
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        let mut transactions: Vec<Transaction> =
+            self.dense.borrow().iter().flatten().cloned().collect();
+        transactions.extend(self.sparse.borrow().values().cloned());
+        transactions.sort_by_key(|t| t.tx);
+        Ok(transactions)
+    }
+    /// Removes a transaction, returning it if it existed. The vector isn't shrunk, since the IDs
+    /// after it are likely still in use.
+    fn remove(&self, id: u64) -> Result<Option<Transaction>> {
+        if let Some(index) = Self::index(id) {
+            if let Some(slot @ Some(_)) = self.dense.borrow_mut().get_mut(index) {
+                self.occupied.set(self.occupied.get() - 1);
+                return Ok(slot.take());
+            }
+        }
+        Ok(self.sparse.borrow_mut().remove(&id))
+    }
+    /// Clients are only erased from a database, never from memory
+    fn erased(&self, _id: u64) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// ERASED_IDS_TREE is the tree which the IDs of erased transactions are kept in, without
+/// anything else about them
+pub const ERASED_IDS_TREE: &str = "erased_transaction_ids";
+
+pub struct SledRepo {
+    tree: sled::Tree,
+    /// The IDs of erased transactions, keyed by ID with empty values
+    erased: sled::Tree,
+    cipher: Option<Cipher>,
+}
+
+impl SledRepo {
+    pub fn new(db: &sled::Db) -> Result<SledRepo> {
+        SledRepo::for_tenant(db, None)
+    }
+
+    /// for_tenant stores the tenant's transactions in their own tree, isolated from every other
+    /// tenant's
+    pub fn for_tenant(db: &sled::Db, tenant: Option<TenantId>) -> Result<SledRepo> {
+        Ok(SledRepo {
+            tree: db.open_tree(tenant::tree_name(tenant, "transactions"))?,
+            erased: db.open_tree(tenant::tree_name(tenant, ERASED_IDS_TREE))?,
+            cipher: None,
+        })
+    }
+
+    /// encrypted makes the repo encrypt everything it stores with `cipher`
+    pub fn encrypted(self, cipher: Cipher) -> SledRepo {
+        SledRepo {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+}
+
+impl TransactionsRepo for SledRepo {
+    /// Gets a single transaction by ID
+    fn get(&self, id: u64) -> Result<Option<Transaction>> {
+        match self.tree.get(id.to_be_bytes())? {
+            Some(bytes) => Ok(Some(encryption::decode(self.cipher.as_ref(), &bytes)?)),
+            None => Ok(None),
+        }
+    }
+    /// Upserts a transaction
+    fn save(&self, transaction: Transaction) -> Result<u64> {
+        self.tree.insert(
+            transaction.tx.to_be_bytes(),
+            encryption::encode(self.cipher.as_ref(), &transaction)?,
+        )?;
+        Ok(transaction.tx)
+    }
+    /// Gets all of a client's transactions, ordered by ID
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        let mut transactions = Vec::new();
+        for bytes in self.tree.iter().values() {
+            let transaction: Transaction = encryption::decode(self.cipher.as_ref(), &bytes?)?;
+            if transaction.client == client {
+                transactions.push(transaction);
+            }
+        }
+        Ok(transactions)
+    }
+    /// Gets every transaction, ordered by ID
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        self.tree
+            .iter()
+            .values()
+            .map(|bytes| encryption::decode(self.cipher.as_ref(), &bytes?))
+            .collect()
+    }
+    /// Removes a transaction, returning it if it existed
+    fn remove(&self, id: u64) -> Result<Option<Transaction>> {
+        match self.tree.remove(id.to_be_bytes())? {
+            Some(bytes) => Ok(Some(encryption::decode(self.cipher.as_ref(), &bytes)?)),
+            None => Ok(None),
+        }
+    }
+    fn erased(&self, id: u64) -> Result<bool> {
+        Ok(self.erased.contains_key(id.to_be_bytes())?)
+    }
+}
+
+/// OverlayRepo reads through to another repo but keeps every write in memory, leaving the
+/// underlying repo untouched
+pub struct OverlayRepo {
+    inner: Box<dyn TransactionsRepo>,
+    overlay: MemoryRepo,
+    /// The IDs of transactions removed from the underlying repo, which are hidden instead
+    removed: RefCell<HashSet<u64>>,
+}
+
+impl OverlayRepo {
+    pub fn new(inner: Box<dyn TransactionsRepo>) -> OverlayRepo {
+        OverlayRepo {
+            inner,
+            overlay: MemoryRepo::new(),
+            removed: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// merge combines the transactions in the underlying repo with those in the overlay, which
+    /// are preferred, ordered by ID
+    fn merge(&self, inner: Vec<Transaction>, overlay: Vec<Transaction>) -> Vec<Transaction> {
+        let removed = self.removed.borrow();
+        let mut transactions: HashMap<u64, Transaction> = inner
+            .into_iter()
+            .filter(|t| !removed.contains(&t.tx))
+            .map(|t| (t.tx, t))
+            .collect();
+        for t in overlay {
+            transactions.insert(t.tx, t);
+        }
+        let mut transactions: Vec<Transaction> = transactions.into_values().collect();
+        transactions.sort_by_key(|t| t.tx);
+        transactions
+    }
+}
+
+impl TransactionsRepo for OverlayRepo {
+    fn get(&self, id: u64) -> Result<Option<Transaction>> {
+        match self.overlay.get(id)? {
+            Some(transaction) => Ok(Some(transaction)),
+            None if self.removed.borrow().contains(&id) => Ok(None),
+            None => self.inner.get(id),
+        }
+    }
+    fn save(&self, transaction: Transaction) -> Result<u64> {
+        self.overlay.save(transaction)
+    }
+    fn get_by_client(&self, client: u64) -> Result<Vec<Transaction>> {
+        Ok(self.merge(
+            self.inner.get_by_client(client)?,
+            self.overlay.get_by_client(client)?,
+        ))
+    }
+    fn get_all(&self) -> Result<Vec<Transaction>> {
+        Ok(self.merge(self.inner.get_all()?, self.overlay.get_all()?))
+    }
+    fn remove(&self, id: u64) -> Result<Option<Transaction>> {
+        let transaction = self.get(id)?;
+        self.overlay.remove(id)?;
+        self.removed.borrow_mut().insert(id);
+        Ok(transaction)
+    }
+    fn erased(&self, id: u64) -> Result<bool> {
+        self.inner.erased(id)
+    }
 }
 
 #[cfg(test)]
@@ -183,14 +835,23 @@ mod tests {
             },
             client: 1,
             amount: Decimal::from(8),
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         };
 
         let tx = transaction.tx + 1;
-        let res = transaction.apply(TransactionCommand {
-            tx,
-            kind: TransactionKind::Dispute,
-            client: transaction.client,
-        });
+        let res = transaction.apply(
+            TransactionCommand {
+                tx,
+                kind: TransactionKind::Dispute,
+                client: transaction.client,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            },
+            &Ownership::default(),
+        );
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err(),
@@ -211,14 +872,23 @@ mod tests {
             },
             client: 1,
             amount: Decimal::from(8),
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         };
 
         let client = transaction.client + 1;
-        let res = transaction.apply(TransactionCommand {
-            client,
-            kind: TransactionKind::Dispute,
-            tx: transaction.tx,
-        });
+        let res = transaction.apply(
+            TransactionCommand {
+                client,
+                kind: TransactionKind::Dispute,
+                tx: transaction.tx,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            },
+            &Ownership::default(),
+        );
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err(),
@@ -254,6 +924,31 @@ mod tests {
                 TransactionKind::Dispute,
                 TransactionKind::ChargeBack,
             ),
+            (
+                "escrow -> release",
+                TransactionKind::Escrow { amount },
+                TransactionKind::Release,
+            ),
+            (
+                "escrow -> refund",
+                TransactionKind::Escrow { amount },
+                TransactionKind::Refund,
+            ),
+            (
+                "pending deposit -> settle",
+                TransactionKind::PendingDeposit { amount },
+                TransactionKind::Settle { withdrawal: false },
+            ),
+            (
+                "pending deposit -> fail",
+                TransactionKind::PendingDeposit { amount },
+                TransactionKind::Fail { withdrawal: false },
+            ),
+            (
+                "settle -> dispute",
+                TransactionKind::Settle { withdrawal: false },
+                TransactionKind::Dispute,
+            ),
         ];
 
         for (name, from, to) in cases {
@@ -262,12 +957,21 @@ mod tests {
                 kind: from,
                 client: 1,
                 amount,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
             };
-            let res = transaction.apply(TransactionCommand {
-                kind: to,
-                tx: transaction.tx,
-                client: transaction.client,
-            });
+            let res = transaction.apply(
+                TransactionCommand {
+                    kind: to,
+                    tx: transaction.tx,
+                    client: transaction.client,
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                },
+                &Ownership::default(),
+            );
             assert!(res.is_ok(), "{}", name);
             assert_eq!(res.unwrap().kind, to)
         }
@@ -294,6 +998,36 @@ mod tests {
                 TransactionKind::Deposit { amount },
                 TransactionKind::ChargeBack,
             ),
+            (
+                "deposit -> release",
+                TransactionKind::Deposit { amount },
+                TransactionKind::Release,
+            ),
+            (
+                "escrow -> dispute",
+                TransactionKind::Escrow { amount },
+                TransactionKind::Dispute,
+            ),
+            (
+                "release -> refund",
+                TransactionKind::Release,
+                TransactionKind::Refund,
+            ),
+            (
+                "pending deposit -> dispute",
+                TransactionKind::PendingDeposit { amount },
+                TransactionKind::Dispute,
+            ),
+            (
+                "deposit -> settle",
+                TransactionKind::Deposit { amount },
+                TransactionKind::Settle { withdrawal: false },
+            ),
+            (
+                "settle -> fail",
+                TransactionKind::Settle { withdrawal: false },
+                TransactionKind::Fail { withdrawal: false },
+            ),
             (
                 "withdrawal -> withdrawal",
                 TransactionKind::Withdrawal { amount },
@@ -357,12 +1091,21 @@ mod tests {
                 kind: from,
                 client: 1,
                 amount,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
             };
-            let res = transaction.apply(TransactionCommand {
-                kind: to,
-                tx: transaction.tx,
-                client: transaction.client,
-            });
+            let res = transaction.apply(
+                TransactionCommand {
+                    kind: to,
+                    tx: transaction.tx,
+                    client: transaction.client,
+                    correlation: None,
+                    tenant: None,
+                    timestamp: None,
+                },
+                &Ownership::default(),
+            );
             assert!(res.is_err(), "{}", name);
             assert_eq!(
                 res.unwrap_err(),
@@ -383,6 +1126,9 @@ mod tests {
             kind: TransactionKind::Deposit { amount },
             tx: 1,
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         };
 
         let res = Transaction::try_from(command);
@@ -400,6 +1146,9 @@ mod tests {
             kind: TransactionKind::Withdrawal { amount },
             tx: 1,
             client: 1,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
         };
 
         let res = Transaction::try_from(command);
@@ -409,6 +1158,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_canonical_name() {
+        let cases = vec![
+            ("deposit", Some("deposit")),
+            ("Deposit", Some("deposit")),
+            ("DEPOSIT", Some("deposit")),
+            ("withdraw", Some("withdrawal")),
+            ("Withdrawal", Some("withdrawal")),
+            ("charge_back", Some("chargeback")),
+            ("Charge-Back", Some("chargeback")),
+            ("chargeback", Some("chargeback")),
+            ("refund", Some("refund")),
+            ("transfer", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(TransactionKind::canonical_name(name), expected, "{}", name);
+        }
+    }
+
     #[test]
     fn test_try_from_invalid() -> Result<()> {
         let cases = vec![
@@ -422,6 +1190,9 @@ mod tests {
                 kind,
                 tx: 1,
                 client: 1,
+                correlation: None,
+                tenant: None,
+                timestamp: None,
             };
             let res = Transaction::try_from(command);
             assert!(res.is_err());
@@ -435,4 +1206,178 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_sled_repo() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let repo = SledRepo::new(&db)?;
+        let amount = Decimal::new(25, 1);
+        repo.save(Transaction {
+            tx: 7,
+            kind: TransactionKind::Dispute,
+            client: 2,
+            amount,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+
+        let transaction = repo.get(7)?.unwrap();
+        assert_eq!(transaction.kind, TransactionKind::Dispute);
+        assert_eq!(transaction.amount, amount);
+        assert!(repo.get(8)?.is_none());
+        assert_eq!(repo.get_by_client(2)?.len(), 1);
+        assert!(repo.get_by_client(3)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_by_client() -> Result<()> {
+        let repo = MemoryRepo::new();
+        let amount = Decimal::from(1);
+        for (tx, client) in [(3, 1), (1, 1), (2, 2)] {
+            repo.save(Transaction {
+                tx,
+                client,
+                amount,
+                kind: TransactionKind::Deposit { amount },
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+        let txs: Vec<u64> = repo.get_by_client(1)?.iter().map(|t| t.tx).collect();
+        assert_eq!(txs, vec![1, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dense_repo() -> Result<()> {
+        const FAR: u64 = MIN_DENSE_LEN * 4;
+        let repo = DenseRepo::new();
+        let amount = Decimal::from(1);
+        for (tx, client) in [(2, 1), (0, 2), (FAR, 1), (5, 1)] {
+            repo.save(Transaction {
+                tx,
+                client,
+                amount,
+                kind: TransactionKind::Deposit { amount },
+                correlation: None,
+                tenant: None,
+                timestamp: None,
+            })?;
+        }
+        assert_eq!(repo.dense.borrow().len(), 6);
+        assert_eq!(repo.sparse.borrow().len(), 1);
+
+        repo.save(Transaction {
+            tx: 2,
+            client: 1,
+            amount,
+            kind: TransactionKind::Dispute,
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        assert_eq!(repo.get(2)?.unwrap().kind, TransactionKind::Dispute);
+        assert_eq!(repo.get(FAR)?.unwrap().client, 1);
+        assert!(repo.get(1)?.is_none());
+        assert!(repo.get(MIN_DENSE_LEN)?.is_none());
+
+        let txs: Vec<u64> = repo.get_by_client(1)?.iter().map(|t| t.tx).collect();
+        assert_eq!(txs, vec![2, 5, FAR]);
+        assert_eq!(repo.get_all()?.len(), 4);
+
+        assert_eq!(repo.remove(5)?.unwrap().tx, 5);
+        assert_eq!(repo.remove(FAR)?.unwrap().tx, FAR);
+        assert!(repo.remove(5)?.is_none());
+        assert_eq!(repo.get_all()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dense_repo_occupancy() -> Result<()> {
+        let amount = Decimal::from(1);
+        let deposit = |tx| Transaction {
+            tx,
+            client: 1,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+
+        // strided IDs would leave almost all of the vector empty, so are kept in the map
+        let strided = DenseRepo::new();
+        for i in 0..100 {
+            strided.save(deposit(i * 60_000))?;
+        }
+        assert_eq!(strided.dense.borrow().len(), 1);
+        assert_eq!(strided.sparse.borrow().len(), 99);
+        assert_eq!(strided.get(99 * 60_000)?.unwrap().tx, 99 * 60_000);
+        assert_eq!(strided.get_all()?.len(), 100);
+
+        // sequential IDs (even with a few gaps) keep growing the vector
+        let sequential = DenseRepo::new();
+        for tx in (0..MIN_DENSE_LEN * 4).filter(|tx| tx % 3 != 1) {
+            sequential.save(deposit(tx))?;
+        }
+        assert_eq!(sequential.dense.borrow().len() as u64, MIN_DENSE_LEN * 4);
+        assert!(sequential.sparse.borrow().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_repo_with_hasher() -> Result<()> {
+        let repo = MemoryRepo::with_hasher(fxhash::FxBuildHasher::default());
+        let amount = Decimal::from(1);
+        repo.save(Transaction {
+            tx: 7,
+            client: 1,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        })?;
+        assert_eq!(repo.get(7)?.unwrap().client, 1);
+        assert!(repo.get(8)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_repo() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let amount = Decimal::from(1);
+        let deposit = Transaction {
+            tx: 1,
+            client: 1,
+            amount,
+            kind: TransactionKind::Deposit { amount },
+            correlation: None,
+            tenant: None,
+            timestamp: None,
+        };
+        SledRepo::new(&db)?.save(deposit)?;
+
+        let repo = OverlayRepo::new(Box::new(SledRepo::new(&db)?));
+        repo.save(Transaction {
+            kind: TransactionKind::Dispute,
+            ..deposit
+        })?;
+        repo.save(Transaction { tx: 2, ..deposit })?;
+
+        assert_eq!(repo.get(1)?.unwrap().kind, TransactionKind::Dispute);
+        assert_eq!(repo.get_by_client(1)?.len(), 2);
+        assert_eq!(repo.get_all()?[0].kind, TransactionKind::Dispute);
+        assert_eq!(SledRepo::new(&db)?.get(1)?.unwrap().kind, deposit.kind);
+        assert!(SledRepo::new(&db)?.get(2)?.is_none());
+
+        assert_eq!(repo.remove(1)?.unwrap().kind, TransactionKind::Dispute);
+        assert!(repo.get(1)?.is_none());
+        assert_eq!(repo.get_all()?.len(), 1);
+        assert!(SledRepo::new(&db)?.get(1)?.is_some());
+        Ok(())
+    }
 }
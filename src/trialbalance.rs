@@ -0,0 +1,175 @@
+use std::io;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::ledger::{ChartOfAccounts, LedgerAccount, Posting};
+
+/// TrialBalanceFormat is the layout of a trial balance report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrialBalanceFormat {
+    /// A row per account, followed by a total row
+    Csv,
+    /// An object with the accounts & the totals
+    Json,
+}
+
+impl FromStr for TrialBalanceFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<TrialBalanceFormat> {
+        match s {
+            "csv" => Ok(TrialBalanceFormat::Csv),
+            "json" => Ok(TrialBalanceFormat::Json),
+            _ => bail!("expected csv or json, got {:?}", s),
+        }
+    }
+}
+
+/// TrialBalanceLine is an internal account's total debits & credits, and its closing balance as
+/// the net of them (positive for a debit balance, negative for a credit balance)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrialBalanceLine {
+    pub account: String,
+    pub code: String,
+    pub name: String,
+    pub debits: Decimal,
+    pub credits: Decimal,
+    pub balance: Decimal,
+}
+
+/// TrialBalance is the totals of every internal account, which balance when the total debits
+/// equal the total credits
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrialBalance {
+    pub accounts: Vec<TrialBalanceLine>,
+    pub debits: Decimal,
+    pub credits: Decimal,
+    pub balanced: bool,
+}
+
+/// trial_balance totals the postings against each internal account, listing every account in the
+/// chart even if nothing was posted to it
+pub fn trial_balance(postings: &[Posting], chart: &ChartOfAccounts) -> TrialBalance {
+    let accounts: Vec<TrialBalanceLine> = LedgerAccount::ALL
+        .into_iter()
+        .map(|account| {
+            let (debits, credits) = postings
+                .iter()
+                .filter(|p| p.account == account)
+                .fold((Decimal::from(0), Decimal::from(0)), |(d, c), p| {
+                    (d + p.debit, c + p.credit)
+                });
+            let entry = chart.entry(account);
+            TrialBalanceLine {
+                account: account.to_string(),
+                code: entry.code.clone(),
+                name: entry.name.clone(),
+                debits,
+                credits,
+                balance: debits - credits,
+            }
+        })
+        .collect();
+    let debits = accounts.iter().map(|a| a.debits).sum();
+    let credits = accounts.iter().map(|a| a.credits).sum();
+    TrialBalance {
+        accounts,
+        debits,
+        credits,
+        balanced: debits == credits,
+    }
+}
+
+/// write_trial_balance writes a trial balance in the given layout
+pub fn write_trial_balance<W: io::Write>(
+    writer: W,
+    trial_balance: &TrialBalance,
+    format: TrialBalanceFormat,
+) -> Result<()> {
+    match format {
+        TrialBalanceFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for line in &trial_balance.accounts {
+                writer.serialize(line)?;
+            }
+            writer.serialize(TrialBalanceLine {
+                account: "total".to_string(),
+                code: String::new(),
+                name: String::new(),
+                debits: trial_balance.debits,
+                credits: trial_balance.credits,
+                balance: trial_balance.debits - trial_balance.credits,
+            })?;
+            writer.flush()?;
+        }
+        TrialBalanceFormat::Json => {
+            let mut writer = io::BufWriter::new(writer);
+            serde_json::to_writer_pretty(&mut writer, trial_balance)?;
+            io::Write::write_all(&mut writer, b"\n")?;
+            io::Write::flush(&mut writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trial_balance() -> Result<()> {
+        let d = Decimal::from;
+        let posting = |entry, account, debit: i64, credit: i64| Posting {
+            entry,
+            tx: entry,
+            client: Some(1),
+            erasure: None,
+            account,
+            debit: d(debit),
+            credit: d(credit),
+        };
+        let postings = [
+            posting(1, LedgerAccount::Cash, 10, 0),
+            posting(1, LedgerAccount::ClientFunds, 0, 10),
+            posting(2, LedgerAccount::ClientFunds, 4, 0),
+            posting(2, LedgerAccount::Cash, 0, 4),
+        ];
+        let chart = ChartOfAccounts::default();
+        let balance = trial_balance(&postings, &chart);
+        assert_eq!(balance.accounts.len(), LedgerAccount::ALL.len());
+        assert_eq!(
+            balance.accounts[2],
+            TrialBalanceLine {
+                account: "client_funds".to_string(),
+                code: "2000".to_string(),
+                name: "Client funds".to_string(),
+                debits: d(4),
+                credits: d(10),
+                balance: d(-6),
+            }
+        );
+        assert_eq!((balance.debits, balance.credits), (d(14), d(14)));
+        assert!(balance.balanced);
+
+        let mut out = Vec::new();
+        write_trial_balance(&mut out, &balance, TrialBalanceFormat::Csv)?;
+        let out = String::from_utf8(out)?;
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "account,code,name,debits,credits,balance");
+        assert_eq!(lines[1], "cash,1000,Cash,10,4,6");
+        assert_eq!(lines[7], "total,,,14,14,0");
+
+        let mut out = Vec::new();
+        write_trial_balance(&mut out, &balance, TrialBalanceFormat::Json)?;
+        let json: serde_json::Value = serde_json::from_slice(&out)?;
+        assert_eq!(json["balanced"], true);
+        assert_eq!(json["accounts"][0]["debits"], "10");
+
+        let unbalanced = trial_balance(&postings[..3], &chart);
+        assert!(!unbalanced.balanced);
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+/// MemoryUsage is the memory used by the process, as far as it can be measured on this platform
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MemoryUsage {
+    /// The peak resident set size in bytes, i.e. the most physical memory used at any point
+    pub peak_rss: Option<u64>,
+    /// The number of bytes currently allocated by the application, according to jemalloc
+    pub allocated: Option<u64>,
+    /// The number of bytes in physically resident pages mapped by jemalloc
+    pub resident: Option<u64>,
+}
+
+impl MemoryUsage {
+    /// measure gets the current memory usage of the process
+    pub fn measure() -> MemoryUsage {
+        let (allocated, resident) = allocator_stats();
+        MemoryUsage {
+            peak_rss: peak_rss(),
+            allocated,
+            resident,
+        }
+    }
+}
+
+/// peak_rss reads the high water mark of the resident set size, which is only available on Linux
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_status(&status, "VmHWM:")
+}
+
+/// current_rss reads the current resident set size in bytes, which is only available on Linux
+pub fn current_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_status(&status, "VmRSS:")
+}
+
+/// parse_status gets a size in bytes, e.g. the peak resident set size (`VmHWM:`), from the
+/// contents of /proc/self/status
+fn parse_status(status: &str, field: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with(field))?;
+    let kb: u64 = line
+        .trim_start_matches(field)
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(feature = "jemalloc")]
+fn allocator_stats() -> (Option<u64>, Option<u64>) {
+    use tikv_jemalloc_ctl::{epoch, stats};
+    // statistics are cached, so they need refreshing before being read
+    if epoch::advance().is_err() {
+        return (None, None);
+    }
+    (
+        stats::allocated::read().ok().map(|b| b as u64),
+        stats::resident::read().ok().map(|b| b as u64),
+    )
+}
+
+#[cfg(not(feature = "jemalloc"))]
+fn allocator_stats() -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
+/// Bytes formats a number of bytes with a binary unit, e.g. 1.5 MiB
+pub struct Bytes(pub u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64;
+        let mut unit = "B";
+        for u in UNITS {
+            if value < 1024.0 {
+                break;
+            }
+            value /= 1024.0;
+            unit = u;
+        }
+        write!(f, "{:.1} {}", value, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        let status =
+            "Name:\tpayments\nVmPeak:\t  20000 kB\nVmHWM:\t    1536 kB\nVmRSS:\t 1024 kB\n";
+        assert_eq!(parse_status(status, "VmHWM:"), Some(1536 * 1024));
+        assert_eq!(parse_status(status, "VmRSS:"), Some(1024 * 1024));
+        assert_eq!(parse_status("Name:\tpayments\n", "VmHWM:"), None);
+    }
+
+    #[test]
+    fn test_bytes() {
+        assert_eq!(Bytes(512).to_string(), "512 B");
+        assert_eq!(Bytes(1536).to_string(), "1.5 KiB");
+        assert_eq!(Bytes(3 << 30).to_string(), "3.0 GiB");
+    }
+}
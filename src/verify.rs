@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::accounts::AccountStatement;
+use crate::accounts::{Account, AccountsRepo, MemoryRepo as AccountsMemoryRepo};
+use crate::snapshot::Snapshot;
+use crate::transactions::{
+    MemoryRepo as TransactionsMemoryRepo, Transaction, TransactionKind, TransactionsRepo,
+};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum VerifyError {
+    #[error("{0} record(s) differ from a replay of the event log")]
+    Diverged(usize),
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ReplayError {
+    #[error("tx {0} isn't in the event log")]
+    UnknownTx(u64),
+    #[error("{0:?} is neither a transaction ID nor an RFC 3339 timestamp")]
+    InvalidUntil(String),
+}
+
+/// Until is the point in an event log up to which it's replayed: a transaction ID or a timestamp
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Until {
+    Tx(u64),
+    Timestamp(DateTime<Utc>),
+}
+
+impl FromStr for Until {
+    type Err = ReplayError;
+    fn from_str(s: &str) -> Result<Until, ReplayError> {
+        if let Ok(tx) = s.parse() {
+            return Ok(Until::Tx(tx));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|timestamp| Until::Timestamp(timestamp.with_timezone(&Utc)))
+            .map_err(|_| ReplayError::InvalidUntil(s.to_string()))
+    }
+}
+
+/// events_until is the part of an event log up to a point: up to & including the first event for
+/// a transaction (i.e. its creation), or every event before the first one which happened after a
+/// timestamp. Events without a timestamp never end the log early.
+pub fn events_until(events: &[Transaction], until: Until) -> Result<&[Transaction], ReplayError> {
+    let end = match until {
+        Until::Tx(tx) => events
+            .iter()
+            .position(|event| event.tx == tx)
+            .map(|i| i + 1)
+            .ok_or(ReplayError::UnknownTx(tx))?,
+        Until::Timestamp(until) => events
+            .iter()
+            .position(|event| event.timestamp.is_some_and(|t| t > until))
+            .unwrap_or(events.len()),
+    };
+    Ok(&events[..end])
+}
+
+/// Divergence is a record whose persisted state differs from its state when the event log is
+/// replayed from scratch, each written as JSON (or empty if there's no such record)
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Divergence {
+    /// The kind of record: account, transaction or statement
+    pub record: &'static str,
+    /// The client or transaction ID
+    pub id: u64,
+    pub persisted: String,
+    pub replayed: String,
+}
+
+/// replay applies every event to the accounts & transactions it names, in the order they were
+/// logged, into repositories which are expected to be empty
+pub fn replay(
+    events: &[Transaction],
+    transactions: &dyn TransactionsRepo,
+    accounts: &dyn AccountsRepo,
+) -> Result<()> {
+    for event in events {
+        let account = match accounts.get(event.client)? {
+            Some(acc) => acc.apply(*event),
+            None => Account::new(*event),
+        }
+        .with_context(|| format!("unable to replay tx={} client={}", event.tx, event.client))?;
+        accounts.save(account)?;
+        // a forced resolve leaves the transaction resolved as usual, while the event records
+        // that it was forced
+        let transaction = match event.kind {
+            TransactionKind::ForceResolve => Transaction {
+                kind: TransactionKind::Resolve,
+                ..*event
+            },
+            _ => *event,
+        };
+        transactions.save(transaction)?;
+    }
+    Ok(())
+}
+
+fn json<T: Serialize>(records: Vec<T>, id: impl Fn(&T) -> u64) -> Result<BTreeMap<u64, String>> {
+    records
+        .into_iter()
+        .map(|record| Ok((id(&record), serde_json::to_string(&record)?)))
+        .collect()
+}
+
+/// divergences compares every record by its serialized form, so any difference (even in the
+/// scale of an amount) is reported
+fn divergences(
+    record: &'static str,
+    persisted: &BTreeMap<u64, String>,
+    replayed: &BTreeMap<u64, String>,
+    ids: BTreeSet<u64>,
+) -> Vec<Divergence> {
+    ids.into_iter()
+        .filter(|id| persisted.get(id) != replayed.get(id))
+        .map(|id| Divergence {
+            record,
+            id,
+            persisted: persisted.get(&id).cloned().unwrap_or_default(),
+            replayed: replayed.get(&id).cloned().unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// verify replays a snapshot's event log into fresh in-memory repositories, and returns every
+/// account & transaction whose persisted state differs from the replay's, along with the
+/// replayed accounts. Transactions which aren't persisted aren't compared, since they may have
+/// been evicted by retention.
+pub fn verify(snapshot: &Snapshot) -> Result<(Vec<Account>, Vec<Divergence>)> {
+    let transactions = TransactionsMemoryRepo::new();
+    let accounts = AccountsMemoryRepo::new();
+    replay(&snapshot.events, &transactions, &accounts)?;
+    let replayed_accounts = accounts.get_all()?;
+    let found = compare(snapshot, &replayed_accounts, transactions.get_all()?)?;
+    Ok((replayed_accounts, found))
+}
+
+/// compare returns every account & transaction in a snapshot whose state differs from the
+/// replayed state, along with replayed accounts which aren't in the snapshot. Transactions which
+/// aren't in the snapshot aren't compared, since they may have been evicted by retention.
+pub fn compare(
+    snapshot: &Snapshot,
+    accounts: &[Account],
+    transactions: Vec<Transaction>,
+) -> Result<Vec<Divergence>> {
+    let persisted = json(snapshot.accounts.clone(), Account::client)?;
+    let replayed = json(accounts.to_vec(), Account::client)?;
+    let ids = persisted.keys().chain(replayed.keys()).copied().collect();
+    let mut found = divergences("account", &persisted, &replayed, ids);
+
+    let persisted = json(snapshot.transactions.clone(), |t| t.tx)?;
+    let replayed = json(transactions, |t| t.tx)?;
+    let ids = persisted.keys().copied().collect();
+    found.extend(divergences("transaction", &persisted, &replayed, ids));
+    Ok(found)
+}
+
+/// verify_statements compares a statements file against the replayed accounts. Balances are
+/// compared by value, since statements may be written at a different scale.
+pub fn verify_statements(
+    statements: &BTreeMap<u64, AccountStatement>,
+    accounts: &[Account],
+) -> Vec<Divergence> {
+    let replayed: BTreeMap<u64, AccountStatement> = accounts
+        .iter()
+        .map(|acc| (acc.client(), AccountStatement::from(*acc)))
+        .collect();
+    let format = |statement: Option<&AccountStatement>| {
+        statement
+            .map(|s| {
+                format!(
+                    "available={} held={} total={} locked={}",
+                    s.available, s.held, s.total, s.locked
+                )
+            })
+            .unwrap_or_default()
+    };
+    statements
+        .keys()
+        .chain(replayed.keys())
+        .copied()
+        .collect::<BTreeSet<u64>>()
+        .into_iter()
+        .filter(|client| statements.get(client) != replayed.get(client))
+        .map(|client| Divergence {
+            record: "statement",
+            id: client,
+            persisted: format(statements.get(&client)),
+            replayed: format(replayed.get(&client)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::read_statements;
+    use crate::events::MemoryRepo as EventsMemoryRepo;
+    use crate::payments::{AdminAction, PaymentsEngine};
+    use crate::testkit::command;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let transactions = TransactionsMemoryRepo::new();
+        let accounts = AccountsMemoryRepo::new();
+        let events = EventsMemoryRepo::new();
+        let engine = PaymentsEngine::new(&transactions, &accounts, &events);
+        let process = |kind, tx, client| engine.process_transaction(command(kind, client, tx));
+        let amount = Decimal::from(10);
+        process(TransactionKind::Deposit { amount }, 1, 1)?;
+        process(TransactionKind::Deposit { amount }, 2, 2)?;
+        process(TransactionKind::Dispute, 1, 1)?;
+        engine.process_admin(AdminAction::ForceResolve { client: 1, tx: 1 })?;
+        process(TransactionKind::Withdrawal { amount }, 3, 2)?;
+
+        let mut snapshot = Snapshot::capture(&transactions, &accounts, &events)?;
+        let (replayed, found) = verify(&snapshot)?;
+        assert_eq!(found, vec![]);
+        assert_eq!(replayed.len(), 2);
+
+        // an account which was changed without an event, and a transaction which was evicted
+        snapshot.accounts.retain(|acc| acc.client() != 2);
+        snapshot.transactions.retain(|t| t.tx != 2);
+        let (_, found) = verify(&snapshot)?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].record, "account");
+        assert_eq!(found[0].id, 2);
+        assert!(found[0].persisted.is_empty());
+        assert!(found[0].replayed.contains("\"client\":2"));
+
+        let statements = read_statements(
+            "client,available,held,total,locked\n1,10.0000,0,10,false\n2,1,0,1,false\n".as_bytes(),
+        )?;
+        let found = verify_statements(&statements, &replayed);
+        assert_eq!(
+            found,
+            vec![Divergence {
+                record: "statement",
+                id: 2,
+                persisted: "available=1 held=0 total=1 locked=false".to_string(),
+                replayed: "available=0 held=0 total=0 locked=false".to_string(),
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_events_until() -> Result<()> {
+        let event = |tx, timestamp: Option<&str>| -> Result<Transaction> {
+            let amount = Decimal::from(1);
+            Ok(Transaction {
+                tx,
+                amount,
+                kind: TransactionKind::Deposit { amount },
+                client: 1,
+                correlation: None,
+                tenant: None,
+                timestamp: timestamp.map(str::parse).transpose()?,
+            })
+        };
+        let events = vec![
+            event(1, Some("2024-01-01T00:00:00Z"))?,
+            event(2, None)?,
+            event(1, Some("2024-01-02T00:00:00Z"))?,
+            event(3, Some("2024-01-03T00:00:00Z"))?,
+        ];
+        let txs = |until: &str| -> Result<Vec<u64>> {
+            Ok(events_until(&events, until.parse()?)?
+                .iter()
+                .map(|e| e.tx)
+                .collect())
+        };
+        assert_eq!(txs("1")?, vec![1]);
+        assert_eq!(txs("3")?, vec![1, 2, 1, 3]);
+        assert_eq!(txs("2024-01-02T12:00:00+00:00")?, vec![1, 2, 1]);
+        assert!(txs("2023-12-31T00:00:00Z")?.is_empty());
+        assert_eq!(
+            events_until(&events, Until::Tx(4)).unwrap_err(),
+            ReplayError::UnknownTx(4)
+        );
+        assert_eq!(
+            "yesterday".parse::<Until>(),
+            Err(ReplayError::InvalidUntil("yesterday".to_string()))
+        );
+        Ok(())
+    }
+}